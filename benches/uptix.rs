@@ -0,0 +1,142 @@
+//! Baselines for parsing nix files, writing uptix.lock and resolving
+//! dependencies against a mock registry
+
+#![allow(clippy::needless_return)]
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::stream::{self, StreamExt};
+use serde_json::json;
+use std::fs;
+use std::path::PathBuf;
+use uptix::config::Config;
+use uptix::lock::{self, LockData, LockFile};
+use uptix::{deps, http};
+
+/// How many declarations each benchmark works with
+const SIZES: &[usize] = &[10, 100, 1000];
+
+/// A nix file with `count` declarations of every kind
+fn nix_file(dir: &tempfile::TempDir, count: usize) -> PathBuf {
+    let mut content = "{ uptix, ... }:\n{\n".to_string();
+    for i in 0..count {
+        content.push_str(&format!(
+            r#"  image{i} = uptix.dockerImage "example/app{i}:1.0";
+  branch{i} = uptix.githubBranch {{
+    owner = "example";
+    repo = "repo{i}";
+    branch = "main";
+  }};
+  release{i} = uptix.githubRelease {{
+    owner = "example";
+    repo = "repo{i}";
+  }};
+"#
+        ));
+    }
+    content.push_str("}\n");
+    let path = dir.path().join(format!("bench{}.nix", count));
+    fs::write(&path, content).unwrap();
+    return path;
+}
+
+fn parsing(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let config = Config::default();
+    let mut group = c.benchmark_group("parsing");
+    for &count in SIZES {
+        let path = nix_file(&dir, count);
+        let path = path.to_str().unwrap();
+        group.throughput(Throughput::Elements(3 * count as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), path, |b, path| {
+            b.iter(|| deps::collect_file_dependencies(path, &config).unwrap());
+        });
+    }
+    group.finish();
+}
+
+fn lock_serialization(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("uptix.lock");
+    let mut group = c.benchmark_group("lock_serialization");
+    for &count in SIZES {
+        let lock_file: LockFile = (0..count)
+            .map(|i| {
+                let lock = json!({
+                    "owner": "example",
+                    "repo": format!("repo{}", i),
+                    "rev": "b4ffde65f46336ab88eb53be808477a3936bae11",
+                    "sha256": "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j",
+                });
+                return (
+                    format!("$GITHUB_BRANCH$:example/repo{}:main$", i),
+                    LockData::from(lock),
+                );
+            })
+            .collect();
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(count),
+            &lock_file,
+            |b, lock_file| {
+                b.iter(|| lock::write(&path, lock_file).unwrap());
+            },
+        );
+    }
+    group.finish();
+}
+
+fn resolution(c: &mut Criterion) {
+    let registry = mockito::server_address().to_string();
+    let _ping_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+    let _manifest_mock = mockito::mock(
+        "HEAD",
+        mockito::Matcher::Regex(r"^/v2/bench/app\d+/manifests/1\.0$".to_string()),
+    )
+    .with_status(200)
+    .with_header("docker-content-digest", "sha256:foobar")
+    .create();
+
+    let config: Config = toml::from_str(&format!(
+        "[docker.registries.\"{}\"]\ninsecure = true",
+        registry
+    ))
+    .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("resolution");
+    for &count in &SIZES[..2] {
+        let mut content = "{\n".to_string();
+        for i in 0..count {
+            content.push_str(&format!(
+                "  image{} = uptix.dockerImage \"{}/bench/app{}:1.0\";\n",
+                i, registry, i
+            ));
+        }
+        content.push_str("}\n");
+        let path = dir.path().join(format!("resolution{}.nix", count));
+        fs::write(&path, content).unwrap();
+        let dependencies =
+            deps::collect_file_dependencies(path.to_str().unwrap(), &config).unwrap();
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(count),
+            &dependencies,
+            |b, dependencies| {
+                b.iter(|| {
+                    runtime.block_on(async {
+                        let locks: Vec<_> = stream::iter(dependencies)
+                            .map(|dependency| dependency.lock())
+                            .buffered(http::jobs())
+                            .collect()
+                            .await;
+                        assert!(locks.iter().all(|lock| lock.is_ok()));
+                    })
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parsing, lock_serialization, resolution);
+criterion_main!(benches);
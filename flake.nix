@@ -28,13 +28,15 @@
           openssl
           makeWrapper
         ];
+        # for the tests of shallow clones
+        nativeCheckInputs = [ git ];
         preBuild = ''
           export OPENSSL_DIR="${pkgs.openssl.dev}"
           export OPENSSL_LIB_DIR="${pkgs.openssl.out}/lib"
         '';
         postInstall = ''
           wrapProgram $out/bin/uptix \
-            --prefix PATH : ${lib.makeBinPath [ nix-prefetch-git ]}
+            --prefix PATH : ${lib.makeBinPath [ git nix-prefetch-git ]}
         '';
 
         meta = {
@@ -20,18 +20,86 @@ let
       else [{ inherit name; value = set.${name}; }])
     (attrNames set))
   );
+  # this needs to match how the name is built by uptix
+  dockerRef = { image, tag ? null, registry ? null, ... }:
+    (if registry == null then "" else "${registry}/")
+    + image
+    + (if tag == null then "" else ":${tag}");
+  dockerKey = { arch ? null, platform ? arch, ... } @ args:
+    if platform == null then dockerRef args else "${dockerRef args}\$${platform}";
+  dockerSpecRef = spec: if isString spec then spec else dockerRef spec;
+  dockerSpecKey = spec: if isString spec then spec else dockerKey spec;
+  # tag patterns (i.e. grafana/grafana:10.*) are locked along with the
+  # concrete tag they resolved to
+  lockedDockerRef = spec: entry:
+    if entry ? tag
+    then "${head (match "(.*):[^:/]*" (dockerSpecRef spec))}:${entry.tag}"
+    else dockerSpecRef spec;
+  # repositories outside of github.com have their host on the key
+  hostPrefix = { host ? "github.com", ... }:
+    if host == "github.com" then "" else "${host}/";
+  githubReleaseKey =
+    { owner, repo, asset ? null, versionConstraint ? null, allowPrerelease ? null
+    , tagPrefix ? null, tagPattern ? null, tagRegex ? null, tag ? null, ... } @ args:
+    "$GITHUB_RELEASE$:${hostPrefix args}${owner}/${repo}\$${gitFlags args}"
+    + (if asset == null then "" else "\$${asset}")
+    + (if versionConstraint == null then "" else "@${versionConstraint}")
+    + (if allowPrerelease == null then ""
+    else if allowPrerelease then "+prerelease"
+    else "-prerelease")
+    + (if tagPrefix == null then "" else "#prefix:${tagPrefix}")
+    + (if tagPattern == null then "" else "#pattern:${tagPattern}")
+    + (if tagRegex == null then "" else "#regex:${tagRegex}")
+    + (if tag == null then "" else "#tag:${tag}");
   # from nixpkgs.lib
   importJSON = path: fromJSON (readFile path);
   hasPrefix = pref: str: substring 0 (stringLength pref) str == pref;
 in
 {
-  dockerImage = name: "${name}@${lockFor name}";
-  githubBranch = { owner, repo, branch, ... } @ args:
-    (filterFalse (lockFor "$GITHUB_BRANCH$:${owner}/${repo}:${branch}\$${gitFlags args}"))
-    // (removeAttrs args [ "branch" ]);
+  dockerImage = spec:
+    let entry = lockFor (dockerSpecKey spec); in
+    if isAttrs entry
+    then "${lockedDockerRef spec entry}@${entry.digest}"
+    else "${dockerSpecRef spec}@${entry}";
+  # maps each platform (i.e. linux/arm64) to the image for that platform
+  dockerImagePlatforms = spec:
+    let entry = lockFor "${dockerSpecKey spec}\$platforms"; in
+    mapAttrs (_: digest: "${lockedDockerRef spec entry}@${digest}") entry.platforms;
+  # arguments for dockerTools.pullImage, including the hash of the image
+  dockerPullImage = spec:
+    let
+      entry = lockFor "${dockerSpecKey spec}\$pullImage";
+      ref = dockerSpecRef spec;
+      untagged = match "(.*):([^:/]*)" ref;
+      platform = if isString spec then null else spec.platform or spec.arch or null;
+      parts = if platform == null then [ ] else filter isString (split "/" platform);
+    in
+    {
+      imageName = if untagged == null then ref else head untagged;
+      imageDigest = entry.digest;
+      sha256 = entry.sha256;
+      finalImageTag = entry.tag or (if untagged == null then "latest" else elemAt untagged 1);
+    }
+    // (if parts == [ ] then { }
+    # a bare architecture implies linux, as it does for uptix
+    else if length parts == 1 then { os = "linux"; arch = head parts; }
+    else { os = head parts; arch = elemAt parts 1; });
+  githubBranch = { owner, repo, branch ? "HEAD", ... } @ args:
+    (filterFalse (removeAttrs
+      (lockFor "$GITHUB_BRANCH$:${hostPrefix args}${owner}/${repo}:${branch}\$${gitFlags args}")
+      [ "submodules" ]))
+    // (removeAttrs args [ "branch" "verifySignature" "lockSubmodules" "requireChecks" "sshKey" "host" ]);
   githubRelease = { owner, repo, ... } @ args:
-    (filterFalse (lockFor "$GITHUB_RELEASE$:${owner}/${repo}\$${gitFlags args}"))
-    // args;
+    (filterFalse (removeAttrs (lockFor (githubReleaseKey args)) [ "asset" "submodules" "metadata" ]))
+    // (removeAttrs args [
+      "asset" "versionConstraint" "allowPrerelease" "tagPrefix" "tagPattern"
+      "tagRegex" "tag" "verifySignature" "lockSubmodules" "host"
+    ]);
+  # arguments for fetchurl, for the release asset matching the asset pattern
+  githubReleaseAsset = { owner, repo, asset, ... } @ args:
+    (lockFor (githubReleaseKey args)).asset;
+  # declarations are resolved by the functions above, this just groups them
+  deps = decls: decls;
   version = githubRelease:
     let rev = githubRelease.rev; in
     if hasPrefix "v" rev
@@ -0,0 +1,407 @@
+use crate::error::Error;
+use crate::http;
+use crate::lock::{GitHubLock, LockData, LockFile};
+use crate::util;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Seek};
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+/// Where OSV publishes its data, as one zip of advisories per ecosystem
+pub const OSV_URL: &str = "https://osv-vulnerabilities.storage.googleapis.com";
+
+/// The ecosystem `uptix db sync` downloads by default: advisories whose
+/// affected versions are tags and commits of git repositories, which is
+/// what uptix locks
+pub const DEFAULT_ECOSYSTEM: &str = "GIT";
+
+/// File describing a database, next to the directory of each ecosystem
+const METADATA_FILE: &str = "uptix-db.json";
+
+const HINTS: http::Hints = http::Hints {
+    unauthorized: "the OSV bucket is public, so check for a proxy in the way",
+    not_found: "check the name of the ecosystem, as OSV spells it (i.e. GIT or OSS-Fuzz)",
+};
+
+/// What `uptix db sync` last downloaded
+#[derive(Serialize, Deserialize, PartialEq, Default, Debug)]
+pub struct Metadata {
+    /// when it was downloaded, in seconds since the epoch
+    pub synced_at: i64,
+    /// how many advisories each ecosystem has
+    pub ecosystems: BTreeMap<String, usize>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct Range {
+    #[serde(rename = "type")]
+    kind: String,
+    repo: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct Affected {
+    ranges: Vec<Range>,
+    versions: Vec<String>,
+}
+
+/// An OSV advisory, with only what is needed to match it
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+struct Advisory {
+    id: String,
+    summary: Option<String>,
+    aliases: Vec<String>,
+    affected: Vec<Affected>,
+}
+
+/// An advisory affecting what an entry of uptix.lock is locked to
+#[derive(PartialEq, Debug)]
+pub struct Finding {
+    pub key: String,
+    /// the tag the advisory lists as affected
+    pub version: String,
+    /// i.e. `GHSA-xxxx-xxxx-xxxx`, or a CVE
+    pub id: String,
+    pub aliases: Vec<String>,
+    pub summary: Option<String>,
+}
+
+/// A repository URL as advisories and uptix.lock are compared on, i.e.
+/// `github.com/owner/repo` for `https://github.com/Owner/repo.git`
+fn normalize_repo(url: &str) -> String {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let url = url.trim_end_matches('/');
+    let url = url.strip_suffix(".git").unwrap_or(url);
+    return url.to_lowercase();
+}
+
+/// The repository an entry was fetched from, and the versions (tags or
+/// commits) it is locked to
+fn locked_versions(lock: &LockData) -> Option<(String, Vec<&str>)> {
+    let repository = |lock: &GitHubLock| -> String {
+        let host = lock.githubBase.as_deref().unwrap_or("github.com");
+        return normalize_repo(&format!("{}/{}/{}", host, lock.owner, lock.repo));
+    };
+    return match lock {
+        LockData::GitHub(lock) => Some((repository(lock), vec![lock.rev.as_str()])),
+        LockData::Release(release) => {
+            Some((repository(&release.lock), vec![release.lock.rev.as_str()]))
+        }
+        LockData::Action(action) => Some((
+            normalize_repo(&format!("github.com/{}/{}", action.owner, action.repo)),
+            vec![action.reference.as_str(), action.rev.as_str()],
+        )),
+        _ => None,
+    };
+}
+
+/// Writes the advisories (JSON files) of a zip archive to `directory`, one
+/// at a time, returning how many there were. Their checksums are checked as
+/// they are read, so a corrupt archive fails rather than being cut short.
+fn unzip(archive: fs::File, directory: &Path) -> zip::result::ZipResult<usize> {
+    let mut archive = zip::ZipArchive::new(archive)?;
+    let mut count = 0;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let path = match file.enclosed_name() {
+            Some(path) if file.is_file() => path,
+            _ => continue,
+        };
+        let name = match path.file_name() {
+            Some(name) if name.to_string_lossy().ends_with(".json") => name.to_owned(),
+            _ => continue,
+        };
+        let mut output = fs::File::create(directory.join(name))?;
+        io::copy(&mut file, &mut output)?;
+        count += 1;
+    }
+    return Ok(count);
+}
+
+/// Downloads the advisories of each ecosystem from `base_url` (i.e.
+/// `OSV_URL`) into `db`, one JSON file per advisory, replacing what was
+/// there for that ecosystem
+pub async fn sync(db: &Path, ecosystems: &[String], base_url: &str) -> Result<Metadata, Error> {
+    let mut metadata = Metadata {
+        synced_at: crate::report::now(),
+        ecosystems: BTreeMap::new(),
+    };
+    fs::create_dir_all(db)?;
+    for ecosystem in ecosystems {
+        let url = format!("{}/{}/all.zip", base_url.trim_end_matches('/'), ecosystem);
+        let request = http::client()?
+            .get(&url)
+            .header(reqwest::header::USER_AGENT, util::user_agent());
+        let response = http::send(request, http::transient).await?;
+        if let Some(error) = http::status_error(&response, &HINTS) {
+            return Err(error);
+        }
+        if !response.status().is_success() {
+            return Err(Error::DatabaseSyncFailed {
+                url,
+                reason: format!("the download failed with {}", response.status()),
+            });
+        }
+        // the archive is kept on disk rather than in memory, since the
+        // largest ecosystems are hundreds of megabytes
+        let mut archive = tokio::fs::File::from_std(tempfile::tempfile()?);
+        let mut response = response;
+        while let Some(chunk) = response.chunk().await? {
+            archive.write_all(&chunk).await?;
+        }
+        let mut archive = archive.into_std().await;
+        archive.rewind()?;
+        // written aside first, so that a failed sync leaves the previous
+        // advisories in place
+        let directory = db.join(ecosystem);
+        let staging = db.join(format!("{}.tmp", ecosystem));
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        fs::create_dir_all(&staging)?;
+        let unzipping = staging.clone();
+        let count = tokio::task::spawn_blocking(move || unzip(archive, &unzipping))
+            .await
+            .map_err(io::Error::other)?
+            .map_err(|error| Error::DatabaseSyncFailed {
+                url: url.clone(),
+                reason: format!("not a zip archive uptix can read: {}", error),
+            })?;
+        if directory.exists() {
+            fs::remove_dir_all(&directory)?;
+        }
+        fs::rename(&staging, &directory)?;
+        metadata.ecosystems.insert(ecosystem.clone(), count);
+    }
+    // ecosystems synced before, but not this time, are still there
+    if let Ok(previous) = read_metadata(db) {
+        for (ecosystem, count) in previous.ecosystems {
+            metadata.ecosystems.entry(ecosystem).or_insert(count);
+        }
+    }
+    fs::write(
+        db.join(METADATA_FILE),
+        serde_json::to_string_pretty(&metadata)?,
+    )?;
+    return Ok(metadata);
+}
+
+/// What `uptix db sync` last wrote to `db`
+pub fn read_metadata(db: &Path) -> Result<Metadata, Error> {
+    let content = fs::read_to_string(db.join(METADATA_FILE)).map_err(|_| {
+        Error::MissingVulnerabilityDatabase {
+            path: db.display().to_string(),
+        }
+    })?;
+    return Ok(serde_json::from_str(&content)?);
+}
+
+/// Matches the entries of a lock file against the advisories on `db`,
+/// without any request. An entry is affected when an advisory lists the
+/// tag it is locked to as an affected version of its repository. The
+/// ranges of advisories are bounded by commits, which can't be ordered
+/// without the history of the repository, so they aren't evaluated: entries
+/// locked to a commit alone (i.e. branches) are never matched.
+pub fn audit(db: &Path, lock_file: &LockFile) -> Result<Vec<Finding>, Error> {
+    let metadata = read_metadata(db)?;
+    let mut locked: BTreeMap<String, Vec<(&str, BTreeSet<&str>)>> = BTreeMap::new();
+    for (key, lock) in lock_file {
+        if let Some((repository, versions)) = locked_versions(lock) {
+            locked
+                .entry(repository)
+                .or_default()
+                .push((key, versions.into_iter().collect()));
+        }
+    }
+    let mut findings = vec![];
+    for ecosystem in metadata.ecosystems.keys() {
+        let entries = match fs::read_dir(db.join(ecosystem)) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries {
+            let content = fs::read(entry?.path())?;
+            // advisories in a shape uptix doesn't know are skipped
+            let advisory: Advisory = match serde_json::from_slice(&content) {
+                Ok(advisory) => advisory,
+                Err(_) => continue,
+            };
+            for affected in &advisory.affected {
+                let repositories: BTreeSet<String> = affected
+                    .ranges
+                    .iter()
+                    .filter(|range| range.kind == "GIT")
+                    .filter_map(|range| range.repo.as_deref().map(normalize_repo))
+                    .collect();
+                for repository in repositories {
+                    for (key, versions) in locked.get(&repository).into_iter().flatten() {
+                        let version = affected
+                            .versions
+                            .iter()
+                            .find(|version| versions.contains(version.as_str()));
+                        if let Some(version) = version {
+                            findings.push(Finding {
+                                key: key.to_string(),
+                                version: version.clone(),
+                                id: advisory.id.clone(),
+                                aliases: advisory.aliases.clone(),
+                                summary: advisory.summary.clone(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+    findings.sort_by(|a, b| (&a.key, &a.id).cmp(&(&b.key, &b.id)));
+    findings.dedup_by(|a, b| a.key == b.key && a.id == b.id);
+    return Ok(findings);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{audit, normalize_repo, sync, unzip};
+    use crate::error::Error;
+    use crate::lock::LockFile;
+    use serde_json::json;
+    use std::fs;
+    use std::io::{Cursor, Seek, Write};
+
+    /// A zip archive with the given files, the first one stored and the
+    /// rest deflated
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (i, (name, contents)) in files.iter().enumerate() {
+            let method = match i {
+                0 => zip::CompressionMethod::Stored,
+                _ => zip::CompressionMethod::Deflated,
+            };
+            let options = zip::write::SimpleFileOptions::default().compression_method(method);
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        return writer.finish().unwrap().into_inner();
+    }
+
+    fn unzipped(archive: &[u8]) -> Result<Vec<(String, String)>, String> {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(archive).unwrap();
+        file.rewind().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+        unzip(file, directory.path()).map_err(|e| e.to_string())?;
+        let mut files: Vec<_> = fs::read_dir(directory.path())
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                return (name, fs::read_to_string(path).unwrap());
+            })
+            .collect();
+        files.sort();
+        return Ok(files);
+    }
+
+    #[test]
+    fn it_unzips_archives() {
+        let archive = zip(&[
+            ("a.json", b"stored"),
+            ("advisories/b.json", b"deflated"),
+            ("README", b"skipped"),
+        ]);
+        assert_eq!(
+            unzipped(&archive),
+            Ok(vec![
+                ("a.json".to_string(), "stored".to_string()),
+                ("b.json".to_string(), "deflated".to_string()),
+            ])
+        );
+        assert!(unzipped(b"not a zip").is_err());
+        // the checksum of the stored file no longer matches
+        let mut corrupt = archive.clone();
+        let offset = corrupt.windows(6).position(|w| w == b"stored").unwrap();
+        corrupt[offset] = b'S';
+        assert!(unzipped(&corrupt).is_err());
+        assert_eq!(
+            normalize_repo("https://GitHub.com/Owner/Repo.git/"),
+            "github.com/owner/repo"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_audits_offline() {
+        let advisory = json!({
+            "id": "OSV-2024-1",
+            "summary": "Something bad",
+            "aliases": ["CVE-2024-0001"],
+            "affected": [{
+                "ranges": [{
+                    "type": "GIT",
+                    "repo": "https://github.com/owner/vulnerable",
+                    "events": [{ "introduced": "0" }, { "fixed": "abc123" }],
+                }],
+                "versions": ["v1.0.0", "v1.1.0"],
+            }],
+        });
+        let unrelated = json!({
+            "id": "OSV-2024-2",
+            "affected": [{ "ranges": [{ "type": "SEMVER" }], "versions": ["v1.0.0"] }],
+        });
+        let archive = zip(&[
+            ("OSV-2024-1.json", advisory.to_string().as_bytes()),
+            ("OSV-2024-2.json", unrelated.to_string().as_bytes()),
+        ]);
+        let _zip_mock = mockito::mock("GET", "/GIT/all.zip")
+            .with_status(200)
+            .with_body(archive)
+            .create();
+        let db = tempfile::tempdir().unwrap();
+        let missing = audit(db.path(), &LockFile::new());
+        assert!(matches!(
+            missing,
+            Err(Error::MissingVulnerabilityDatabase { .. })
+        ));
+        let metadata = sync(db.path(), &["GIT".to_string()], &mockito::server_url())
+            .await
+            .unwrap();
+        assert_eq!(metadata.ecosystems["GIT"], 2);
+
+        let repository = |repo: &str, rev: &str| {
+            return json!({
+                "owner": "owner",
+                "repo": repo,
+                "rev": rev,
+                "sha256": "",
+                "fetchSubmodules": false,
+                "deepClone": false,
+                "leaveDotGit": false,
+            });
+        };
+        let lock_file: LockFile = serde_json::from_value(json!({
+            "vulnerable": repository("vulnerable", "v1.1.0"),
+            "fixed": repository("vulnerable", "v1.2.0"),
+            "other": repository("other", "v1.0.0"),
+            "action": { "owner": "Owner", "repo": "Vulnerable", "ref": "v1.0.0", "rev": "def456" },
+            "postgres:15": "sha256:abc",
+        }))
+        .unwrap();
+        let findings = audit(db.path(), &lock_file).unwrap();
+        let found: Vec<_> = findings
+            .iter()
+            .map(|f| (f.key.as_str(), f.version.as_str(), f.id.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("action", "v1.0.0", "OSV-2024-1"),
+                ("vulnerable", "v1.1.0", "OSV-2024-1"),
+            ]
+        );
+        assert_eq!(findings[0].aliases, vec!["CVE-2024-0001"]);
+        mockito::reset();
+    }
+}
@@ -0,0 +1,82 @@
+//! Blocking versions of what [`Updater`] does, for build scripts and other
+//! tools which don't use async. Each call runs on a runtime of its own, so
+//! they can't be made from within an async runtime.
+//!
+//! ```no_run
+//! let plan = uptix::blocking::update("path/to/project")?;
+//! println!("{} entries changed", plan.changed.len());
+//! # Ok::<(), uptix::Error>(())
+//! ```
+
+use crate::error::Error;
+use crate::plan::Plan;
+use crate::updater::Updater;
+use std::future::Future;
+use std::path::Path;
+
+fn block_on<T>(future: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    return runtime.block_on(future);
+}
+
+/// Locks every dependency of a project to its latest version and writes
+/// uptix.lock, as `uptix update` does. Returns how uptix.lock changed.
+pub fn update(root: impl AsRef<Path>) -> Result<Plan, Error> {
+    let updater = Updater::discover(root)?;
+    let plan = plan(&updater)?;
+    updater.apply(&plan)?;
+    return Ok(plan);
+}
+
+/// Like [`Updater::plan`], for updaters set up with a configuration, client
+/// or progress of their own
+pub fn plan(updater: &Updater) -> Result<Plan, Error> {
+    return block_on(updater.plan());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::update;
+    use crate::lock::{self, LockData};
+    use std::fs;
+
+    #[test]
+    fn it_updates_without_a_runtime() {
+        let registry = mockito::server_address().to_string();
+        let _ping_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _manifest_mock = mockito::mock("HEAD", "/v2/library/blocking/manifests/1.0")
+            .with_status(200)
+            .with_header("docker-content-digest", "sha256:blocking")
+            .create();
+        let _image_mock = mockito::mock("GET", "/v2/library/blocking/manifests/sha256:blocking")
+            .with_status(404)
+            .create();
+        let project = tempfile::tempdir().unwrap();
+        fs::write(
+            project.path().join("default.nix"),
+            format!(
+                "{{ uptix, ... }}: {{ image = uptix.dockerImage \"{}/library/blocking:1.0\"; }}",
+                registry
+            ),
+        )
+        .unwrap();
+        fs::write(
+            project.path().join("uptix.toml"),
+            format!("[docker.registries.\"{}\"]\ninsecure = true", registry),
+        )
+        .unwrap();
+
+        let plan = update(project.path()).unwrap();
+        let key = format!("{}/library/blocking:1.0", registry);
+        assert_eq!(plan.added.len(), 1);
+        assert_eq!(plan.added[0].key, key);
+        let lock_file = lock::read(&project.path().join("uptix.lock")).unwrap();
+        assert_eq!(
+            lock_file[&key],
+            LockData::Digest("sha256:blocking".to_string())
+        );
+        mockito::reset();
+    }
+}
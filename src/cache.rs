@@ -0,0 +1,334 @@
+use crate::config::CacheConfig;
+use crate::util;
+use base64::Engine;
+use openssl::sha::{sha256, Sha256};
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::Duration;
+
+lazy_static! {
+    /// How long entries (and resolved dependencies) are reused, from
+    /// uptix.toml
+    static ref CONFIG: RwLock<CacheConfig> = RwLock::new(CacheConfig::default());
+}
+
+tokio::task_local! {
+    /// Cache settings of the `Updater` being planned, which take the place
+    /// of the ones configured for the whole process
+    static SCOPED_CONFIG: CacheConfig;
+}
+
+pub fn configure(config: &CacheConfig) {
+    *CONFIG.write().unwrap() = config.clone();
+}
+
+/// Runs `future` with cache settings of its own, rather than the ones given
+/// to `configure`
+pub async fn scoped<F: Future>(config: CacheConfig, future: F) -> F::Output {
+    return SCOPED_CONFIG.scope(config, future).await;
+}
+
+fn ttl(read: fn(&CacheConfig) -> u64) -> Duration {
+    let seconds = SCOPED_CONFIG
+        .try_with(read)
+        .unwrap_or_else(|_| read(&CONFIG.read().unwrap()));
+    return Duration::from_secs(seconds);
+}
+
+/// Best-effort storage for data which is expensive to fetch but safe to
+/// lose, such as API responses along with their ETags. Failing to read or
+/// write it never fails a run.
+#[derive(Clone)]
+pub struct Cache {
+    dir: Option<PathBuf>,
+    /// how long entries are fresh for, during which they can be used
+    /// without asking upstream whether they changed
+    ttl: Duration,
+    /// what entries were fetched with, if they took credentials
+    credential: Option<Credential>,
+}
+
+/// Keys derived from a credential (i.e. a token), rather than the credential
+/// itself, so that it's never written to disk
+#[derive(Clone)]
+struct Credential {
+    /// keeps entries apart from those fetched with other credentials
+    identity: [u8; 32],
+    /// encrypts entries, which may hold private data
+    key: [u8; 32],
+}
+
+impl Credential {
+    fn new(secret: &str) -> Credential {
+        let derive = |purpose: &str| {
+            let mut hasher = Sha256::new();
+            hasher.update(purpose.as_bytes());
+            hasher.update(b"\0");
+            hasher.update(secret.as_bytes());
+            return hasher.finish();
+        };
+        return Credential {
+            identity: derive("uptix cache identity"),
+            key: derive("uptix cache key"),
+        };
+    }
+
+    /// Encrypts with AES-256-GCM, as the nonce, tag and ciphertext in base64
+    fn seal(&self, value: &str) -> Option<String> {
+        let mut nonce = [0; 12];
+        openssl::rand::rand_bytes(&mut nonce).ok()?;
+        let mut tag = [0; 16];
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(&nonce),
+            &[],
+            value.as_bytes(),
+            &mut tag,
+        )
+        .ok()?;
+        let sealed = [&nonce[..], &tag, &ciphertext].concat();
+        return Some(base64::engine::general_purpose::STANDARD.encode(sealed));
+    }
+
+    fn open(&self, sealed: &str) -> Option<String> {
+        let sealed = base64::engine::general_purpose::STANDARD
+            .decode(sealed.trim())
+            .ok()?;
+        if sealed.len() < 28 {
+            return None;
+        }
+        let (nonce, rest) = sealed.split_at(12);
+        let (tag, ciphertext) = rest.split_at(16);
+        let value = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key,
+            Some(nonce),
+            &[],
+            ciphertext,
+            tag,
+        )
+        .ok()?;
+        return String::from_utf8(value).ok();
+    }
+}
+
+impl Default for Cache {
+    /// Lives on `$UPTIX_CACHE_DIR`, or under the user's cache directory
+    fn default() -> Self {
+        let dir = env::var_os("UPTIX_CACHE_DIR")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("XDG_CACHE_HOME").map(|d| PathBuf::from(d).join("uptix")))
+            .or_else(|| env::var_os("HOME").map(|d| PathBuf::from(d).join(".cache/uptix")));
+        return Cache {
+            dir,
+            ttl: ttl(|config| config.ttl),
+            credential: None,
+        };
+    }
+}
+
+impl Cache {
+    /// The same storage, holding what dependencies resolved to, which stays
+    /// fresh for its own TTL
+    pub fn resolved() -> Cache {
+        return Cache {
+            ttl: ttl(|config| config.resolved_ttl),
+            ..Cache::default()
+        };
+    }
+
+    #[cfg(test)]
+    pub fn new(dir: &std::path::Path) -> Cache {
+        return Cache {
+            dir: Some(dir.to_path_buf()),
+            ttl: Duration::ZERO,
+            credential: None,
+        };
+    }
+
+    #[cfg(test)]
+    pub fn with_ttl(self, ttl: Duration) -> Cache {
+        return Cache { ttl, ..self };
+    }
+
+    /// The same storage, for entries fetched with a credential (i.e. a
+    /// token). They are kept apart from those fetched with other credentials
+    /// (or none), and encrypted with a key derived from the credential, since
+    /// they may hold private data.
+    pub fn for_credential(self, secret: Option<&str>) -> Cache {
+        return Cache {
+            credential: secret.map(Credential::new),
+            ..self
+        };
+    }
+
+    /// Whether entries are ever fresh, which is up to uptix.toml
+    pub fn has_ttl(&self) -> bool {
+        return !self.ttl.is_zero();
+    }
+
+    /// Keys (i.e. URLs) are hashed, since they don't make for valid file
+    /// names
+    fn path(&self, namespace: &str, key: &str) -> Option<PathBuf> {
+        let mut path = self.directory(namespace, key)?;
+        path.set_extension("json");
+        return Some(path);
+    }
+
+    /// A directory of its own for a key, i.e. for a git repository. Keys
+    /// are hashed with SHA-256, so that the same key maps to the same
+    /// directory across builds of uptix.
+    pub fn directory(&self, namespace: &str, key: &str) -> Option<PathBuf> {
+        let hash = match &self.credential {
+            Some(credential) => {
+                let mut hasher = Sha256::new();
+                hasher.update(&credential.identity);
+                hasher.update(key.as_bytes());
+                hasher.finish()
+            }
+            None => sha256(key.as_bytes()),
+        };
+        let dir = self.dir.as_ref()?.join(namespace);
+        return Some(dir.join(util::hex(&hash)));
+    }
+
+    /// Decrypts entries fetched with a credential
+    fn decode(&self, content: String) -> Option<String> {
+        return match &self.credential {
+            Some(credential) => credential.open(&content),
+            None => Some(content),
+        };
+    }
+
+    pub fn read(&self, namespace: &str, key: &str) -> Option<String> {
+        let content = fs::read_to_string(self.path(namespace, key)?).ok()?;
+        return self.decode(content);
+    }
+
+    /// Reads an entry written (or refreshed) less than the TTL ago
+    pub fn read_fresh(&self, namespace: &str, key: &str) -> Option<String> {
+        if !self.has_ttl() {
+            return None;
+        }
+        let path = self.path(namespace, key)?;
+        let age = fs::metadata(&path).ok()?.modified().ok()?.elapsed().ok()?;
+        if age > self.ttl {
+            return None;
+        }
+        return self.decode(fs::read_to_string(path).ok()?);
+    }
+
+    /// Entries are only readable by the user, since some (i.e. registry
+    /// tokens) are credentials
+    pub fn write(&self, namespace: &str, key: &str, value: &str) {
+        let sealed = match &self.credential {
+            Some(credential) => match credential.seal(value) {
+                Some(sealed) => Some(sealed),
+                None => return,
+            },
+            None => None,
+        };
+        let value = sealed.as_deref().unwrap_or(value);
+        if let Some(path) = self.path(namespace, key) {
+            let _ = fs::create_dir_all(path.parent().unwrap());
+            let _ = fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(path)
+                .and_then(|mut file| file.write_all(value.as_bytes()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cache;
+    use std::time::Duration;
+
+    #[test]
+    fn it_caches() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path());
+        assert_eq!(
+            cache.read("github", "https://api.github.com/repos/a/b"),
+            None
+        );
+        cache.write("github", "https://api.github.com/repos/a/b", "{}");
+        assert_eq!(
+            cache.read("github", "https://api.github.com/repos/a/b"),
+            Some("{}".to_string()),
+        );
+        assert_eq!(
+            cache.read("github", "https://api.github.com/repos/a/c"),
+            None
+        );
+        assert_eq!(
+            cache.read_fresh("github", "https://api.github.com/repos/a/b"),
+            None
+        );
+        let fresh = Cache::new(dir.path()).with_ttl(Duration::from_secs(60));
+        assert_eq!(
+            fresh.read_fresh("github", "https://api.github.com/repos/a/b"),
+            Some("{}".to_string()),
+        );
+        let stale = Cache::new(dir.path()).with_ttl(Duration::from_nanos(1));
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(
+            stale.read_fresh("github", "https://api.github.com/repos/a/b"),
+            None
+        );
+
+        let nowhere = Cache {
+            dir: None,
+            ttl: Duration::ZERO,
+            credential: None,
+        };
+        nowhere.write("github", "key", "{}");
+        assert_eq!(nowhere.read("github", "key"), None);
+    }
+
+    #[test]
+    fn it_hashes_keys_stably() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path());
+        // as `printf %s <key> | sha256sum` prints it
+        assert_eq!(
+            cache.directory("github", "https://api.github.com/repos/a/b"),
+            Some(
+                dir.path()
+                    .join("github")
+                    .join("03c76211d35e1ef90ca423d9ab9d83737287fa2546bffc53dde251dde7f06ee5")
+            ),
+        );
+    }
+
+    #[test]
+    fn it_keeps_credentials_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = "https://api.github.com/repos/a/private";
+        let alice = Cache::new(dir.path()).for_credential(Some("alice-token"));
+        alice.write("github", url, r#"{"private": true}"#);
+        assert_eq!(
+            alice.read("github", url),
+            Some(r#"{"private": true}"#.to_string()),
+        );
+        // neither other credentials nor anonymous requests see the entry
+        let bob = Cache::new(dir.path()).for_credential(Some("bob-token"));
+        assert_eq!(bob.read("github", url), None);
+        assert_eq!(Cache::new(dir.path()).read("github", url), None);
+        // and it isn't readable on disk without the credential
+        let path = alice.path("github", url).unwrap();
+        let content = std::fs::read_to_string(path).unwrap();
+        assert!(!content.contains("private"));
+        assert!(!content.contains("alice-token"));
+    }
+}
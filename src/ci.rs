@@ -0,0 +1,136 @@
+use crate::lock::LOCK_FILE_PATH;
+use std::path::{Path, PathBuf};
+
+/// Where `uptix generate workflow` writes the workflow by default
+pub const DEFAULT_WORKFLOW_PATH: &str = ".github/workflows/uptix.yml";
+
+/// When the workflow runs by default: every Monday at 06:00 UTC
+pub const DEFAULT_SCHEDULE: &str = "0 6 * * 1";
+
+/// The release of uptix the workflow runs, which is the one generating it,
+/// so that scheduled runs don't pick up whatever is on the main branch
+const FLAKE: &str = concat!("github:luizribeiro/uptix/v", env!("CARGO_PKG_VERSION"));
+
+/// Checks a schedule is a cron expression GitHub Actions accepts, which has
+/// exactly five fields
+pub fn parse_schedule(schedule: &str) -> Result<String, String> {
+    let fields: Vec<&str> = schedule.split_whitespace().collect();
+    if fields.len() != 5 {
+        return Err(format!(
+            "expected 5 fields (minute, hour, day, month and weekday), got {}",
+            fields.len()
+        ));
+    }
+    return Ok(fields.join(" "));
+}
+
+/// Checks the path of the lock file the workflow keeps up to date, which
+/// uptix always names uptix.lock
+pub fn parse_lock_path(path: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(path);
+    if path.file_name() != Some(LOCK_FILE_PATH.as_ref()) {
+        return Err(format!(
+            "uptix only reads lock files named {}, in the directory it runs from",
+            LOCK_FILE_PATH
+        ));
+    }
+    return Ok(path);
+}
+
+/// A GitHub Actions workflow running `uptix update --create-pr` and then
+/// `uptix check` on a schedule, from the directory of `lock_path`. uptix's
+/// cache is kept between runs, and the workflow's own token is used both
+/// for the GitHub API and to open the pull request.
+pub fn workflow(schedule: &str, lock_path: &Path) -> String {
+    let directory = lock_path
+        .parent()
+        .map(|p| p.to_string_lossy().to_string())
+        .filter(|p| !p.is_empty());
+    let defaults = match directory {
+        Some(directory) => format!(
+            "    defaults:\n      run:\n        working-directory: \"{}\"\n",
+            directory.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        None => String::new(),
+    };
+    return format!(
+        r#"# Generated by `uptix generate workflow`
+name: uptix
+
+on:
+  schedule:
+    - cron: "{schedule}"
+  workflow_dispatch:
+
+permissions:
+  # to push the uptix/update branch and open a pull request from it
+  contents: write
+  pull-requests: write
+
+jobs:
+  uptix:
+    runs-on: ubuntu-latest
+{defaults}    env:
+      GITHUB_TOKEN: ${{{{ secrets.GITHUB_TOKEN }}}}
+    steps:
+      - uses: actions/checkout@v4
+      - uses: cachix/install-nix-action@v27
+      - uses: actions/cache@v4
+        with:
+          path: ~/.cache/uptix
+          key: uptix-${{{{ github.run_id }}}}
+          restore-keys: uptix-
+      - name: Open a pull request with updates
+        run: |
+          git config user.name "github-actions[bot]"
+          git config user.email "41898282+github-actions[bot]@users.noreply.github.com"
+          nix run {flake} -- update --create-pr
+      - name: Check hand-pinned images
+        if: always()
+        run: nix run {flake} -- check
+"#,
+        schedule = schedule,
+        defaults = defaults,
+        flake = FLAKE,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_lock_path, parse_schedule, workflow, DEFAULT_SCHEDULE};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn it_parses_schedules() {
+        assert_eq!(parse_schedule(" 0 6  * * 1 "), Ok("0 6 * * 1".to_string()));
+        assert!(parse_schedule("@weekly").is_err());
+    }
+
+    #[test]
+    fn it_only_takes_lock_files_uptix_reads() {
+        assert_eq!(
+            parse_lock_path("infra/uptix.lock"),
+            Ok(PathBuf::from("infra/uptix.lock"))
+        );
+        assert!(parse_lock_path("infra/deps.lock").is_err());
+        assert!(parse_lock_path("infra/").is_err());
+    }
+
+    #[test]
+    fn it_generates_workflows() {
+        let text = workflow(DEFAULT_SCHEDULE, Path::new("uptix.lock"));
+        assert!(text.contains("    - cron: \"0 6 * * 1\"\n"));
+        assert!(text.contains("      GITHUB_TOKEN: ${{ secrets.GITHUB_TOKEN }}\n"));
+        assert!(text.contains("          key: uptix-${{ github.run_id }}\n"));
+        assert!(!text.contains("working-directory"));
+        let pinned = format!(
+            "nix run github:luizribeiro/uptix/v{} -- check\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        assert!(text.contains(&pinned));
+        let text = workflow("30 2 * * *", Path::new("infra/uptix.lock"));
+        assert!(text.contains(
+            "    runs-on: ubuntu-latest\n    defaults:\n      run:\n        working-directory: \"infra\"\n    env:\n"
+        ));
+    }
+}
@@ -0,0 +1,531 @@
+use crate::deps::Profile;
+use crate::error::Error;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const DEFAULT_CONFIG_PATH: &str = "uptix.toml";
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 1024 * 1024;
+pub const DEFAULT_TIMEOUT: u64 = 30;
+pub const DEFAULT_RETRIES: u32 = 3;
+pub const DEFAULT_DEADLINE: u64 = 300;
+pub const DEFAULT_JOBS: usize = 8;
+pub const DEFAULT_PREFETCH_JOBS: usize = 2;
+pub const DEFAULT_EOL_URL: &str = "https://endoflife.date/api";
+
+#[derive(Default, Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub discovery: DiscoveryConfig,
+    pub parsing: ParsingConfig,
+    pub docker: DockerConfig,
+    pub network: NetworkConfig,
+    pub lints: LintsConfig,
+    pub eol: EolConfig,
+    pub github: GitHubConfig,
+    pub gitlab: GitLabConfig,
+    pub cache: CacheConfig,
+    pub credentials: CredentialsConfig,
+    pub hooks: HooksConfig,
+    pub notifications: Vec<NotificationConfig>,
+}
+
+#[derive(Default, Deserialize, PartialEq, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// seconds during which cached responses are reused without asking
+    /// upstream again (0 always revalidates them)
+    pub ttl: u64,
+    /// seconds during which dependencies are locked to what they last
+    /// resolved to, without any requests at all (0 disables it)
+    pub resolved_ttl: u64,
+}
+
+#[derive(Default, Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HooksConfig {
+    /// commands run with `sh -c` after `uptix update` changed uptix.lock
+    /// (i.e. `nix flake check`), which is restored if any of them fails
+    pub post_update: Vec<String>,
+}
+
+#[derive(Default, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialsFormat {
+    #[default]
+    Sops,
+    Age,
+}
+
+/// An encrypted file holding the secrets `*_env` settings name, so that they
+/// don't need to sit on disk in plaintext
+#[derive(Default, Deserialize, PartialEq, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CredentialsConfig {
+    /// path of the file, whose decrypted contents are `NAME=value` lines
+    pub file: Option<String>,
+    /// whether the file is encrypted with sops or age, which is guessed from
+    /// its extension (`.age`) when left out
+    pub format: Option<CredentialsFormat>,
+    /// age identity to decrypt the file with, instead of sops' keys.txt
+    pub identity: Option<String>,
+}
+
+#[derive(Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    /// directories to scan, defaulting to the current directory
+    pub roots: Vec<RootConfig>,
+    /// glob patterns (relative to each scanned directory) to skip
+    pub exclude: Vec<String>,
+    /// whether github inputs on flake.nix files should be locked as well
+    pub flake_inputs: bool,
+    /// whether symlinks should be followed while scanning
+    pub follow_symlinks: bool,
+    /// files larger than this many bytes are skipped (0 disables the limit)
+    pub max_file_size: u64,
+    /// glob patterns for non-nix files (i.e. templates) which should be
+    /// scanned for uptix.dockerImage declarations as well
+    pub templates: Vec<String>,
+    /// whether actions used by workflows under `.github/workflows` should
+    /// be locked as well
+    pub github_actions: bool,
+}
+
+#[derive(Default, Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RootConfig {
+    pub path: String,
+    /// glob patterns (relative to this root) to skip, on top of the
+    /// globally excluded ones
+    pub exclude: Vec<String>,
+    pub follow_symlinks: bool,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            roots: vec![],
+            exclude: vec![],
+            flake_inputs: false,
+            follow_symlinks: false,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            templates: vec![],
+            github_actions: false,
+        }
+    }
+}
+
+impl DiscoveryConfig {
+    /// Returns the directories to scan, each with all of the patterns which
+    /// should be excluded from it
+    pub fn scan_roots(&self) -> Vec<RootConfig> {
+        if self.roots.is_empty() {
+            return vec![RootConfig {
+                path: ".".to_string(),
+                exclude: self.exclude.clone(),
+                follow_symlinks: self.follow_symlinks,
+            }];
+        }
+        return self
+            .roots
+            .iter()
+            .map(|root| RootConfig {
+                path: root.path.clone(),
+                exclude: [self.exclude.clone(), root.exclude.clone()].concat(),
+                follow_symlinks: self.follow_symlinks || root.follow_symlinks,
+            })
+            .collect();
+    }
+}
+
+#[derive(Default, Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParsingConfig {
+    /// names other than uptix through which the uptix functions are called
+    pub prefixes: Vec<String>,
+}
+
+#[derive(Default, Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DockerConfig {
+    /// platform (i.e. linux/arm64) to resolve images for, unless they set
+    /// their own
+    pub platform: Option<String>,
+    /// credentials and mirrors for registries, keyed by registry host
+    pub registries: BTreeMap<String, RegistryConfig>,
+}
+
+#[derive(Default, Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegistryConfig {
+    pub username: Option<String>,
+    /// name of the environment variable holding the password (or token), so
+    /// that secrets don't need to be committed to uptix.toml
+    pub password_env: Option<String>,
+    /// whether the registry should be talked to over plain HTTP, for
+    /// local registries without TLS
+    pub insecure: bool,
+    /// registries (i.e. pull-through caches) to try before this one, in
+    /// order. Prefix them with `http://` if they don't support https.
+    pub mirrors: Vec<String>,
+    /// registry implementation (generic, quay or harbor), when it can't be
+    /// guessed from the registry itself
+    pub profile: Option<Profile>,
+    /// cosign signatures to require from images on this registry, before
+    /// locking them
+    pub cosign: Option<CosignConfig>,
+}
+
+#[derive(Default, Deserialize, PartialEq, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CosignConfig {
+    /// public key signatures must be made with, as a path or KMS URI
+    pub key: Option<String>,
+    /// for keyless signatures, the identity (i.e. the workflow) on the
+    /// signing certificate
+    pub certificate_identity: Option<String>,
+    /// for keyless signatures, the OIDC issuer of the signing certificate
+    pub certificate_oidc_issuer: Option<String>,
+}
+
+#[derive(Default, Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitHubConfig {
+    /// tokens and API endpoints, keyed by host (i.e. github.com, or the
+    /// host of a GitHub Enterprise instance)
+    pub hosts: BTreeMap<String, GitHubHostConfig>,
+    /// only advance branches to commits whose checks passed, unless a
+    /// dependency says otherwise with `requireChecks`
+    pub require_checks: bool,
+    /// branch to open pull requests against, instead of the current one
+    pub base_branch: Option<String>,
+}
+
+#[derive(Default, Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitHubHostConfig {
+    /// name of the environment variable holding the API token
+    pub token_env: Option<String>,
+    /// API endpoint, when it isn't at `https://<host>/api/v3`
+    pub api_url: Option<String>,
+    /// GitHub App to authenticate as, instead of using a token
+    pub app: Option<GitHubAppConfig>,
+}
+
+#[derive(Default, Deserialize, PartialEq, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitHubAppConfig {
+    pub id: u64,
+    /// path of the app's private key, in PEM
+    pub private_key_path: Option<String>,
+    /// name of the environment variable holding the app's private key, for
+    /// CI systems which hand out secrets that way
+    pub private_key_env: Option<String>,
+    /// installation to mint tokens for, which can be left out when the app
+    /// is only installed once
+    pub installation_id: Option<u64>,
+}
+
+/// Where `uptix update --create-mr` opens merge requests, for what can't be
+/// told from the `origin` remote
+#[derive(Default, Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitLabConfig {
+    /// URL of the GitLab instance (i.e. `https://gitlab.example.com`)
+    pub url: Option<String>,
+    /// path of the project, including its groups (i.e. `infra/nixos`)
+    pub project: Option<String>,
+    /// branch to open merge requests against, instead of the current one
+    pub target_branch: Option<String>,
+    /// name of the environment variable holding the API token, instead of
+    /// GITLAB_TOKEN
+    pub token_env: Option<String>,
+}
+
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// proxy for all requests, overriding the HTTP(S)_PROXY environment
+    /// variables
+    pub proxy: Option<String>,
+    /// hosts (or domains, i.e. `.corp.example.com`) to reach without the
+    /// configured proxy
+    pub no_proxy: Vec<String>,
+    /// seconds a request may spend connecting, or waiting for more of the
+    /// response, before it's abandoned
+    pub timeout: u64,
+    /// how many times requests which failed transiently are retried
+    pub retries: u32,
+    /// seconds a dependency may take to be locked, across all retries
+    pub deadline: u64,
+    /// how many dependencies are locked at once
+    pub jobs: usize,
+    /// how many repositories are cloned at once by nix-prefetch-git
+    pub prefetch_jobs: usize,
+    /// hash repositories from a shallow clone of just the locked revision
+    /// where possible, rather than with nix-prefetch-git
+    pub shallow_clone: bool,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            no_proxy: vec![],
+            timeout: DEFAULT_TIMEOUT,
+            retries: DEFAULT_RETRIES,
+            deadline: DEFAULT_DEADLINE,
+            jobs: DEFAULT_JOBS,
+            prefetch_jobs: DEFAULT_PREFETCH_JOBS,
+            shallow_clone: false,
+        }
+    }
+}
+
+#[derive(Default, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LintLevel {
+    Allow,
+    #[default]
+    Warn,
+    /// warns while updating, but fails `uptix check`
+    Deny,
+}
+
+#[derive(Default, Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LintsConfig {
+    /// Docker images using the latest tag, implicitly or explicitly
+    pub latest_tag: LintLevel,
+    /// Docker images following a release cycle which reached end of life
+    pub end_of_life: LintLevel,
+}
+
+#[derive(Default, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationFormat {
+    /// the summary of the run as is
+    #[default]
+    Json,
+    /// a message for Slack's incoming webhooks
+    Slack,
+    /// a message for Discord's webhooks
+    Discord,
+}
+
+/// A webhook the summary of each `uptix update` and `uptix check` is posted to
+#[derive(Default, Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationConfig {
+    pub url: Option<String>,
+    /// name of the environment variable holding the URL, since webhook URLs
+    /// (i.e. Slack's) double as their secret
+    pub url_env: Option<String>,
+    pub format: NotificationFormat,
+    /// skip runs which neither updated nor found anything, and didn't fail
+    pub skip_empty: bool,
+}
+
+#[derive(Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct EolConfig {
+    /// API serving release cycles in endoflife.date's format, at
+    /// `<url>/<product>.json`
+    pub url: String,
+    /// endoflife.date products of images whose name doesn't match them, i.e.
+    /// `"bitnami/postgresql" = "postgresql"`
+    pub products: BTreeMap<String, String>,
+}
+
+impl Default for EolConfig {
+    fn default() -> Self {
+        return EolConfig {
+            url: DEFAULT_EOL_URL.to_string(),
+            products: BTreeMap::new(),
+        };
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, Error> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let content = fs::read_to_string(path)?;
+        return Ok(toml::from_str(&content)?);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Config, CosignConfig, CredentialsFormat, DiscoveryConfig, LintLevel, NotificationConfig,
+        NotificationFormat, RootConfig,
+    };
+    use std::path::Path;
+
+    #[test]
+    fn it_parses() {
+        let config: Config = toml::from_str(
+            r#"
+            [discovery]
+            exclude = ["third_party/**", "tests/fixtures/**"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                discovery: DiscoveryConfig {
+                    exclude: vec![
+                        "third_party/**".to_string(),
+                        "tests/fixtures/**".to_string(),
+                    ],
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+    }
+
+    #[test]
+    fn it_merges_root_excludes() {
+        let config: Config = toml::from_str(
+            r#"
+            [discovery]
+            exclude = ["**/fixtures/**"]
+
+            [[discovery.roots]]
+            path = "hosts"
+
+            [[discovery.roots]]
+            path = "pkgs"
+            exclude = ["vendored/**"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.discovery.scan_roots(),
+            vec![
+                RootConfig {
+                    path: "hosts".to_string(),
+                    exclude: vec!["**/fixtures/**".to_string()],
+                    ..Default::default()
+                },
+                RootConfig {
+                    path: "pkgs".to_string(),
+                    exclude: vec!["**/fixtures/**".to_string(), "vendored/**".to_string()],
+                    ..Default::default()
+                },
+            ],
+        );
+        assert_eq!(
+            Config::default().discovery.scan_roots(),
+            vec![RootConfig {
+                path: ".".to_string(),
+                ..Default::default()
+            }],
+        );
+    }
+
+    #[test]
+    fn it_parses_lints() {
+        let config: Config = toml::from_str(
+            r#"
+            [lints]
+            latest_tag = "deny"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.lints.latest_tag, LintLevel::Deny);
+        assert_eq!(Config::default().lints.latest_tag, LintLevel::Warn);
+    }
+
+    #[test]
+    fn it_parses_cosign() {
+        let config: Config = toml::from_str(
+            r#"
+            [docker.registries."ghcr.io".cosign]
+            key = "cosign.pub"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.docker.registries["ghcr.io"].cosign,
+            Some(CosignConfig {
+                key: Some("cosign.pub".to_string()),
+                ..Default::default()
+            }),
+        );
+    }
+
+    #[test]
+    fn it_parses_github_hosts() {
+        let config: Config = toml::from_str(
+            r#"
+            [github.hosts."ghe.example.com"]
+            token_env = "GHE_TOKEN"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.github.hosts["ghe.example.com"].token_env.as_deref(),
+            Some("GHE_TOKEN"),
+        );
+    }
+
+    #[test]
+    fn it_parses_credentials() {
+        let config: Config = toml::from_str(
+            r#"
+            [credentials]
+            file = "secrets.env.enc"
+            format = "age"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.credentials.file.as_deref(), Some("secrets.env.enc"));
+        assert_eq!(config.credentials.format, Some(CredentialsFormat::Age));
+    }
+
+    #[test]
+    fn it_parses_hooks() {
+        let config: Config = toml::from_str(
+            r#"
+            [hooks]
+            post_update = ["nix flake check"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.hooks.post_update, vec!["nix flake check"]);
+        assert!(Config::default().hooks.post_update.is_empty());
+    }
+
+    #[test]
+    fn it_parses_notifications() {
+        let config: Config = toml::from_str(
+            r#"
+            [[notifications]]
+            url_env = "SLACK_WEBHOOK"
+            format = "slack"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.notifications,
+            vec![NotificationConfig {
+                url_env: Some("SLACK_WEBHOOK".to_string()),
+                format: NotificationFormat::Slack,
+                ..Default::default()
+            }],
+        );
+    }
+
+    #[test]
+    fn it_defaults_when_missing() {
+        let config = Config::load(Path::new("./does-not-exist.toml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+}
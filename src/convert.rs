@@ -0,0 +1,356 @@
+use crate::config::Config;
+use crate::deps::github;
+use crate::error::Error;
+use crate::lock::{self, LockData};
+use crate::util::{self, Location, ParsingContext};
+use rnix::ast::{self, Expr};
+use rnix::SyntaxNode;
+use rowan::ast::AstNode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+
+lazy_static! {
+    static ref COMMIT_RE: regex::Regex = regex::Regex::new(r"^[0-9a-f]{40}$").unwrap();
+}
+
+/// A raw fetcher call which can be replaced with an uptix function
+#[derive(Debug)]
+pub struct Conversion {
+    /// where the fetcher is called
+    pub location: Location,
+    /// the text being replaced and what it should be replaced with
+    pub original: String,
+    pub replacement: String,
+    /// lock entry which keeps the currently pinned version
+    pub lock_key: String,
+    pub lock_entry: Value,
+    /// false when the replacement doesn't evaluate to the same kind of value
+    /// as the original, in which case it can't be applied automatically
+    pub in_place: bool,
+    pub note: Option<String>,
+    span: miette::SourceSpan,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(non_snake_case)]
+struct FetchFromGitHub {
+    owner: String,
+    repo: String,
+    rev: String,
+    sha256: Option<String>,
+    hash: Option<String>,
+    #[serde(default)]
+    fetchSubmodules: bool,
+    #[serde(default)]
+    deepClone: bool,
+    #[serde(default)]
+    leaveDotGit: bool,
+}
+
+#[derive(Deserialize)]
+#[allow(non_snake_case)]
+struct PullImage {
+    imageName: String,
+    imageDigest: String,
+    finalImageTag: Option<String>,
+}
+
+fn line_indentation(content: &str, offset: usize) -> &str {
+    let line_start = content[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = &content[line_start..];
+    return &line[..line.len() - line.trim_start().len()];
+}
+
+fn convert_fetch_from_github(
+    context: &ParsingContext,
+    content: &str,
+    call: &SyntaxNode,
+    argument: &SyntaxNode,
+) -> Option<Conversion> {
+    let fetcher: FetchFromGitHub = util::from_attr_set(argument).ok()?;
+    let sha256 = fetcher.sha256.or(fetcher.hash)?;
+    let flags = github::flags(
+        Some(fetcher.fetchSubmodules),
+        Some(fetcher.deepClone),
+        Some(fetcher.leaveDotGit),
+    );
+
+    let mut attrs = vec![
+        ("owner", util::nix_string(&fetcher.owner)),
+        ("repo", util::nix_string(&fetcher.repo)),
+    ];
+    // the branch a commit came from can't be known without asking GitHub,
+    // so fetchers pinned to one follow the default branch
+    let (function, lock_key, note) = if COMMIT_RE.is_match(&fetcher.rev) {
+        (
+            "githubBranch",
+            format!(
+                "$GITHUB_BRANCH$:{}/{}:{}${}",
+                fetcher.owner,
+                fetcher.repo,
+                github::branch::DEFAULT_BRANCH,
+                flags
+            ),
+            Some("pinned to a commit, so the default branch is followed".to_string()),
+        )
+    } else {
+        (
+            "githubRelease",
+            format!(
+                "$GITHUB_RELEASE$:{}/{}${}",
+                fetcher.owner, fetcher.repo, flags
+            ),
+            None,
+        )
+    };
+    for (name, enabled) in [
+        ("fetchSubmodules", fetcher.fetchSubmodules),
+        ("deepClone", fetcher.deepClone),
+        ("leaveDotGit", fetcher.leaveDotGit),
+    ] {
+        if enabled {
+            attrs.push((name, "true".to_string()));
+        }
+    }
+
+    let indentation = line_indentation(content, usize::from(argument.text_range().start()));
+    let body: String = attrs
+        .iter()
+        .map(|(name, value)| format!("{}  {} = {};\n", indentation, name, value))
+        .collect();
+    return Some(Conversion {
+        location: context.location(call),
+        original: argument.to_string(),
+        replacement: format!("(uptix.{} {{\n{}{}}})", function, body, indentation),
+        lock_key,
+        lock_entry: json!({
+            "owner": fetcher.owner,
+            "repo": fetcher.repo,
+            "rev": fetcher.rev,
+            "sha256": sha256,
+            "fetchSubmodules": fetcher.fetchSubmodules,
+            "deepClone": fetcher.deepClone,
+            "leaveDotGit": fetcher.leaveDotGit,
+        }),
+        in_place: true,
+        note,
+        span: context.location(argument).span,
+    });
+}
+
+fn convert_pull_image(
+    context: &ParsingContext,
+    call: &SyntaxNode,
+    argument: &SyntaxNode,
+) -> Option<Conversion> {
+    let image: PullImage = util::from_attr_set(argument).ok()?;
+    let name = match &image.finalImageTag {
+        Some(tag) => format!("{}:{}", image.imageName, tag),
+        None => image.imageName.clone(),
+    };
+    let location = context.location(call);
+    return Some(Conversion {
+        original: call.to_string(),
+        replacement: format!("uptix.dockerImage {}", util::nix_string(&name)),
+        lock_key: name,
+        lock_entry: Value::String(image.imageDigest),
+        in_place: false,
+        note: Some(
+            "uptix.dockerImage evaluates to an image reference rather than an image \
+             tarball, so usages need to be updated by hand"
+                .to_string(),
+        ),
+        span: location.span,
+        location,
+    });
+}
+
+/// Finds `fetchFromGitHub` and `dockerTools.pullImage` calls whose arguments
+/// are plain literals, and which could thus be managed by uptix instead
+pub fn find_conversions(file_path: &str, config: &Config) -> Result<Vec<Conversion>, Error> {
+    let content = util::read_source(file_path, config.discovery.max_file_size)?;
+    let ast = rnix::Root::parse(&content);
+    let context = ParsingContext::new(file_path, &content);
+    if let Some(error) = ast.errors().first() {
+        return Err(context.syntax_error(error));
+    }
+
+    let mut conversions = vec![];
+    for apply in ast.syntax().descendants().filter_map(ast::Apply::cast) {
+        let (func, mut argument) = match (apply.lambda(), apply.argument()) {
+            (Some(func), Some(argument)) => (func.to_string(), argument),
+            _ => continue,
+        };
+        while let Expr::Paren(paren) = &argument {
+            argument = match paren.expr() {
+                Some(inner) => inner,
+                None => break,
+            };
+        }
+        let (call, argument) = match argument {
+            Expr::AttrSet(attr_set) => (apply.syntax(), attr_set.syntax().clone()),
+            _ => continue,
+        };
+        let conversion = match func.rsplit('.').next() {
+            Some("fetchFromGitHub") => {
+                convert_fetch_from_github(&context, &content, call, &argument)
+            }
+            Some("pullImage") if func.ends_with("dockerTools.pullImage") => {
+                convert_pull_image(&context, call, &argument)
+            }
+            _ => None,
+        };
+        conversions.extend(conversion);
+    }
+    return Ok(conversions);
+}
+
+/// Rewrites `file_path` with the conversions which can be applied in place,
+/// returning how many of them were applied
+pub fn apply(file_path: &str, conversions: &[Conversion]) -> Result<usize, Error> {
+    let mut content = fs::read_to_string(file_path)?;
+    let mut applicable: Vec<_> = conversions.iter().filter(|c| c.in_place).collect();
+    // replace from the end of the file so earlier offsets remain valid
+    applicable.sort_by_key(|c| std::cmp::Reverse(c.span.offset()));
+    for conversion in &applicable {
+        let start = conversion.span.offset();
+        content.replace_range(
+            start..start + conversion.span.len(),
+            &conversion.replacement,
+        );
+    }
+    fs::write(file_path, content)?;
+    return Ok(applicable.len());
+}
+
+/// Adds the lock entries of the conversions which can be applied in place,
+/// keeping any entries which are already there
+pub fn update_lock_file(path: &Path, conversions: &[Conversion]) -> Result<(), Error> {
+    let mut lock_file = lock::read(path)?;
+    for conversion in conversions.iter().filter(|c| c.in_place) {
+        lock_file
+            .entry(conversion.lock_key.clone())
+            .or_insert_with(|| LockData::from(conversion.lock_entry.clone()));
+    }
+    return lock::write(path, &lock_file);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, find_conversions, update_lock_file};
+    use crate::config::Config;
+    use serde_json::json;
+    use std::fs;
+
+    const SOURCE: &str = r#"{ pkgs, ... }:
+{
+  release = pkgs.fetchFromGitHub {
+    owner = "luizribeiro";
+    repo = "hello-world-rs";
+    rev = "v1.0.0";
+    sha256 = "0000000000000000000000000000000000000000000000000000";
+  };
+  commit = pkgs.fetchFromGitHub {
+    owner = "torvalds";
+    repo = "linux";
+    rev = "5aed5285a952e0b949eb3ba02c12fa4fcfef535f";
+    hash = "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+    fetchSubmodules = true;
+  };
+  dynamic = pkgs.fetchFromGitHub {
+    owner = "foo";
+    repo = "bar";
+    rev = version;
+    sha256 = "0000000000000000000000000000000000000000000000000000";
+  };
+  image = pkgs.dockerTools.pullImage {
+    imageName = "postgres/postgres";
+    imageDigest = "sha256:abcdef";
+    finalImageTag = "16";
+    sha256 = "0000000000000000000000000000000000000000000000000000";
+  };
+}
+"#;
+
+    #[test]
+    fn it_finds_conversions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("default.nix");
+        fs::write(&path, SOURCE).unwrap();
+
+        let conversions = find_conversions(path.to_str().unwrap(), &Config::default()).unwrap();
+        let summary: Vec<_> = conversions
+            .iter()
+            .map(|c| (c.location.line, c.lock_key.as_str(), c.in_place))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (3, "$GITHUB_RELEASE$:luizribeiro/hello-world-rs$", true),
+                (9, "$GITHUB_BRANCH$:torvalds/linux:HEAD$f", true),
+                (22, "postgres/postgres:16", false),
+            ],
+        );
+        assert_eq!(
+            conversions[1].lock_entry,
+            json!({
+                "owner": "torvalds",
+                "repo": "linux",
+                "rev": "5aed5285a952e0b949eb3ba02c12fa4fcfef535f",
+                "sha256": "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=",
+                "fetchSubmodules": true,
+                "deepClone": false,
+                "leaveDotGit": false,
+            }),
+        );
+        assert_eq!(conversions[2].lock_entry, json!("sha256:abcdef"));
+    }
+
+    #[test]
+    fn it_applies_conversions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("default.nix");
+        let lock_path = dir.path().join("uptix.lock");
+        fs::write(&path, SOURCE).unwrap();
+        fs::write(
+            &lock_path,
+            r#"{ "$GITHUB_RELEASE$:luizribeiro/hello-world-rs$": {} }"#,
+        )
+        .unwrap();
+
+        let conversions = find_conversions(path.to_str().unwrap(), &Config::default()).unwrap();
+        assert_eq!(apply(path.to_str().unwrap(), &conversions).unwrap(), 2);
+        update_lock_file(&lock_path, &conversions).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains(
+            r#"  release = pkgs.fetchFromGitHub (uptix.githubRelease {
+    owner = "luizribeiro";
+    repo = "hello-world-rs";
+  });
+  commit = pkgs.fetchFromGitHub (uptix.githubBranch {
+    owner = "torvalds";
+    repo = "linux";
+    fetchSubmodules = true;
+  });"#
+        ));
+        assert!(content.contains("pkgs.dockerTools.pullImage {"));
+        // the converted file is still valid nix
+        assert!(rnix::Root::parse(&content).errors().is_empty());
+
+        let lock_file: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&lock_path).unwrap()).unwrap();
+        assert_eq!(
+            lock_file["$GITHUB_RELEASE$:luizribeiro/hello-world-rs$"],
+            json!({})
+        );
+        assert_eq!(
+            lock_file["$GITHUB_BRANCH$:torvalds/linux:HEAD$f"]["rev"],
+            "5aed5285a952e0b949eb3ba02c12fa4fcfef535f",
+        );
+        assert!(lock_file.get("postgres/postgres:16").is_none());
+    }
+}
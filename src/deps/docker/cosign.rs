@@ -0,0 +1,152 @@
+use crate::config::CosignConfig;
+use crate::error::Error;
+use crate::http;
+use crate::process;
+use std::io::ErrorKind;
+use tokio::process::Command;
+
+const COSIGN: &str = "cosign";
+
+fn verification_error(reference: &str, reason: String) -> Error {
+    return Error::SignatureVerification {
+        image: reference.to_string(),
+        reason,
+    };
+}
+
+/// Arguments for `cosign verify`, either against a public key or, for
+/// keyless signatures, against the identity on the signing certificate.
+/// Signatures on insecure registries are fetched over plain HTTP, as the
+/// image is.
+fn args(config: &CosignConfig, reference: &str, insecure: bool) -> Result<Vec<String>, Error> {
+    let mut args = vec![
+        "verify".to_string(),
+        "--output".to_string(),
+        "json".to_string(),
+    ];
+    if insecure {
+        args.extend([
+            "--allow-insecure-registry".to_string(),
+            "--allow-http-registry".to_string(),
+        ]);
+    }
+    match (
+        &config.key,
+        &config.certificate_identity,
+        &config.certificate_oidc_issuer,
+    ) {
+        (Some(key), _, _) => args.extend(["--key".to_string(), key.clone()]),
+        (None, Some(identity), Some(issuer)) => args.extend([
+            "--certificate-identity".to_string(),
+            identity.clone(),
+            "--certificate-oidc-issuer".to_string(),
+            issuer.clone(),
+        ]),
+        _ => {
+            return Err(verification_error(
+                reference,
+                "cosign needs either a key, or a certificate_identity and \
+                 certificate_oidc_issuer for keyless signatures"
+                    .to_string(),
+            ))
+        }
+    }
+    args.push(reference.to_string());
+    return Ok(args);
+}
+
+/// Verifies the cosign signature of an image pinned to a digest (i.e.
+/// `ghcr.io/org/app@sha256:...`)
+pub async fn verify(config: &CosignConfig, reference: &str, insecure: bool) -> Result<(), Error> {
+    let mut command = Command::new(COSIGN);
+    command
+        .args(args(config, reference, insecure)?)
+        .envs(http::proxy_env());
+    let output = process::output(&mut command)
+        .await
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => verification_error(
+                reference,
+                format!(
+                    "{} is needed to verify signatures, but it isn't on PATH",
+                    COSIGN
+                ),
+            ),
+            _ => Error::IOError(e),
+        })?;
+    if !output.status.success() {
+        return Err(verification_error(
+            reference,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::args;
+    use crate::config::CosignConfig;
+
+    const REFERENCE: &str = "ghcr.io/org/app@sha256:foobar";
+
+    #[test]
+    fn it_builds_args() {
+        let config = CosignConfig {
+            key: Some("cosign.pub".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            args(&config, REFERENCE, false).unwrap(),
+            vec![
+                "verify",
+                "--output",
+                "json",
+                "--key",
+                "cosign.pub",
+                REFERENCE
+            ],
+        );
+        let config = CosignConfig {
+            certificate_identity: Some(
+                "https://github.com/org/app/.github/workflows/release.yml@refs/heads/main"
+                    .to_string(),
+            ),
+            certificate_oidc_issuer: Some(
+                "https://token.actions.githubusercontent.com".to_string(),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(
+            args(&config, REFERENCE, false).unwrap(),
+            vec![
+                "verify",
+                "--output",
+                "json",
+                "--certificate-identity",
+                "https://github.com/org/app/.github/workflows/release.yml@refs/heads/main",
+                "--certificate-oidc-issuer",
+                "https://token.actions.githubusercontent.com",
+                REFERENCE,
+            ],
+        );
+        assert!(args(&CosignConfig::default(), REFERENCE, false).is_err());
+        let config = CosignConfig {
+            key: Some("cosign.pub".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            args(&config, REFERENCE, true).unwrap(),
+            vec![
+                "verify",
+                "--output",
+                "json",
+                "--allow-insecure-registry",
+                "--allow-http-registry",
+                "--key",
+                "cosign.pub",
+                REFERENCE
+            ],
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use crate::config::DockerConfig;
+use crate::netrc;
+use crate::secrets;
+use base64::Engine;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const ENV_PREFIX: &str = "UPTIX_REGISTRY_AUTH_";
+
+/// Names under which Docker Hub credentials may be stored on the docker
+/// config, since its registry lives on a different host
+const DOCKER_HUB_ALIASES: &[&str] = &["index.docker.io", "docker.io", "registry-1.docker.io"];
+
+#[derive(PartialEq, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for Credentials {
+    // keeps passwords out of logs and error messages
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("Credentials")
+            .field("username", &self.username)
+            .finish_non_exhaustive();
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct DockerConfigFile {
+    #[serde(default)]
+    auths: BTreeMap<String, DockerAuth>,
+}
+
+#[derive(Deserialize)]
+struct DockerAuth {
+    auth: Option<String>,
+}
+
+impl Credentials {
+    /// Parses credentials in the `username:password` form
+    fn parse(auth: &str) -> Option<Credentials> {
+        let (username, password) = auth.split_once(':')?;
+        return Some(Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        });
+    }
+}
+
+/// Name of the environment variable holding credentials for `registry`, i.e.
+/// `UPTIX_REGISTRY_AUTH_GHCR_IO` for `ghcr.io`
+pub fn env_var_name(registry: &str) -> String {
+    let host: String = registry
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect();
+    return format!("{}{}", ENV_PREFIX, host);
+}
+
+/// Files which may hold registry credentials, in the order podman looks them
+/// up, followed by the docker config. They all share the same format.
+fn auth_file_paths(var: impl Fn(&str) -> Option<OsString>) -> Vec<PathBuf> {
+    let home = var("HOME").map(PathBuf::from);
+    let config_home = var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(home.as_ref()?.join(".config")));
+    let docker_config = match var("DOCKER_CONFIG") {
+        Some(dir) => Some(Path::new(&dir).join("config.json")),
+        None => home.map(|home| home.join(".docker/config.json")),
+    };
+    return [
+        var("REGISTRY_AUTH_FILE").map(PathBuf::from),
+        var("XDG_RUNTIME_DIR").map(|dir| Path::new(&dir).join("containers/auth.json")),
+        config_home.map(|dir| dir.join("containers/auth.json")),
+        docker_config,
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+}
+
+/// Strips the scheme and path from keys of the docker config (which look
+/// like `https://index.docker.io/v1/`)
+fn host_of(key: &str) -> &str {
+    let host = key.split_once("://").map_or(key, |(_, rest)| rest);
+    return host.split('/').next().unwrap();
+}
+
+fn from_docker_config(path: &Path, registry: &str) -> Option<Credentials> {
+    let content = fs::read_to_string(path).ok()?;
+    let config: DockerConfigFile = serde_json::from_str(&content).ok()?;
+    let is_docker_hub = DOCKER_HUB_ALIASES.contains(&registry);
+    let auth = config.auths.iter().find_map(|(key, auth)| {
+        let host = host_of(key);
+        let matches = host == registry || (is_docker_hub && DOCKER_HUB_ALIASES.contains(&host));
+        return if matches { auth.auth.as_ref() } else { None };
+    })?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(auth)
+        .ok()?;
+    return Credentials::parse(&String::from_utf8(decoded).ok()?);
+}
+
+fn from_config(config: &DockerConfig, registry: &str) -> Option<Credentials> {
+    let registry_config = config.registries.get(registry)?;
+    return Some(Credentials {
+        username: registry_config.username.clone()?,
+        password: secrets::var(registry_config.password_env.as_ref()?)?,
+    });
+}
+
+fn from_netrc(registry: &str) -> Option<Credentials> {
+    let login = netrc::lookup(registry)?;
+    return Some(Credentials {
+        username: login.login?,
+        password: login.password?,
+    });
+}
+
+/// Looks up credentials for `registry` from, in order of precedence, the
+/// `UPTIX_REGISTRY_AUTH_<HOST>` environment variable, uptix.toml, podman's
+/// auth.json, the docker config file and .netrc
+pub fn lookup(config: &DockerConfig, registry: &str) -> Option<Credentials> {
+    if let Some(auth) = secrets::var(&env_var_name(registry)) {
+        return Credentials::parse(&auth);
+    }
+    return from_config(config, registry)
+        .or_else(|| {
+            auth_file_paths(|name| env::var_os(name))
+                .iter()
+                .find_map(|path| from_docker_config(path, registry))
+        })
+        .or_else(|| from_netrc(registry));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{auth_file_paths, env_var_name, from_config, from_docker_config, Credentials};
+    use crate::config::{DockerConfig, RegistryConfig};
+    use std::ffi::OsString;
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn it_names_env_vars() {
+        assert_eq!(env_var_name("ghcr.io"), "UPTIX_REGISTRY_AUTH_GHCR_IO");
+        assert_eq!(
+            env_var_name("localhost:5000"),
+            "UPTIX_REGISTRY_AUTH_LOCALHOST_5000"
+        );
+    }
+
+    #[test]
+    fn it_reads_docker_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        fs::write(
+            &path,
+            r#"{
+                "auths": {
+                    "https://index.docker.io/v1/": { "auth": "aHVidXNlcjpodWJwYXNz" },
+                    "harbor.example.com": { "auth": "cm9ib3Q6aHVudGVyMg==" }
+                },
+                "credsStore": "desktop"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            from_docker_config(&path, "registry-1.docker.io"),
+            Some(Credentials {
+                username: "hubuser".to_string(),
+                password: "hubpass".to_string(),
+            }),
+        );
+        assert_eq!(
+            from_docker_config(&path, "harbor.example.com"),
+            Some(Credentials {
+                username: "robot".to_string(),
+                password: "hunter2".to_string(),
+            }),
+        );
+        assert_eq!(from_docker_config(&path, "ghcr.io"), None);
+    }
+
+    #[test]
+    fn it_finds_auth_files() {
+        let var = |name: &str| -> Option<OsString> {
+            return match name {
+                "HOME" => Some("/home/luiz".into()),
+                "XDG_RUNTIME_DIR" => Some("/run/user/1000".into()),
+                _ => None,
+            };
+        };
+        assert_eq!(
+            auth_file_paths(var),
+            vec![
+                PathBuf::from("/run/user/1000/containers/auth.json"),
+                PathBuf::from("/home/luiz/.config/containers/auth.json"),
+                PathBuf::from("/home/luiz/.docker/config.json"),
+            ],
+        );
+        let var = |name: &str| -> Option<OsString> {
+            return match name {
+                "REGISTRY_AUTH_FILE" => Some("/etc/uptix/auth.json".into()),
+                "DOCKER_CONFIG" => Some("/etc/docker".into()),
+                _ => None,
+            };
+        };
+        assert_eq!(
+            auth_file_paths(var),
+            vec![
+                PathBuf::from("/etc/uptix/auth.json"),
+                PathBuf::from("/etc/docker/config.json"),
+            ],
+        );
+    }
+
+    #[test]
+    fn it_reads_config() {
+        std::env::set_var("UPTIX_TEST_ARTIFACTORY_TOKEN", "hunter2");
+        let mut config = DockerConfig::default();
+        config.registries.insert(
+            "artifactory.example.com".to_string(),
+            RegistryConfig {
+                username: Some("uptix".to_string()),
+                password_env: Some("UPTIX_TEST_ARTIFACTORY_TOKEN".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            from_config(&config, "artifactory.example.com"),
+            Some(Credentials {
+                username: "uptix".to_string(),
+                password: "hunter2".to_string(),
+            }),
+        );
+        assert_eq!(from_config(&config, "ghcr.io"), None);
+    }
+}
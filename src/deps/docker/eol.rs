@@ -0,0 +1,191 @@
+use crate::config::EolConfig;
+use crate::error::Error;
+use crate::http;
+use crate::util;
+use serde::Deserialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Products on endoflife.date whose name differs from their image's
+const PRODUCT_ALIASES: &[(&str, &str)] = &[
+    ("postgres", "postgresql"),
+    ("node", "nodejs"),
+    ("mongo", "mongodb"),
+    ("httpd", "apache-http-server"),
+    ("golang", "go"),
+];
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Eol {
+    Flag(bool),
+    Date(String),
+}
+
+/// A release cycle (i.e. a major version) of a product
+#[derive(Deserialize)]
+struct Cycle {
+    cycle: String,
+    eol: Eol,
+}
+
+/// The endoflife.date product an image (i.e. `library/postgres`) belongs to
+fn product<'a>(image: &'a str, config: &'a EolConfig) -> &'a str {
+    if let Some(product) = config.products.get(image) {
+        return product;
+    }
+    let name = image.rsplit('/').next().unwrap();
+    return PRODUCT_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, product)| product);
+}
+
+/// Finds the most specific cycle a tag belongs to, so that `12.3-alpine`
+/// and `12.*` both fall under `12`
+fn find_cycle<'a>(cycles: &'a [Cycle], tag: &str) -> Option<&'a Cycle> {
+    let tag = tag.trim_end_matches('*').trim_end_matches('.');
+    let tag = tag.strip_prefix('v').unwrap_or(tag);
+    return cycles
+        .iter()
+        .filter(|c| {
+            tag == c.cycle
+                || tag.starts_with(&format!("{}.", c.cycle))
+                || tag.starts_with(&format!("{}-", c.cycle))
+        })
+        .max_by_key(|c| c.cycle.len());
+}
+
+/// Today's date as `YYYY-MM-DD`, which compares like the dates on
+/// endoflife.date
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86400) as i64;
+    // converts days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    return format!("{:04}-{:02}-{:02}", year, month, day);
+}
+
+fn describe(product: &str, cycle: &Cycle, today: &str) -> Option<String> {
+    return match &cycle.eol {
+        Eol::Flag(true) => Some(format!("{} {} reached end of life", product, cycle.cycle)),
+        Eol::Date(date) if date.as_str() <= today => Some(format!(
+            "{} {} reached end of life on {}",
+            product, cycle.cycle, date
+        )),
+        _ => None,
+    };
+}
+
+/// Whether the release cycle an image's tag follows reached end of life,
+/// according to endoflife.date (or the dataset configured in its place)
+pub async fn end_of_life(
+    config: &EolConfig,
+    image: &str,
+    tag: &str,
+) -> Result<Option<String>, Error> {
+    let product = product(image, config);
+    let request = http::client()?
+        .get(format!("{}/{}.json", config.url, product))
+        .header(reqwest::header::USER_AGENT, util::user_agent());
+    let response = http::send(request, http::transient).await?;
+    // most images aren't tracked there
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let cycles: Vec<Cycle> = response.error_for_status()?.json().await?;
+    return Ok(find_cycle(&cycles, tag).and_then(|cycle| describe(product, cycle, &today())));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{end_of_life, find_cycle, product, today, Cycle};
+    use crate::config::EolConfig;
+
+    #[test]
+    fn it_maps_products() {
+        let mut config = EolConfig::default();
+        assert_eq!(product("library/postgres", &config), "postgresql");
+        assert_eq!(product("library/redis", &config), "redis");
+        config
+            .products
+            .insert("bitnami/postgresql".to_string(), "postgresql".to_string());
+        assert_eq!(product("bitnami/postgresql", &config), "postgresql");
+    }
+
+    #[test]
+    fn it_finds_cycles() {
+        let cycles: Vec<Cycle> = serde_json::from_str(
+            r#"[
+                { "cycle": "16", "eol": "2028-11-09" },
+                { "cycle": "12", "eol": "2024-11-14" },
+                { "cycle": "1.2", "eol": true },
+                { "cycle": "1.20", "eol": false }
+            ]"#,
+        )
+        .unwrap();
+        let cycle = |tag| find_cycle(&cycles, tag).map(|c| c.cycle.as_str());
+        assert_eq!(cycle("12"), Some("12"));
+        assert_eq!(cycle("12.3-alpine"), Some("12"));
+        assert_eq!(cycle("12.*"), Some("12"));
+        assert_eq!(cycle("1.20.3"), Some("1.20"));
+        assert_eq!(cycle("v1.2.3"), Some("1.2"));
+        assert_eq!(cycle("120"), None);
+        assert_eq!(cycle("latest"), None);
+    }
+
+    #[test]
+    fn it_knows_today() {
+        let today = today();
+        assert_eq!(today.len(), 10);
+        assert!(today.as_str() > "2024-01-01");
+    }
+
+    #[tokio::test]
+    async fn it_flags_end_of_life() {
+        let _postgres = mockito::mock("GET", "/postgresql.json")
+            .with_status(200)
+            .with_body(
+                r#"[
+                    { "cycle": "16", "eol": "2999-11-09" },
+                    { "cycle": "12", "eol": "2024-11-14" }
+                ]"#,
+            )
+            .create();
+        let _unknown = mockito::mock("GET", "/grafana.json")
+            .with_status(404)
+            .create();
+        let config = EolConfig {
+            url: mockito::server_url(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            end_of_life(&config, "library/postgres", "12-alpine")
+                .await
+                .unwrap(),
+            Some("postgresql 12 reached end of life on 2024-11-14".to_string()),
+        );
+        assert_eq!(
+            end_of_life(&config, "library/postgres", "16")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            end_of_life(&config, "grafana/grafana", "9").await.unwrap(),
+            None
+        );
+
+        mockito::reset();
+    }
+}
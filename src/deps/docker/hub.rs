@@ -0,0 +1,128 @@
+use crate::error::Error;
+use crate::http;
+use crate::util;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+pub const HUB_URL: &str = "https://hub.docker.com";
+
+lazy_static! {
+    /// Deprecations already looked up during this run, by repository, since
+    /// `uptix list` and `uptix check` can ask about the same image more than
+    /// once (i.e. for each of its tags)
+    static ref DEPRECATIONS: Mutex<BTreeMap<String, Option<String>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Repository details from Docker Hub's API, which (unlike the registry)
+/// knows whether a repository is still maintained
+#[derive(Deserialize)]
+struct Repository {
+    status_description: Option<String>,
+    description: Option<String>,
+}
+
+impl Repository {
+    fn deprecation(&self) -> Option<String> {
+        let description = self.description.clone().unwrap_or_default();
+        // deprecated official images say so (and where they moved to) on
+        // their description
+        if description.to_lowercase().contains("deprecated") {
+            return Some(description);
+        }
+        return match self.status_description.as_deref() {
+            Some("inactive") => Some("the repository is inactive".to_string()),
+            _ => None,
+        };
+    }
+}
+
+/// Why a Docker Hub repository is deprecated, if it is. Each repository is
+/// only looked up once per run.
+pub async fn deprecation(base_url: &str, image: &str) -> Result<Option<String>, Error> {
+    let url = format!("{}/v2/repositories/{}/", base_url, image);
+    if let Some(known) = DEPRECATIONS.lock().unwrap().get(&url) {
+        return Ok(known.clone());
+    }
+    let request = http::client()?
+        .get(&url)
+        .header(reqwest::header::USER_AGENT, util::user_agent());
+    let response = http::send(request, http::transient).await?;
+    let deprecation = match response.status() {
+        reqwest::StatusCode::NOT_FOUND => None,
+        _ => response
+            .error_for_status()?
+            .json::<Repository>()
+            .await?
+            .deprecation(),
+    };
+    DEPRECATIONS
+        .lock()
+        .unwrap()
+        .insert(url, deprecation.clone());
+    return Ok(deprecation);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::deprecation;
+
+    #[tokio::test]
+    async fn it_detects_deprecated_repositories() {
+        let base_url = mockito::server_url();
+        let openjdk = mockito::mock("GET", "/v2/repositories/library/openjdk/")
+            .with_status(200)
+            .with_body(
+                r#"{
+                    "status": 1,
+                    "status_description": "active",
+                    "description": "Pre-release / non-production builds of OpenJDK (DEPRECATED)"
+                }"#,
+            )
+            .create();
+        let _abandoned = mockito::mock("GET", "/v2/repositories/someone/abandoned/")
+            .with_status(200)
+            .with_body(r#"{ "status": 0, "status_description": "inactive" }"#)
+            .create();
+        let _grafana = mockito::mock("GET", "/v2/repositories/grafana/grafana/")
+            .with_status(200)
+            .with_body(
+                r#"{
+                    "status": 1,
+                    "status_description": "active",
+                    "description": "The official Grafana docker container"
+                }"#,
+            )
+            .create();
+        let _missing = mockito::mock("GET", "/v2/repositories/someone/missing/")
+            .with_status(404)
+            .create();
+
+        assert_eq!(
+            deprecation(&base_url, "library/openjdk").await.unwrap(),
+            Some("Pre-release / non-production builds of OpenJDK (DEPRECATED)".to_string()),
+        );
+        assert_eq!(
+            deprecation(&base_url, "someone/abandoned").await.unwrap(),
+            Some("the repository is inactive".to_string()),
+        );
+        assert_eq!(
+            deprecation(&base_url, "grafana/grafana").await.unwrap(),
+            None
+        );
+        assert_eq!(
+            deprecation(&base_url, "someone/missing").await.unwrap(),
+            None
+        );
+        // repositories which were already looked up aren't asked about again
+        openjdk.assert();
+        assert!(deprecation(&base_url, "library/openjdk")
+            .await
+            .unwrap()
+            .is_some());
+        openjdk.assert();
+
+        mockito::reset();
+    }
+}
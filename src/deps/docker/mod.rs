@@ -0,0 +1,1282 @@
+use crate::config::{CosignConfig, DockerConfig, EolConfig};
+use crate::deps::{assert_kind, DependencyMetadata, Lockable};
+use crate::error::Error;
+use crate::lock::{DockerLock, LockData, LockFile, LockMetadata};
+use crate::semver::Version;
+use crate::util;
+use crate::util::ParsingContext;
+use async_trait::async_trait;
+use credentials::Credentials;
+use platform::Platform;
+use regex::Regex;
+use registry::{Endpoint, ManifestIndex, ManifestIndexEntry, Registry};
+use rnix::{SyntaxKind, SyntaxNode};
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+mod cosign;
+mod credentials;
+mod eol;
+mod hub;
+mod platform;
+mod prefetch;
+mod profile;
+mod reference;
+mod registry;
+
+pub use profile::Profile;
+pub use registry::rate_limits;
+
+#[derive(Default, PartialEq, Clone, Debug)]
+pub struct Docker {
+    name: String,
+    registry: String,
+    image: String,
+    tag: String,
+    arch: Option<String>,
+    platform: Option<String>,
+    /// platform to resolve the image for when none was given explicitly
+    default_platform: Option<String>,
+    /// whether the digests for every platform should be locked
+    all_platforms: bool,
+    /// whether the hash `dockerTools.pullImage` needs should be locked too
+    needs_nix_hash: bool,
+    use_https: bool,
+    credentials: Option<Credentials>,
+    /// overrides the registry profile guessed from its host
+    profile: Option<Profile>,
+    /// registries to try before the image's own one
+    mirrors: Vec<Endpoint>,
+    /// signatures required from the image, before locking it
+    cosign: Option<CosignConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct DockerOptions {
+    image: String,
+    tag: Option<String>,
+    registry: Option<String>,
+    arch: Option<String>,
+    platform: Option<String>,
+    insecure: Option<bool>,
+}
+
+const DEFAULT_REGISTRY: &str = "registry-1.docker.io";
+const DEFAULT_TAG: &str = "latest";
+/// Tag selecting the highest stable semantic version of an image
+const STABLE_SEMVER: &str = "stable-semver";
+
+lazy_static! {
+    static ref NUMBER_RE: Regex = Regex::new(r"\d+").unwrap();
+    /// Versions without pre-release or variant suffixes (i.e. `16.2`, but not
+    /// `16.2-rc1` or `16.2-alpine`)
+    static ref STABLE_VERSION_RE: Regex = Regex::new(r"^v?\d+\.\d+(\.\d+)?$").unwrap();
+    /// Locks of images on the previous lock file, by key, so that images
+    /// which didn't move aren't prefetched or measured again
+    static ref KNOWN_LOCKS: Mutex<BTreeMap<String, DockerLock>> = Mutex::new(BTreeMap::new());
+}
+
+/// Remembers the nix hashes and sizes of images on a lock file
+pub fn remember_hashes(lock_file: &LockFile) {
+    let mut known = KNOWN_LOCKS.lock().unwrap();
+    for (key, value) in lock_file {
+        let lock = match value {
+            LockData::Docker(lock) => lock.clone(),
+            LockData::Digest(digest) => DockerLock {
+                tag: None,
+                digest: digest.clone(),
+                platforms: None,
+                sha256: None,
+                metadata: None,
+            },
+            _ => continue,
+        };
+        known.insert(key.clone(), lock);
+    }
+}
+
+/// Orders tags as versions when both are (so that `11.0.0` is newer than
+/// `11.0.0-beta1`), and by the numbers in them otherwise, so that `10.4.10`
+/// is newer than `10.4.9`. On ties, plain tags are preferred over suffixed
+/// ones (i.e. `10.4.2` over `10.4.2-ubuntu`).
+fn compare_tags(a: &str, b: &str) -> Ordering {
+    if let (Some(a), Some(b)) = (Version::parse(a), Version::parse(b)) {
+        if a != b {
+            return a.cmp(&b);
+        }
+    }
+    let numbers = |tag: &str| -> Vec<u64> {
+        NUMBER_RE
+            .find_iter(tag)
+            .filter_map(|m| m.as_str().parse().ok())
+            .collect()
+    };
+    return numbers(a)
+        .cmp(&numbers(b))
+        .then_with(|| b.len().cmp(&a.len()))
+        .then_with(|| a.cmp(b));
+}
+
+/// The repository of an image on its registry. Official images on Docker
+/// Hub live under the library namespace, while their name is kept as
+/// written, since that's what the nix module looks them up with.
+fn repository(registry: &str, image: String) -> String {
+    if registry == DEFAULT_REGISTRY && !image.contains('/') {
+        return format!("library/{}", image);
+    }
+    return image;
+}
+
+impl Docker {
+    pub fn new(context: &ParsingContext, node: &SyntaxNode) -> Result<Docker, Error> {
+        if node.kind() == SyntaxKind::NODE_ATTR_SET {
+            let options: DockerOptions = util::from_attr_set(node)?;
+            return Docker::from_options(options);
+        }
+        let string_node = assert_kind(
+            context,
+            "uptix.dockerImage",
+            node,
+            SyntaxKind::NODE_STRING,
+            r#"here are some examples of allowed parameters:
+ - homeassistant/home-assistant:stable
+ - grafana/grafana
+ - custom.registry.io/foo/bar:tag
+
+or, in order to set additional options:
+
+  uptix.dockerImage {
+    image = "postgres";
+    tag = "16";
+    arch = "arm64";
+  }"#,
+        )?;
+        let text = util::string_value(string_node).ok_or_else(|| {
+            Error::NixParsingError("Docker image names can't use interpolation".to_string())
+        })?;
+        return Docker::from(text.as_str());
+    }
+
+    pub fn from(text: &str) -> Result<Docker, Error> {
+        let reference = reference::parse(text).map_err(Error::NixParsingError)?;
+        let name = text.to_string();
+        let registry = reference.registry.unwrap_or(DEFAULT_REGISTRY.to_string());
+        let image = repository(&registry, reference.repository);
+        let tag = reference.tag.unwrap_or(DEFAULT_TAG.to_string());
+
+        return Ok(Docker {
+            name,
+            registry,
+            image,
+            tag,
+            arch: None,
+            platform: None,
+            default_platform: None,
+            all_platforms: false,
+            needs_nix_hash: false,
+            use_https: true,
+            credentials: None,
+            profile: None,
+            mirrors: vec![],
+            cosign: None,
+        });
+    }
+
+    fn from_options(options: DockerOptions) -> Result<Docker, Error> {
+        if options.arch.is_some() && options.platform.is_some() {
+            return Err(Error::NixParsingError(
+                "arch and platform can't be set at the same time".to_string(),
+            ));
+        }
+        // this needs to match how the name is built on the nix module
+        let name = format!(
+            "{}{}{}",
+            options
+                .registry
+                .as_ref()
+                .map_or("".to_string(), |r| format!("{}/", r)),
+            options.image,
+            options
+                .tag
+                .as_ref()
+                .map_or("".to_string(), |t| format!(":{}", t)),
+        );
+        let registry = options.registry.unwrap_or(DEFAULT_REGISTRY.to_string());
+        let image = repository(&registry, options.image);
+        return Ok(Docker {
+            name,
+            registry,
+            image,
+            tag: options.tag.unwrap_or(DEFAULT_TAG.to_string()),
+            arch: options.arch,
+            platform: options.platform,
+            default_platform: None,
+            all_platforms: false,
+            needs_nix_hash: false,
+            use_https: !options.insecure.unwrap_or(false),
+            credentials: None,
+            profile: None,
+            mirrors: vec![],
+            cosign: None,
+        });
+    }
+
+    /// The name of the image without its tag, as given to
+    /// `dockerTools.pullImage`
+    fn image_name(&self) -> &str {
+        return self
+            .name
+            .strip_suffix(&format!(":{}", self.tag))
+            .unwrap_or(&self.name);
+    }
+
+    /// Whether the image follows the latest tag, implicitly or explicitly
+    pub fn uses_latest_tag(&self) -> bool {
+        return self.tag == DEFAULT_TAG;
+    }
+
+    /// Why the image is deprecated, for images on Docker Hub
+    pub async fn deprecation(&self) -> Result<Option<String>, Error> {
+        if self.registry != DEFAULT_REGISTRY {
+            return Ok(None);
+        }
+        return hub::deprecation(hub::HUB_URL, &self.image).await;
+    }
+
+    /// Whether the release the image's tag follows reached end of life
+    pub async fn end_of_life(&self, config: &EolConfig) -> Result<Option<String>, Error> {
+        if self.uses_latest_tag() || self.tag == STABLE_SEMVER {
+            return Ok(None);
+        }
+        return eol::end_of_life(config, &self.image, &self.tag).await;
+    }
+
+    pub fn identity(&self) -> String {
+        return format!("{}/{}", self.registry, self.image);
+    }
+
+    /// Makes the image be locked with its digest for every platform, as
+    /// done by `uptix.dockerImagePlatforms`
+    pub fn with_all_platforms(mut self) -> Result<Docker, Error> {
+        if self.platform.is_some() || self.arch.is_some() {
+            return Err(Error::NixParsingError(
+                "uptix.dockerImagePlatforms locks every platform, so arch and platform can't be set"
+                    .to_string(),
+            ));
+        }
+        self.all_platforms = true;
+        return Ok(self);
+    }
+
+    /// Resolves the image for a platform (i.e. `linux/arm64`), as the
+    /// `platform` option of `uptix.dockerImage` does
+    pub fn with_platform(mut self, platform: &str) -> Docker {
+        self.arch = None;
+        self.platform = Some(platform.to_string());
+        return self;
+    }
+
+    /// Makes the hash of the image tarball be locked along with its digest,
+    /// as done by `uptix.dockerPullImage`
+    pub fn with_nix_hash(mut self) -> Docker {
+        self.needs_nix_hash = true;
+        return self;
+    }
+
+    /// Applies the `[docker]` settings from uptix.toml, looking up the
+    /// credentials for the image's registry
+    pub fn configure(&mut self, config: &DockerConfig) {
+        self.default_platform = config.platform.clone();
+        self.credentials = credentials::lookup(config, &self.registry);
+        let registry_config = config.registries.get(&self.registry);
+        self.profile = registry_config.and_then(|r| r.profile);
+        self.cosign = registry_config.and_then(|r| r.cosign.clone());
+        if registry_config.is_some_and(|r| r.insecure) {
+            self.use_https = false;
+        }
+        self.mirrors = registry_config
+            .map_or(&vec![], |r| &r.mirrors)
+            .iter()
+            .map(|mirror| {
+                let (registry, use_https) = match mirror.strip_prefix("http://") {
+                    Some(registry) => (registry, false),
+                    None => (mirror.strip_prefix("https://").unwrap_or(mirror), true),
+                };
+                let mirror_config = config.registries.get(registry);
+                Endpoint {
+                    registry: registry.to_string(),
+                    use_https: use_https && !mirror_config.is_some_and(|r| r.insecure),
+                    credentials: credentials::lookup(config, registry),
+                    profile: mirror_config.and_then(|r| r.profile),
+                }
+            })
+            .collect();
+    }
+
+    /// The platform to resolve the image for, if any. Without one, the digest
+    /// of whatever the tag points at (usually a manifest list) is used.
+    fn target_platform(&self) -> Option<Platform> {
+        return self
+            .platform
+            .as_ref()
+            .or(self.arch.as_ref())
+            .or(self.default_platform.as_ref())
+            .map(|platform| Platform::parse(platform));
+    }
+
+    /// Whether the tag is a pattern (i.e. `10.*` or `stable-semver`) to be
+    /// resolved to the newest matching tag, rather than a fixed tag
+    fn is_tag_pattern(&self) -> bool {
+        return self.tag.contains('*') || self.tag == STABLE_SEMVER;
+    }
+
+    /// The image's own registry, tried after all of its mirrors failed
+    fn upstream(&self) -> Endpoint {
+        return Endpoint {
+            registry: self.registry.clone(),
+            use_https: self.use_https,
+            credentials: self.credentials.clone(),
+            profile: self.profile,
+        };
+    }
+
+    async fn latest_tag(&self, registry: &Registry) -> Result<String, Error> {
+        let pattern = globset::Glob::new(&self.tag)?.compile_matcher();
+        let is_match = |tag: &str| match self.tag.as_str() {
+            STABLE_SEMVER => STABLE_VERSION_RE.is_match(tag),
+            _ => pattern.is_match(tag),
+        };
+        return registry
+            .tags(&self.image)
+            .await?
+            .into_iter()
+            .filter(|tag| is_match(tag))
+            .max_by(|a, b| compare_tags(a, b))
+            .ok_or_else(|| Error::NoMatchingTag {
+                image: self.image.clone(),
+                pattern: self.tag.clone(),
+            });
+    }
+
+    async fn digest(&self, registry: &Registry, tag: &str) -> Result<String, Error> {
+        let not_found = || Error::ImageNotFound {
+            name: self.name.clone(),
+        };
+        let platform = match self.target_platform() {
+            Some(platform) => platform,
+            None => {
+                return registry
+                    .manifest_digest(&self.image, tag)
+                    .await?
+                    .ok_or_else(not_found)
+            }
+        };
+        let manifest = registry
+            .manifest(&self.image, tag)
+            .await?
+            .ok_or_else(not_found)?;
+        let index = match manifest.index()? {
+            Some(index) => index,
+            // single platform images can only be used as they are
+            None => return manifest.digest.ok_or_else(not_found),
+        };
+        return index
+            .manifests
+            .iter()
+            .find(|m| m.platform.as_ref().is_some_and(|p| platform.matches(p)))
+            .map(|m| m.digest.clone())
+            .ok_or_else(|| {
+                let available: Vec<_> = index
+                    .manifests
+                    .iter()
+                    .filter_map(|m| m.platform.as_ref().map(|p| p.to_string()))
+                    .collect();
+                Error::UnsupportedPlatform {
+                    name: self.name.clone(),
+                    platform: platform.to_string(),
+                    available: available.join(", "),
+                }
+            });
+    }
+
+    /// Returns the digest of the manifest list along with the digest of the
+    /// image for each of the platforms in it
+    async fn platform_digests(
+        &self,
+        registry: &Registry,
+        tag: &str,
+    ) -> Result<(String, BTreeMap<String, String>), Error> {
+        let manifest =
+            registry
+                .manifest(&self.image, tag)
+                .await?
+                .ok_or_else(|| Error::ImageNotFound {
+                    name: self.name.clone(),
+                })?;
+        let index = manifest.index()?.ok_or_else(|| Error::NotMultiPlatform {
+            name: self.name.clone(),
+        })?;
+        let digest = match manifest.digest {
+            Some(digest) => digest,
+            None => self.digest(registry, tag).await?,
+        };
+        let platforms = index
+            .manifests
+            .into_iter()
+            .filter_map(|m| Some((m.platform?, m.digest)))
+            // attestations are listed with an unknown platform
+            .filter(|(platform, _)| platform.os != "unknown")
+            .map(|(platform, digest)| (platform.to_string(), digest))
+            .collect();
+        return Ok((digest, platforms));
+    }
+
+    /// Returns the digest the tag currently points at, or `None` if it still
+    /// points at `pinned` (or at a manifest list which contains it)
+    pub async fn drift(&self, pinned: &str) -> Result<Option<String>, Error> {
+        let registry = Registry::connect(&self.upstream(), &self.image).await?;
+        let manifest = registry
+            .manifest(&self.image, &self.tag)
+            .await?
+            .ok_or_else(|| Error::ImageNotFound {
+                name: self.name.clone(),
+            })?;
+        let digest = match &manifest.digest {
+            Some(digest) => digest.clone(),
+            None => self.digest(&registry, &self.tag).await?,
+        };
+        if digest == pinned {
+            return Ok(None);
+        }
+        let platform_digests = manifest.index()?.map_or(vec![], |index| index.manifests);
+        if platform_digests.iter().any(|m| m.digest == pinned) {
+            return Ok(None);
+        }
+        return Ok(Some(digest));
+    }
+
+    /// Locks the image using the given registry, which serves the same
+    /// content (and therefore digests) as the image's own one
+    async fn lock_from(&self, endpoint: &Endpoint) -> Result<LockData, Error> {
+        let registry = Registry::connect(endpoint, &self.image).await?;
+        let tag = match self.is_tag_pattern() {
+            true => Some(self.latest_tag(&registry).await?),
+            false => None,
+        };
+        let reference = tag.as_ref().unwrap_or(&self.tag);
+        if self.all_platforms {
+            let (digest, platforms) = self.platform_digests(&registry, reference).await?;
+            self.verify(&digest).await?;
+            return Ok(LockData::Docker(DockerLock {
+                tag,
+                digest,
+                platforms: Some(platforms),
+                sha256: None,
+                metadata: None,
+            }));
+        }
+        let digest = self.digest(&registry, reference).await?;
+        self.verify(&digest).await?;
+        // the tarball embeds the tag, so both need to match
+        let known = KNOWN_LOCKS
+            .lock()
+            .unwrap()
+            .get(&self.key())
+            .filter(|known| known.digest == digest && known.tag == tag)
+            .cloned();
+        let metadata = match &known {
+            Some(known) => known.metadata.clone(),
+            // the size is only reported on, so failing to measure the image
+            // doesn't fail the lock
+            None => self.image_size(&registry, &digest).await.unwrap_or(None),
+        };
+        if self.needs_nix_hash {
+            let sha256 = match known.and_then(|known| known.sha256) {
+                Some(sha256) => sha256,
+                None => {
+                    let image_name = self.image_name();
+                    let platform = self.target_platform();
+                    prefetch::nix_hash(image_name, &digest, reference, platform.as_ref()).await?
+                }
+            };
+            return Ok(LockData::Docker(DockerLock {
+                tag,
+                digest,
+                platforms: None,
+                sha256: Some(sha256),
+                metadata,
+            }));
+        }
+        if tag.is_none() && metadata.is_none() {
+            return Ok(LockData::Digest(digest));
+        }
+        return Ok(LockData::Docker(DockerLock {
+            tag,
+            digest,
+            platforms: None,
+            sha256: None,
+            metadata,
+        }));
+    }
+
+    /// The platform of a multi-platform image whose manifest is read for its
+    /// details: the one it's pulled for, or else the first actual platform
+    /// (rather than an attestation)
+    fn platform_entry<'a>(&self, index: &'a ManifestIndex) -> Option<&'a ManifestIndexEntry> {
+        let target = self.target_platform();
+        return index
+            .manifests
+            .iter()
+            .find(|m| match (&target, &m.platform) {
+                (Some(target), Some(platform)) => target.matches(platform),
+                (None, Some(platform)) => platform.os != "unknown",
+                (_, None) => false,
+            });
+    }
+
+    /// Measures the image a digest points at, for images which were bumped.
+    /// Images which didn't move keep the size on the previous lock, since
+    /// fetching a manifest counts as a pull on Docker Hub.
+    async fn image_size(
+        &self,
+        registry: &Registry,
+        digest: &str,
+    ) -> Result<Option<LockMetadata>, Error> {
+        let mut manifest = match registry.manifest(&self.image, digest).await? {
+            Some(manifest) => manifest,
+            None => return Ok(None),
+        };
+        if let Some(index) = manifest.index()? {
+            let entry = match self.platform_entry(&index) {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+            manifest = match registry.manifest(&self.image, &entry.digest).await? {
+                Some(manifest) => manifest,
+                None => return Ok(None),
+            };
+        }
+        return Ok(manifest.image()?.map(|image| LockMetadata {
+            size: Some(image.layers.iter().map(|layer| layer.size).sum()),
+            layers: Some(image.layers.len()),
+            ..Default::default()
+        }));
+    }
+
+    /// Checks the cosign signature of the resolved digest, when the image's
+    /// registry requires one. Signatures live next to the image on its own
+    /// registry, even when it was resolved through a mirror.
+    async fn verify(&self, digest: &str) -> Result<(), Error> {
+        return match &self.cosign {
+            Some(config) => {
+                let reference = format!("{}@{}", self.identity(), digest);
+                cosign::verify(config, &reference, !self.use_https).await
+            }
+            None => Ok(()),
+        };
+    }
+
+    /// Reads the `org.opencontainers.image.*` annotations of the image,
+    /// falling back to the labels on its config
+    async fn image_metadata(&self) -> Result<DependencyMetadata, Error> {
+        let registry = Registry::connect(&self.upstream(), &self.image).await?;
+        let tag = match self.is_tag_pattern() {
+            true => self.latest_tag(&registry).await?,
+            false => self.tag.clone(),
+        };
+        let not_found = || Error::ImageNotFound {
+            name: self.name.clone(),
+        };
+        let mut manifest = registry
+            .manifest(&self.image, &tag)
+            .await?
+            .ok_or_else(not_found)?;
+        let mut annotations = BTreeMap::new();
+        if let Some(index) = manifest.index()? {
+            // without a target platform, any image will do for its labels
+            let digest = self
+                .platform_entry(&index)
+                .map(|entry| entry.digest.clone());
+            annotations = index.annotations;
+            manifest = match digest {
+                Some(digest) => registry
+                    .manifest(&self.image, &digest)
+                    .await?
+                    .ok_or_else(not_found)?,
+                None => return Ok(DependencyMetadata::from_annotations(&annotations)),
+            };
+        }
+        let image = manifest.image()?.ok_or_else(not_found)?;
+        let labels = match &image.config {
+            Some(config) => registry
+                .config(&self.image, &config.digest)
+                .await?
+                .config
+                .and_then(|c| c.labels)
+                .unwrap_or_default(),
+            None => BTreeMap::new(),
+        };
+        // annotations on the index take precedence over the ones on the
+        // image manifest, which take precedence over labels
+        let mut merged = labels;
+        merged.extend(image.annotations);
+        merged.extend(annotations);
+        let mut metadata = DependencyMetadata::from_annotations(&merged);
+        metadata.size = Some(image.layers.iter().map(|layer| layer.size).sum());
+        metadata.layers = Some(image.layers.len());
+        // Docker Hub's API may be unreachable where the registry (or a
+        // mirror) is, which shouldn't hide the rest of the metadata
+        metadata.deprecated = match self.deprecation().await {
+            Ok(deprecated) => deprecated,
+            Err(error) => {
+                tracing::debug!(%error, "couldn't tell whether {} is deprecated", self.image);
+                None
+            }
+        };
+        if metadata.resolved_version.is_none() && self.is_tag_pattern() {
+            metadata.resolved_version = Some(tag);
+        }
+        return Ok(metadata);
+    }
+
+    /// Lets the image share a session with the other images on its mirrors
+    /// and registry
+    pub fn plan_sessions(&self) {
+        for endpoint in self.mirrors.iter().chain([self.upstream()].iter()) {
+            registry::plan(&endpoint.registry, &self.image);
+        }
+    }
+
+    /// Locks the image through its mirrors, falling back to its own registry
+    async fn lock_with_fallback(&self) -> Result<LockData, Error> {
+        for mirror in &self.mirrors {
+            match self.lock_from(mirror).await {
+                Ok(lock) => return Ok(lock),
+                Err(error) => tracing::warn!(
+                    %error,
+                    "mirror {} failed for {}, falling back",
+                    mirror.registry,
+                    self.name,
+                ),
+            }
+        }
+        return self.lock_from(&self.upstream()).await;
+    }
+}
+
+#[async_trait]
+impl Lockable for Docker {
+    fn key(&self) -> String {
+        if self.all_platforms {
+            return format!("{}$platforms", self.name);
+        }
+        let key = match self.platform.as_ref().or(self.arch.as_ref()) {
+            Some(platform) => format!("{}${}", self.name, platform),
+            None => self.name.to_string(),
+        };
+        return match self.needs_nix_hash {
+            true => format!("{}$pullImage", key),
+            false => key,
+        };
+    }
+
+    async fn metadata(&self) -> Result<DependencyMetadata, Error> {
+        return self.image_metadata().await;
+    }
+
+    async fn lock(&self) -> Result<LockData, Error> {
+        return self.lock_with_fallback().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare_tags, remember_hashes, Docker, Endpoint};
+    use crate::config::{DockerConfig, RegistryConfig};
+    use crate::deps::test_util;
+    use crate::deps::{DependencyMetadata, Lockable};
+    use crate::lock::LockFile;
+    use serde_json::json;
+
+    /// An image on the mock registry, which is served over plain HTTP
+    fn mocked(reference: &str) -> Docker {
+        return Docker {
+            registry: mockito::server_address().to_string(),
+            use_https: false,
+            ..Docker::from(reference).unwrap()
+        };
+    }
+
+    #[test]
+    fn it_parses() {
+        let dependencies: Vec<_> = test_util::deps(
+            r#"{
+            hass = uptix.dockerImage "homeassistant/home-assistant:stable";
+            customRepo = uptix.dockerImage "foo.io/baz/bar";
+        }"#,
+        )
+        .unwrap()
+        .iter()
+        .map(|d| d.kind().as_docker().unwrap().clone())
+        .collect();
+        let expected_dependencies = vec![
+            Docker {
+                name: "homeassistant/home-assistant:stable".to_string(),
+                registry: "registry-1.docker.io".to_string(),
+                image: "homeassistant/home-assistant".to_string(),
+                tag: "stable".to_string(),
+                use_https: true,
+                ..Default::default()
+            },
+            Docker {
+                name: "foo.io/baz/bar".to_string(),
+                registry: "foo.io".to_string(),
+                image: "baz/bar".to_string(),
+                tag: "latest".to_string(),
+                use_https: true,
+                ..Default::default()
+            },
+        ];
+        assert_eq!(dependencies, expected_dependencies);
+    }
+
+    #[test]
+    fn it_parses_attr_sets() {
+        let dependencies: Vec<_> = test_util::deps(
+            r#"{
+            postgres = uptix.dockerImage {
+                image = "postgres";
+                tag = "16";
+                arch = "arm64";
+            };
+            local = uptix.dockerImage {
+                image = "foo/bar";
+                registry = "localhost";
+                insecure = true;
+            };
+        }"#,
+        )
+        .unwrap()
+        .iter()
+        .map(|d| d.kind().as_docker().unwrap().clone())
+        .collect();
+        // official images are named as given, but live under library/
+        let expected_dependencies = vec![
+            Docker {
+                arch: Some("arm64".to_string()),
+                ..Docker::from("postgres:16").unwrap()
+            },
+            Docker {
+                use_https: false,
+                ..Docker::from("localhost/foo/bar").unwrap()
+            },
+        ];
+        assert_eq!(dependencies, expected_dependencies);
+        assert_eq!(dependencies[0].key(), "postgres:16$arm64");
+        assert_eq!(dependencies[1].key(), "localhost/foo/bar");
+    }
+
+    #[test]
+    fn it_builds_images() {
+        let dependency = Docker::from("library/postgres:16")
+            .unwrap()
+            .with_platform("linux/arm64");
+        assert_eq!(dependency.key(), "library/postgres:16$linux/arm64");
+        assert_eq!(
+            dependency.identity(),
+            "registry-1.docker.io/library/postgres"
+        );
+    }
+
+    #[test]
+    fn it_parses_official_images() {
+        let dependencies: Vec<_> = test_util::deps(r#"{ db = uptix.dockerImage "postgres:16"; }"#)
+            .unwrap()
+            .iter()
+            .map(|d| d.kind().as_docker().unwrap().clone())
+            .collect();
+        assert_eq!(dependencies[0].name, "postgres:16");
+        assert_eq!(dependencies[0].registry, "registry-1.docker.io");
+        assert_eq!(dependencies[0].image, "library/postgres");
+        assert_eq!(dependencies[0].tag, "16");
+        assert_eq!(dependencies[0].key(), "postgres:16");
+    }
+
+    #[tokio::test]
+    async fn it_locks() {
+        let registry = mockito::server_address().to_string();
+        let _auth_mock = mockito::mock("GET", "/v2/")
+            .with_status(200)
+            .with_header(
+                "WWW-Authenticate",
+                format!(r#"Bearer realm="http://{}/token""#, registry).as_str(),
+            )
+            .with_body("{}")
+            .create();
+        let _token_mock = mockito::mock("GET", "/token")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(r#"{"token": "hunter2"}"#)
+            .create();
+        let _manifest_mock =
+            mockito::mock("HEAD", "/v2/homeassistant/home-assistant/manifests/stable")
+                .with_status(200)
+                .with_header("docker-content-digest", "sha256:foobar")
+                .create();
+        let _image_mock = mockito::mock(
+            "GET",
+            "/v2/homeassistant/home-assistant/manifests/sha256:foobar",
+        )
+        .with_status(200)
+        .with_header(
+            "content-type",
+            "application/vnd.docker.distribution.manifest.v2+json",
+        )
+        .with_body(
+            r#"{
+                        "schemaVersion": 2,
+                        "config": { "digest": "sha256:config", "size": 10 },
+                        "layers": [
+                            { "digest": "sha256:base", "size": 1024 },
+                            { "digest": "sha256:app", "size": 512 }
+                        ]
+                    }"#,
+        )
+        .create();
+
+        let dependency = mocked("homeassistant/home-assistant:stable");
+        let lock = dependency.lock().await.unwrap();
+        let lock_value = serde_json::to_value(lock).unwrap();
+
+        assert_eq!(
+            lock_value,
+            json!({
+                "digest": "sha256:foobar",
+                "metadata": { "size": 1536, "layers": 2 },
+            })
+        );
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_reuses_known_hashes() {
+        let _auth_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _manifest_mock = mockito::mock("HEAD", "/v2/library/known/manifests/1.0")
+            .with_status(200)
+            .with_header("docker-content-digest", "sha256:known")
+            .create();
+
+        let dependency = mocked("library/known:1.0").with_nix_hash();
+        let mut lock_file = LockFile::new();
+        lock_file.insert(
+            dependency.key(),
+            json!({
+                "digest": "sha256:known",
+                "sha256": "sha256-abc=",
+                "metadata": { "size": 1024, "layers": 1 },
+            })
+            .into(),
+        );
+        remember_hashes(&lock_file);
+        // neither nix-prefetch-docker nor the manifest are needed for images
+        // which didn't move
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(
+            lock_value,
+            serde_json::to_value(&lock_file[&dependency.key()]).unwrap()
+        );
+        mockito::reset();
+    }
+
+    #[test]
+    fn it_configures_insecure_registries() {
+        let mut config = DockerConfig::default();
+        config.registries.insert(
+            "localhost:5000".to_string(),
+            RegistryConfig {
+                insecure: true,
+                ..Default::default()
+            },
+        );
+        let mut local = Docker::from("localhost:5000/foo/bar:1.0").unwrap();
+        assert_eq!(local.registry, "localhost:5000");
+        assert_eq!(local.tag, "1.0");
+        local.configure(&config);
+        assert!(!local.use_https);
+        let mut remote = Docker::from("ghcr.io/foo/bar").unwrap();
+        remote.configure(&config);
+        assert!(remote.use_https);
+    }
+
+    #[tokio::test]
+    async fn it_falls_back_from_mirrors() {
+        let registry = mockito::server_address().to_string();
+        let _auth_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _manifest_mock = mockito::mock("HEAD", "/v2/library/redis/manifests/7")
+            .with_status(200)
+            .with_header("docker-content-digest", "sha256:foobar")
+            .create();
+
+        // images which can't be measured are locked all the same
+        let _image_mock = mockito::mock("GET", "/v2/library/redis/manifests/sha256:foobar")
+            .with_status(404)
+            .create();
+
+        let mut config = DockerConfig::default();
+        config.registries.insert(
+            "registry-1.docker.io".to_string(),
+            RegistryConfig {
+                mirrors: vec!["http://mirror.internal:5000".to_string()],
+                ..Default::default()
+            },
+        );
+        let mut dependency = Docker::from("library/redis:7").unwrap();
+        dependency.configure(&config);
+        assert_eq!(
+            dependency.mirrors,
+            vec![Endpoint {
+                registry: "mirror.internal:5000".to_string(),
+                use_https: false,
+                credentials: None,
+                profile: None,
+            }],
+        );
+
+        // the first mirror is not reachable, while the second one works
+        dependency.mirrors = vec![
+            Endpoint {
+                registry: "127.0.0.1:1".to_string(),
+                use_https: false,
+                credentials: None,
+                profile: None,
+            },
+            Endpoint {
+                registry,
+                use_https: false,
+                credentials: None,
+                profile: None,
+            },
+        ];
+        dependency.registry = "127.0.0.1:1".to_string();
+        let lock = dependency.lock().await.unwrap();
+        assert_eq!(serde_json::to_value(lock).unwrap(), "sha256:foobar");
+        mockito::reset();
+    }
+
+    #[test]
+    fn it_orders_tags() {
+        let mut tags = vec!["10.4.10", "10.4.2-ubuntu", "9.5.20", "10.4.9", "10.4.2"];
+        tags.sort_by(|a, b| compare_tags(a, b));
+        assert_eq!(
+            tags,
+            vec!["9.5.20", "10.4.2-ubuntu", "10.4.2", "10.4.9", "10.4.10"],
+        );
+        let mut tags = vec!["11.0.0", "11.0.0-beta10", "11.0.0-beta.2", "10.4.10"];
+        tags.sort_by(|a, b| compare_tags(a, b));
+        assert_eq!(
+            tags,
+            vec!["10.4.10", "11.0.0-beta.2", "11.0.0-beta10", "11.0.0"],
+        );
+    }
+
+    #[tokio::test]
+    async fn it_locks_tag_patterns() {
+        let _auth_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _tags_mock = mockito::mock("GET", "/v2/grafana/grafana/tags/list")
+            .with_status(200)
+            .with_body(r#"{ "tags": ["9.5.20", "10.4.2", "10.4.3", "10.4.3-ubuntu", "latest"] }"#)
+            .create();
+        let _manifest_mock = mockito::mock("HEAD", "/v2/grafana/grafana/manifests/10.4.3")
+            .with_status(200)
+            .with_header("docker-content-digest", "sha256:foobar")
+            .create();
+
+        // images which can't be measured are locked all the same
+        let _image_mock = mockito::mock("GET", "/v2/grafana/grafana/manifests/sha256:foobar")
+            .with_status(404)
+            .create();
+
+        let dependency = mocked("grafana/grafana:10.*");
+        assert_eq!(dependency.key(), "grafana/grafana:10.*");
+        let lock = dependency.lock().await.unwrap();
+        assert_eq!(
+            serde_json::to_value(lock).unwrap(),
+            serde_json::json!({ "tag": "10.4.3", "digest": "sha256:foobar" }),
+        );
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_locks_stable_versions() {
+        let registry = mockito::server_address().to_string();
+        let _auth_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _tags_mock = mockito::mock("GET", "/v2/library/postgres/tags/list")
+            .with_status(200)
+            .with_body(
+                r#"{ "tags": ["15.6", "16", "16.2", "16.2-alpine", "17.0-rc1", "17beta2", "latest"] }"#,
+            )
+            .create();
+        let _manifest_mock = mockito::mock("HEAD", "/v2/library/postgres/manifests/16.2")
+            .with_status(200)
+            .with_header("docker-content-digest", "sha256:foobar")
+            .create();
+
+        // images which can't be measured are locked all the same
+        let _image_mock = mockito::mock("GET", "/v2/library/postgres/manifests/sha256:foobar")
+            .with_status(404)
+            .create();
+
+        let mut dependency = Docker::from("library/postgres:stable-semver").unwrap();
+        dependency.registry = registry;
+        dependency.use_https = false;
+        let lock = dependency.lock().await.unwrap();
+        assert_eq!(
+            serde_json::to_value(lock).unwrap(),
+            serde_json::json!({ "tag": "16.2", "digest": "sha256:foobar" }),
+        );
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_detects_drift() {
+        let registry = mockito::server_address().to_string();
+        let _auth_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _manifest_mock = mockito::mock("GET", "/v2/library/redis/manifests/7")
+            .with_status(200)
+            .with_header(
+                "content-type",
+                "application/vnd.docker.distribution.manifest.list.v2+json",
+            )
+            .with_header("docker-content-digest", "sha256:list")
+            .with_body(
+                r#"{
+                    "schemaVersion": 2,
+                    "manifests": [
+                        { "digest": "sha256:amd64", "platform": { "os": "linux", "architecture": "amd64" } }
+                    ]
+                }"#,
+            )
+            .create();
+
+        let mut redis = Docker::from("library/redis:7").unwrap();
+        redis.registry = registry;
+        redis.use_https = false;
+        assert_eq!(redis.drift("sha256:list").await.unwrap(), None);
+        assert_eq!(redis.drift("sha256:amd64").await.unwrap(), None);
+        assert_eq!(
+            redis.drift("sha256:old").await.unwrap(),
+            Some("sha256:list".to_string()),
+        );
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_reads_metadata() {
+        let registry = mockito::server_address().to_string();
+        let _auth_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _index_mock = mockito::mock("GET", "/v2/grafana/grafana/manifests/10.4.3")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.oci.image.index.v1+json")
+            .with_body(
+                r#"{
+                    "schemaVersion": 2,
+                    "manifests": [
+                        { "digest": "sha256:amd64", "platform": { "os": "linux", "architecture": "amd64" } }
+                    ],
+                    "annotations": { "org.opencontainers.image.version": "10.4.3" }
+                }"#,
+            )
+            .create();
+        let _manifest_mock = mockito::mock("GET", "/v2/grafana/grafana/manifests/sha256:amd64")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.oci.image.manifest.v1+json")
+            .with_body(
+                r#"{
+                    "schemaVersion": 2,
+                    "config": { "digest": "sha256:config", "size": 1024 },
+                    "layers": [
+                        { "digest": "sha256:a", "size": 3145728 },
+                        { "digest": "sha256:b", "size": 1048576 }
+                    ],
+                    "annotations": { "org.opencontainers.image.source": "https://github.com/grafana/grafana" }
+                }"#,
+            )
+            .create();
+        let _config_mock = mockito::mock("GET", "/v2/grafana/grafana/blobs/sha256:config")
+            .with_status(200)
+            .with_body(
+                r#"{
+                    "config": {
+                        "Labels": {
+                            "org.opencontainers.image.source": "https://example.com/overridden",
+                            "org.opencontainers.image.description": "The open observability platform"
+                        }
+                    }
+                }"#,
+            )
+            .create();
+
+        let mut grafana = Docker::from("grafana/grafana:10.4.3").unwrap();
+        grafana.registry = registry;
+        grafana.use_https = false;
+        assert_eq!(
+            grafana.metadata().await.unwrap(),
+            DependencyMetadata {
+                resolved_version: Some("10.4.3".to_string()),
+                source_url: Some("https://github.com/grafana/grafana".to_string()),
+                description: Some("The open observability platform".to_string()),
+                size: Some(4194304),
+                layers: Some(2),
+                ..Default::default()
+            },
+        );
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_locks_platforms() {
+        let _auth_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _manifest_mock = mockito::mock("GET", "/v2/library/redis/manifests/7")
+            .with_status(200)
+            .with_header(
+                "content-type",
+                "application/vnd.docker.distribution.manifest.list.v2+json",
+            )
+            .with_header("docker-content-digest", "sha256:list")
+            .with_body(
+                r#"{
+                    "schemaVersion": 2,
+                    "manifests": [
+                        { "digest": "sha256:amd64", "platform": { "os": "linux", "architecture": "amd64" } },
+                        { "digest": "sha256:armv7", "platform": { "os": "linux", "architecture": "arm", "variant": "v7" } },
+                        { "digest": "sha256:arm64", "platform": { "os": "linux", "architecture": "arm64", "variant": "v8" } }
+                    ]
+                }"#,
+            )
+            .create();
+        let _image_mock = mockito::mock(
+            "GET",
+            mockito::Matcher::Regex(r"^/v2/library/redis/manifests/sha256:".to_string()),
+        )
+        .with_status(404)
+        .create();
+
+        let redis = |platform: Option<&str>, default_platform: Option<&str>| Docker {
+            name: "redis:7".to_string(),
+            platform: platform.map(|p| p.to_string()),
+            default_platform: default_platform.map(|p| p.to_string()),
+            ..mocked("library/redis:7")
+        };
+        let digest = |docker: Docker| async move {
+            serde_json::to_value(docker.lock().await.unwrap()).unwrap()
+        };
+        assert_eq!(
+            digest(redis(Some("linux/arm64"), None)).await,
+            "sha256:arm64"
+        );
+        assert_eq!(
+            digest(redis(None, Some("linux/arm/v7"))).await,
+            "sha256:armv7"
+        );
+        assert_eq!(
+            digest(redis(Some("linux/amd64"), Some("linux/arm64"))).await,
+            "sha256:amd64",
+        );
+        assert_eq!(
+            redis(Some("linux/arm64"), None).key(),
+            "redis:7$linux/arm64"
+        );
+        assert_eq!(redis(None, Some("linux/arm64")).key(), "redis:7");
+        assert!(redis(Some("windows/amd64"), None).lock().await.is_err());
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_locks_all_platforms() {
+        let registry = mockito::server_address().to_string();
+        let _auth_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _manifest_mock = mockito::mock("GET", "/v2/library/nginx/manifests/1.25")
+            .with_status(200)
+            .with_header("content-type", "application/vnd.oci.image.index.v1+json")
+            .with_header("docker-content-digest", "sha256:index")
+            .with_body(
+                r#"{
+                    "schemaVersion": 2,
+                    "manifests": [
+                        { "digest": "sha256:amd64", "platform": { "os": "linux", "architecture": "amd64" } },
+                        { "digest": "sha256:arm64", "platform": { "os": "linux", "architecture": "arm64", "variant": "v8" } },
+                        { "digest": "sha256:attestation", "platform": { "os": "unknown", "architecture": "unknown" } }
+                    ]
+                }"#,
+            )
+            .create();
+
+        let dependencies = test_util::deps(
+            r#"{ nginx = uptix.dockerImagePlatforms { image = "nginx"; tag = "1.25"; }; }"#,
+        )
+        .unwrap();
+        let mut dependency = dependencies[0].kind().as_docker().unwrap().clone();
+        dependency.registry = registry;
+        dependency.use_https = false;
+        assert_eq!(dependency.key(), "nginx:1.25$platforms");
+        let lock = dependency.lock().await.unwrap();
+        assert_eq!(
+            serde_json::to_value(lock).unwrap(),
+            serde_json::json!({
+                "digest": "sha256:index",
+                "platforms": {
+                    "linux/amd64": "sha256:amd64",
+                    "linux/arm64/v8": "sha256:arm64",
+                },
+            }),
+        );
+        mockito::reset();
+
+        assert!(test_util::deps(
+            r#"{ nginx = uptix.dockerImagePlatforms { image = "nginx"; arch = "arm64"; }; }"#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn it_parses_pull_images() {
+        let dependencies = test_util::deps(
+            r#"{
+            grafana = uptix.dockerPullImage "grafana/grafana:10.4.3";
+            postgres = uptix.dockerPullImage { image = "postgres"; tag = "16"; arch = "arm64"; };
+        }"#,
+        )
+        .unwrap();
+        let grafana = dependencies[0].kind().as_docker().unwrap();
+        assert!(grafana.needs_nix_hash);
+        assert!(!grafana.uses_latest_tag());
+        assert_eq!(grafana.image_name(), "grafana/grafana");
+        assert_eq!(grafana.key(), "grafana/grafana:10.4.3$pullImage");
+        let postgres = dependencies[1].kind().as_docker().unwrap();
+        assert_eq!(postgres.image_name(), "postgres");
+        assert_eq!(postgres.key(), "postgres:16$arm64$pullImage");
+    }
+
+    #[test]
+    fn it_provides_helpful_errors() {
+        let result = test_util::deps("{ hass = uptix.dockerImage 42; }");
+        assert!(result.is_err());
+        match result {
+            Err(crate::error::Error::UnexpectedArgument {
+                function,
+                src: _,
+                argument_pos,
+                expected_type,
+                help: _,
+            }) => {
+                assert_eq!(function, "uptix.dockerImage");
+                assert_eq!(expected_type, "NODE_STRING");
+                assert_eq!(argument_pos, (27, 2).into());
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
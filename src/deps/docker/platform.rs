@@ -0,0 +1,72 @@
+use serde::Deserialize;
+use std::fmt;
+
+const DEFAULT_OS: &str = "linux";
+
+/// Platform an image is built for, written as `os/architecture[/variant]`
+/// (i.e. `linux/arm64/v8`). A bare architecture implies linux.
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+pub struct Platform {
+    pub os: String,
+    pub architecture: String,
+    pub variant: Option<String>,
+}
+
+impl Platform {
+    pub fn parse(platform: &str) -> Platform {
+        let parts: Vec<&str> = platform.split('/').collect();
+        let (os, architecture, variant) = match parts.as_slice() {
+            [architecture] => (DEFAULT_OS, *architecture, None),
+            [os, architecture] => (*os, *architecture, None),
+            [os, architecture, variant, ..] => (*os, *architecture, Some(*variant)),
+            [] => unreachable!(),
+        };
+        return Platform {
+            os: os.to_string(),
+            architecture: architecture.to_string(),
+            variant: variant.map(|v| v.to_string()),
+        };
+    }
+
+    /// Whether an image built for `other` can be used on this platform. The
+    /// variant is only compared when one was asked for.
+    pub fn matches(&self, other: &Platform) -> bool {
+        return self.os == other.os
+            && self.architecture == other.architecture
+            && (self.variant.is_none() || self.variant == other.variant);
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(variant) = &self.variant {
+            write!(f, "/{}", variant)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Platform;
+
+    #[test]
+    fn it_parses() {
+        assert_eq!(Platform::parse("arm64").to_string(), "linux/arm64");
+        assert_eq!(Platform::parse("linux/amd64").to_string(), "linux/amd64");
+        assert_eq!(Platform::parse("linux/arm/v7").to_string(), "linux/arm/v7");
+    }
+
+    #[test]
+    fn it_matches() {
+        let arm = Platform::parse("linux/arm");
+        let arm_v7 = Platform::parse("linux/arm/v7");
+        let arm_v6 = Platform::parse("linux/arm/v6");
+        assert!(arm.matches(&arm_v7));
+        assert!(arm_v7.matches(&arm_v7));
+        assert!(!arm_v7.matches(&arm_v6));
+        assert!(!arm.matches(&Platform::parse("linux/arm64")));
+        assert!(!arm.matches(&Platform::parse("windows/arm")));
+    }
+}
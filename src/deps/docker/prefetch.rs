@@ -0,0 +1,102 @@
+use super::platform::Platform;
+use crate::error::Error;
+use crate::http;
+use crate::process;
+use crate::timings;
+use serde::Deserialize;
+use std::io::ErrorKind;
+use tokio::process::Command;
+
+const NIX_PREFETCH_DOCKER: &str = "nix-prefetch-docker";
+
+#[derive(Deserialize)]
+struct PrefetchOutput {
+    /// newer versions of nix-prefetch-docker print an SRI hash instead
+    hash: Option<String>,
+    sha256: Option<String>,
+}
+
+fn parse_output(output: &str) -> Result<String, Error> {
+    let output: PrefetchOutput = serde_json::from_str(output)?;
+    return output
+        .hash
+        .or(output.sha256)
+        .ok_or_else(|| Error::PrefetchFailed {
+            tool: NIX_PREFETCH_DOCKER.to_string(),
+            reason: "it did not print a hash".to_string(),
+        });
+}
+
+/// Computes the hash `dockerTools.pullImage` expects for an image, which is
+/// the hash of the image tarball rather than of its manifest. The tarball
+/// embeds the final name and tag, so they must match the ones given to
+/// `dockerTools.pullImage`.
+pub async fn nix_hash(
+    image_name: &str,
+    digest: &str,
+    tag: &str,
+    platform: Option<&Platform>,
+) -> Result<String, Error> {
+    let _timer = timings::start("hashing");
+    let mut command = Command::new(NIX_PREFETCH_DOCKER);
+    command
+        .args(["--json", "--quiet"])
+        .args(["--image-name", image_name])
+        .args(["--image-digest", digest])
+        .args(["--final-image-name", image_name])
+        .args(["--final-image-tag", tag])
+        .envs(http::proxy_env());
+    if let Some(platform) = platform {
+        command
+            .args(["--os", &platform.os])
+            .args(["--arch", &platform.architecture]);
+    }
+    let output = process::output(&mut command)
+        .await
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => Error::PrefetchFailed {
+                tool: NIX_PREFETCH_DOCKER.to_string(),
+                reason: "it is needed to lock uptix.dockerPullImage, but it isn't on PATH"
+                    .to_string(),
+            },
+            _ => Error::IOError(e),
+        })?;
+    if !output.status.success() {
+        return Err(Error::PrefetchFailed {
+            tool: NIX_PREFETCH_DOCKER.to_string(),
+            reason: format!(
+                "{}@{}: {}",
+                image_name,
+                digest,
+                String::from_utf8_lossy(&output.stderr).trim(),
+            ),
+        });
+    }
+    return parse_output(&String::from_utf8_lossy(&output.stdout));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_output;
+
+    #[test]
+    fn it_parses_output() {
+        let output = r#"{
+          "imageName": "grafana/grafana",
+          "imageDigest": "sha256:foobar",
+          "sha256": "0ymqcy3fwzsxrfzaxq1nbvvmidaawxwbyih4b10i8hm3mz4ijrgy",
+          "finalImageName": "grafana/grafana",
+          "finalImageTag": "10.4.3"
+        }"#;
+        assert_eq!(
+            parse_output(output).unwrap(),
+            "0ymqcy3fwzsxrfzaxq1nbvvmidaawxwbyih4b10i8hm3mz4ijrgy",
+        );
+        let output = r#"{ "hash": "sha256-Wg4+K3ltd6bS3BL8ReuRSnxJ3PAs3YnSgAAHV0hAAgM=" }"#;
+        assert_eq!(
+            parse_output(output).unwrap(),
+            "sha256-Wg4+K3ltd6bS3BL8ReuRSnxJ3PAs3YnSgAAHV0hAAgM=",
+        );
+        assert!(parse_output("{}").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Path of Harbor's token endpoint, used to recognize Harbor registries
+const HARBOR_TOKEN_PATH: &str = "/service/token";
+const HARBOR_SERVICE: &str = "harbor-registry";
+const QUAY_HOST: &str = "quay.io";
+
+/// Quirks of a registry implementation which get in the way of the standard
+/// token authentication flow
+#[derive(Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    Generic,
+    /// quay.io (or Red Hat Quay), whose robot accounts need to be named on
+    /// token requests
+    Quay,
+    /// Harbor, whose token endpoint needs its service even when the
+    /// challenge doesn't name one
+    Harbor,
+}
+
+impl Profile {
+    /// Guesses the profile from the registry host, before talking to it
+    pub fn detect(registry: &str) -> Profile {
+        if registry == QUAY_HOST || registry.ends_with(".quay.io") {
+            return Profile::Quay;
+        }
+        return Profile::Generic;
+    }
+
+    /// Refines the profile once the registry's authentication challenge is
+    /// known
+    pub fn refine(self, challenge: &BTreeMap<String, String>) -> Profile {
+        let is_harbor = challenge
+            .get("realm")
+            .is_some_and(|realm| realm.ends_with(HARBOR_TOKEN_PATH));
+        return match self {
+            Profile::Generic if is_harbor => Profile::Harbor,
+            profile => profile,
+        };
+    }
+
+    /// Query parameters for a token request, on top of the scope
+    pub fn token_params(
+        &self,
+        registry: &str,
+        challenge: &BTreeMap<String, String>,
+        username: Option<&str>,
+    ) -> Vec<(&'static str, String)> {
+        let service = challenge.get("service").cloned().or_else(|| match self {
+            Profile::Generic => Some(registry.to_string()),
+            Profile::Quay => Some(QUAY_HOST.to_string()),
+            Profile::Harbor => Some(HARBOR_SERVICE.to_string()),
+        });
+        let mut params = vec![];
+        if let Some(service) = service {
+            params.push(("service", service));
+        }
+        if let (Profile::Quay, Some(username)) = (self, username) {
+            params.push(("account", username.to_string()));
+        }
+        return params;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profile;
+    use std::collections::BTreeMap;
+
+    fn challenge(params: &[(&str, &str)]) -> BTreeMap<String, String> {
+        return params
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+    }
+
+    #[test]
+    fn it_detects_profiles() {
+        assert_eq!(Profile::detect("quay.io"), Profile::Quay);
+        assert_eq!(Profile::detect("ghcr.io"), Profile::Generic);
+        let harbor = challenge(&[("realm", "https://harbor.example.com/service/token")]);
+        assert_eq!(Profile::Generic.refine(&harbor), Profile::Harbor);
+        assert_eq!(Profile::Quay.refine(&harbor), Profile::Quay);
+    }
+
+    #[test]
+    fn it_builds_token_params() {
+        let params = Profile::Quay.token_params(
+            "quay.io",
+            &challenge(&[("realm", "https://quay.io/v2/auth")]),
+            Some("org+robot"),
+        );
+        assert_eq!(
+            params,
+            vec![
+                ("service", "quay.io".to_string()),
+                ("account", "org+robot".to_string()),
+            ],
+        );
+        let params = Profile::Harbor.token_params(
+            "harbor.example.com",
+            &challenge(&[("service", "custom")]),
+            Some("robot$ci"),
+        );
+        assert_eq!(params, vec![("service", "custom".to_string())]);
+        let params = Profile::Harbor.token_params("harbor.example.com", &challenge(&[]), None);
+        assert_eq!(params, vec![("service", "harbor-registry".to_string())]);
+    }
+}
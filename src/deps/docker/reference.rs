@@ -0,0 +1,162 @@
+use regex::Regex;
+
+lazy_static! {
+    /// A registry host, with an optional port (i.e. `localhost:5000`)
+    static ref HOST_RE: Regex =
+        Regex::new(r"^[A-Za-z0-9](?:[A-Za-z0-9.-]*[A-Za-z0-9])?(?::[0-9]+)?$").unwrap();
+    /// A component of a repository path, which must be lowercase
+    static ref COMPONENT_RE: Regex =
+        Regex::new(r"^[a-z0-9]+(?:(?:[._]|__|-+)[a-z0-9]+)*$").unwrap();
+    /// A tag, or a pattern of tags such as `1.*`
+    static ref TAG_RE: Regex = Regex::new(r"^[A-Za-z0-9_*][A-Za-z0-9_.*-]{0,127}$").unwrap();
+}
+
+/// The parts of an image reference such as `ghcr.io/foo/bar:1.0`
+#[derive(PartialEq, Debug)]
+pub struct Reference {
+    pub registry: Option<String>,
+    pub repository: String,
+    pub tag: Option<String>,
+}
+
+/// Whether the first component of a reference names a registry rather than
+/// a namespace, which is how docker tells them apart
+fn is_host(component: &str) -> bool {
+    return component.contains('.') || component.contains(':') || component == "localhost";
+}
+
+/// Parses an image reference, following the grammar docker uses. Official
+/// images on Docker Hub (i.e. `postgres`) are left without their `library/`
+/// namespace, which is up to the caller to add.
+pub fn parse(text: &str) -> Result<Reference, String> {
+    if text.contains('@') {
+        return Err(format!(
+            "{} is pinned to a digest, which uptix locks on its own, so it should be dropped",
+            text
+        ));
+    }
+    let (registry, path) = match text.split_once('/') {
+        Some((first, rest)) if is_host(first) => {
+            if !HOST_RE.is_match(first) {
+                return Err(format!("{} is not a valid registry", first));
+            }
+            (Some(first.to_string()), rest)
+        }
+        _ => (None, text),
+    };
+    // a colon after the last slash starts the tag, others are in the host
+    let last_slash = path.rfind('/').map_or(0, |i| i + 1);
+    let (repository, tag) = match path[last_slash..].find(':') {
+        Some(i) => {
+            let tag = &path[last_slash + i + 1..];
+            if !TAG_RE.is_match(tag) {
+                return Err(format!("{} is not a valid tag", tag));
+            }
+            (&path[..last_slash + i], Some(tag.to_string()))
+        }
+        None => (path, None),
+    };
+    if let Some(component) = repository.split('/').find(|c| !COMPONENT_RE.is_match(c)) {
+        return Err(match component.chars().any(|c| c.is_ascii_uppercase()) {
+            true => format!("{} has uppercase letters, which images can't", repository),
+            false => format!("{} is not a valid image name", repository),
+        });
+    }
+    return Ok(Reference {
+        registry,
+        repository: repository.to_string(),
+        tag,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Reference};
+    use proptest::prelude::*;
+
+    #[test]
+    fn it_parses_references() {
+        assert_eq!(
+            parse("localhost:5000/img").unwrap(),
+            Reference {
+                registry: Some("localhost:5000".to_string()),
+                repository: "img".to_string(),
+                tag: None,
+            }
+        );
+        assert_eq!(
+            parse("ghcr.io/a/b/c:1.*").unwrap(),
+            Reference {
+                registry: Some("ghcr.io".to_string()),
+                repository: "a/b/c".to_string(),
+                tag: Some("1.*".to_string()),
+            }
+        );
+        assert_eq!(parse("foo/bar").unwrap().registry, None);
+        assert_eq!(
+            parse("postgres:16").unwrap(),
+            Reference {
+                registry: None,
+                repository: "postgres".to_string(),
+                tag: Some("16".to_string()),
+            }
+        );
+        assert!(parse("Foo/bar").is_err());
+        assert!(parse("foo/bar:").is_err());
+        assert!(parse("foo//bar").is_err());
+    }
+
+    fn component() -> impl Strategy<Value = String> {
+        return "[a-z0-9]{1,8}([._-][a-z0-9]{1,8})?";
+    }
+
+    /// Like `component`, but without dots, which would make it a host
+    fn namespace() -> impl Strategy<Value = String> {
+        return "[a-z0-9]{1,8}([_-][a-z0-9]{1,8})?";
+    }
+
+    fn host() -> impl Strategy<Value = String> {
+        return prop_oneof![Just("localhost".to_string()), "[a-z0-9]{1,8}\\.[a-z]{2,3}",];
+    }
+
+    proptest! {
+        #[test]
+        fn it_parses_valid_references(
+            host in proptest::option::of(host()),
+            port in proptest::option::of(1u16..),
+            namespace in namespace(),
+            path in proptest::collection::vec(component(), 1..5),
+            tag in proptest::option::of("[A-Za-z0-9_][A-Za-z0-9_.-]{0,20}"),
+        ) {
+            let registry = host.map(|host| match port {
+                Some(port) => format!("{}:{}", host, port),
+                None => host,
+            });
+            let repository = format!("{}/{}", namespace, path.join("/"));
+            let mut text = repository.clone();
+            if let Some(registry) = &registry {
+                text = format!("{}/{}", registry, text);
+            }
+            if let Some(tag) = &tag {
+                text = format!("{}:{}", text, tag);
+            }
+            prop_assert_eq!(parse(&text), Ok(Reference { registry, repository, tag }));
+        }
+
+        #[test]
+        fn it_rejects_uppercase_names(path in "[a-z]{1,8}/[a-z]{0,4}[A-Z][a-z]{0,4}") {
+            prop_assert!(parse(&path).is_err());
+        }
+
+        #[test]
+        fn it_rejects_digests(name in component(), digest in "[0-9a-f]{64}") {
+            let text = format!("library/{}@sha256:{}", name, digest);
+            prop_assert!(parse(&text).is_err());
+        }
+
+        #[test]
+        fn it_never_panics(text in "\\PC*") {
+            let _ = parse(&text);
+        }
+    }
+}
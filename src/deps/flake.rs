@@ -0,0 +1,272 @@
+use crate::config::GitHubConfig;
+use crate::deps::github::{self, GitHubHost};
+use crate::deps::{Dependency, DependencyKind, Lockable};
+use crate::error::Error;
+use crate::lock::{GitHubLock, LockData};
+use crate::util;
+use crate::util::ParsingContext;
+use async_trait::async_trait;
+use regex::Regex;
+use rnix::ast::{self, Expr, HasEntry};
+use rnix::SyntaxNode;
+use rowan::ast::AstNode;
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, PartialEq, Clone, Debug)]
+pub struct FlakeInput {
+    url: String,
+    owner: String,
+    repo: String,
+    reference: Option<String>,
+    github: GitHubHost,
+    override_scheme: Option<String>,
+    override_domain: Option<String>,
+    override_nix_sha256: Option<String>,
+}
+
+lazy_static! {
+    static ref GITHUB_URL_RE: Regex =
+        Regex::new(r#"^github:([^/?]+)/([^/?]+)(?:/([^?]+))?(?:\?(.*))?$"#).unwrap();
+}
+
+impl FlakeInput {
+    fn from_url(url: &str) -> Option<FlakeInput> {
+        // only github: flake references are supported for now
+        let caps = GITHUB_URL_RE.captures(url)?;
+        let query_ref = caps.get(4).and_then(|q| {
+            q.as_str()
+                .split('&')
+                .find_map(|p| p.strip_prefix("ref=").or_else(|| p.strip_prefix("rev=")))
+        });
+        return Some(FlakeInput {
+            url: url.to_string(),
+            owner: caps[1].to_string(),
+            repo: caps[2].to_string(),
+            reference: caps
+                .get(3)
+                .map(|m| m.as_str())
+                .or(query_ref)
+                .map(|r| r.to_string()),
+            ..Default::default()
+        });
+    }
+
+    pub fn configure(&mut self, config: &GitHubConfig) {
+        self.github = GitHubHost::new(config, github::DEFAULT_HOST);
+    }
+
+    pub fn identity(&self) -> String {
+        return format!("github:{}/{}", self.owner, self.repo);
+    }
+}
+
+fn collect_inputs(
+    attr_set: &ast::AttrSet,
+    prefix: &[String],
+    inputs: &mut Vec<(FlakeInput, SyntaxNode)>,
+) {
+    for entry in attr_set.attrpath_values() {
+        let (key, value) = match (entry.attrpath(), entry.value()) {
+            (Some(key), Some(value)) => (key, value),
+            _ => continue,
+        };
+        let mut path = prefix.to_vec();
+        path.extend(key.attrs().map(|attr| attr.to_string()));
+
+        if path.len() == 3 && path[0] == "inputs" && path[2] == "url" {
+            let url = util::string_value(value.syntax());
+            if let Some(input) = url.and_then(|u| FlakeInput::from_url(&u)) {
+                inputs.push((input, value.syntax().clone()));
+            }
+        } else if path.len() < 3 && path[0] == "inputs" {
+            if let Expr::AttrSet(value) = value {
+                collect_inputs(&value, &path, inputs);
+            }
+        }
+    }
+}
+
+pub fn collect_flake_inputs(context: &ParsingContext, root: &SyntaxNode) -> Vec<Dependency> {
+    let mut inputs = vec![];
+    if let Some(attr_set) = root.children().find_map(ast::AttrSet::cast) {
+        collect_inputs(&attr_set, &[], &mut inputs);
+    }
+    return inputs
+        .into_iter()
+        .map(|(i, node)| Dependency::new_with_kind(context, &node, DependencyKind::FlakeInput(i)))
+        .collect();
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubCommitInfo {
+    sha: String,
+}
+
+async fn fetch_github_commit_info(dependency: &FlakeInput) -> Result<GitHubCommitInfo, Error> {
+    let reference = dependency.reference.as_deref().unwrap_or("HEAD");
+    let url = github::api_url(
+        &dependency.github,
+        &dependency.override_scheme,
+        &dependency.override_domain,
+        &dependency.owner,
+        &dependency.repo,
+        &format!("commits/{}", reference),
+    )?;
+    return github::github_api_request(&dependency.github, url).await;
+}
+
+#[async_trait]
+impl Lockable for FlakeInput {
+    fn key(&self) -> String {
+        return format!("$FLAKE_INPUT$:{}", self.url);
+    }
+
+    async fn lock(&self) -> Result<LockData, Error> {
+        let rev = fetch_github_commit_info(self).await?.sha;
+        let sha256 = match &self.override_nix_sha256 {
+            Some(s) => s.to_string(),
+            None => {
+                github::compute_nix_sha256(
+                    &self.github,
+                    &self.owner,
+                    &self.repo,
+                    &rev,
+                    None,
+                    None,
+                    None,
+                )
+                .await?
+            }
+        };
+        return Ok(LockData::GitHub(GitHubLock {
+            owner: self.owner.clone(),
+            repo: self.repo.clone(),
+            rev,
+            sha256,
+            fetchSubmodules: false,
+            deepClone: false,
+            leaveDotGit: false,
+            githubBase: None,
+            submodules: None,
+            metadata: None,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect_flake_inputs, FlakeInput};
+    use crate::deps::github::GitHubHost;
+    use crate::deps::Lockable;
+    use crate::error::Error;
+    use crate::util::ParsingContext;
+    use serde_json::json;
+
+    #[test]
+    fn it_parses() {
+        let ast = rnix::Root::parse(
+            r#"{
+                inputs.nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+                inputs.utils = {
+                    url = "github:numtide/flake-utils";
+                };
+                inputs = {
+                    uptix.url = "github:luizribeiro/uptix?ref=main";
+                    local.url = "path:./local";
+                };
+                outputs = { self, ... }: {
+                    inputs.foo.url = "github:not/an-input";
+                };
+            }"#,
+        );
+        let context = ParsingContext::new("./flake.nix", &ast.syntax().to_string());
+        let inputs: Vec<_> = collect_flake_inputs(&context, &ast.syntax())
+            .iter()
+            .map(|d| d.kind().as_flake_input().unwrap().clone())
+            .collect();
+        assert_eq!(
+            inputs,
+            vec![
+                FlakeInput {
+                    url: "github:NixOS/nixpkgs/nixos-unstable".to_string(),
+                    owner: "NixOS".to_string(),
+                    repo: "nixpkgs".to_string(),
+                    reference: Some("nixos-unstable".to_string()),
+                    ..Default::default()
+                },
+                FlakeInput {
+                    url: "github:numtide/flake-utils".to_string(),
+                    owner: "numtide".to_string(),
+                    repo: "flake-utils".to_string(),
+                    ..Default::default()
+                },
+                FlakeInput {
+                    url: "github:luizribeiro/uptix?ref=main".to_string(),
+                    owner: "luizribeiro".to_string(),
+                    repo: "uptix".to_string(),
+                    reference: Some("main".to_string()),
+                    ..Default::default()
+                },
+            ],
+        );
+    }
+
+    #[tokio::test]
+    async fn it_locks() {
+        let address = mockito::server_address().to_string();
+        let _commit_mock = mockito::mock("GET", "/repos/numtide/flake-utils/commits/HEAD")
+            .match_header(
+                reqwest::header::USER_AGENT.as_str(),
+                mockito::Matcher::Regex(r"^uptix/[0-9.]+$".to_string()),
+            )
+            .match_header("authorization", "Bearer hunter2")
+            .with_status(200)
+            .with_body(r#"{ "sha": "5aed5285a952e0b949eb3ba02c12fa4fcfef535f" }"#)
+            .create();
+
+        let dependency = FlakeInput {
+            url: "github:numtide/flake-utils".to_string(),
+            owner: "numtide".to_string(),
+            repo: "flake-utils".to_string(),
+            github: GitHubHost {
+                host: "github.com".to_string(),
+                token: Some("hunter2".to_string()),
+                ..Default::default()
+            },
+            override_scheme: Some("http".to_string()),
+            override_domain: Some(address),
+            override_nix_sha256: Some(
+                "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j".to_string(),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(dependency.key(), "$FLAKE_INPUT$:github:numtide/flake-utils");
+        let lock = dependency.lock().await.unwrap();
+        let lock_value = serde_json::to_value(lock).unwrap();
+
+        assert_eq!(
+            lock_value,
+            json!({
+                "owner": "numtide",
+                "repo": "flake-utils",
+                "rev": "5aed5285a952e0b949eb3ba02c12fa4fcfef535f",
+                "sha256": "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j",
+                "fetchSubmodules": false,
+                "deepClone": false,
+                "leaveDotGit": false,
+            }),
+        );
+
+        let _missing_mock = mockito::mock("GET", "/repos/numtide/missing/commits/HEAD")
+            .with_status(404)
+            .with_body(r#"{ "message": "Not Found" }"#)
+            .create();
+        let missing = FlakeInput {
+            repo: "missing".to_string(),
+            ..dependency
+        };
+        assert!(matches!(missing.lock().await, Err(Error::NotFound { .. })));
+
+        mockito::reset();
+    }
+}
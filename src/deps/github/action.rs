@@ -0,0 +1,105 @@
+use crate::config::GitHubConfig;
+use crate::deps::github::{self, GitHubHost};
+use crate::deps::Lockable;
+use crate::error::Error;
+use crate::lock::{GitHubActionLock, LockData};
+use async_trait::async_trait;
+use serde::Deserialize;
+
+/// An action used by a GitHub Actions workflow (i.e. `actions/checkout@v4`),
+/// locked to the commit its ref points to
+#[derive(Default, PartialEq, Clone, Debug)]
+pub struct GitHubAction {
+    owner: String,
+    repo: String,
+    reference: String,
+    github: GitHubHost,
+    override_scheme: Option<String>,
+    override_domain: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitHubCommitInfo {
+    sha: String,
+}
+
+impl GitHubAction {
+    pub fn new(owner: &str, repo: &str, reference: &str) -> GitHubAction {
+        return GitHubAction {
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            reference: reference.to_string(),
+            ..Default::default()
+        };
+    }
+
+    pub fn configure(&mut self, config: &GitHubConfig) {
+        self.github = GitHubHost::new(config, github::DEFAULT_HOST);
+    }
+
+    pub fn identity(&self) -> String {
+        return format!("github:{}/{}", self.owner, self.repo);
+    }
+}
+
+#[async_trait]
+impl Lockable for GitHubAction {
+    fn key(&self) -> String {
+        return format!(
+            "$GITHUB_ACTION$:{}/{}@{}",
+            self.owner, self.repo, self.reference
+        );
+    }
+
+    async fn lock(&self) -> Result<LockData, Error> {
+        // tags and branches alike resolve to the commit they point to
+        let url = github::api_url(
+            &self.github,
+            &self.override_scheme,
+            &self.override_domain,
+            &self.owner,
+            &self.repo,
+            &format!("commits/{}", self.reference),
+        )?;
+        let commit: GitHubCommitInfo = github::github_api_request(&self.github, url).await?;
+        return Ok(LockData::Action(GitHubActionLock {
+            owner: self.owner.clone(),
+            repo: self.repo.clone(),
+            reference: self.reference.clone(),
+            rev: commit.sha,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GitHubAction;
+    use crate::deps::Lockable;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn it_locks() {
+        let _commit_mock = mockito::mock("GET", "/repos/actions/checkout/commits/v4")
+            .with_status(200)
+            .with_body(r#"{ "sha": "b4ffde65f46336ab88eb53be808477a3936bae11" }"#)
+            .create();
+
+        let dependency = GitHubAction {
+            override_scheme: Some("http".to_string()),
+            override_domain: Some(mockito::server_address().to_string()),
+            ..GitHubAction::new("actions", "checkout", "v4")
+        };
+        assert_eq!(dependency.key(), "$GITHUB_ACTION$:actions/checkout@v4");
+        assert_eq!(
+            serde_json::to_value(dependency.lock().await.unwrap()).unwrap(),
+            json!({
+                "owner": "actions",
+                "repo": "checkout",
+                "ref": "v4",
+                "rev": "b4ffde65f46336ab88eb53be808477a3936bae11",
+            }),
+        );
+
+        mockito::reset();
+    }
+}
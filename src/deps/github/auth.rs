@@ -0,0 +1,302 @@
+use crate::config::GitHubAppConfig;
+use crate::error::Error;
+use crate::http;
+use crate::secrets;
+use crate::util;
+use base64::Engine;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    /// Installation tokens minted during this run, keyed by API and app.
+    /// They last for an hour, which is longer than any run.
+    static ref INSTALLATION_TOKENS: Mutex<BTreeMap<String, String>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Where `gh auth login` keeps its tokens
+fn gh_hosts_path() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("GH_CONFIG_DIR") {
+        return Some(PathBuf::from(dir).join("hosts.yml"));
+    }
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".config")))?;
+    return Some(config_home.join("gh/hosts.yml"));
+}
+
+/// Finds the `oauth_token` of a host on gh's hosts.yml, which looks like:
+///
+/// ```yaml
+/// github.com:
+///     user: luizribeiro
+///     oauth_token: gho_...
+/// ```
+///
+/// Only this flat layout is understood, which is all gh writes.
+fn parse_gh_hosts(content: &str, host: &str) -> Option<String> {
+    let mut in_host = false;
+    let mut indent = None;
+    for line in content.lines() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let depth = line.len() - line.trim_start().len();
+        if depth == 0 {
+            in_host = line.trim_end().trim_end_matches(':').trim_matches('"') == host;
+            indent = None;
+            continue;
+        }
+        if !in_host {
+            continue;
+        }
+        // nested entries (i.e. under `users:`) belong to other accounts
+        if *indent.get_or_insert(depth) != depth {
+            continue;
+        }
+        if let Some(token) = line.trim().strip_prefix("oauth_token:") {
+            let token = token.trim().trim_matches('"').trim_matches('\'');
+            return Some(token.to_string()).filter(|t| !t.is_empty());
+        }
+    }
+    return None;
+}
+
+/// The token `gh auth login` stored for the host, unless gh keeps it on the
+/// system keyring instead
+pub fn gh_token(host: &str) -> Option<String> {
+    let content = fs::read_to_string(gh_hosts_path()?).ok()?;
+    return parse_gh_hosts(&content, host);
+}
+
+fn app_error(message: String) -> Error {
+    return Error::GitHubError(format!("GitHub App authentication failed: {}", message));
+}
+
+fn base64url(data: &[u8]) -> String {
+    return base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(data);
+}
+
+/// Builds the JWT a GitHub App authenticates with, signed with RS256. It is
+/// backdated a minute to allow for clock drift, as GitHub recommends.
+fn app_jwt(app_id: u64, private_key: &str, now: u64) -> Result<String, Error> {
+    let header = base64url(
+        json!({ "alg": "RS256", "typ": "JWT" })
+            .to_string()
+            .as_bytes(),
+    );
+    let claims = json!({ "iat": now - 60, "exp": now + 540, "iss": app_id.to_string() });
+    let signing_input = format!("{}.{}", header, base64url(claims.to_string().as_bytes()));
+    let sign = || -> Result<Vec<u8>, openssl::error::ErrorStack> {
+        let key = PKey::private_key_from_pem(private_key.as_bytes())?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+        signer.update(signing_input.as_bytes())?;
+        return signer.sign_to_vec();
+    };
+    let signature = sign().map_err(|e| app_error(format!("invalid private key: {}", e)))?;
+    return Ok(format!("{}.{}", signing_input, base64url(&signature)));
+}
+
+fn read_private_key(app: &GitHubAppConfig) -> Result<String, Error> {
+    if let Some(name) = &app.private_key_env {
+        return secrets::var(name).ok_or_else(|| app_error(format!("{} is not set", name)));
+    }
+    return match &app.private_key_path {
+        Some(path) => Ok(fs::read_to_string(path)?),
+        None => Err(app_error(
+            "either private_key_path or private_key_env must be set".to_string(),
+        )),
+    };
+}
+
+/// What responses fetched as an app are cached under: its key, rather than
+/// the installation tokens it mints, which only last for an hour
+pub fn app_credential(app: &GitHubAppConfig) -> Result<String, Error> {
+    return Ok(format!(
+        "{}:{:?}:{}",
+        app.id,
+        app.installation_id,
+        read_private_key(app)?
+    ));
+}
+
+#[derive(Deserialize)]
+struct Installation {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    token: String,
+}
+
+async fn app_request<T: serde::de::DeserializeOwned>(
+    method: reqwest::Method,
+    url: String,
+    jwt: &str,
+) -> Result<T, Error> {
+    let request = http::client()?
+        .request(method, &url)
+        .header(reqwest::header::USER_AGENT, util::user_agent())
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+        .bearer_auth(jwt);
+    let response = http::send(request, http::transient).await?;
+    if !response.status().is_success() {
+        return Err(app_error(format!(
+            "request to {} failed with {}",
+            url,
+            response.status()
+        )));
+    }
+    return Ok(response.json().await?);
+}
+
+/// Mints (or reuses) an installation token for a GitHub App
+pub async fn installation_token(api_url: &str, app: &GitHubAppConfig) -> Result<String, Error> {
+    let key = format!("{}#{}", api_url, app.id);
+    if let Some(token) = INSTALLATION_TOKENS.lock().unwrap().get(&key) {
+        return Ok(token.clone());
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let jwt = app_jwt(app.id, &read_private_key(app)?, now)?;
+    let installation_id = match app.installation_id {
+        Some(id) => id,
+        None => {
+            let url = format!("{}/app/installations", api_url);
+            let installations: Vec<Installation> =
+                app_request(reqwest::Method::GET, url, &jwt).await?;
+            match installations.as_slice() {
+                [installation] => installation.id,
+                _ => {
+                    return Err(app_error(format!(
+                        "the app has {} installations, set installation_id to pick one",
+                        installations.len()
+                    )))
+                }
+            }
+        }
+    };
+    let url = format!(
+        "{}/app/installations/{}/access_tokens",
+        api_url, installation_id
+    );
+    let token: AccessToken = app_request(reqwest::Method::POST, url, &jwt).await?;
+    INSTALLATION_TOKENS
+        .lock()
+        .unwrap()
+        .insert(key, token.token.clone());
+    return Ok(token.token);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{app_jwt, installation_token, parse_gh_hosts};
+    use crate::config::GitHubAppConfig;
+    use base64::Engine;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::sign::Verifier;
+
+    fn private_key() -> PKey<openssl::pkey::Private> {
+        return PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn it_signs_app_jwts() {
+        let key = private_key();
+        let pem = String::from_utf8(key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        let jwt = app_jwt(12345, &pem, 1700000000).unwrap();
+        let parts: Vec<_> = jwt.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        let decode = |part: &str| {
+            base64::engine::general_purpose::URL_SAFE_NO_PAD
+                .decode(part)
+                .unwrap()
+        };
+        let claims: serde_json::Value = serde_json::from_slice(&decode(parts[1])).unwrap();
+        assert_eq!(
+            claims,
+            serde_json::json!({ "iat": 1699999940u64, "exp": 1700000540u64, "iss": "12345" }),
+        );
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &key).unwrap();
+        verifier
+            .update(format!("{}.{}", parts[0], parts[1]).as_bytes())
+            .unwrap();
+        assert!(verifier.verify(&decode(parts[2])).unwrap());
+        assert!(app_jwt(12345, "not a key", 1700000000).is_err());
+    }
+
+    #[tokio::test]
+    async fn it_mints_installation_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.pem");
+        std::fs::write(&path, private_key().private_key_to_pem_pkcs8().unwrap()).unwrap();
+        let _installations_mock = mockito::mock("GET", "/app/installations")
+            .with_status(200)
+            .with_body(r#"[{ "id": 42 }]"#)
+            .create();
+        let token_mock = mockito::mock("POST", "/app/installations/42/access_tokens")
+            .match_header(
+                "authorization",
+                mockito::Matcher::Regex("^Bearer ey".to_string()),
+            )
+            .with_status(201)
+            .with_body(r#"{ "token": "ghs_installation", "expires_at": "2030-01-01T00:00:00Z" }"#)
+            .expect(1)
+            .create();
+        let app = GitHubAppConfig {
+            id: 12345,
+            private_key_path: Some(path.to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            assert_eq!(
+                installation_token(&mockito::server_url(), &app)
+                    .await
+                    .unwrap(),
+                "ghs_installation"
+            );
+        }
+        token_mock.assert();
+        mockito::reset();
+    }
+
+    #[test]
+    fn it_parses_gh_hosts() {
+        let content = r#"
+github.com:
+    users:
+        someone:
+            oauth_token: gho_other
+    user: luizribeiro
+    oauth_token: gho_github
+    git_protocol: https
+ghe.example.com:
+    oauth_token: "ghp_enterprise"
+keyring.example.com:
+    user: luizribeiro
+"#;
+        assert_eq!(
+            parse_gh_hosts(content, "github.com"),
+            Some("gho_github".to_string())
+        );
+        assert_eq!(
+            parse_gh_hosts(content, "ghe.example.com"),
+            Some("ghp_enterprise".to_string())
+        );
+        assert_eq!(parse_gh_hosts(content, "keyring.example.com"), None);
+        assert_eq!(parse_gh_hosts(content, "gitlab.com"), None);
+    }
+}
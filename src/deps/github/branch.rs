@@ -1,22 +1,49 @@
+use crate::config::GitHubConfig;
 use crate::deps::assert_kind;
-use crate::deps::github;
-use crate::deps::Lockable;
+use crate::deps::github::{self, GitHubHost};
+use crate::deps::{DependencyMetadata, Lockable};
 use crate::error::Error;
+use crate::lock::{GitHubLock, LockData, SubmoduleLock};
 use crate::util;
 use crate::util::ParsingContext;
 use async_trait::async_trait;
 use rnix::{SyntaxKind, SyntaxNode};
 use serde::{Deserialize, Serialize};
 
+/// Stands for the default branch of a repository on keys, for dependencies
+/// which don't name a branch
+pub const DEFAULT_BRANCH: &str = "HEAD";
+/// How many commits back from the tip of a branch are checked, when
+/// requiring checks to pass
+const MAX_CHECKED_COMMITS: usize = 10;
+
 #[derive(Default, Serialize, Deserialize, PartialEq, Clone, Debug)]
 #[allow(non_snake_case)]
 pub struct GitHubBranch {
     owner: String,
     repo: String,
-    branch: String,
+    /// the repository's default branch when left out
+    branch: Option<String>,
     fetchSubmodules: Option<bool>,
     deepClone: Option<bool>,
     leaveDotGit: Option<bool>,
+    /// fail unless the commit is signed, as verified by GitHub
+    verifySignature: Option<bool>,
+    /// record the revision of each submodule on the lock, along with
+    /// `fetchSubmodules`
+    lockSubmodules: Option<bool>,
+    /// only advance to commits whose statuses and check runs passed
+    requireChecks: Option<bool>,
+    /// private key (i.e. a deploy key) to resolve and clone the repository
+    /// with over SSH, instead of going through the GitHub API
+    sshKey: Option<String>,
+    /// host of a GitHub Enterprise instance, instead of github.com
+    host: Option<String>,
+    #[serde(skip)]
+    github: GitHubHost,
+    /// `require_checks` from uptix.toml, for when `requireChecks` is unset
+    #[serde(skip)]
+    checks_by_default: bool,
     override_scheme: Option<String>,
     override_domain: Option<String>,
     override_nix_sha256: Option<String>,
@@ -38,71 +65,350 @@ impl GitHubBranch {
   }"#,
         )?)
     }
+
+    /// Follows the default branch of a repository on github.com, for
+    /// locking it without a nix file. `configure` applies uptix.toml.
+    pub fn repository(owner: &str, repo: &str) -> GitHubBranch {
+        let mut dependency = GitHubBranch {
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            ..Default::default()
+        };
+        dependency.configure(&GitHubConfig::default());
+        return dependency;
+    }
+
+    /// Follows the given branch instead of the default one
+    pub fn with_branch(mut self, branch: &str) -> GitHubBranch {
+        self.branch = Some(branch.to_string());
+        return self;
+    }
+
+    /// Fetches submodules along with the repository
+    pub fn with_submodules(mut self) -> GitHubBranch {
+        self.fetchSubmodules = Some(true);
+        return self;
+    }
+
+    /// Resolves and clones the repository over SSH with the given key
+    pub fn with_ssh_key(mut self, ssh_key: &str) -> GitHubBranch {
+        self.sshKey = Some(ssh_key.to_string());
+        return self;
+    }
+
+    pub fn configure(&mut self, config: &GitHubConfig) {
+        self.github = GitHubHost::new(config, self.host());
+        self.checks_by_default = config.require_checks;
+    }
+
+    fn host(&self) -> &str {
+        return self.host.as_deref().unwrap_or(github::DEFAULT_HOST);
+    }
+
+    async fn lock_submodules(&self, rev: &str) -> Result<Option<Vec<SubmoduleLock>>, Error> {
+        if !self.lockSubmodules.unwrap_or(false) {
+            return Ok(None);
+        }
+        let submodules = github::fetch_submodules(&self.github, |p| api_url(self, p), rev).await?;
+        return Ok(Some(submodules));
+    }
+
+    /// Whether the repository is reached over SSH rather than the GitHub API
+    pub fn uses_ssh(&self) -> bool {
+        return self.sshKey.is_some();
+    }
+
+    /// Resolves the branch with `git ls-remote`, for repositories which are
+    /// only reachable over SSH
+    async fn ssh_rev(&self, ssh_key: &str) -> Result<String, Error> {
+        let reference = match &self.branch {
+            Some(branch) => format!("refs/heads/{}", branch),
+            None => DEFAULT_BRANCH.to_string(),
+        };
+        let url = github::ssh_url(self.host(), &self.owner, &self.repo);
+        let rev = github::shallow::ls_remote(&url, &reference, Some(ssh_key)).await?;
+        return rev.ok_or_else(|| {
+            Error::GitHubError(format!("{} has no {}", self.identity(), reference))
+        });
+    }
+
+    async fn lock_over_ssh(&self, ssh_key: &str) -> Result<GitHubLock, Error> {
+        let needs_api = self.verifySignature.unwrap_or(false)
+            || self.requireChecks.unwrap_or(false)
+            || self.lockSubmodules.unwrap_or(false);
+        if needs_api {
+            return Err(Error::GitHubError(format!(
+                "{} can't use verifySignature, requireChecks or lockSubmodules along with \
+                 sshKey, since they need the GitHub API",
+                self.identity()
+            )));
+        }
+        let rev = self.ssh_rev(ssh_key).await?;
+        let sha256 = match &self.override_nix_sha256 {
+            Some(s) => s.to_string(),
+            None => {
+                github::compute_ssh_sha256(
+                    &self.github,
+                    &self.owner,
+                    &self.repo,
+                    &rev,
+                    github::FetchOptions {
+                        fetch_submodules: self.fetchSubmodules,
+                        deep_clone: self.deepClone,
+                        leave_dot_git: self.leaveDotGit,
+                    },
+                    ssh_key,
+                )
+                .await?
+            }
+        };
+        return Ok(GitHubLock {
+            owner: self.owner.clone(),
+            repo: self.repo.clone(),
+            rev,
+            sha256,
+            fetchSubmodules: self.fetchSubmodules.unwrap_or(false),
+            deepClone: self.deepClone.unwrap_or(false),
+            leaveDotGit: self.leaveDotGit.unwrap_or(false),
+            githubBase: self.host.clone(),
+            submodules: None,
+            metadata: None,
+        });
+    }
+
+    /// Whether `new_rev` descends from `old_rev`, which isn't the case when
+    /// the branch was force-pushed (or `old_rev` is gone altogether)
+    pub async fn is_fast_forward(&self, old_rev: &str, new_rev: &str) -> Result<bool, Error> {
+        let url = api_url(self, &format!("compare/{}...{}", old_rev, new_rev))?;
+        let comparison: Option<GitHubComparison> =
+            github::github_api_request_if_found(&self.github, url).await?;
+        return Ok(matches!(
+            comparison.as_ref().map(|c| c.status.as_str()),
+            Some("ahead" | "identical")
+        ));
+    }
+
+    pub fn identity(&self) -> String {
+        return format!(
+            "github:{}{}/{}",
+            github::host_prefix(&self.host),
+            self.owner,
+            self.repo
+        );
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubCommitAuthor {
+    name: String,
+    date: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubCommitDetails {
+    author: GitHubCommitAuthor,
+    message: String,
+    verification: Option<github::Verification>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct GitHubCommitInfo {
     sha: String,
+    commit: Option<GitHubCommitDetails>,
+}
+
+impl GitHubCommitInfo {
+    /// The date, author and subject of the commit
+    fn metadata(&self) -> DependencyMetadata {
+        let details = self.commit.as_ref();
+        return DependencyMetadata {
+            resolved_version: Some(self.sha.chars().take(12).collect()),
+            description: details.map(|c| {
+                let subject = c.message.lines().next().unwrap_or_default();
+                format!("{} ({})", subject, c.author.name)
+            }),
+            timestamp: details.map(|c| c.author.date.clone()),
+            ..Default::default()
+        };
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct GitHubBranchInfo {
+    name: Option<String>,
     commit: GitHubCommitInfo,
+    /// set when the tip didn't pass its checks, and an older commit is
+    /// used instead
+    #[serde(skip)]
+    behind_tip: bool,
 }
 
-async fn fetch_github_branch_info(dependency: &GitHubBranch) -> Result<GitHubBranchInfo, Error> {
-    let client = reqwest::Client::new();
-    let url_as_str = format!(
-        "{}://{}/repos/{}/{}/branches/{}",
-        dependency
-            .override_scheme
-            .as_ref()
-            .unwrap_or(&"https".to_string()),
-        dependency
-            .override_domain
-            .as_ref()
-            .unwrap_or(&"api.github.com".to_string()),
-        dependency.owner,
-        dependency.repo,
-        dependency.branch,
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubRepoInfo {
+    default_branch: String,
+}
+
+#[derive(Deserialize)]
+struct GitHubComparison {
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct CombinedStatus {
+    state: String,
+    total_count: usize,
+}
+
+#[derive(Deserialize)]
+struct CheckRun {
+    status: String,
+    conclusion: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CheckRuns {
+    check_runs: Vec<CheckRun>,
+}
+
+/// Whether all of the statuses and check runs of a commit passed, with at
+/// least one of them there
+async fn passes_checks(dependency: &GitHubBranch, sha: &str) -> Result<bool, Error> {
+    let url = api_url(dependency, &format!("commits/{}/status", sha))?;
+    let status: CombinedStatus = github::github_api_request(&dependency.github, url).await?;
+    if status.total_count > 0 && status.state != "success" {
+        return Ok(false);
+    }
+    let url = api_url(dependency, &format!("commits/{}/check-runs", sha))?;
+    let runs: CheckRuns = github::github_api_request(&dependency.github, url).await?;
+    let runs_pass = runs.check_runs.iter().all(|run| {
+        run.status == "completed"
+            && matches!(
+                run.conclusion.as_deref(),
+                Some("success" | "neutral" | "skipped")
+            )
+    });
+    return Ok(runs_pass && status.total_count + runs.check_runs.len() > 0);
+}
+
+fn api_url(dependency: &GitHubBranch, path: &str) -> Result<reqwest::Url, Error> {
+    return github::api_url(
+        &dependency.github,
+        &dependency.override_scheme,
+        &dependency.override_domain,
+        &dependency.owner,
+        &dependency.repo,
+        path,
     );
-    let url = reqwest::Url::parse(&url_as_str).unwrap();
-    let response = client
-        .request(reqwest::Method::GET, url)
-        .header(reqwest::header::USER_AGENT, util::user_agent())
-        .send()
-        .await?
-        .text()
-        .await?;
-    return Ok(serde_json::from_str(&response)?);
+}
+
+async fn fetch_github_branch_info(dependency: &GitHubBranch) -> Result<GitHubBranchInfo, Error> {
+    let branch = match &dependency.branch {
+        Some(branch) => branch.clone(),
+        // looked up on every run, so that renames (i.e. master to main)
+        // are followed
+        None => {
+            let url = api_url(dependency, "")?;
+            let repo: GitHubRepoInfo = github::github_api_request(&dependency.github, url).await?;
+            repo.default_branch
+        }
+    };
+    let url = api_url(dependency, &format!("branches/{}", branch))?;
+    let mut info: GitHubBranchInfo = github::github_api_request(&dependency.github, url).await?;
+    if !dependency
+        .requireChecks
+        .unwrap_or(dependency.checks_by_default)
+        || passes_checks(dependency, &info.commit.sha).await?
+    {
+        return Ok(info);
+    }
+    // walks back from the tip, which may still be waiting on its checks
+    let url = api_url(
+        dependency,
+        &format!(
+            "commits?sha={}&per_page={}",
+            info.commit.sha, MAX_CHECKED_COMMITS
+        ),
+    )?;
+    let commits: Vec<GitHubCommitInfo> =
+        github::github_api_request(&dependency.github, url).await?;
+    for commit in commits.into_iter().skip(1) {
+        if passes_checks(dependency, &commit.sha).await? {
+            info.commit = commit;
+            info.behind_tip = true;
+            return Ok(info);
+        }
+    }
+    return Err(Error::FailingChecks {
+        name: dependency.identity(),
+        branch: info.name.unwrap_or(branch),
+        count: MAX_CHECKED_COMMITS,
+    });
 }
 
 #[async_trait]
 impl Lockable for GitHubBranch {
     fn key(&self) -> String {
         return format!(
-            "$GITHUB_BRANCH$:{}/{}:{}${}",
+            "$GITHUB_BRANCH$:{}{}/{}:{}${}",
+            github::host_prefix(&self.host),
             self.owner,
             self.repo,
-            self.branch,
+            self.branch.as_deref().unwrap_or(DEFAULT_BRANCH),
             github::flags(self.fetchSubmodules, self.deepClone, self.leaveDotGit),
         );
     }
 
-    async fn lock(&self) -> Result<Box<dyn erased_serde::Serialize>, Error> {
-        let rev = fetch_github_branch_info(self).await?.commit.sha;
+    async fn metadata(&self) -> Result<DependencyMetadata, Error> {
+        if let Some(ssh_key) = &self.sshKey {
+            let rev = self.ssh_rev(ssh_key).await?;
+            return Ok(DependencyMetadata {
+                resolved_version: Some(rev.chars().take(12).collect()),
+                ..Default::default()
+            });
+        }
+        let info = fetch_github_branch_info(self).await?;
+        let mut metadata = info.commit.metadata();
+        if self.branch.is_none() {
+            metadata.resolved_from = info
+                .name
+                .map(|name| format!("the default branch, {}", name));
+        }
+        if info.behind_tip {
+            metadata.resolved_from = Some("the newest commit with passing checks".to_string());
+        }
+        if let Some(submodules) = self.lock_submodules(&info.commit.sha).await? {
+            metadata.submodules = submodules.iter().map(|s| s.to_string()).collect();
+        }
+        return Ok(metadata);
+    }
+
+    async fn lock(&self) -> Result<LockData, Error> {
+        if let Some(ssh_key) = &self.sshKey {
+            return Ok(LockData::GitHub(self.lock_over_ssh(ssh_key).await?));
+        }
+        let commit = fetch_github_branch_info(self).await?.commit;
+        if self.verifySignature.unwrap_or(false) {
+            let verification = commit.commit.as_ref().and_then(|c| c.verification.as_ref());
+            github::check_signature(&self.identity(), &commit.sha, verification)?;
+        }
+        let rev = commit.sha;
         let sha256 = match &self.override_nix_sha256 {
             Some(s) => s.to_string(),
-            None => github::compute_nix_sha256(
-                &self.owner,
-                &self.repo,
-                &rev,
-                self.fetchSubmodules,
-                self.deepClone,
-                self.leaveDotGit,
-            )?,
+            None => {
+                github::compute_nix_sha256(
+                    &self.github,
+                    &self.owner,
+                    &self.repo,
+                    &rev,
+                    self.fetchSubmodules,
+                    self.deepClone,
+                    self.leaveDotGit,
+                )
+                .await?
+            }
         };
-        return Ok(Box::new(github::GitHubLock {
+        let submodules = self.lock_submodules(&rev).await?;
+        return Ok(LockData::GitHub(GitHubLock {
             owner: self.owner.clone(),
             repo: self.repo.clone(),
             rev,
@@ -110,6 +416,9 @@ impl Lockable for GitHubBranch {
             fetchSubmodules: self.fetchSubmodules.unwrap_or(false),
             deepClone: self.deepClone.unwrap_or(false),
             leaveDotGit: self.leaveDotGit.unwrap_or(false),
+            githubBase: self.host.clone(),
+            submodules,
+            metadata: None,
         }));
     }
 }
@@ -118,7 +427,8 @@ impl Lockable for GitHubBranch {
 mod tests {
     use super::GitHubBranch;
     use crate::deps::test_util;
-    use crate::deps::Lockable;
+    use crate::deps::{DependencyMetadata, Lockable};
+    use crate::error::Error;
     use serde_json::json;
 
     #[test]
@@ -140,19 +450,19 @@ mod tests {
         )
         .unwrap()
         .iter()
-        .map(|d| d.as_git_hub_branch().unwrap().clone())
+        .map(|d| d.kind().as_git_hub_branch().unwrap().clone())
         .collect();
         let expected_dependencies = vec![
             GitHubBranch {
                 owner: "luizribeiro".to_string(),
                 repo: "uptix".to_string(),
-                branch: "main".to_string(),
+                branch: Some("main".to_string()),
                 ..Default::default()
             },
             GitHubBranch {
                 owner: "luizribeiro".to_string(),
                 repo: "uptix".to_string(),
-                branch: "main".to_string(),
+                branch: Some("main".to_string()),
                 fetchSubmodules: Some(true),
                 ..Default::default()
             },
@@ -160,15 +470,48 @@ mod tests {
         assert_eq!(dependencies, expected_dependencies);
     }
 
+    #[test]
+    fn it_builds_branches() {
+        let dependency = GitHubBranch::repository("luizribeiro", "uptix")
+            .with_branch("main")
+            .with_submodules();
+        assert_eq!(dependency.github.api_url, "https://api.github.com");
+        assert_eq!(
+            GitHubBranch {
+                github: Default::default(),
+                ..dependency
+            },
+            GitHubBranch {
+                owner: "luizribeiro".to_string(),
+                repo: "uptix".to_string(),
+                branch: Some("main".to_string()),
+                fetchSubmodules: Some(true),
+                ..Default::default()
+            }
+        );
+    }
+
     #[test]
     fn it_has_a_key() {
         let dependency = GitHubBranch {
             owner: "luizribeiro".to_string(),
             repo: "uptix".to_string(),
-            branch: "main".to_string(),
+            branch: Some("main".to_string()),
             ..Default::default()
         };
         assert_eq!(dependency.key(), "$GITHUB_BRANCH$:luizribeiro/uptix:main$");
+        let enterprise = GitHubBranch {
+            host: Some("ghe.example.com".to_string()),
+            ..dependency
+        };
+        assert_eq!(
+            enterprise.key(),
+            "$GITHUB_BRANCH$:ghe.example.com/luizribeiro/uptix:main$"
+        );
+        assert_eq!(
+            enterprise.identity(),
+            "github:ghe.example.com/luizribeiro/uptix"
+        );
     }
 
     #[tokio::test]
@@ -176,7 +519,7 @@ mod tests {
         let address = mockito::server_address().to_string();
         let _branch_mock = mockito::mock("GET", "/repos/luizribeiro/uptix/branches/main")
             .match_header(
-                &reqwest::header::USER_AGENT.to_string(),
+                reqwest::header::USER_AGENT.as_str(),
                 mockito::Matcher::Regex(r"^uptix/[0-9.]+$".to_string()),
             )
             .with_status(200)
@@ -192,7 +535,7 @@ mod tests {
         let dependency = GitHubBranch {
             owner: "luizribeiro".to_string(),
             repo: "uptix".to_string(),
-            branch: "main".to_string(),
+            branch: Some("main".to_string()),
             override_scheme: Some("http".to_string()),
             override_domain: Some(address),
             override_nix_sha256: Some(
@@ -219,6 +562,302 @@ mod tests {
         mockito::reset();
     }
 
+    #[tokio::test]
+    async fn it_tracks_the_default_branch() {
+        let address = mockito::server_address().to_string();
+        let _repo_mock = mockito::mock("GET", "/repos/luizribeiro/renamed")
+            .with_status(200)
+            .with_body(r#"{ "default_branch": "trunk" }"#)
+            .create();
+        let _branch_mock = mockito::mock("GET", "/repos/luizribeiro/renamed/branches/trunk")
+            .with_status(200)
+            .with_body(
+                r#"{
+                    "name": "trunk",
+                    "commit": { "sha": "b28012d8b7f8ef54492c66f3a77074391e9818b9" }
+                }"#,
+            )
+            .create();
+
+        let dependencies = test_util::deps(
+            r#"{ renamed = uptix.githubBranch { owner = "luizribeiro"; repo = "renamed"; }; }"#,
+        )
+        .unwrap();
+        let mut dependency = dependencies[0].kind().as_git_hub_branch().unwrap().clone();
+        assert_eq!(dependency.branch, None);
+        assert_eq!(
+            dependency.key(),
+            "$GITHUB_BRANCH$:luizribeiro/renamed:HEAD$"
+        );
+        dependency.override_scheme = Some("http".to_string());
+        dependency.override_domain = Some(address);
+        dependency.override_nix_sha256 =
+            Some("1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j".to_string());
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(
+            lock_value["rev"],
+            "b28012d8b7f8ef54492c66f3a77074391e9818b9"
+        );
+        assert_eq!(
+            dependency.metadata().await.unwrap().resolved_from,
+            Some("the default branch, trunk".to_string()),
+        );
+
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_requires_passing_checks() {
+        let address = mockito::server_address().to_string();
+        let _branch_mock = mockito::mock("GET", "/repos/luizribeiro/checked/branches/main")
+            .with_status(200)
+            .with_body(r#"{ "name": "main", "commit": { "sha": "tip" } }"#)
+            .create();
+        let _tip_status_mock =
+            mockito::mock("GET", "/repos/luizribeiro/checked/commits/tip/status")
+                .with_status(200)
+                .with_body(r#"{ "state": "pending", "total_count": 1 }"#)
+                .create();
+        let _commits_mock = mockito::mock("GET", "/repos/luizribeiro/checked/commits")
+            .match_query(mockito::Matcher::UrlEncoded("sha".into(), "tip".into()))
+            .with_status(200)
+            .with_body(r#"[{ "sha": "tip" }, { "sha": "broken" }, { "sha": "green" }]"#)
+            .create();
+        let _broken_status_mock =
+            mockito::mock("GET", "/repos/luizribeiro/checked/commits/broken/status")
+                .with_status(200)
+                .with_body(r#"{ "state": "pending", "total_count": 0 }"#)
+                .create();
+        let _broken_runs_mock = mockito::mock(
+            "GET",
+            "/repos/luizribeiro/checked/commits/broken/check-runs",
+        )
+        .with_status(200)
+        .with_body(
+            r#"{ "check_runs": [
+                        { "status": "completed", "conclusion": "success" },
+                        { "status": "completed", "conclusion": "failure" }
+                    ] }"#,
+        )
+        .create();
+        let _green_status_mock =
+            mockito::mock("GET", "/repos/luizribeiro/checked/commits/green/status")
+                .with_status(200)
+                .with_body(r#"{ "state": "success", "total_count": 2 }"#)
+                .create();
+        let _green_runs_mock =
+            mockito::mock("GET", "/repos/luizribeiro/checked/commits/green/check-runs")
+                .with_status(200)
+                .with_body(
+                    r#"{ "check_runs": [
+                        { "status": "completed", "conclusion": "skipped" }
+                    ] }"#,
+                )
+                .create();
+
+        let dependency = GitHubBranch {
+            owner: "luizribeiro".to_string(),
+            repo: "checked".to_string(),
+            branch: Some("main".to_string()),
+            requireChecks: Some(true),
+            override_scheme: Some("http".to_string()),
+            override_domain: Some(address.clone()),
+            override_nix_sha256: Some(
+                "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j".to_string(),
+            ),
+            ..Default::default()
+        };
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(lock_value["rev"], "green");
+        assert_eq!(
+            dependency
+                .metadata()
+                .await
+                .unwrap()
+                .resolved_from
+                .as_deref(),
+            Some("the newest commit with passing checks"),
+        );
+
+        let _commits_mock = mockito::mock("GET", "/repos/luizribeiro/checked/commits")
+            .match_query(mockito::Matcher::UrlEncoded("sha".into(), "tip".into()))
+            .with_status(200)
+            .with_body(r#"[{ "sha": "tip" }, { "sha": "broken" }]"#)
+            .create();
+        assert!(matches!(
+            dependency.lock().await,
+            Err(Error::FailingChecks { .. })
+        ));
+
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_needs_the_api_for_some_options_over_ssh() {
+        let dependency = GitHubBranch {
+            owner: "luizribeiro".to_string(),
+            repo: "private".to_string(),
+            sshKey: Some("/run/secrets/deploy-key".to_string()),
+            verifySignature: Some(true),
+            ..Default::default()
+        };
+        assert!(matches!(
+            dependency.lock().await,
+            Err(Error::GitHubError(message)) if message.contains("sshKey")
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_detects_rewritten_history() {
+        let _ahead_mock = mockito::mock("GET", "/repos/luizribeiro/rewritten/compare/old...new")
+            .with_status(200)
+            .with_body(r#"{ "status": "ahead", "ahead_by": 2, "behind_by": 0 }"#)
+            .create();
+        let _diverged_mock =
+            mockito::mock("GET", "/repos/luizribeiro/rewritten/compare/old...forced")
+                .with_status(200)
+                .with_body(r#"{ "status": "diverged", "ahead_by": 1, "behind_by": 3 }"#)
+                .create();
+        let _gone_mock = mockito::mock("GET", "/repos/luizribeiro/rewritten/compare/gone...new")
+            .with_status(404)
+            .with_body(r#"{ "message": "Not Found" }"#)
+            .create();
+
+        let dependency = GitHubBranch {
+            owner: "luizribeiro".to_string(),
+            repo: "rewritten".to_string(),
+            branch: Some("main".to_string()),
+            override_scheme: Some("http".to_string()),
+            override_domain: Some(mockito::server_address().to_string()),
+            ..Default::default()
+        };
+        assert!(dependency.is_fast_forward("old", "new").await.unwrap());
+        assert!(!dependency.is_fast_forward("old", "forced").await.unwrap());
+        assert!(!dependency.is_fast_forward("gone", "new").await.unwrap());
+
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_locks_submodules() {
+        let address = mockito::server_address().to_string();
+        let _branch_mock = mockito::mock("GET", "/repos/luizribeiro/modules/branches/main")
+            .with_status(200)
+            .with_body(r#"{ "commit": { "sha": "abcd" } }"#)
+            .create();
+        let _tree_mock = mockito::mock("GET", "/repos/luizribeiro/modules/git/trees/abcd")
+            .match_query(mockito::Matcher::UrlEncoded("recursive".into(), "1".into()))
+            .with_status(200)
+            .with_body(
+                r#"{
+                    "tree": [
+                        { "path": "README.md", "type": "blob", "sha": "1111" },
+                        { "path": "vendor", "type": "tree", "sha": "2222" },
+                        { "path": "vendor/lib", "type": "commit", "sha": "3333" }
+                    ],
+                    "truncated": false
+                }"#,
+            )
+            .create();
+        // `[submodule "lib"]\n\tpath = vendor/lib\n\turl = https://example.com/lib.git`
+        let _gitmodules_mock = mockito::mock("GET", "/repos/luizribeiro/modules/contents/.gitmodules")
+            .match_query(mockito::Matcher::UrlEncoded("ref".into(), "abcd".into()))
+            .with_status(200)
+            .with_body(
+                r#"{
+                    "content": "W3N1Ym1vZHVsZSAibGliIl0KCXBhdGggPSB2ZW5kb3IvbGliCgl1cmwgPSBo\ndHRwczovL2V4YW1wbGUuY29tL2xpYi5naXQK\n",
+                    "encoding": "base64"
+                }"#,
+            )
+            .create();
+
+        let dependency = GitHubBranch {
+            owner: "luizribeiro".to_string(),
+            repo: "modules".to_string(),
+            branch: Some("main".to_string()),
+            fetchSubmodules: Some(true),
+            lockSubmodules: Some(true),
+            override_scheme: Some("http".to_string()),
+            override_domain: Some(address),
+            override_nix_sha256: Some(
+                "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j".to_string(),
+            ),
+            ..Default::default()
+        };
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(
+            lock_value["submodules"],
+            json!([{
+                "path": "vendor/lib",
+                "url": "https://example.com/lib.git",
+                "rev": "3333",
+            }]),
+        );
+        assert_eq!(
+            dependency.metadata().await.unwrap().submodules,
+            vec!["vendor/lib: https://example.com/lib.git@3333"],
+        );
+
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_has_commit_metadata() {
+        let address = mockito::server_address().to_string();
+        let _branch_mock = mockito::mock("GET", "/repos/luizribeiro/uptix/branches/metadata")
+            .with_status(200)
+            .with_body(
+                r#"{
+                    "commit": {
+                        "sha": "b28012d8b7f8ef54492c66f3a77074391e9818b9",
+                        "commit": {
+                            "author": {
+                                "name": "Luiz Ribeiro",
+                                "date": "2024-03-01T12:34:56Z"
+                            },
+                            "message": "Add metadata\n\nWith a longer body"
+                        }
+                    }
+                }"#,
+            )
+            .create();
+
+        let dependency = GitHubBranch {
+            owner: "luizribeiro".to_string(),
+            repo: "uptix".to_string(),
+            branch: Some("metadata".to_string()),
+            override_scheme: Some("http".to_string()),
+            override_domain: Some(address),
+            ..Default::default()
+        };
+        assert_eq!(
+            dependency.metadata().await.unwrap(),
+            DependencyMetadata {
+                resolved_version: Some("b28012d8b7f8".to_string()),
+                description: Some("Add metadata (Luiz Ribeiro)".to_string()),
+                timestamp: Some("2024-03-01T12:34:56Z".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let dependency = GitHubBranch {
+            verifySignature: Some(true),
+            override_nix_sha256: Some(
+                "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j".to_string(),
+            ),
+            ..dependency
+        };
+        match dependency.lock().await {
+            Err(crate::error::Error::UnverifiedSignature { name, reason, .. }) => {
+                assert_eq!(name, "github:luizribeiro/uptix");
+                assert_eq!(reason, "unsigned");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        mockito::reset();
+    }
+
     #[test]
     fn it_provides_helpful_errors() {
         let result = test_util::deps("{ hass = uptix.githubBranch 42; }");
@@ -235,7 +874,7 @@ mod tests {
                 assert_eq!(expected_type, "NODE_ATTR_SET");
                 assert_eq!(argument_pos, (28, 2).into());
             }
-            _ => assert!(false),
+            other => panic!("unexpected result: {:?}", other),
         }
     }
 }
@@ -1,20 +1,472 @@
+pub mod action;
+mod auth;
 pub mod branch;
+pub mod pull;
 pub mod release;
+pub(super) mod shallow;
+mod tarball;
 
+use crate::cache::Cache;
+use crate::config::{GitHubAppConfig, GitHubConfig};
 use crate::error::Error;
+use crate::http;
+use crate::lock::{GitHubLock, LockData, LockFile, SubmoduleLock};
+use crate::nar;
+use crate::netrc;
+use crate::process;
+use crate::secrets;
+use crate::timings;
+use crate::util;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
-use std::process::Command;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tokio::process::Command;
+use tokio::sync::Semaphore;
 
+pub const DEFAULT_HOST: &str = "github.com";
+const DEFAULT_API_URL: &str = "https://api.github.com";
+const DEFAULT_TOKEN_ENV: &str = "GITHUB_TOKEN";
+const CACHE_NAMESPACE: &str = "github";
+const HASHES_NAMESPACE: &str = "hashes";
+const RATE_LIMIT_REMAINING_HEADER: &str = "x-ratelimit-remaining";
+const RATE_LIMIT_RESET_HEADER: &str = "x-ratelimit-reset";
+const HINTS: http::Hints = http::Hints {
+    unauthorized: "set GITHUB_TOKEN (or token_env for the host on uptix.toml) to a token \
+                   with access to the repository",
+    not_found: "check the spelling of the owner and repository, and that the token has \
+                access to it if it is private",
+};
+
+lazy_static! {
+    /// Hashes of revisions which were already locked, so that they don't
+    /// need to be prefetched again
+    static ref KNOWN_HASHES: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+    /// Bounds how many clones run at once, since each can take a lot of
+    /// bandwidth and disk
+    static ref PREFETCH_POOL: Semaphore = Semaphore::new(http::prefetch_jobs());
+}
+
+#[derive(Deserialize)]
+struct TreeEntry {
+    path: String,
+    #[serde(rename = "type")]
+    kind: String,
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct Tree {
+    tree: Vec<TreeEntry>,
+    #[serde(default)]
+    truncated: bool,
+}
+
+#[derive(Deserialize)]
+struct FileContents {
+    content: String,
+}
+
+/// Reads the URL of each submodule path from a `.gitmodules` file
+fn parse_gitmodules(content: &str) -> BTreeMap<String, String> {
+    let mut urls = BTreeMap::new();
+    let (mut path, mut url) = (None, None);
+    for line in content.lines().map(str::trim).chain(["["]) {
+        if line.starts_with('[') {
+            if let (Some(path), Some(url)) = (path.take(), url.take()) {
+                urls.insert(path, url);
+            }
+            continue;
+        }
+        match line.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+            Some(("path", value)) => path = Some(value.to_string()),
+            Some(("url", value)) => url = Some(value.to_string()),
+            _ => (),
+        }
+    }
+    return urls;
+}
+
+/// Resolves the revision of every submodule of a repository at a revision,
+/// given a function building its API URLs
+pub(super) async fn fetch_submodules(
+    host: &GitHubHost,
+    api_url: impl Fn(&str) -> Result<reqwest::Url, Error>,
+    rev: &str,
+) -> Result<Vec<SubmoduleLock>, Error> {
+    let url = api_url(&format!("git/trees/{}?recursive=1", rev))?;
+    let tree: Tree = github_api_request(host, url).await?;
+    if tree.truncated {
+        return Err(Error::GitHubError(format!(
+            "the tree at {} is too large to list its submodules",
+            rev
+        )));
+    }
+    let url = api_url(&format!("contents/.gitmodules?ref={}", rev))?;
+    let urls = match github_api_request_if_found::<FileContents>(host, url).await? {
+        Some(file) => {
+            let encoded: String = file.content.split_whitespace().collect();
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .map_err(|e| Error::GitHubError(format!("invalid .gitmodules: {}", e)))?;
+            parse_gitmodules(&String::from_utf8_lossy(&decoded))
+        }
+        None => BTreeMap::new(),
+    };
+    return Ok(tree
+        .tree
+        .into_iter()
+        // submodules are the commits on the tree of their parent
+        .filter(|entry| entry.kind == "commit")
+        .map(|entry| SubmoduleLock {
+            url: urls.get(&entry.path).cloned(),
+            path: entry.path,
+            rev: entry.sha,
+        })
+        .collect());
+}
+
+/// Where to reach a GitHub host, and how to authenticate with it
+#[derive(Default, PartialEq, Clone, Debug)]
+pub struct GitHubHost {
+    /// the host repositories are on (i.e. github.com)
+    pub host: String,
+    pub api_url: String,
+    pub token: Option<String>,
+    pub app: Option<GitHubAppConfig>,
+}
+
+impl GitHubHost {
+    /// The API of github.com lives on its own host, while GitHub Enterprise
+    /// serves it under `/api/v3`. Tokens come from the environment variable
+    /// configured for the host (`GITHUB_TOKEN` for github.com), falling back
+    /// to the one stored by `gh auth login` and then to the password .netrc
+    /// holds for the API's host. Hosts with a GitHub App mint installation
+    /// tokens instead.
+    pub fn new(config: &GitHubConfig, host: &str) -> GitHubHost {
+        let host_config = config.hosts.get(host);
+        let api_url = match host_config.and_then(|h| h.api_url.clone()) {
+            Some(api_url) => api_url,
+            None if host == DEFAULT_HOST => DEFAULT_API_URL.to_string(),
+            None => format!("https://{}/api/v3", host),
+        };
+        let token_env = match host_config.and_then(|h| h.token_env.as_deref()) {
+            Some(token_env) => Some(token_env),
+            None if host == DEFAULT_HOST => Some(DEFAULT_TOKEN_ENV),
+            None => None,
+        };
+        let api_host = reqwest::Url::parse(&api_url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string));
+        return GitHubHost {
+            host: host.to_string(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            app: host_config.and_then(|h| h.app.clone()),
+            token: token_env
+                .and_then(secrets::var)
+                .or_else(|| auth::gh_token(host))
+                .or_else(|| netrc::lookup(api_host.as_deref()?)?.password),
+        };
+    }
+
+    async fn auth_token(&self) -> Result<Option<String>, Error> {
+        return match &self.app {
+            Some(app) => Ok(Some(auth::installation_token(&self.api_url, app).await?)),
+            None => Ok(self.token.clone()),
+        };
+    }
+
+    /// What requests to the host are authenticated with, which cached
+    /// responses are kept apart (and encrypted) by
+    fn credential(&self) -> Result<Option<String>, Error> {
+        return match &self.app {
+            Some(app) => Ok(Some(auth::app_credential(app)?)),
+            None => Ok(self.token.clone()),
+        };
+    }
+}
+
+/// Builds the URL for a repository's API endpoint. The override fields
+/// point requests at a mock server in tests.
+pub(super) fn api_url(
+    host: &GitHubHost,
+    override_scheme: &Option<String>,
+    override_domain: &Option<String>,
+    owner: &str,
+    repo: &str,
+    path: &str,
+) -> Result<reqwest::Url, Error> {
+    let base = match override_domain {
+        Some(domain) => format!(
+            "{}://{}",
+            override_scheme.as_deref().unwrap_or("https"),
+            domain
+        ),
+        None => host.api_url.clone(),
+    };
+    let url_as_str = match path {
+        "" => format!("{}/repos/{}/{}", base, owner, repo),
+        _ => format!("{}/repos/{}/{}/{}", base, owner, repo, path),
+    };
+    return Ok(reqwest::Url::parse(&url_as_str)?);
+}
+
+/// A response from the GitHub API, kept to be revalidated with its ETag
 #[derive(Serialize, Deserialize)]
-#[allow(non_snake_case)]
-pub struct GitHubLock {
-    owner: String,
-    repo: String,
-    rev: String,
-    sha256: String,
-    fetchSubmodules: bool,
-    deepClone: bool,
-    leaveDotGit: bool,
+struct CachedResponse {
+    etag: String,
+    body: String,
+}
+
+/// Sends a GET request to the GitHub API, authenticated when the host has
+/// a token
+pub(super) async fn github_api_request<T: serde::de::DeserializeOwned>(
+    host: &GitHubHost,
+    url: reqwest::Url,
+) -> Result<T, Error> {
+    return conditional_request(&Cache::default(), host, url).await;
+}
+
+/// Like `github_api_request`, but with `None` for URLs GitHub has nothing at
+pub(super) async fn github_api_request_if_found<T: serde::de::DeserializeOwned>(
+    host: &GitHubHost,
+    url: reqwest::Url,
+) -> Result<Option<T>, Error> {
+    return conditional_request_if_found(&Cache::default(), host, url).await;
+}
+
+/// Sends the request with the ETag of the last response for the same URL,
+/// reusing that response when GitHub says it's still fresh (which also
+/// doesn't count against the rate limit)
+async fn conditional_request<T: serde::de::DeserializeOwned>(
+    cache: &Cache,
+    host: &GitHubHost,
+    url: reqwest::Url,
+) -> Result<T, Error> {
+    let response = conditional_request_if_found(cache, host, url.clone()).await?;
+    return response.ok_or_else(|| Error::NotFound {
+        url: url.to_string(),
+        hint: HINTS.not_found.to_string(),
+    });
+}
+
+async fn conditional_request_if_found<T: serde::de::DeserializeOwned>(
+    cache: &Cache,
+    host: &GitHubHost,
+    url: reqwest::Url,
+) -> Result<Option<T>, Error> {
+    let cache = &cache.clone().for_credential(host.credential()?.as_deref());
+    // responses cached recently enough aren't even revalidated
+    let fresh: Option<CachedResponse> = cache
+        .read_fresh(CACHE_NAMESPACE, url.as_str())
+        .and_then(|cached| serde_json::from_str(&cached).ok());
+    if let Some(fresh) = fresh {
+        return Ok(Some(serde_json::from_str(&fresh.body)?));
+    }
+    let cached: Option<CachedResponse> = cache
+        .read(CACHE_NAMESPACE, url.as_str())
+        .and_then(|cached| serde_json::from_str(&cached).ok());
+    let client = http::client()?;
+    let mut request = client
+        .request(reqwest::Method::GET, url.clone())
+        .header(reqwest::header::USER_AGENT, util::user_agent());
+    if let Some(token) = host.auth_token().await? {
+        request = request.bearer_auth(token);
+    }
+    if let Some(cached) = &cached {
+        request = request.header(reqwest::header::IF_NONE_MATCH, &cached.etag);
+    }
+    let response = send(request).await?;
+    if let (reqwest::StatusCode::NOT_MODIFIED, Some(cached)) = (response.status(), &cached) {
+        if cache.has_ttl() {
+            // fresh again, for as long as the TTL
+            cache.write(
+                CACHE_NAMESPACE,
+                url.as_str(),
+                &serde_json::to_string(cached)?,
+            );
+        }
+        return Ok(Some(serde_json::from_str(&cached.body)?));
+    }
+    let status = response.status();
+    if status == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if let Some(error) = http::status_error(&response, &HINTS) {
+        return Err(error);
+    }
+    if !status.is_success() {
+        #[derive(Deserialize)]
+        struct ErrorBody {
+            message: Option<String>,
+        }
+        let message = response
+            .json::<ErrorBody>()
+            .await
+            .ok()
+            .and_then(|body| body.message)
+            .unwrap_or_default();
+        return Err(Error::GitHubError(format!(
+            "request to {} failed with {}: {}",
+            url, status, message
+        )));
+    }
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(|etag| etag.to_string());
+    let body = response.text().await?;
+    let value = serde_json::from_str(&body)?;
+    if let Some(etag) = etag {
+        let cached = serde_json::to_string(&CachedResponse { etag, body })?;
+        cache.write(CACHE_NAMESPACE, url.as_str(), &cached);
+    }
+    return Ok(Some(value));
+}
+
+fn header_value(response: &reqwest::Response, name: &str) -> Option<String> {
+    return response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+}
+
+/// Formats the epoch seconds of `x-ratelimit-reset` as a time of day
+fn format_reset(epoch: u64) -> String {
+    let seconds = epoch % 86400;
+    return format!("{:02}:{:02} UTC", seconds / 3600, seconds % 3600 / 60);
+}
+
+fn rate_limited(response: &reqwest::Response) -> Error {
+    let until = header_value(response, RATE_LIMIT_RESET_HEADER)
+        .and_then(|reset| reset.parse().ok())
+        .map_or("later".to_string(), |reset| {
+            format!("until {}", format_reset(reset))
+        });
+    return Error::GitHubRateLimited {
+        host: response.url().host_str().unwrap_or_default().to_string(),
+        until,
+    };
+}
+
+fn is_rate_limited(response: &reqwest::Response) -> bool {
+    let status = response.status();
+    return status == reqwest::StatusCode::FORBIDDEN
+        || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+}
+
+/// Waits and retries when GitHub asks to through `Retry-After` (as it does
+/// for secondary rate limits). Running out of the primary rate limit isn't
+/// retried, since it can take up to an hour to reset.
+fn classify(response: &reqwest::Response) -> http::Retry {
+    if !is_rate_limited(response) {
+        return http::transient(response);
+    }
+    return match http::retry_after(response) {
+        Some(delay) => http::Retry::After(Some(delay)),
+        None => http::Retry::No,
+    };
+}
+
+/// Sends a request with the shared retry policy, failing with a rate limit
+/// error if GitHub still refuses it after that
+async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response, Error> {
+    let response = http::send(request, classify).await?;
+    if !is_rate_limited(&response) {
+        return Ok(response);
+    }
+    let exhausted = header_value(&response, RATE_LIMIT_REMAINING_HEADER).as_deref() == Some("0");
+    if http::retry_after(&response).is_some() || exhausted {
+        return Err(rate_limited(&response));
+    }
+    // i.e. a repository the token has no access to
+    return Ok(response);
+}
+
+fn hash_key(host: &str, owner: &str, repo: &str, rev: &str, flags: &str) -> String {
+    return format!("{}/{}/{}@{}${}", host, owner, repo, rev, flags);
+}
+
+/// The hash of a revision, if it was computed before by this run or, as
+/// kept in the cache, by an earlier one
+fn known_hash(cache: &Cache, key: &str) -> Option<String> {
+    if let Some(sha256) = KNOWN_HASHES.lock().unwrap().get(key) {
+        return Some(sha256.clone());
+    }
+    let sha256 = cache.read(HASHES_NAMESPACE, key)?;
+    tracing::debug!(key, "reusing cached hash");
+    KNOWN_HASHES
+        .lock()
+        .unwrap()
+        .insert(key.to_string(), sha256.clone());
+    return Some(sha256);
+}
+
+/// Whether a revision is a commit, rather than a tag or branch which can be
+/// moved to another one
+fn is_commit(rev: &str) -> bool {
+    return rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit());
+}
+
+/// Keeps the hash of a revision for later runs too. Only commits are kept
+/// in the cache, since tags (i.e. of releases) can be moved.
+fn remember_hash(cache: &Cache, key: String, rev: &str, sha256: &str) {
+    if is_commit(rev) {
+        cache.write(HASHES_NAMESPACE, &key, sha256);
+    }
+    KNOWN_HASHES.lock().unwrap().insert(key, sha256.to_string());
+}
+
+/// Remembers the hashes on a lock file, so that commits which didn't change
+/// since it was written aren't prefetched again. Entries locked to a tag
+/// are left out, since the tag may have been moved since.
+pub fn remember_hashes(lock_file: &LockFile) {
+    let mut known = KNOWN_HASHES.lock().unwrap();
+    for value in lock_file.values() {
+        let lock = match value {
+            LockData::GitHub(lock) => lock,
+            LockData::Release(release) => &release.lock,
+            _ => continue,
+        };
+        if !is_commit(&lock.rev) {
+            continue;
+        }
+        let host = lock.githubBase.as_deref().unwrap_or(DEFAULT_HOST);
+        let flags = flags(
+            Some(lock.fetchSubmodules),
+            Some(lock.deepClone),
+            Some(lock.leaveDotGit),
+        );
+        known.insert(
+            hash_key(host, &lock.owner, &lock.repo, &lock.rev, &flags),
+            lock.sha256.clone(),
+        );
+    }
+}
+
+/// Whether GitHub verified the signature of a commit or tag
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) struct Verification {
+    verified: bool,
+    reason: String,
+}
+
+/// Fails unless a revision has a verified signature
+pub(super) fn check_signature(
+    name: &str,
+    rev: &str,
+    verification: Option<&Verification>,
+) -> Result<(), Error> {
+    return match verification {
+        Some(Verification { verified: true, .. }) => Ok(()),
+        _ => Err(Error::UnverifiedSignature {
+            name: name.to_string(),
+            rev: rev.to_string(),
+            reason: verification.map_or("unsigned".to_string(), |v| v.reason.clone()),
+        }),
+    };
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -22,13 +474,242 @@ struct GitHubPrefetchInfo {
     sha256: String,
 }
 
-fn compute_nix_sha256(
+pub(super) async fn compute_nix_sha256(
+    github: &GitHubHost,
     owner: &str,
     repo: &str,
     rev: &str,
     fetch_submodules: Option<bool>,
     deep_clone: Option<bool>,
     leave_dot_git: Option<bool>,
+) -> Result<String, Error> {
+    let host = &github.host;
+    let key = hash_key(
+        host,
+        owner,
+        repo,
+        rev,
+        &flags(fetch_submodules, deep_clone, leave_dot_git),
+    );
+    let cache = Cache::default();
+    if let Some(sha256) = known_hash(&cache, &key) {
+        return Ok(sha256);
+    }
+    let sha256 = hash_repository(
+        github,
+        owner,
+        repo,
+        rev,
+        fetch_submodules,
+        deep_clone,
+        leave_dot_git,
+    )
+    .await?;
+    remember_hash(&cache, key, rev, &sha256);
+    return Ok(sha256);
+}
+
+/// Hashes a revision of a repository the way `fetchFromGitHub` would,
+/// without looking up the hashes already known
+async fn hash_repository(
+    github: &GitHubHost,
+    owner: &str,
+    repo: &str,
+    rev: &str,
+    fetch_submodules: Option<bool>,
+    deep_clone: Option<bool>,
+    leave_dot_git: Option<bool>,
+) -> Result<String, Error> {
+    let host = &github.host;
+    // without anything only git provides, fetchFromGitHub downloads a
+    // tarball, which can be hashed without nix-prefetch-git
+    if !needs_git(fetch_submodules, deep_clone, leave_dot_git) {
+        // private repositories can only be downloaded through the API,
+        // which serves the same tarball
+        let token = github.auth_token().await?;
+        let url = match token {
+            Some(_) => tarball::api_tarball_url(&github.api_url, owner, repo, rev),
+            None => tarball::archive_url(host, owner, repo, rev),
+        };
+        return tarball::compute_sha256(&url, token.as_deref()).await;
+    }
+    let url = format!("https://{}/{}/{}/", host, owner, repo);
+    return prefetch_git(&url, rev, fetch_submodules, deep_clone, leave_dot_git, None).await;
+}
+
+fn needs_git(
+    fetch_submodules: Option<bool>,
+    deep_clone: Option<bool>,
+    leave_dot_git: Option<bool>,
+) -> bool {
+    return fetch_submodules.unwrap_or(false)
+        || deep_clone.unwrap_or(false)
+        || leave_dot_git.unwrap_or(false);
+}
+
+/// Hashes a locked repository again, bypassing the hashes already known,
+/// either the way uptix does when locking it or through nix's own fetchers
+/// (`nix-prefetch-url` and `nix-prefetch-git`)
+pub async fn rehash(
+    github: &GitHubHost,
+    lock: &GitHubLock,
+    with_nix: bool,
+) -> Result<String, Error> {
+    let (fetch_submodules, deep_clone, leave_dot_git) = (
+        Some(lock.fetchSubmodules),
+        Some(lock.deepClone),
+        Some(lock.leaveDotGit),
+    );
+    if !with_nix {
+        return hash_repository(
+            github,
+            &lock.owner,
+            &lock.repo,
+            &lock.rev,
+            fetch_submodules,
+            deep_clone,
+            leave_dot_git,
+        )
+        .await;
+    }
+    if !needs_git(fetch_submodules, deep_clone, leave_dot_git) {
+        let url = tarball::archive_url(&github.host, &lock.owner, &lock.repo, &lock.rev);
+        return nix_prefetch_url(&url, true).await;
+    }
+    let url = format!("https://{}/{}/{}/", github.host, lock.owner, lock.repo);
+    let _permit = PREFETCH_POOL.acquire().await.unwrap();
+    return nix_prefetch_git(
+        &url,
+        &lock.rev,
+        fetch_submodules,
+        deep_clone,
+        leave_dot_git,
+        None,
+    )
+    .await;
+}
+
+/// Hashes a release asset again, like `rehash`
+pub async fn rehash_asset(url: &str, with_nix: bool) -> Result<String, Error> {
+    return match with_nix {
+        true => nix_prefetch_url(url, false).await,
+        false => compute_url_sha256(url).await,
+    };
+}
+
+/// Hashes what a URL points at with nix-prefetch-url, as `fetchurl` does,
+/// or unpacked as `fetchzip` does
+async fn nix_prefetch_url(url: &str, unpack: bool) -> Result<String, Error> {
+    let _permit = PREFETCH_POOL.acquire().await.unwrap();
+    let _timer = timings::start("hashing");
+    let mut command = Command::new("nix-prefetch-url");
+    if unpack {
+        command.arg("--unpack");
+    }
+    command
+        .args(["--type", "sha256"])
+        .arg(url)
+        .envs(http::proxy_env());
+    let output = process::output(&mut command).await?;
+    if !output.status.success() {
+        return Err(Error::GitHubError(format!(
+            "nix-prefetch-url failed for {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        )));
+    }
+    return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+}
+
+/// What `fetchFromGitHub` is asked to fetch along with a revision
+#[derive(Default, Clone, Copy, Debug)]
+pub(super) struct FetchOptions {
+    pub fetch_submodules: Option<bool>,
+    pub deep_clone: Option<bool>,
+    pub leave_dot_git: Option<bool>,
+}
+
+/// Like `compute_nix_sha256`, but cloning the repository over SSH with the
+/// given (i.e. deploy) key, for private repositories without API access
+pub(super) async fn compute_ssh_sha256(
+    github: &GitHubHost,
+    owner: &str,
+    repo: &str,
+    rev: &str,
+    options: FetchOptions,
+    ssh_key: &str,
+) -> Result<String, Error> {
+    let host = &github.host;
+    let key = hash_key(
+        host,
+        owner,
+        repo,
+        rev,
+        &flags(
+            options.fetch_submodules,
+            options.deep_clone,
+            options.leave_dot_git,
+        ),
+    );
+    let cache = Cache::default();
+    if let Some(sha256) = known_hash(&cache, &key) {
+        return Ok(sha256);
+    }
+    let url = ssh_url(host, owner, repo);
+    let sha256 = prefetch_git(
+        &url,
+        rev,
+        options.fetch_submodules,
+        options.deep_clone,
+        options.leave_dot_git,
+        Some(ssh_key),
+    )
+    .await?;
+    remember_hash(&cache, key, rev, &sha256);
+    return Ok(sha256);
+}
+
+/// The URL to clone a repository from over SSH
+pub(super) fn ssh_url(host: &str, owner: &str, repo: &str) -> String {
+    return format!("git@{}:{}/{}.git", host, owner, repo);
+}
+
+/// Hashes a revision from a clone, for what can't be downloaded as a tarball
+#[tracing::instrument(skip(fetch_submodules, deep_clone, leave_dot_git, ssh_key))]
+async fn prefetch_git(
+    url: &str,
+    rev: &str,
+    fetch_submodules: Option<bool>,
+    deep_clone: Option<bool>,
+    leave_dot_git: Option<bool>,
+    ssh_key: Option<&str>,
+) -> Result<String, Error> {
+    let _permit = PREFETCH_POOL.acquire().await.unwrap();
+    let _timer = timings::start("hashing");
+    // without a .git on the result, a shallow clone of just rev (and of its
+    // submodules) ends up with the same files as nix-prefetch-git
+    if http::shallow_clone() && !deep_clone.unwrap_or(false) && !leave_dot_git.unwrap_or(false) {
+        let fetch_submodules = fetch_submodules.unwrap_or(false);
+        return shallow::compute_sha256(url, rev, fetch_submodules, ssh_key).await;
+    }
+    return nix_prefetch_git(
+        url,
+        rev,
+        fetch_submodules,
+        deep_clone,
+        leave_dot_git,
+        ssh_key,
+    )
+    .await;
+}
+
+async fn nix_prefetch_git(
+    url: &str,
+    rev: &str,
+    fetch_submodules: Option<bool>,
+    deep_clone: Option<bool>,
+    leave_dot_git: Option<bool>,
+    ssh_key: Option<&str>,
 ) -> Result<String, Error> {
     let mut options = vec![];
     if deep_clone.unwrap_or(false) {
@@ -44,18 +725,56 @@ fn compute_nix_sha256(
         // https://nixos.org/manual/nixpkgs/stable/#fetchgit
         options.push("--leave-dotGit");
     }
-    let output = Command::new("nix-prefetch-git")
+    let mut command = Command::new("nix-prefetch-git");
+    command
         .args(options)
         .arg("--quiet")
         .arg("--rev")
         .arg(rev)
-        .arg(format!("https://github.com/{}/{}/", owner, repo,))
-        .output()
-        .expect("failed to execute process");
+        .arg(url)
+        .envs(http::proxy_env())
+        .envs(shallow::ssh_env(ssh_key));
+    let output = process::output(&mut command).await?;
+    if !output.status.success() {
+        return Err(Error::GitHubError(format!(
+            "nix-prefetch-git failed for {}: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        )));
+    }
     let prefetch_info: GitHubPrefetchInfo = serde_json::from_slice(&output.stdout)?;
     return Ok(prefetch_info.sha256);
 }
 
+/// Computes the hash `fetchurl` expects for a file, which is the sha256 of
+/// its contents. The file is hashed as it downloads, however large it is.
+pub(super) async fn compute_url_sha256(url: &str) -> Result<String, Error> {
+    let _timer = timings::start("hashing");
+    let request = http::client()?
+        .get(url)
+        .header(reqwest::header::USER_AGENT, util::user_agent());
+    let response = http::send(request, http::transient).await?;
+    if let Some(error) = http::status_error(&response, &HINTS) {
+        return Err(error);
+    }
+    if !response.status().is_success() {
+        return Err(Error::GitHubError(format!(
+            "downloading {} failed with {}",
+            url,
+            response.status()
+        )));
+    }
+    return Ok(nar::to_nix_base32(&http::sha256(response).await?));
+}
+
+/// Prefix for lock keys of repositories outside of github.com
+pub fn host_prefix(host: &Option<String>) -> String {
+    return match host.as_deref() {
+        None | Some(DEFAULT_HOST) => "".to_string(),
+        Some(host) => format!("{}/", host),
+    };
+}
+
 pub fn flags(
     fetch_submodules: Option<bool>,
     deep_clone: Option<bool>,
@@ -76,3 +795,345 @@ pub fn flags(
         },
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        compute_nix_sha256, compute_url_sha256, conditional_request, conditional_request_if_found,
+        flags, format_reset, hash_key, known_hash, parse_gitmodules, remember_hash,
+        remember_hashes, GitHubHost,
+    };
+    use crate::cache::Cache;
+    use crate::config::{GitHubConfig, GitHubHostConfig};
+    use crate::error::Error;
+    use serde_json::{json, Value};
+    use std::time::Duration;
+
+    #[test]
+    fn it_finds_api_urls() {
+        std::env::set_var("UPTIX_TEST_GHE_TOKEN", "hunter2");
+        let mut config = GitHubConfig::default();
+        config.hosts.insert(
+            "ghe.example.com".to_string(),
+            GitHubHostConfig {
+                token_env: Some("UPTIX_TEST_GHE_TOKEN".to_string()),
+                ..Default::default()
+            },
+        );
+        config.hosts.insert(
+            "git.example.com".to_string(),
+            GitHubHostConfig {
+                api_url: Some("https://api.git.example.com/".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            GitHubHost::new(&config, "github.com").api_url,
+            "https://api.github.com"
+        );
+        assert_eq!(
+            GitHubHost::new(&config, "ghe.example.com"),
+            GitHubHost {
+                host: "ghe.example.com".to_string(),
+                api_url: "https://ghe.example.com/api/v3".to_string(),
+                token: Some("hunter2".to_string()),
+                app: None,
+            },
+        );
+        assert_eq!(
+            GitHubHost::new(&config, "git.example.com"),
+            GitHubHost {
+                host: "git.example.com".to_string(),
+                api_url: "https://api.git.example.com".to_string(),
+                token: None,
+                app: None,
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn it_revalidates_cached_responses() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path());
+        let host = GitHubHost::default();
+        let url = reqwest::Url::parse(&format!(
+            "{}/repos/luizribeiro/etag/branches/main",
+            mockito::server_url()
+        ))
+        .unwrap();
+
+        let fresh_mock = mockito::mock("GET", "/repos/luizribeiro/etag/branches/main")
+            .with_status(200)
+            .with_header("etag", r#""abc""#)
+            .with_body(r#"{ "commit": { "sha": "1234" } }"#)
+            .create();
+        let value: Value = conditional_request(&cache, &host, url.clone())
+            .await
+            .unwrap();
+        assert_eq!(value, json!({ "commit": { "sha": "1234" } }));
+        fresh_mock.assert();
+
+        let revalidated_mock = mockito::mock("GET", "/repos/luizribeiro/etag/branches/main")
+            .match_header("if-none-match", r#""abc""#)
+            .with_status(304)
+            .create();
+        let value: Value = conditional_request(&cache, &host, url.clone())
+            .await
+            .unwrap();
+        assert_eq!(value, json!({ "commit": { "sha": "1234" } }));
+        revalidated_mock.assert();
+
+        let unused_mock = mockito::mock("GET", "/repos/luizribeiro/etag/branches/main")
+            .expect(0)
+            .create();
+        let cache = Cache::new(dir.path()).with_ttl(Duration::from_secs(60));
+        let value: Value = conditional_request(&cache, &host, url).await.unwrap();
+        assert_eq!(value, json!({ "commit": { "sha": "1234" } }));
+        unused_mock.assert();
+    }
+
+    #[tokio::test]
+    async fn it_keeps_authenticated_responses_apart() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path()).with_ttl(Duration::from_secs(60));
+        let host = |token: &str| GitHubHost {
+            token: Some(token.to_string()),
+            ..Default::default()
+        };
+        let url = reqwest::Url::parse(&format!(
+            "{}/repos/luizribeiro/private-etag/branches/main",
+            mockito::server_url()
+        ))
+        .unwrap();
+
+        let alice_mock = mockito::mock("GET", "/repos/luizribeiro/private-etag/branches/main")
+            .match_header("authorization", "Bearer alice")
+            .with_status(200)
+            .with_header("etag", r#""abc""#)
+            .with_body(r#"{ "commit": { "sha": "1234" } }"#)
+            .expect(1)
+            .create();
+        for _ in 0..2 {
+            let value: Value = conditional_request(&cache, &host("alice"), url.clone())
+                .await
+                .unwrap();
+            assert_eq!(value, json!({ "commit": { "sha": "1234" } }));
+        }
+        alice_mock.assert();
+
+        // another token doesn't get (or revalidate) what the first one fetched
+        let bob_mock = mockito::mock("GET", "/repos/luizribeiro/private-etag/branches/main")
+            .match_header("authorization", "Bearer bob")
+            .match_header("if-none-match", mockito::Matcher::Missing)
+            .with_status(404)
+            .create();
+        let value: Option<Value> = conditional_request_if_found(&cache, &host("bob"), url)
+            .await
+            .unwrap();
+        assert_eq!(value, None);
+        bob_mock.assert();
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_handles_rate_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path());
+        let host = GitHubHost::default();
+        let url = |repo: &str| {
+            reqwest::Url::parse(&format!(
+                "{}/repos/luizribeiro/{}/releases/latest",
+                mockito::server_url(),
+                repo
+            ))
+            .unwrap()
+        };
+
+        let _secondary_mock = mockito::mock("GET", "/repos/luizribeiro/secondary/releases/latest")
+            .with_status(403)
+            .with_header("retry-after", "0")
+            .expect(1)
+            .create();
+        let _release_mock = mockito::mock("GET", "/repos/luizribeiro/secondary/releases/latest")
+            .with_status(200)
+            .with_body(r#"{ "tag_name": "v1.0.0" }"#)
+            .create();
+        let value: Value = conditional_request(&cache, &host, url("secondary"))
+            .await
+            .unwrap();
+        assert_eq!(value, json!({ "tag_name": "v1.0.0" }));
+
+        let _primary_mock = mockito::mock("GET", "/repos/luizribeiro/primary/releases/latest")
+            .with_status(403)
+            .with_header("x-ratelimit-remaining", "0")
+            .with_header("x-ratelimit-reset", "1700058720")
+            .with_body(r#"{ "message": "API rate limit exceeded" }"#)
+            .create();
+        match conditional_request::<Value>(&cache, &host, url("primary")).await {
+            Err(Error::GitHubRateLimited { until, .. }) => assert_eq!(until, "until 14:32 UTC"),
+            _ => panic!("expected a rate limit error"),
+        }
+
+        let _forbidden_mock = mockito::mock("GET", "/repos/luizribeiro/private/releases/latest")
+            .with_status(404)
+            .with_body(r#"{ "message": "Not Found" }"#)
+            .create();
+        match conditional_request::<Value>(&cache, &host, url("private")).await {
+            Err(Error::NotFound { url, hint }) => {
+                assert!(url.ends_with("/repos/luizribeiro/private/releases/latest"));
+                assert!(hint.contains("spelling"));
+            }
+            _ => panic!("expected a not found error"),
+        }
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_hashes_urls() {
+        let _asset_mock = mockito::mock("GET", "/releases/download/v1.0.0/hello.txt")
+            .with_status(200)
+            .with_body("hello")
+            .create();
+        let _missing_mock = mockito::mock("GET", "/releases/download/v1.0.0/missing.txt")
+            .with_status(404)
+            .create();
+
+        let url = |name: &str| {
+            format!(
+                "{}/releases/download/v1.0.0/{}",
+                mockito::server_url(),
+                name
+            )
+        };
+        assert_eq!(
+            compute_url_sha256(&url("hello.txt")).await.unwrap(),
+            "094qif9n4cq4fdg459qzbhg1c6wywawwaaivx0k0x8xhbyx4vwic"
+        );
+        assert!(compute_url_sha256(&url("missing.txt")).await.is_err());
+        mockito::reset();
+    }
+
+    #[test]
+    fn it_formats_resets() {
+        assert_eq!(format_reset(1700058720), "14:32 UTC");
+    }
+
+    #[test]
+    fn it_parses_gitmodules() {
+        let urls = parse_gitmodules(
+            r#"
+[submodule "vendor/lib"]
+	path = vendor/lib
+	url = https://github.com/luizribeiro/lib.git
+[submodule "docs"]
+	url = ../docs.git
+	path = docs
+	branch = main
+"#,
+        );
+        assert_eq!(
+            urls.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("docs".to_string(), "../docs.git".to_string()),
+                (
+                    "vendor/lib".to_string(),
+                    "https://github.com/luizribeiro/lib.git".to_string()
+                ),
+            ],
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reuses_locked_hashes() {
+        let commit = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let mut lock_file = crate::lock::LockFile::new();
+        lock_file.insert(
+            "$GITHUB_BRANCH$:luizribeiro/known:main$".to_string(),
+            json!({
+                "owner": "luizribeiro",
+                "repo": "known",
+                "rev": commit,
+                "sha256": "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j",
+                "fetchSubmodules": false,
+                "deepClone": false,
+                "leaveDotGit": false,
+            })
+            .into(),
+        );
+        lock_file.insert("grafana/grafana".to_string(), json!("sha256:foobar").into());
+        remember_hashes(&lock_file);
+        assert_eq!(
+            compute_nix_sha256(
+                &GitHubHost {
+                    host: "github.com".to_string(),
+                    ..Default::default()
+                },
+                "luizribeiro",
+                "known",
+                commit,
+                None,
+                None,
+                None
+            )
+            .await
+            .unwrap(),
+            "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j",
+        );
+    }
+
+    #[test]
+    fn it_prefetches_locked_tags_again() {
+        let mut lock_file = crate::lock::LockFile::new();
+        lock_file.insert(
+            "$GITHUB_RELEASE$:luizribeiro/retagged$".to_string(),
+            json!({
+                "owner": "luizribeiro",
+                "repo": "retagged",
+                "rev": "v1.0.0",
+                "sha256": "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j",
+                "fetchSubmodules": false,
+                "deepClone": false,
+                "leaveDotGit": false,
+            })
+            .into(),
+        );
+        remember_hashes(&lock_file);
+        let dir = tempfile::tempdir().unwrap();
+        let key = hash_key(
+            "github.com",
+            "luizribeiro",
+            "retagged",
+            "v1.0.0",
+            &flags(Some(false), Some(false), Some(false)),
+        );
+        // the tag may point somewhere else now
+        assert_eq!(known_hash(&Cache::new(dir.path()), &key), None);
+    }
+
+    #[test]
+    fn it_keeps_hashes_of_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path());
+        let commit = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let sha256 = "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j";
+        remember_hash(
+            &cache,
+            "github.com/o/kept@commit$".to_string(),
+            commit,
+            sha256,
+        );
+        remember_hash(&cache, "github.com/o/kept@tag$".to_string(), "v1.0", sha256);
+
+        // as a later run would see it
+        let cache = Cache::new(dir.path());
+        assert_eq!(
+            cache.read("hashes", "github.com/o/kept@commit$").as_deref(),
+            Some(sha256)
+        );
+        assert_eq!(cache.read("hashes", "github.com/o/kept@tag$"), None);
+        assert_eq!(
+            known_hash(&cache, "github.com/o/kept@commit$").as_deref(),
+            Some(sha256)
+        );
+    }
+}
@@ -1,13 +1,26 @@
+use crate::config::GitHubConfig;
 use crate::deps::assert_kind;
-use crate::deps::github;
-use crate::deps::Lockable;
+use crate::deps::github::{self, GitHubHost};
+use crate::deps::{self, DependencyMetadata, Lockable};
 use crate::error::Error;
+use crate::lock::{AssetLock, GitHubLock, LockData, LockMetadata, ReleaseLock, SubmoduleLock};
+use crate::semver::{Constraint, Version};
 use crate::util;
 use crate::util::ParsingContext;
 use async_trait::async_trait;
 use rnix::{SyntaxKind, SyntaxNode};
 use serde::{Deserialize, Serialize};
 
+const RELEASES_PER_PAGE: usize = 100;
+
+const ASSET_EXAMPLE: &str = r#"here is an example of valid usage:
+
+  uptix.githubReleaseAsset {
+    owner = "luizribeiro";
+    repo = "uptix";
+    asset = "uptix-*-x86_64-linux.tar.gz";
+  }"#;
+
 #[derive(Default, Serialize, Deserialize, PartialEq, Clone, Debug)]
 #[allow(non_snake_case)]
 pub struct GitHubRelease {
@@ -16,9 +29,40 @@ pub struct GitHubRelease {
     fetchSubmodules: Option<bool>,
     deepClone: Option<bool>,
     leaveDotGit: Option<bool>,
+    /// glob matching the name of a release asset to lock, i.e.
+    /// `uptix-*-x86_64-linux.tar.gz`
+    asset: Option<String>,
+    /// range of versions to pick the newest release from, i.e. `~1.4` or
+    /// `>=2, <3`, instead of the latest release
+    versionConstraint: Option<String>,
+    /// whether prereleases may be picked, or should be left out even when
+    /// they were published as full releases
+    allowPrerelease: Option<bool>,
+    /// only releases whose tag starts with this are picked, i.e. `cli-v` on
+    /// monorepos tagging several components (`cli-v1.2.3`, `lib-v4.5.6`)
+    tagPrefix: Option<String>,
+    /// like `tagPrefix`, but a glob the whole tag must match (i.e.
+    /// `cli-v*.*.*`)
+    tagPattern: Option<String>,
+    /// like `tagPattern`, but a regex. Its first group, if any, is taken to
+    /// be the version (i.e. `^cli-v(.*)$`).
+    tagRegex: Option<String>,
+    /// a specific tag to lock, i.e. `v2.4.1`, instead of the newest one
+    tag: Option<String>,
+    /// fail unless the tag (or the commit it points to) is signed, as
+    /// verified by GitHub
+    verifySignature: Option<bool>,
+    /// record the revision of each submodule on the lock, along with
+    /// `fetchSubmodules`
+    lockSubmodules: Option<bool>,
+    /// host of a GitHub Enterprise instance, instead of github.com
+    host: Option<String>,
+    #[serde(skip)]
+    github: GitHubHost,
     override_scheme: Option<String>,
     override_domain: Option<String>,
     override_nix_sha256: Option<String>,
+    override_asset_sha256: Option<String>,
 }
 
 impl GitHubRelease {
@@ -36,66 +80,521 @@ impl GitHubRelease {
   }"#,
         )?)
     }
+
+    /// Parses `uptix.githubReleaseAsset`, which is a release with an asset
+    pub fn new_asset(context: &ParsingContext, node: &SyntaxNode) -> Result<GitHubRelease, Error> {
+        let release: GitHubRelease = util::from_attr_set(assert_kind(
+            context,
+            "uptix.githubReleaseAsset",
+            node,
+            SyntaxKind::NODE_ATTR_SET,
+            ASSET_EXAMPLE,
+        )?)?;
+        if release.asset.is_none() {
+            return Err(Error::NixParsingError(format!(
+                "uptix.githubReleaseAsset needs an asset pattern, {}",
+                ASSET_EXAMPLE
+            )));
+        }
+        return Ok(release);
+    }
+
+    /// Follows the latest release of a repository on github.com, for
+    /// locking it without a nix file. `configure` applies uptix.toml.
+    pub fn repository(owner: &str, repo: &str) -> GitHubRelease {
+        let mut dependency = GitHubRelease {
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            ..Default::default()
+        };
+        dependency.configure(&GitHubConfig::default());
+        return dependency;
+    }
+
+    /// Locks the given tag instead of the newest release
+    pub fn with_tag(mut self, tag: &str) -> GitHubRelease {
+        self.tag = Some(tag.to_string());
+        return self;
+    }
+
+    /// Picks the newest release within a range of versions (i.e. `~1.4`)
+    pub fn with_version_constraint(mut self, constraint: &str) -> GitHubRelease {
+        self.versionConstraint = Some(constraint.to_string());
+        return self;
+    }
+
+    /// Locks the release asset matching a glob, as
+    /// `uptix.githubReleaseAsset` does
+    pub fn with_asset(mut self, pattern: &str) -> GitHubRelease {
+        self.asset = Some(pattern.to_string());
+        return self;
+    }
+
+    pub fn configure(&mut self, config: &GitHubConfig) {
+        self.github = GitHubHost::new(config, self.host());
+    }
+
+    fn host(&self) -> &str {
+        return self.host.as_deref().unwrap_or(github::DEFAULT_HOST);
+    }
+
+    async fn lock_submodules(&self, rev: &str) -> Result<Option<Vec<SubmoduleLock>>, Error> {
+        if !self.lockSubmodules.unwrap_or(false) {
+            return Ok(None);
+        }
+        let submodules = github::fetch_submodules(&self.github, |p| api_url(self, p), rev).await?;
+        return Ok(Some(submodules));
+    }
+
+    pub fn identity(&self) -> String {
+        return format!(
+            "github:{}{}/{}",
+            github::host_prefix(&self.host),
+            self.owner,
+            self.repo
+        );
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Default, Serialize, Deserialize, Debug)]
 struct GitHubLatestReleaseInfo {
     tag_name: String,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    prerelease: bool,
+    #[serde(default)]
+    assets: Vec<GitHubReleaseAsset>,
+    html_url: Option<String>,
+    body: Option<String>,
+    published_at: Option<String>,
+    /// how the release was picked, when there is no latest release
+    #[serde(skip)]
+    fallback: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitHubTag {
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GitHubReleaseAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl GitHubRelease {
+    async fn lock_asset(
+        &self,
+        pattern: &str,
+        release: &GitHubLatestReleaseInfo,
+    ) -> Result<AssetLock, Error> {
+        let matcher = globset::Glob::new(pattern)?.compile_matcher();
+        let asset = release
+            .assets
+            .iter()
+            .find(|asset| matcher.is_match(&asset.name))
+            .ok_or_else(|| Error::NoMatchingAsset {
+                name: format!("{}/{}", self.owner, self.repo),
+                tag: release.tag_name.clone(),
+                pattern: pattern.to_string(),
+            })?;
+        let sha256 = match &self.override_asset_sha256 {
+            Some(s) => s.to_string(),
+            None => github::compute_url_sha256(&asset.browser_download_url).await?,
+        };
+        return Ok(AssetLock {
+            name: asset.name.clone(),
+            url: asset.browser_download_url.clone(),
+            sha256,
+        });
+    }
+}
+
+fn api_url(dependency: &GitHubRelease, path: &str) -> Result<reqwest::Url, Error> {
+    return github::api_url(
+        &dependency.github,
+        &dependency.override_scheme,
+        &dependency.override_domain,
+        &dependency.owner,
+        &dependency.repo,
+        path,
+    );
 }
 
 async fn fetch_github_latest_release(
     dependency: &GitHubRelease,
 ) -> Result<GitHubLatestReleaseInfo, Error> {
-    let client = reqwest::Client::new();
-    let url_as_str = format!(
-        "{}://{}/repos/{}/{}/releases/latest",
-        dependency
-            .override_scheme
-            .as_ref()
-            .unwrap_or(&"https".to_string()),
-        dependency
-            .override_domain
-            .as_ref()
-            .unwrap_or(&"api.github.com".to_string()),
-        dependency.owner,
-        dependency.repo,
+    let url = api_url(dependency, "releases/latest")?;
+    if let Some(release) = github::github_api_request_if_found(&dependency.github, url).await? {
+        return Ok(release);
+    }
+    // GitHub answers the same way for repositories which don't exist (or
+    // which the token can't see), which shouldn't be mistaken for having no
+    // latest release
+    let url = api_url(dependency, "")?;
+    let _: serde_json::Value = github::github_api_request(&dependency.github, url).await?;
+    // prereleases are never the latest release, so repositories which only
+    // publish those (or only tag their versions) don't have one
+    let releases = fetch_github_releases(dependency).await?;
+    if let Some(release) = pick_release(releases, None, true, &TagFilter::default()) {
+        return Ok(GitHubLatestReleaseInfo {
+            fallback: Some("the newest prerelease, as there is no latest release".to_string()),
+            ..release
+        });
+    }
+    let tags = fetch_github_tags(dependency).await?;
+    // tags aren't listed in any particular order, so versions are compared
+    let newest = tags
+        .iter()
+        .filter_map(|tag| Some((Version::parse(&tag.name)?, tag)))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, tag)| tag)
+        .or(tags.first())
+        .ok_or_else(|| {
+            Error::GitHubError(format!(
+                "{}/{} has no releases or tags",
+                dependency.owner, dependency.repo
+            ))
+        })?;
+    return Ok(GitHubLatestReleaseInfo {
+        tag_name: newest.name.clone(),
+        fallback: Some("the newest tag, as there are no releases".to_string()),
+        ..Default::default()
+    });
+}
+
+async fn fetch_github_releases(
+    dependency: &GitHubRelease,
+) -> Result<Vec<GitHubLatestReleaseInfo>, Error> {
+    let mut releases = vec![];
+    for page in 1.. {
+        let path = format!("releases?per_page={}&page={}", RELEASES_PER_PAGE, page);
+        let url = api_url(dependency, &path)?;
+        let batch: Vec<GitHubLatestReleaseInfo> =
+            github::github_api_request(&dependency.github, url).await?;
+        let done = batch.len() < RELEASES_PER_PAGE;
+        releases.extend(batch);
+        if done {
+            break;
+        }
+    }
+    return Ok(releases);
+}
+
+async fn fetch_github_tags(dependency: &GitHubRelease) -> Result<Vec<GitHubTag>, Error> {
+    let mut tags = vec![];
+    for page in 1.. {
+        let path = format!("tags?per_page={}&page={}", RELEASES_PER_PAGE, page);
+        let url = api_url(dependency, &path)?;
+        let batch: Vec<GitHubTag> = github::github_api_request(&dependency.github, url).await?;
+        let done = batch.len() < RELEASES_PER_PAGE;
+        tags.extend(batch);
+        if done {
+            break;
+        }
+    }
+    return Ok(tags);
+}
+
+#[derive(Deserialize, Debug)]
+struct GitObject {
+    #[serde(rename = "type")]
+    kind: String,
+    sha: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitRef {
+    object: GitObject,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitTag {
+    object: GitObject,
+    verification: Option<github::Verification>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitCommit {
+    verification: Option<github::Verification>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CommitInfo {
+    commit: GitCommit,
+}
+
+/// Checks the signature of an annotated tag or, failing that, of the commit
+/// the tag points to
+async fn verify_signature(dependency: &GitHubRelease, tag: &str) -> Result<(), Error> {
+    let url = api_url(dependency, &format!("git/ref/tags/{}", tag))?;
+    let tag_ref: GitRef = github::github_api_request(&dependency.github, url).await?;
+    let mut commit = tag_ref.object.sha;
+    if tag_ref.object.kind == "tag" {
+        let url = api_url(dependency, &format!("git/tags/{}", commit))?;
+        let annotated: GitTag = github::github_api_request(&dependency.github, url).await?;
+        let identity = dependency.identity();
+        if github::check_signature(&identity, tag, annotated.verification.as_ref()).is_ok() {
+            return Ok(());
+        }
+        commit = annotated.object.sha;
+    }
+    let url = api_url(dependency, &format!("commits/{}", commit))?;
+    let info: CommitInfo = github::github_api_request(&dependency.github, url).await?;
+    return github::check_signature(
+        &dependency.identity(),
+        tag,
+        info.commit.verification.as_ref(),
     );
-    let url = reqwest::Url::parse(&url_as_str)?;
-    let response = client
-        .request(reqwest::Method::GET, url)
-        .header(reqwest::header::USER_AGENT, util::user_agent())
-        .send()
-        .await?
-        .text()
-        .await?;
-    return Ok(serde_json::from_str(&response)?);
+}
+
+/// Which tags belong to the component a dependency follows, on repositories
+/// which tag several of them
+#[derive(Default)]
+struct TagFilter {
+    /// comes before the version on every tag of the component
+    prefix: String,
+    pattern: Option<globset::GlobMatcher>,
+    regex: Option<regex::Regex>,
+}
+
+impl TagFilter {
+    fn new(dependency: &GitHubRelease) -> Result<TagFilter, Error> {
+        let pattern = match &dependency.tagPattern {
+            Some(pattern) => Some(globset::Glob::new(pattern)?.compile_matcher()),
+            None => None,
+        };
+        // the literal start of a pattern (i.e. `cli-v` on `cli-v*`) works
+        // as a prefix too
+        let prefix = match (&dependency.tagPrefix, &dependency.tagPattern) {
+            (Some(prefix), _) => prefix.clone(),
+            (None, Some(pattern)) => pattern
+                .split(['*', '?', '[', '{'])
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            (None, None) => String::new(),
+        };
+        let regex = match &dependency.tagRegex {
+            Some(regex) => Some(regex::Regex::new(regex).map_err(|e| {
+                Error::NixParsingError(format!("invalid tagRegex {}: {}", regex, e))
+            })?),
+            None => None,
+        };
+        return Ok(TagFilter {
+            prefix,
+            pattern,
+            regex,
+        });
+    }
+
+    fn matches(&self, tag: &str) -> bool {
+        return tag.starts_with(&self.prefix)
+            && self.pattern.as_ref().is_none_or(|p| p.is_match(tag))
+            && self.regex.as_ref().is_none_or(|r| r.is_match(tag));
+    }
+
+    /// The version on a tag of the component, i.e. `1.2.3` on `cli-v1.2.3`
+    fn version(&self, tag: &str) -> Option<Version> {
+        let tag = tag.strip_prefix(&self.prefix)?;
+        let group = self
+            .regex
+            .as_ref()
+            .and_then(|r| r.captures(tag)?.get(1))
+            .map(|group| group.as_str());
+        return Version::parse(group.unwrap_or(tag));
+    }
+}
+
+/// Whether a release is a prerelease, either because it is marked as one or
+/// because its tag says so (i.e. an RC published as a full release)
+fn is_prerelease(release: &GitHubLatestReleaseInfo, tags: &TagFilter) -> bool {
+    return release.prerelease
+        || tags
+            .version(&release.tag_name)
+            .is_some_and(|v| v.is_prerelease());
+}
+
+/// Picks the newest release whose tag satisfies the constraint or, without
+/// one, the most recent release. Drafts and tags of other components are
+/// always left out.
+fn pick_release(
+    releases: Vec<GitHubLatestReleaseInfo>,
+    constraint: Option<&Constraint>,
+    allow_prerelease: bool,
+    tags: &TagFilter,
+) -> Option<GitHubLatestReleaseInfo> {
+    let mut candidates = releases.into_iter().filter(|r| {
+        !r.draft && tags.matches(&r.tag_name) && (allow_prerelease || !is_prerelease(r, tags))
+    });
+    let constraint = match constraint {
+        Some(constraint) => constraint,
+        // releases are listed from the most recent one
+        None => return candidates.next(),
+    };
+    return candidates
+        .filter_map(|r| Some((tags.version(&r.tag_name)?, r)))
+        .filter(|(version, _)| match allow_prerelease {
+            true => constraint.matches_prerelease(version),
+            false => constraint.matches(version),
+        })
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, release)| release);
+}
+
+/// Looks up the release of a specific tag, which may also be a tag without
+/// a release
+async fn fetch_github_tag(
+    dependency: &GitHubRelease,
+    tag: &str,
+) -> Result<GitHubLatestReleaseInfo, Error> {
+    let url = api_url(dependency, &format!("releases/tags/{}", tag))?;
+    if let Some(release) = github::github_api_request_if_found(&dependency.github, url).await? {
+        return Ok(release);
+    }
+    let url = api_url(dependency, &format!("git/ref/tags/{}", tag))?;
+    let tag_ref: Option<GitRef> =
+        github::github_api_request_if_found(&dependency.github, url).await?;
+    return match tag_ref {
+        Some(_) => Ok(GitHubLatestReleaseInfo {
+            tag_name: tag.to_string(),
+            fallback: Some("a tag without a release".to_string()),
+            ..Default::default()
+        }),
+        None => Err(Error::GitHubError(format!(
+            "{}/{} has no tag {}",
+            dependency.owner, dependency.repo, tag
+        ))),
+    };
+}
+
+async fn fetch_github_release(
+    dependency: &GitHubRelease,
+) -> Result<GitHubLatestReleaseInfo, Error> {
+    if let Some(tag) = &dependency.tag {
+        return fetch_github_tag(dependency, tag).await;
+    }
+    if dependency.versionConstraint.is_none()
+        && dependency.allowPrerelease.is_none()
+        && dependency.tagPrefix.is_none()
+        && dependency.tagPattern.is_none()
+        && dependency.tagRegex.is_none()
+    {
+        return fetch_github_latest_release(dependency).await;
+    }
+    let constraint = match &dependency.versionConstraint {
+        Some(constraint) => Some(Constraint::parse(constraint)?),
+        None => None,
+    };
+    let releases = fetch_github_releases(dependency).await?;
+    let allow_prerelease = dependency.allowPrerelease.unwrap_or(false);
+    let tags = TagFilter::new(dependency)?;
+    return pick_release(releases, constraint.as_ref(), allow_prerelease, &tags).ok_or_else(|| {
+        Error::NoMatchingRelease {
+            name: format!("{}/{}", dependency.owner, dependency.repo),
+            constraint: dependency
+                .versionConstraint
+                .as_deref()
+                .unwrap_or("the constraints")
+                .to_string(),
+        }
+    });
 }
 
 #[async_trait]
 impl Lockable for GitHubRelease {
     fn key(&self) -> String {
-        return format!(
-            "$GITHUB_RELEASE$:{}/{}${}",
+        let key = format!(
+            "$GITHUB_RELEASE$:{}{}/{}${}",
+            github::host_prefix(&self.host),
             self.owner,
             self.repo,
             github::flags(self.fetchSubmodules, self.deepClone, self.leaveDotGit)
         );
+        let key = match &self.asset {
+            Some(asset) => format!("{}${}", key, asset),
+            None => key,
+        };
+        let key = match &self.versionConstraint {
+            Some(constraint) => format!("{}@{}", key, constraint),
+            None => key,
+        };
+        let key = match self.allowPrerelease {
+            Some(true) => format!("{}+prerelease", key),
+            Some(false) => format!("{}-prerelease", key),
+            None => key,
+        };
+        let key = match &self.tagPrefix {
+            Some(prefix) => format!("{}#prefix:{}", key, prefix),
+            None => key,
+        };
+        let key = match &self.tagPattern {
+            Some(pattern) => format!("{}#pattern:{}", key, pattern),
+            None => key,
+        };
+        let key = match &self.tagRegex {
+            Some(regex) => format!("{}#regex:{}", key, regex),
+            None => key,
+        };
+        return match &self.tag {
+            Some(tag) => format!("{}#tag:{}", key, tag),
+            None => key,
+        };
+    }
+
+    async fn metadata(&self) -> Result<DependencyMetadata, Error> {
+        let release = fetch_github_release(self).await?;
+        let submodules = self.lock_submodules(&release.tag_name).await?;
+        return Ok(DependencyMetadata {
+            resolved_version: Some(release.tag_name),
+            source_url: release.html_url,
+            timestamp: release.published_at,
+            resolved_from: release.fallback,
+            submodules: submodules
+                .unwrap_or_default()
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            notes: release.body.as_deref().and_then(deps::truncate_notes),
+            ..Default::default()
+        });
     }
 
-    async fn lock(&self) -> Result<Box<dyn erased_serde::Serialize>, Error> {
-        let rev = fetch_github_latest_release(self).await?.tag_name;
+    async fn lock(&self) -> Result<LockData, Error> {
+        let release = fetch_github_release(self).await?;
+        if self.verifySignature.unwrap_or(false) {
+            verify_signature(self, &release.tag_name).await?;
+        }
+        let asset = match &self.asset {
+            Some(pattern) => Some(self.lock_asset(pattern, &release).await?),
+            None => None,
+        };
+        let rev = release.tag_name;
         let sha256 = match &self.override_nix_sha256 {
             Some(s) => s.to_string(),
-            None => github::compute_nix_sha256(
-                &self.owner,
-                &self.repo,
-                &rev,
-                self.fetchSubmodules,
-                self.deepClone,
-                self.leaveDotGit,
-            )?,
-        };
-        return Ok(Box::new(github::GitHubLock {
+            None => {
+                github::compute_nix_sha256(
+                    &self.github,
+                    &self.owner,
+                    &self.repo,
+                    &rev,
+                    self.fetchSubmodules,
+                    self.deepClone,
+                    self.leaveDotGit,
+                )
+                .await?
+            }
+        };
+        let submodules = self.lock_submodules(&rev).await?;
+        // kept so that `uptix update` doesn't look the release up again
+        let metadata = LockMetadata {
+            url: release.html_url,
+            notes: release.body.as_deref().and_then(deps::truncate_notes),
+            ..Default::default()
+        };
+        let lock = GitHubLock {
             owner: self.owner.clone(),
             repo: self.repo.clone(),
             rev,
@@ -103,7 +602,14 @@ impl Lockable for GitHubRelease {
             fetchSubmodules: self.fetchSubmodules.unwrap_or(false),
             deepClone: self.deepClone.unwrap_or(false),
             leaveDotGit: self.leaveDotGit.unwrap_or(false),
-        }));
+            githubBase: self.host.clone(),
+            submodules,
+            metadata: (metadata != LockMetadata::default()).then_some(metadata),
+        };
+        return match asset {
+            Some(asset) => Ok(LockData::Release(ReleaseLock { lock, asset })),
+            None => Ok(LockData::GitHub(lock)),
+        };
     }
 }
 
@@ -111,7 +617,8 @@ impl Lockable for GitHubRelease {
 mod tests {
     use super::GitHubRelease;
     use crate::deps::test_util;
-    use crate::deps::Lockable;
+    use crate::deps::{DependencyMetadata, Lockable};
+    use crate::error::Error;
     use serde_json::json;
 
     #[test]
@@ -126,7 +633,7 @@ mod tests {
         )
         .unwrap()
         .iter()
-        .map(|d| d.as_git_hub_release().unwrap().clone())
+        .map(|d| d.kind().as_git_hub_release().unwrap().clone())
         .collect();
         let expected_dependencies = vec![GitHubRelease {
             owner: "luizribeiro".to_string(),
@@ -136,6 +643,33 @@ mod tests {
         assert_eq!(dependencies, expected_dependencies);
     }
 
+    #[test]
+    fn it_builds_releases() {
+        let dependency = GitHubRelease::repository("luizribeiro", "uptix")
+            .with_version_constraint("~1.4")
+            .with_asset("uptix-*-x86_64-linux.tar.gz");
+        assert_eq!(dependency.github.api_url, "https://api.github.com");
+        assert_eq!(
+            GitHubRelease {
+                github: Default::default(),
+                ..dependency
+            },
+            GitHubRelease {
+                owner: "luizribeiro".to_string(),
+                repo: "uptix".to_string(),
+                versionConstraint: Some("~1.4".to_string()),
+                asset: Some("uptix-*-x86_64-linux.tar.gz".to_string()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            GitHubRelease::repository("luizribeiro", "uptix")
+                .with_tag("v1.0.0")
+                .tag,
+            Some("v1.0.0".to_string())
+        );
+    }
+
     #[test]
     fn it_has_a_key() {
         let dependency = GitHubRelease {
@@ -151,7 +685,7 @@ mod tests {
         let address = mockito::server_address().to_string();
         let _latest_release_mock = mockito::mock("GET", "/repos/luizribeiro/uptix/releases/latest")
             .match_header(
-                &reqwest::header::USER_AGENT.to_string(),
+                reqwest::header::USER_AGENT.as_str(),
                 mockito::Matcher::Regex(r"^uptix/[0-9.]+$".to_string()),
             )
             .with_status(200)
@@ -191,6 +725,540 @@ mod tests {
         mockito::reset();
     }
 
+    #[tokio::test]
+    async fn it_has_release_notes() {
+        let address = mockito::server_address().to_string();
+        let _latest_release_mock = mockito::mock("GET", "/repos/luizribeiro/notes/releases/latest")
+            .with_status(200)
+            .with_body(
+                r#"{
+                    "tag_name": "v2.0.0",
+                    "html_url": "https://github.com/luizribeiro/notes/releases/tag/v2.0.0",
+                    "body": "What's Changed\r\n\r\n* Drop support for v1 locks"
+                }"#,
+            )
+            .create();
+
+        let dependency = GitHubRelease {
+            owner: "luizribeiro".to_string(),
+            repo: "notes".to_string(),
+            override_scheme: Some("http".to_string()),
+            override_domain: Some(address),
+            override_nix_sha256: Some(
+                "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j".to_string(),
+            ),
+            ..Default::default()
+        };
+        // the notes are kept on the lock, so that they aren't looked up again
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(
+            lock_value["metadata"],
+            json!({
+                "url": "https://github.com/luizribeiro/notes/releases/tag/v2.0.0",
+                "notes": "What's Changed\n* Drop support for v1 locks",
+            }),
+        );
+        assert_eq!(
+            dependency.metadata().await.unwrap(),
+            DependencyMetadata {
+                resolved_version: Some("v2.0.0".to_string()),
+                source_url: Some(
+                    "https://github.com/luizribeiro/notes/releases/tag/v2.0.0".to_string()
+                ),
+                notes: Some("What's Changed\n* Drop support for v1 locks".to_string()),
+                ..Default::default()
+            },
+        );
+
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_locks_assets() {
+        let address = mockito::server_address().to_string();
+        let _latest_release_mock = mockito::mock("GET", "/repos/luizribeiro/hello/releases/latest")
+            .with_status(200)
+            .with_body(
+                r#"{
+                    "tag_name": "v1.2.0",
+                    "assets": [
+                        {
+                            "name": "hello-1.2.0-aarch64-linux.tar.gz",
+                            "browser_download_url": "https://github.com/luizribeiro/hello/releases/download/v1.2.0/hello-1.2.0-aarch64-linux.tar.gz"
+                        },
+                        {
+                            "name": "hello-1.2.0-x86_64-linux.tar.gz",
+                            "browser_download_url": "https://github.com/luizribeiro/hello/releases/download/v1.2.0/hello-1.2.0-x86_64-linux.tar.gz"
+                        }
+                    ]
+                }"#,
+            )
+            .create();
+
+        let dependency = GitHubRelease {
+            owner: "luizribeiro".to_string(),
+            repo: "hello".to_string(),
+            asset: Some("hello-*-x86_64-linux.tar.gz".to_string()),
+            override_scheme: Some("http".to_string()),
+            override_domain: Some(address),
+            override_nix_sha256: Some(
+                "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j".to_string(),
+            ),
+            override_asset_sha256: Some(
+                "0ymqcy3fwzsxrfzaxq1nbvvmidaawxwbyih4b10i8hm3mz4ijrgy".to_string(),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(
+            dependency.key(),
+            "$GITHUB_RELEASE$:luizribeiro/hello$$hello-*-x86_64-linux.tar.gz"
+        );
+        let lock = dependency.lock().await.unwrap();
+        let lock_value = serde_json::to_value(lock).unwrap();
+
+        assert_eq!(
+            lock_value,
+            json!({
+                "owner": "luizribeiro",
+                "repo": "hello",
+                "rev": "v1.2.0",
+                "sha256": "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j",
+                "fetchSubmodules": false,
+                "deepClone": false,
+                "leaveDotGit": false,
+                "asset": {
+                    "name": "hello-1.2.0-x86_64-linux.tar.gz",
+                    "url": "https://github.com/luizribeiro/hello/releases/download/v1.2.0/hello-1.2.0-x86_64-linux.tar.gz",
+                    "sha256": "0ymqcy3fwzsxrfzaxq1nbvvmidaawxwbyih4b10i8hm3mz4ijrgy",
+                },
+            }),
+        );
+
+        let dependency = GitHubRelease {
+            asset: Some("*.zip".to_string()),
+            ..dependency
+        };
+        assert!(dependency.lock().await.is_err());
+
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_locks_version_constraints() {
+        let address = mockito::server_address().to_string();
+        let _releases_mock = mockito::mock("GET", "/repos/luizribeiro/uptix/releases")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("per_page".into(), "100".into()),
+                mockito::Matcher::UrlEncoded("page".into(), "1".into()),
+            ]))
+            .with_status(200)
+            .with_body(
+                r#"[
+                    { "tag_name": "v2.0.0" },
+                    { "tag_name": "v1.5.0-rc.1", "prerelease": true },
+                    { "tag_name": "v1.4.7", "draft": true },
+                    { "tag_name": "v1.4.3" },
+                    { "tag_name": "nightly" },
+                    { "tag_name": "v1.4.1" }
+                ]"#,
+            )
+            .create();
+
+        let dependency = GitHubRelease {
+            owner: "luizribeiro".to_string(),
+            repo: "uptix".to_string(),
+            versionConstraint: Some("~1.4".to_string()),
+            override_scheme: Some("http".to_string()),
+            override_domain: Some(address),
+            override_nix_sha256: Some(
+                "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j".to_string(),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(dependency.key(), "$GITHUB_RELEASE$:luizribeiro/uptix$@~1.4");
+        let lock = dependency.lock().await.unwrap();
+        let lock_value = serde_json::to_value(lock).unwrap();
+        assert_eq!(lock_value["rev"], "v1.4.3");
+
+        let dependency = GitHubRelease {
+            versionConstraint: Some(">=3".to_string()),
+            ..dependency
+        };
+        assert!(dependency.lock().await.is_err());
+
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_handles_prereleases() {
+        let address = mockito::server_address().to_string();
+        let _releases_mock = mockito::mock("GET", "/repos/luizribeiro/hello/releases")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(
+                r#"[
+                    { "tag_name": "v3.0.0-beta.1", "prerelease": true },
+                    { "tag_name": "v2.1.0-rc.1" },
+                    { "tag_name": "v2.0.1" },
+                    { "tag_name": "v2.0.0" }
+                ]"#,
+            )
+            .create();
+
+        let dependency = GitHubRelease {
+            owner: "luizribeiro".to_string(),
+            repo: "hello".to_string(),
+            allowPrerelease: Some(true),
+            override_scheme: Some("http".to_string()),
+            override_domain: Some(address),
+            override_nix_sha256: Some(
+                "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j".to_string(),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(
+            dependency.key(),
+            "$GITHUB_RELEASE$:luizribeiro/hello$+prerelease"
+        );
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(lock_value["rev"], "v3.0.0-beta.1");
+
+        // RCs published as full releases are left out too
+        let dependency = GitHubRelease {
+            allowPrerelease: Some(false),
+            ..dependency
+        };
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(lock_value["rev"], "v2.0.1");
+
+        let dependency = GitHubRelease {
+            allowPrerelease: Some(true),
+            versionConstraint: Some(">=2, <3".to_string()),
+            ..dependency
+        };
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(lock_value["rev"], "v2.1.0-rc.1");
+
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_filters_tags() {
+        let address = mockito::server_address().to_string();
+        let _releases_mock = mockito::mock("GET", "/repos/luizribeiro/monorepo/releases")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(
+                r#"[
+                    { "tag_name": "lib-v4.5.6" },
+                    { "tag_name": "cli-v1.3.0-rc.1" },
+                    { "tag_name": "cli-v1.2.3" },
+                    { "tag_name": "cli-v2.0.0", "draft": true },
+                    { "tag_name": "cli-v1.10.0" },
+                    { "tag_name": "cli-nightly" }
+                ]"#,
+            )
+            .create();
+
+        let dependency = GitHubRelease {
+            owner: "luizribeiro".to_string(),
+            repo: "monorepo".to_string(),
+            tagPrefix: Some("cli-v".to_string()),
+            override_scheme: Some("http".to_string()),
+            override_domain: Some(address),
+            override_nix_sha256: Some(
+                "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j".to_string(),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(
+            dependency.key(),
+            "$GITHUB_RELEASE$:luizribeiro/monorepo$#prefix:cli-v"
+        );
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(lock_value["rev"], "cli-v1.2.3");
+
+        let dependency = GitHubRelease {
+            versionConstraint: Some(">=1".to_string()),
+            ..dependency
+        };
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(lock_value["rev"], "cli-v1.10.0");
+
+        let dependency = GitHubRelease {
+            tagPrefix: None,
+            tagPattern: Some("lib-v*.*.*".to_string()),
+            versionConstraint: None,
+            ..dependency
+        };
+        assert_eq!(
+            dependency.key(),
+            "$GITHUB_RELEASE$:luizribeiro/monorepo$#pattern:lib-v*.*.*"
+        );
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(lock_value["rev"], "lib-v4.5.6");
+
+        let dependency = GitHubRelease {
+            tagPattern: None,
+            tagRegex: Some(r"^cli-v(\d+\.\d+\.\d+)$".to_string()),
+            versionConstraint: Some("<1.5".to_string()),
+            ..dependency
+        };
+        assert_eq!(
+            dependency.key(),
+            r"$GITHUB_RELEASE$:luizribeiro/monorepo$@<1.5#regex:^cli-v(\d+\.\d+\.\d+)$"
+        );
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(lock_value["rev"], "cli-v1.2.3");
+
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_falls_back_without_a_latest_release() {
+        let address = mockito::server_address().to_string();
+        let _latest_mock = mockito::mock(
+            "GET",
+            mockito::Matcher::Regex(
+                r"^/repos/luizribeiro/(prereleases|tagged|missing)/releases/latest$".to_string(),
+            ),
+        )
+        .with_status(404)
+        .with_body(r#"{ "message": "Not Found" }"#)
+        .create();
+        let _prereleases_mock = mockito::mock("GET", "/repos/luizribeiro/prereleases/releases")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(
+                r#"[
+                    { "tag_name": "v2.0.0-beta.2", "prerelease": true },
+                    { "tag_name": "v2.0.0-beta.1", "prerelease": true }
+                ]"#,
+            )
+            .create();
+        let _tagged_mock = mockito::mock("GET", "/repos/luizribeiro/tagged/releases")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body("[]")
+            .create();
+        let _repos_mock = mockito::mock(
+            "GET",
+            mockito::Matcher::Regex(r"^/repos/luizribeiro/(prereleases|tagged)$".to_string()),
+        )
+        .with_status(200)
+        .with_body("{}")
+        .create();
+        let _missing_mock = mockito::mock("GET", "/repos/luizribeiro/missing")
+            .with_status(404)
+            .with_body(r#"{ "message": "Not Found" }"#)
+            .create();
+        // more tags than fit in a page, with the newest one on the second
+        let first_page: Vec<_> = (0..100)
+            .map(|i| json!({ "name": format!("v1.{}.0", i) }))
+            .collect();
+        let _first_tags_mock = mockito::mock("GET", "/repos/luizribeiro/tagged/tags")
+            .match_query(mockito::Matcher::UrlEncoded("page".into(), "1".into()))
+            .with_status(200)
+            .with_body(serde_json::to_string(&first_page).unwrap())
+            .create();
+        let _second_tags_mock = mockito::mock("GET", "/repos/luizribeiro/tagged/tags")
+            .match_query(mockito::Matcher::UrlEncoded("page".into(), "2".into()))
+            .with_status(200)
+            .with_body(r#"[{ "name": "v1.100.0" }, { "name": "nightly" }]"#)
+            .create();
+
+        let dependency = GitHubRelease {
+            owner: "luizribeiro".to_string(),
+            repo: "prereleases".to_string(),
+            override_scheme: Some("http".to_string()),
+            override_domain: Some(address),
+            override_nix_sha256: Some(
+                "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j".to_string(),
+            ),
+            ..Default::default()
+        };
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(lock_value["rev"], "v2.0.0-beta.2");
+        assert_eq!(
+            dependency.metadata().await.unwrap().resolved_from,
+            Some("the newest prerelease, as there is no latest release".to_string()),
+        );
+
+        let dependency = GitHubRelease {
+            repo: "tagged".to_string(),
+            ..dependency
+        };
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(lock_value["rev"], "v1.100.0");
+        assert_eq!(
+            dependency.metadata().await.unwrap().resolved_from,
+            Some("the newest tag, as there are no releases".to_string()),
+        );
+
+        // repositories which don't exist aren't taken for having no releases
+        let dependency = GitHubRelease {
+            repo: "missing".to_string(),
+            ..dependency
+        };
+        assert!(matches!(
+            dependency.lock().await,
+            Err(Error::NotFound { .. })
+        ));
+
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_locks_pinned_tags() {
+        let address = mockito::server_address().to_string();
+        let _release_mock = mockito::mock("GET", "/repos/luizribeiro/pinned/releases/tags/v2.4.1")
+            .with_status(200)
+            .with_body(r#"{ "tag_name": "v2.4.1" }"#)
+            .create();
+        let _missing_mock = mockito::mock(
+            "GET",
+            mockito::Matcher::Regex(r"^/repos/luizribeiro/pinned/.*/v(1\.0|9\.9)$".to_string()),
+        )
+        .with_status(404)
+        .create();
+        let _ref_mock = mockito::mock("GET", "/repos/luizribeiro/pinned/git/ref/tags/v1.0")
+            .with_status(200)
+            .with_body(r#"{ "object": { "type": "commit", "sha": "1234" } }"#)
+            .create();
+
+        let dependency = GitHubRelease {
+            owner: "luizribeiro".to_string(),
+            repo: "pinned".to_string(),
+            tag: Some("v2.4.1".to_string()),
+            override_scheme: Some("http".to_string()),
+            override_domain: Some(address),
+            override_nix_sha256: Some(
+                "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j".to_string(),
+            ),
+            ..Default::default()
+        };
+        assert_eq!(
+            dependency.key(),
+            "$GITHUB_RELEASE$:luizribeiro/pinned$#tag:v2.4.1"
+        );
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(lock_value["rev"], "v2.4.1");
+
+        // tags without a release work too
+        let dependency = GitHubRelease {
+            tag: Some("v1.0".to_string()),
+            ..dependency
+        };
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(lock_value["rev"], "v1.0");
+
+        let dependency = GitHubRelease {
+            tag: Some("v9.9".to_string()),
+            ..dependency
+        };
+        assert!(dependency.lock().await.is_err());
+
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_verifies_signatures() {
+        let address = mockito::server_address().to_string();
+        let _latest_release_mock =
+            mockito::mock("GET", "/repos/luizribeiro/signed/releases/latest")
+                .with_status(200)
+                .with_body(r#"{ "tag_name": "v1.0.0" }"#)
+                .create();
+        let _ref_mock = mockito::mock("GET", "/repos/luizribeiro/signed/git/ref/tags/v1.0.0")
+            .with_status(200)
+            .with_body(r#"{ "object": { "type": "tag", "sha": "tag1234" } }"#)
+            .create();
+        let _tag_mock = mockito::mock("GET", "/repos/luizribeiro/signed/git/tags/tag1234")
+            .with_status(200)
+            .with_body(
+                r#"{
+                    "object": { "type": "commit", "sha": "commit1234" },
+                    "verification": { "verified": false, "reason": "unsigned" }
+                }"#,
+            )
+            .create();
+        let commit_mock = mockito::mock("GET", "/repos/luizribeiro/signed/commits/commit1234")
+            .with_status(200)
+            .with_body(
+                r#"{
+                    "commit": {
+                        "verification": { "verified": true, "reason": "valid" }
+                    }
+                }"#,
+            )
+            .create();
+
+        let dependency = GitHubRelease {
+            owner: "luizribeiro".to_string(),
+            repo: "signed".to_string(),
+            verifySignature: Some(true),
+            override_scheme: Some("http".to_string()),
+            override_domain: Some(address),
+            override_nix_sha256: Some(
+                "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j".to_string(),
+            ),
+            ..Default::default()
+        };
+        let lock_value = serde_json::to_value(dependency.lock().await.unwrap()).unwrap();
+        assert_eq!(lock_value["rev"], "v1.0.0");
+
+        drop(commit_mock);
+        let _commit_mock = mockito::mock("GET", "/repos/luizribeiro/signed/commits/commit1234")
+            .with_status(200)
+            .with_body(
+                r#"{
+                    "commit": {
+                        "verification": { "verified": false, "reason": "unknown_key" }
+                    }
+                }"#,
+            )
+            .create();
+        match dependency.lock().await {
+            Err(crate::error::Error::UnverifiedSignature { rev, reason, .. }) => {
+                assert_eq!(rev, "v1.0.0");
+                assert_eq!(reason, "unknown_key");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        mockito::reset();
+    }
+
+    #[test]
+    fn it_parses_assets() {
+        let dependencies: Vec<_> = test_util::deps(
+            r#"{
+                hello = fetchurl (uptix.githubReleaseAsset {
+                    owner = "luizribeiro";
+                    repo = "hello";
+                    asset = "hello-*-x86_64-linux.tar.gz";
+                });
+            }"#,
+        )
+        .unwrap()
+        .iter()
+        .map(|d| d.kind().as_git_hub_release().unwrap().clone())
+        .collect();
+        assert_eq!(
+            dependencies,
+            vec![GitHubRelease {
+                owner: "luizribeiro".to_string(),
+                repo: "hello".to_string(),
+                asset: Some("hello-*-x86_64-linux.tar.gz".to_string()),
+                ..Default::default()
+            }],
+        );
+        assert!(test_util::deps(
+            r#"{ hello = uptix.githubReleaseAsset { owner = "a"; repo = "b"; }; }"#
+        )
+        .is_err());
+    }
+
     #[test]
     fn it_provides_helpful_errors() {
         let result = test_util::deps("{ hass = uptix.githubRelease 42; }");
@@ -207,7 +1275,7 @@ mod tests {
                 assert_eq!(expected_type, "NODE_ATTR_SET");
                 assert_eq!(argument_pos, (29, 2).into());
             }
-            _ => assert!(false),
+            other => panic!("unexpected result: {:?}", other),
         }
     }
 }
@@ -0,0 +1,325 @@
+use crate::cache::Cache;
+use crate::error::Error;
+use crate::http;
+use crate::nar;
+use crate::process;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::process::Command;
+
+const OBJECTS_NAMESPACE: &str = "git";
+
+lazy_static! {
+    /// Fetches into the same object cache can't run at once, since git
+    /// locks its shallow file while fetching
+    static ref OBJECT_LOCKS: Mutex<BTreeMap<String, Arc<tokio::sync::Mutex<()>>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Makes git authenticate over SSH with just the given key (i.e. a deploy
+/// key), never prompting for anything
+pub fn ssh_env(ssh_key: Option<&str>) -> Vec<(String, String)> {
+    return match ssh_key {
+        Some(key) => vec![(
+            "GIT_SSH_COMMAND".to_string(),
+            format!(
+                "ssh -i '{}' -o IdentitiesOnly=yes -o BatchMode=yes",
+                key.replace('\'', "'\\''")
+            ),
+        )],
+        None => vec![],
+    };
+}
+
+async fn git(
+    dir: Option<&Path>,
+    url: &str,
+    ssh_key: Option<&str>,
+    args: &[&str],
+) -> Result<String, Error> {
+    return git_with_env(dir, url, ssh_key, &[], args).await;
+}
+
+#[tracing::instrument(name = "git", skip(url, ssh_key, env))]
+async fn git_with_env(
+    dir: Option<&Path>,
+    url: &str,
+    ssh_key: Option<&str>,
+    env: &[(&str, &Path)],
+    args: &[&str],
+) -> Result<String, Error> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+    command
+        .args(args)
+        .envs(env.iter().copied())
+        .envs(http::proxy_env())
+        .envs(ssh_env(ssh_key))
+        .env("GIT_TERMINAL_PROMPT", "0");
+    let output = process::output(&mut command).await?;
+    if !output.status.success() {
+        return Err(Error::GitHubError(format!(
+            "git {} failed for {}: {}",
+            args[0],
+            url,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        )));
+    }
+    return Ok(String::from_utf8_lossy(&output.stdout).to_string());
+}
+
+/// Resolves a ref (i.e. `refs/heads/main` or `HEAD`) to the commit it
+/// points to, without cloning anything
+pub async fn ls_remote(
+    url: &str,
+    reference: &str,
+    ssh_key: Option<&str>,
+) -> Result<Option<String>, Error> {
+    let output = git(None, url, ssh_key, &["ls-remote", url, reference]).await?;
+    return Ok(output
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .find(|(_, name)| *name == reference)
+        .map(|(sha, _)| sha.to_string()));
+}
+
+/// Drops every `.git` (submodules have one too), as `fetchgit` does
+/// Computes the hash `fetchgit` would for a revision without `.git`, from a
+/// clone with nothing but that revision (and its submodules)
+pub async fn compute_sha256(
+    url: &str,
+    rev: &str,
+    fetch_submodules: bool,
+    ssh_key: Option<&str>,
+) -> Result<String, Error> {
+    // submodules are cloned on their own, so they only work from a clone
+    if !fetch_submodules {
+        if let Some(objects) = Cache::default().directory(OBJECTS_NAMESPACE, url) {
+            return compute_cached_sha256(url, rev, &objects, ssh_key).await;
+        }
+    }
+    let clone = tempfile::tempdir()?;
+    let dir = Some(clone.path());
+    git(dir, url, ssh_key, &["init", "--quiet"]).await?;
+    git(dir, url, ssh_key, &["remote", "add", "origin", url]).await?;
+    git(
+        dir,
+        url,
+        ssh_key,
+        &[
+            "fetch",
+            "--quiet",
+            "--depth",
+            "1",
+            "--no-tags",
+            "origin",
+            rev,
+        ],
+    )
+    .await?;
+    git(dir, url, ssh_key, &["checkout", "--quiet", "FETCH_HEAD"]).await?;
+    if fetch_submodules {
+        git(
+            dir,
+            url,
+            ssh_key,
+            &[
+                "submodule",
+                "update",
+                "--quiet",
+                "--init",
+                "--recursive",
+                "--depth",
+                "1",
+            ],
+        )
+        .await?;
+    }
+    // submodules have a `.git` of their own, which is left out too
+    return Ok(nar::sha256_path(clone.path(), &[".git"])?);
+}
+
+/// Like `compute_sha256`, but fetching into a bare repository which is kept
+/// between runs, so that only the objects a revision doesn't share with
+/// earlier ones are downloaded
+async fn compute_cached_sha256(
+    url: &str,
+    rev: &str,
+    objects: &Path,
+    ssh_key: Option<&str>,
+) -> Result<String, Error> {
+    let lock = OBJECT_LOCKS
+        .lock()
+        .unwrap()
+        .entry(url.to_string())
+        .or_default()
+        .clone();
+    let _guard = lock.lock().await;
+    let repo = Some(objects);
+    if !objects.join("HEAD").exists() {
+        std::fs::create_dir_all(objects)?;
+        git(repo, url, ssh_key, &["init", "--quiet", "--bare"]).await?;
+    }
+    let reference = format!("refs/uptix/{}", rev);
+    git(
+        repo,
+        url,
+        ssh_key,
+        &[
+            "fetch",
+            "--quiet",
+            "--depth",
+            "1",
+            "--no-tags",
+            url,
+            &format!("+{}:{}", rev, reference),
+        ],
+    )
+    .await?;
+    // checked out with an index of its own, leaving the cache as it was
+    let index = tempfile::tempdir()?;
+    let index = index.path().join("index");
+    let dir = tempfile::tempdir()?;
+    let work_tree = format!("--work-tree={}", dir.path().display());
+    let env = [("GIT_INDEX_FILE", index.as_path())];
+    git_with_env(
+        repo,
+        url,
+        ssh_key,
+        &env,
+        &[&work_tree, "read-tree", &reference],
+    )
+    .await?;
+    git_with_env(
+        repo,
+        url,
+        ssh_key,
+        &env,
+        &[&work_tree, "checkout-index", "--all", "--force"],
+    )
+    .await?;
+    return Ok(nar::sha256_path(dir.path(), &[])?);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compute_cached_sha256, compute_sha256, ls_remote, ssh_env};
+    use crate::nar::{self, Node};
+    use std::collections::BTreeMap;
+    use std::path::Path;
+    use std::process::Command;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args([
+                "-c",
+                "user.name=uptix",
+                "-c",
+                "user.email=uptix@example.com",
+            ])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        return String::from_utf8_lossy(&output.stdout).trim().to_string();
+    }
+
+    #[tokio::test]
+    async fn it_hashes_shallow_clones() {
+        let repo = tempfile::tempdir().unwrap();
+        git(repo.path(), &["init", "--quiet"]);
+        std::fs::write(repo.path().join("README.md"), "old").unwrap();
+        git(repo.path(), &["add", "."]);
+        git(repo.path(), &["commit", "--quiet", "-m", "old"]);
+        let old = git(repo.path(), &["rev-parse", "HEAD"]);
+        std::fs::write(repo.path().join("README.md"), "new").unwrap();
+        git(repo.path(), &["commit", "--quiet", "-am", "new"]);
+        // allows fetching revisions other than the tips of branches
+        git(
+            repo.path(),
+            &["config", "uploadpack.allowAnySHA1InWant", "true"],
+        );
+
+        let url = format!("file://{}", repo.path().display());
+        let expected = nar::sha256(&Node::Directory(BTreeMap::from([(
+            "README.md".to_string(),
+            Node::Regular {
+                executable: false,
+                contents: b"old".to_vec(),
+            },
+        )])))
+        .unwrap();
+        assert_eq!(
+            compute_sha256(&url, &old, true, None).await.unwrap(),
+            expected
+        );
+        assert!(compute_sha256(&url, "0000000", false, None).await.is_err());
+
+        let head = git(repo.path(), &["rev-parse", "HEAD"]);
+        let branch = git(repo.path(), &["symbolic-ref", "HEAD"]);
+        assert_eq!(ls_remote(&url, &branch, None).await.unwrap(), Some(head));
+        assert_eq!(
+            ls_remote(&url, "refs/heads/missing", None).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reuses_fetched_objects() {
+        let repo = tempfile::tempdir().unwrap();
+        git(repo.path(), &["init", "--quiet"]);
+        std::fs::write(repo.path().join("README.md"), "old").unwrap();
+        git(repo.path(), &["add", "."]);
+        git(repo.path(), &["commit", "--quiet", "-m", "old"]);
+        let old = git(repo.path(), &["rev-parse", "HEAD"]);
+        let url = format!("file://{}", repo.path().display());
+        let cache = tempfile::tempdir().unwrap();
+        let objects = cache.path().join("objects");
+        let hash = |contents: &str| {
+            nar::sha256(&Node::Directory(BTreeMap::from([(
+                "README.md".to_string(),
+                Node::Regular {
+                    executable: false,
+                    contents: contents.as_bytes().to_vec(),
+                },
+            )])))
+            .unwrap()
+        };
+        assert_eq!(
+            compute_cached_sha256(&url, &old, &objects, None)
+                .await
+                .unwrap(),
+            hash("old")
+        );
+
+        std::fs::write(repo.path().join("README.md"), "new").unwrap();
+        git(repo.path(), &["commit", "--quiet", "-am", "new"]);
+        let new = git(repo.path(), &["rev-parse", "HEAD"]);
+        assert_eq!(
+            compute_cached_sha256(&url, &new, &objects, None)
+                .await
+                .unwrap(),
+            hash("new")
+        );
+        // the cache is left bare, with both revisions in it
+        assert!(!objects.join("README.md").exists());
+        assert_eq!(git(&objects, &["cat-file", "-t", &old]), "commit");
+    }
+
+    #[test]
+    fn it_uses_ssh_keys() {
+        assert!(ssh_env(None).is_empty());
+        assert_eq!(
+            ssh_env(Some("/run/secrets/it's")),
+            vec![(
+                "GIT_SSH_COMMAND".to_string(),
+                "ssh -i '/run/secrets/it'\\''s' -o IdentitiesOnly=yes -o BatchMode=yes".to_string()
+            )]
+        );
+    }
+}
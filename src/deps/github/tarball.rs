@@ -0,0 +1,266 @@
+use crate::deps::github;
+use crate::error::Error;
+use crate::http;
+use crate::nar;
+use crate::timings;
+use crate::util;
+use flate2::read::GzDecoder;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+/// How many downloaded chunks may wait to be unpacked, which bounds the
+/// memory a download takes when it outpaces the disk
+const CHUNKS_IN_FLIGHT: usize = 16;
+
+fn archive_error(url: &str, reason: &str) -> Error {
+    return Error::GitHubError(format!("could not unpack {}: {}", url, reason));
+}
+
+/// Reads the chunks of a download as they arrive, so that it can be unpacked
+/// on a blocking thread without being buffered first
+struct ChunkReader<B> {
+    chunks: mpsc::Receiver<B>,
+    current: Option<B>,
+    offset: usize,
+}
+
+impl<B: AsRef<[u8]>> ChunkReader<B> {
+    fn new(chunks: mpsc::Receiver<B>) -> ChunkReader<B> {
+        return ChunkReader {
+            chunks,
+            current: None,
+            offset: 0,
+        };
+    }
+}
+
+impl<B: AsRef<[u8]>> Read for ChunkReader<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(chunk) = &self.current {
+                let rest = &chunk.as_ref()[self.offset..];
+                if !rest.is_empty() {
+                    let len = rest.len().min(buf.len());
+                    buf[..len].copy_from_slice(&rest[..len]);
+                    self.offset += len;
+                    return Ok(len);
+                }
+            }
+            // the download is over (or failed) once the sender is dropped
+            match self.chunks.blocking_recv() {
+                Some(chunk) => {
+                    self.current = Some(chunk);
+                    self.offset = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// The directory a tarball unpacked to, which is what `fetchzip` hashes:
+/// GitHub puts every file under one (i.e. `uptix-<rev>/`)
+fn top_level(dir: &Path) -> Result<PathBuf, String> {
+    let entries = fs::read_dir(dir)
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| e.to_string())?;
+    return match &entries[..] {
+        [entry] if entry.file_type().is_ok_and(|t| t.is_dir()) => Ok(entry.path()),
+        _ => Err("expected a single top-level directory".to_string()),
+    };
+}
+
+/// Unpacks a gzipped tarball as it's read, and hashes what it unpacked to
+/// the way `fetchzip` would. Files are unpacked to a temporary directory
+/// rather than into memory, so that large repositories can be hashed too.
+fn hash_tarball(reader: impl Read) -> Result<String, String> {
+    let dir = tempfile::tempdir().map_err(|e| e.to_string())?;
+    let mut archive = tar::Archive::new(GzDecoder::new(reader));
+    archive.set_preserve_mtime(false);
+    archive.unpack(dir.path()).map_err(|e| e.to_string())?;
+    let root = top_level(dir.path())?;
+    return nar::sha256_path(&root, &[]).map_err(|e| e.to_string());
+}
+
+/// The URL of the tarball `fetchFromGitHub` downloads when it doesn't need
+/// git (i.e. without submodules or `.git`)
+pub fn archive_url(host: &str, owner: &str, repo: &str, rev: &str) -> String {
+    return format!("https://{}/{}/{}/archive/{}.tar.gz", host, owner, repo, rev);
+}
+
+/// The API endpoint serving the same tarball as `archive_url`, which works
+/// for private repositories as well
+pub fn api_tarball_url(api_url: &str, owner: &str, repo: &str, rev: &str) -> String {
+    return format!("{}/repos/{}/{}/tarball/{}", api_url, owner, repo, rev);
+}
+
+/// Computes the hash `fetchzip` would for a tarball, without needing nix
+pub async fn compute_sha256(url: &str, token: Option<&str>) -> Result<String, Error> {
+    let _timer = timings::start("hashing");
+    let mut request = http::client()?
+        .get(url)
+        .header(reqwest::header::USER_AGENT, util::user_agent());
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = http::send(request, http::transient).await?;
+    if let Some(error) = http::status_error(&response, &github::HINTS) {
+        return Err(error);
+    }
+    if !response.status().is_success() {
+        return Err(Error::GitHubError(format!(
+            "downloading {} failed with {}",
+            url,
+            response.status()
+        )));
+    }
+    let (sender, receiver) = mpsc::channel(CHUNKS_IN_FLIGHT);
+    let hashing = tokio::task::spawn_blocking(move || hash_tarball(ChunkReader::new(receiver)));
+    let mut response = response;
+    // the sender is moved in, so that it's dropped once the download is over
+    let downloaded: Result<(), Error> = async move {
+        while let Some(chunk) = response.chunk().await? {
+            // the reader stops early when the archive is invalid
+            if sender.send(chunk).await.is_err() {
+                break;
+            }
+        }
+        return Ok(());
+    }
+    .await;
+    let hashed = hashing.await.map_err(io::Error::other)?;
+    // a download cut short also fails to unpack, but that's not the reason
+    downloaded?;
+    return hashed.map_err(|e| archive_error(url, &e));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{api_tarball_url, compute_sha256, hash_tarball, ChunkReader};
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    /// What `tarball` unpacks to, as `nix hash path --base32` would hash it.
+    /// Computed with a separate implementation of the NAR format.
+    const TARBALL_SHA256: &str = "1s9dx8wrflj04s5jmgyca187cl3s40g518b9fjdndinsp8gwpyql";
+
+    fn entry(name: &str, kind: u8, mode: u32, link: &str, contents: &[u8]) -> Vec<u8> {
+        let mut header = vec![0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(format!("{:07o}", mode).as_bytes());
+        header[124..135].copy_from_slice(format!("{:011o}", contents.len()).as_bytes());
+        header[156] = kind;
+        header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        header[257..265].copy_from_slice(b"ustar\x0000");
+        // the checksum counts its own field as spaces
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        let mut entry = header;
+        entry.extend(contents);
+        entry.resize(entry.len().div_ceil(512) * 512, 0);
+        return entry;
+    }
+
+    fn tarball() -> Vec<u8> {
+        let long_name = format!("uptix-1234/{}/file", "nested".repeat(20));
+        let record = format!(" path={}\n", long_name);
+        // the length of a record counts its own (three) digits
+        let pax = format!("{}{}", record.len() + 3, record);
+        let mut tar = vec![];
+        for part in [
+            entry("pax_global_header", b'g', 0o666, "", b"16 comment=1234\n"),
+            entry("uptix-1234/", b'5', 0o775, "", b""),
+            entry("uptix-1234/README.md", b'0', 0o664, "", b"# uptix\n"),
+            entry("uptix-1234/bin/run", b'0', 0o775, "", b"#!/bin/sh\n"),
+            entry("uptix-1234/docs", b'2', 0o777, "README.md", b""),
+            entry("uptix-1234/COPY", b'1', 0o664, "uptix-1234/README.md", b""),
+            entry("uptix-1234/empty/", b'5', 0o775, "", b""),
+            entry("long", b'x', 0o664, "", pax.as_bytes()),
+            entry("uptix-1234/truncated", b'0', 0o664, "", b"long"),
+        ] {
+            tar.extend(part);
+        }
+        tar.extend(vec![0; 1024]);
+        return tar;
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        return encoder.finish().unwrap();
+    }
+
+    #[test]
+    fn it_hashes_tarballs() {
+        let tar = tarball();
+        assert_eq!(hash_tarball(&gzip(&tar)[..]).unwrap(), TARBALL_SHA256);
+        assert!(hash_tarball(&b"not gzip"[..]).is_err());
+        // fetchzip only strips a single top-level directory
+        let mut two_roots = entry("a/", b'5', 0o775, "", b"");
+        two_roots.extend(entry("b/", b'5', 0o775, "", b""));
+        two_roots.extend(vec![0; 1024]);
+        assert!(hash_tarball(&gzip(&two_roots)[..]).is_err());
+        let truncated = &tar[..512 * 3 + 100];
+        assert!(hash_tarball(&gzip(truncated)[..]).is_err());
+    }
+
+    #[test]
+    fn it_hashes_tarballs_as_they_arrive() {
+        let (sender, receiver) = tokio::sync::mpsc::channel(2);
+        let compressed = gzip(&tarball());
+        let download = std::thread::spawn(move || {
+            for chunk in compressed.chunks(7) {
+                // unpacking may stop before the padding at the end
+                let _ = sender.blocking_send(chunk.to_vec());
+            }
+        });
+        assert_eq!(
+            hash_tarball(ChunkReader::new(receiver)).unwrap(),
+            TARBALL_SHA256
+        );
+        download.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_computes_tarball_hashes() {
+        let tar = tarball();
+        let _archive_mock = mockito::mock("GET", "/luizribeiro/uptix/archive/1234.tar.gz")
+            .with_status(200)
+            .with_body(gzip(&tar))
+            .create();
+        let _missing_mock = mockito::mock("GET", "/luizribeiro/uptix/archive/missing.tar.gz")
+            .with_status(404)
+            .create();
+        let _private_mock = mockito::mock("GET", "/repos/luizribeiro/private/tarball/1234")
+            .match_header("authorization", "Bearer hunter2")
+            .with_status(200)
+            .with_body(gzip(&tar))
+            .create();
+        let _unauthorized_mock = mockito::mock("GET", "/repos/luizribeiro/private/tarball/1234")
+            .match_header("authorization", mockito::Matcher::Missing)
+            .with_status(404)
+            .create();
+
+        let url = format!(
+            "{}/luizribeiro/uptix/archive/1234.tar.gz",
+            mockito::server_url()
+        );
+        assert_eq!(compute_sha256(&url, None).await.unwrap(), TARBALL_SHA256);
+        let url = format!(
+            "{}/luizribeiro/uptix/archive/missing.tar.gz",
+            mockito::server_url()
+        );
+        assert!(compute_sha256(&url, None).await.is_err());
+        let url = api_tarball_url(&mockito::server_url(), "luizribeiro", "private", "1234");
+        assert_eq!(
+            compute_sha256(&url, Some("hunter2")).await.unwrap(),
+            TARBALL_SHA256
+        );
+        assert!(compute_sha256(&url, None).await.is_err());
+
+        mockito::reset();
+    }
+}
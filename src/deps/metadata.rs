@@ -0,0 +1,209 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Details about the version a dependency resolves to which aren't needed to
+/// build it, and thus aren't kept on uptix.lock
+#[derive(Serialize, Default, PartialEq, Clone, Debug)]
+pub struct DependencyMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// when the version was made, i.e. the date of a commit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    /// compressed size in bytes, for images
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layers: Option<usize>,
+    /// why the dependency is deprecated, if it is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<String>,
+    /// how the version was found, when it wasn't the usual way
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolved_from: Option<String>,
+    /// the revision of each submodule, i.e. `vendor/lib: <url>@<rev>`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub submodules: Vec<String>,
+    /// what changed on the version, i.e. the notes of a release, truncated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+const NOTES_MAX_LINES: usize = 10;
+const NOTES_MAX_CHARS: usize = 800;
+
+/// Shortens release notes enough to be read on a terminal, keeping their
+/// first lines
+pub fn truncate_notes(notes: &str) -> Option<String> {
+    let lines: Vec<_> = notes
+        .trim()
+        .lines()
+        .map(|line| line.trim_end())
+        .filter(|line| !line.is_empty())
+        .collect();
+    if lines.is_empty() {
+        return None;
+    }
+    let mut truncated: String = lines
+        .iter()
+        .take(NOTES_MAX_LINES)
+        .copied()
+        .collect::<Vec<_>>()
+        .join("\n");
+    if truncated.chars().count() > NOTES_MAX_CHARS {
+        truncated = truncated.chars().take(NOTES_MAX_CHARS).collect();
+    } else if lines.len() <= NOTES_MAX_LINES {
+        return Some(truncated);
+    }
+    return Some(format!("{}…", truncated.trim_end()));
+}
+
+/// Formats a size in bytes with binary units, i.e. `1.5 GiB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    return match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    };
+}
+
+impl DependencyMetadata {
+    /// Builds metadata from `org.opencontainers.image.*` annotations (or
+    /// labels, which use the same keys)
+    pub fn from_annotations(annotations: &BTreeMap<String, String>) -> DependencyMetadata {
+        let get = |keys: &[&str]| {
+            keys.iter()
+                .find_map(|key| annotations.get(&format!("org.opencontainers.image.{}", key)))
+                .cloned()
+        };
+        return DependencyMetadata {
+            resolved_version: get(&["version"]),
+            source_url: get(&["source", "url"]),
+            description: get(&["description", "title"]),
+            timestamp: get(&["created"]),
+            ..Default::default()
+        };
+    }
+}
+
+impl fmt::Display for DependencyMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in [
+            ("version", &self.resolved_version),
+            ("source", &self.source_url),
+            ("description", &self.description),
+            ("date", &self.timestamp),
+            ("resolved from", &self.resolved_from),
+        ] {
+            if let Some(value) = value {
+                writeln!(f, "  {}: {}", name, value)?;
+            }
+        }
+        match (self.size, self.layers) {
+            (Some(size), Some(layers)) => {
+                writeln!(f, "  size: {} ({} layers)", format_size(size), layers)?
+            }
+            (Some(size), None) => writeln!(f, "  size: {}", format_size(size))?,
+            _ => (),
+        }
+        if let Some(reason) = &self.deprecated {
+            writeln!(f, "  deprecated: {}", reason)?;
+        }
+        if !self.submodules.is_empty() {
+            writeln!(f, "  submodules:")?;
+            for submodule in &self.submodules {
+                writeln!(f, "    {}", submodule)?;
+            }
+        }
+        if let Some(notes) = &self.notes {
+            writeln!(f, "  notes:")?;
+            for line in notes.lines() {
+                writeln!(f, "    {}", line)?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_size, truncate_notes, DependencyMetadata};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn it_reads_annotations() {
+        let annotations: BTreeMap<String, String> = [
+            ("org.opencontainers.image.version", "10.4.3"),
+            ("org.opencontainers.image.url", "https://grafana.com"),
+            (
+                "org.opencontainers.image.source",
+                "https://github.com/grafana/grafana",
+            ),
+            ("org.opencontainers.image.title", "Grafana"),
+            ("com.example.unrelated", "foo"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        assert_eq!(
+            DependencyMetadata::from_annotations(&annotations),
+            DependencyMetadata {
+                resolved_version: Some("10.4.3".to_string()),
+                source_url: Some("https://github.com/grafana/grafana".to_string()),
+                description: Some("Grafana".to_string()),
+                ..Default::default()
+            },
+        );
+    }
+
+    #[test]
+    fn it_formats_sizes() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn it_truncates_notes() {
+        assert_eq!(truncate_notes("  \n"), None);
+        assert_eq!(
+            truncate_notes("## Changes\n\n- Fix a bug\r\n"),
+            Some("## Changes\n- Fix a bug".to_string()),
+        );
+        let long = (1..=20)
+            .map(|i| format!("- change {}", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let truncated = truncate_notes(&long).unwrap();
+        assert_eq!(truncated.lines().count(), 10);
+        assert!(truncated.ends_with("- change 10…"));
+        assert_eq!(
+            truncate_notes(&"a".repeat(1000)).unwrap().chars().count(),
+            801
+        );
+    }
+
+    #[test]
+    fn it_displays_notes() {
+        let metadata = DependencyMetadata {
+            resolved_version: Some("v1.2.0".to_string()),
+            notes: Some("- Fix a bug\n- Add a feature".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            metadata.to_string(),
+            "  version: v1.2.0\n  notes:\n    - Fix a bug\n    - Add a feature\n",
+        );
+    }
+}
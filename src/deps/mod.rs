@@ -1,29 +1,73 @@
 mod docker;
-mod github;
-mod test_util;
+mod flake;
+pub mod github;
+mod metadata;
+mod template;
+#[cfg(test)]
+pub(crate) mod test_util;
+mod workflow;
 
-use crate::deps::docker::Docker;
+use crate::cache::Cache;
+use crate::config::{Config, EolConfig};
+use crate::deps::flake::FlakeInput;
+use crate::deps::github::action::GitHubAction;
 use crate::deps::github::branch::GitHubBranch;
 use crate::deps::github::release::GitHubRelease;
 use crate::error::Error;
-use crate::util::ParsingContext;
+use crate::http;
+use crate::lock::{LockData, LockFile};
+use crate::util::{self, Location, ParsingContext};
 use async_trait::async_trait;
 use enum_as_inner::EnumAsInner;
-use erased_serde::Serialize;
+use miette::NamedSource;
+use rnix::ast::{self, Expr};
 use rnix::{SyntaxKind, SyntaxNode};
+use rowan::ast::AstNode;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
+use std::path::Path;
+
+const RESOLVED_NAMESPACE: &str = "resolved";
+
+pub use docker::{rate_limits, Docker, Profile};
+pub use metadata::{format_size, truncate_notes, DependencyMetadata};
+pub use template::collect_template_dependencies;
+pub use workflow::collect_workflow_dependencies;
 
 #[derive(EnumAsInner, Clone, Debug)]
-pub enum Dependency {
+pub enum DependencyKind {
     Docker(Docker),
     GitHubBranch(GitHubBranch),
     GitHubRelease(GitHubRelease),
+    FlakeInput(FlakeInput),
+    GitHubAction(GitHubAction),
+}
+
+#[derive(Clone, Debug)]
+pub struct Dependency {
+    kind: DependencyKind,
+    location: Location,
+    pinned: bool,
+}
+
+#[derive(PartialEq, Debug)]
+enum Directive {
+    Ignore,
+    Pin,
 }
 
 #[async_trait]
 pub trait Lockable {
     fn key(&self) -> String;
-    async fn lock(&self) -> Result<Box<dyn Serialize>, Error>;
+    /// Resolves the dependency. Dropping the future (i.e. on a timeout or
+    /// Ctrl-C) cancels it, killing any process it started.
+    async fn lock(&self) -> Result<LockData, Error>;
+
+    /// Looks up details about the version the dependency resolves to, on
+    /// top of what goes into the lock
+    async fn metadata(&self) -> Result<DependencyMetadata, Error> {
+        return Ok(DependencyMetadata::default());
+    }
 }
 
 impl Dependency {
@@ -32,72 +76,599 @@ impl Dependency {
         func: &str,
         node: &SyntaxNode,
     ) -> Result<Option<Dependency>, Error> {
-        match func {
-            "uptix.dockerImage" => Ok(Some(Dependency::Docker(Docker::new(context, &node)?))),
-            "uptix.githubBranch" => Ok(Some(Dependency::GitHubBranch(GitHubBranch::new(
-                context, &node,
-            )?))),
-            "uptix.githubRelease" => Ok(Some(Dependency::GitHubRelease(GitHubRelease::new(
-                context, &node,
-            )?))),
-            _ => Ok(None),
+        let kind = match Dependency::parse_kind(context, func, node) {
+            Ok(Some(kind)) => kind,
+            Ok(None) => return Ok(None),
+            Err(error) => return Err(context.invalid_argument(func, node, error)),
+        };
+        // point at the whole function call rather than just its argument
+        let call = node
+            .ancestors()
+            .find(|n| n.kind() == SyntaxKind::NODE_APPLY)
+            .unwrap_or(node.clone());
+        return Ok(Some(Dependency::new_with_kind(context, &call, kind)));
+    }
+
+    fn parse_kind(
+        context: &ParsingContext,
+        func: &str,
+        node: &SyntaxNode,
+    ) -> Result<Option<DependencyKind>, Error> {
+        return Ok(Some(match func {
+            "uptix.dockerImage" => DependencyKind::Docker(Docker::new(context, node)?),
+            "uptix.dockerImagePlatforms" => {
+                DependencyKind::Docker(Docker::new(context, node)?.with_all_platforms()?)
+            }
+            "uptix.dockerPullImage" => {
+                DependencyKind::Docker(Docker::new(context, node)?.with_nix_hash())
+            }
+            "uptix.githubBranch" => DependencyKind::GitHubBranch(GitHubBranch::new(context, node)?),
+            "uptix.githubRelease" => {
+                DependencyKind::GitHubRelease(GitHubRelease::new(context, node)?)
+            }
+            "uptix.githubReleaseAsset" => {
+                DependencyKind::GitHubRelease(GitHubRelease::new_asset(context, node)?)
+            }
+            _ => return Ok(None),
+        }));
+    }
+
+    fn new_with_kind(
+        context: &ParsingContext,
+        node: &SyntaxNode,
+        kind: DependencyKind,
+    ) -> Dependency {
+        return Dependency {
+            kind,
+            location: context.location(node),
+            pinned: false,
+        };
+    }
+
+    /// Applies the defaults from the configuration which aren't set on the
+    /// declaration itself
+    fn configure(&mut self, config: &Config) {
+        match &mut self.kind {
+            DependencyKind::Docker(docker) => docker.configure(&config.docker),
+            DependencyKind::GitHubBranch(branch) => branch.configure(&config.github),
+            DependencyKind::GitHubRelease(release) => release.configure(&config.github),
+            DependencyKind::FlakeInput(input) => input.configure(&config.github),
+            DependencyKind::GitHubAction(action) => action.configure(&config.github),
         }
     }
 
+    #[cfg(test)]
+    pub fn kind(&self) -> &DependencyKind {
+        return &self.kind;
+    }
+
+    /// What kind of dependency this is, in plural (i.e. for `--timings`)
+    pub fn type_name(&self) -> &'static str {
+        return match &self.kind {
+            DependencyKind::Docker(_) => "Docker images",
+            DependencyKind::GitHubBranch(_) => "GitHub branches",
+            DependencyKind::GitHubRelease(_) => "GitHub releases",
+            DependencyKind::FlakeInput(_) => "flake inputs",
+            DependencyKind::GitHubAction(_) => "GitHub Actions",
+        };
+    }
+
+    /// What kind of dependency this is, as an identifier (i.e. for the
+    /// labels of `uptix report`)
+    pub fn type_label(&self) -> &'static str {
+        return match &self.kind {
+            DependencyKind::Docker(_) => "docker",
+            DependencyKind::GitHubBranch(_) => "github_branch",
+            DependencyKind::GitHubRelease(_) => "github_release",
+            DependencyKind::FlakeInput(_) => "flake_input",
+            DependencyKind::GitHubAction(_) => "github_action",
+        };
+    }
+
+    /// Pinned dependencies keep their existing lock entry, if there is one
+    pub fn is_pinned(&self) -> bool {
+        return self.pinned;
+    }
+
+    pub fn location(&self) -> &Location {
+        return &self.location;
+    }
+
+    /// Identifies what is being depended on, regardless of which version
+    /// of it is being selected
+    pub fn identity(&self) -> String {
+        match &self.kind {
+            DependencyKind::Docker(d) => d.identity(),
+            DependencyKind::GitHubBranch(d) => d.identity(),
+            DependencyKind::GitHubRelease(d) => d.identity(),
+            DependencyKind::FlakeInput(d) => d.identity(),
+            DependencyKind::GitHubAction(d) => d.identity(),
+        }
+    }
+
+    pub fn uses_latest_tag(&self) -> bool {
+        return match &self.kind {
+            DependencyKind::Docker(d) => d.uses_latest_tag(),
+            _ => false,
+        };
+    }
+
+    /// Why the dependency is deprecated upstream, if it is
+    pub async fn deprecation(&self) -> Result<Option<String>, Error> {
+        return match &self.kind {
+            DependencyKind::Docker(d) => d.deprecation().await,
+            _ => Ok(None),
+        };
+    }
+
+    /// Whether the release the dependency follows reached end of life
+    pub async fn end_of_life(&self, config: &EolConfig) -> Result<Option<String>, Error> {
+        return match &self.kind {
+            DependencyKind::Docker(d) => d.end_of_life(config).await,
+            _ => Ok(None),
+        };
+    }
+
     pub fn key(&self) -> String {
-        match self {
-            Dependency::Docker(d) => d.key(),
-            Dependency::GitHubBranch(d) => d.key(),
-            Dependency::GitHubRelease(d) => d.key(),
+        match &self.kind {
+            DependencyKind::Docker(d) => d.key(),
+            DependencyKind::GitHubBranch(d) => d.key(),
+            DependencyKind::GitHubRelease(d) => d.key(),
+            DependencyKind::FlakeInput(d) => d.key(),
+            DependencyKind::GitHubAction(d) => d.key(),
+        }
+    }
+
+    /// Points a failure to lock the dependency at where it is declared
+    pub fn failure(&self, error: Error) -> Error {
+        let file_path = &self.location.file_path;
+        return Error::UpdateFailed {
+            name: self.key(),
+            src: NamedSource::new(file_path, fs::read_to_string(file_path).unwrap_or_default()),
+            pos: self.location.span,
+            cause: vec![error],
+        };
+    }
+
+    #[tracing::instrument(name = "lock", skip_all, fields(dependency = %self.key()))]
+    pub async fn lock(&self) -> Result<LockData, Error> {
+        // retries and slow but steady downloads could otherwise go on forever
+        let deadline = http::deadline();
+        return tokio::time::timeout(deadline, self.lock_cached(&Cache::resolved()))
+            .await
+            .unwrap_or_else(|_| {
+                Err(Error::DeadlineExceeded {
+                    name: self.key(),
+                    seconds: deadline.as_secs(),
+                })
+            });
+    }
+
+    /// Reuses what the dependency resolved to less than `resolved_ttl` ago,
+    /// so that repeated runs (i.e. while debugging) don't hit upstream
+    async fn lock_cached(&self, cache: &Cache) -> Result<LockData, Error> {
+        let key = self.key();
+        let cached = cache
+            .read_fresh(RESOLVED_NAMESPACE, &key)
+            .and_then(|lock| serde_json::from_str::<LockData>(&lock).ok());
+        if let Some(lock) = cached {
+            tracing::debug!("reusing what it resolved to before");
+            return Ok(lock);
+        }
+        let lock = match &self.kind {
+            DependencyKind::Docker(d) => d.lock().await,
+            DependencyKind::GitHubBranch(d) => d.lock().await,
+            DependencyKind::GitHubRelease(d) => d.lock().await,
+            DependencyKind::FlakeInput(d) => d.lock().await,
+            DependencyKind::GitHubAction(d) => d.lock().await,
+        }?;
+        if cache.has_ttl() {
+            if let Ok(value) = serde_json::to_string(&lock) {
+                cache.write(RESOLVED_NAMESPACE, &key, &value);
+            }
+        }
+        return Ok(lock);
+    }
+
+    /// Warns when a branch moved to a commit which doesn't descend from the
+    /// one it was previously locked to. The check is skipped (with a warning
+    /// of its own) when the compare API can't be reached, rather than
+    /// failing the update.
+    pub async fn rewritten_history(
+        &self,
+        previous: &LockData,
+        current: &LockData,
+    ) -> Option<Error> {
+        let branch = match &self.kind {
+            // without API access, there's no compare API to ask
+            DependencyKind::GitHubBranch(d) if !d.uses_ssh() => d,
+            _ => return None,
+        };
+        let (old_rev, new_rev) = match (previous.rev(), current.rev()) {
+            (Some(old_rev), Some(new_rev)) if old_rev != new_rev => (old_rev, new_rev),
+            _ => return None,
+        };
+        match branch.is_fast_forward(old_rev, new_rev).await {
+            Ok(true) => return None,
+            Ok(false) => {}
+            Err(error) => {
+                tracing::warn!(
+                    %error,
+                    "couldn't tell whether {} was force-pushed",
+                    self.key()
+                );
+                return None;
+            }
         }
+        return Some(Error::RewrittenHistory {
+            name: self.identity(),
+            old_rev: old_rev.to_string(),
+            new_rev: new_rev.to_string(),
+            location: self.location.to_string(),
+        });
     }
 
-    pub async fn lock(&self) -> Result<Box<dyn Serialize>, Error> {
-        match self {
-            Dependency::Docker(d) => d.lock().await,
-            Dependency::GitHubBranch(d) => d.lock().await,
-            Dependency::GitHubRelease(d) => d.lock().await,
+    pub async fn metadata(&self) -> Result<DependencyMetadata, Error> {
+        match &self.kind {
+            DependencyKind::Docker(d) => d.metadata().await,
+            DependencyKind::GitHubBranch(d) => d.metadata().await,
+            DependencyKind::GitHubRelease(d) => d.metadata().await,
+            DependencyKind::FlakeInput(d) => d.metadata().await,
+            DependencyKind::GitHubAction(d) => d.metadata().await,
         }
     }
 }
 
-pub fn collect_file_dependencies(file_path: &str) -> Result<Vec<Dependency>, Error> {
-    let content = fs::read_to_string(file_path).unwrap();
-    let ast = rnix::parse(&content);
-    let context = ParsingContext::new(file_path, &content);
-    return collect_ast_dependencies(&context, ast.node());
+pub fn collect_file_dependencies(
+    file_path: &str,
+    config: &Config,
+) -> Result<Vec<Dependency>, Error> {
+    let content = util::read_source(file_path, config.discovery.max_file_size)?;
+    return collect_source_dependencies(file_path, &content, config);
+}
+
+/// Like [`collect_file_dependencies`], for the contents of a file which may
+/// not be saved yet (i.e. open on an editor)
+pub fn collect_source_dependencies(
+    file_path: &str,
+    content: &str,
+    config: &Config,
+) -> Result<Vec<Dependency>, Error> {
+    let ast = rnix::Root::parse(content);
+    let mut context = ParsingContext::new(file_path, content);
+    if let Some(error) = ast.errors().first() {
+        return Err(context.syntax_error(error));
+    }
+    for prefix in &config.parsing.prefixes {
+        context.add_prefix(prefix);
+    }
+    let mut dependencies = collect_root_dependencies(&mut context, ast.syntax())?;
+    let is_flake = Path::new(file_path).file_name() == Some("flake.nix".as_ref());
+    if is_flake && config.discovery.flake_inputs {
+        dependencies.extend(flake::collect_flake_inputs(&context, &ast.syntax()));
+    }
+    for dependency in &mut dependencies {
+        dependency.configure(config);
+    }
+    return Ok(dependencies);
+}
+
+/// Remembers what was expensive to compute for the entries of a lock file
+/// (i.e. nix hashes), so that dependencies which didn't move since then can
+/// reuse it
+pub fn remember_hashes(lock_file: &LockFile) {
+    github::remember_hashes(lock_file);
+    docker::remember_hashes(lock_file);
+}
+
+/// Plans the images about to be locked, so that the ones on the same
+/// registry authenticate only once
+pub fn plan_sessions(dependencies: &[&Dependency]) {
+    for dependency in dependencies {
+        if let DependencyKind::Docker(docker) = &dependency.kind {
+            docker.plan_sessions();
+        }
+    }
+}
+
+/// Drops repeated declarations of the same dependency (i.e. the same image
+/// used by several modules), so each one is only resolved once. Unpinned
+/// declarations win over pinned ones, as they are the ones asking for updates
+pub fn unique(dependencies: &[Dependency]) -> Vec<&Dependency> {
+    let mut by_key: BTreeMap<String, usize> = BTreeMap::new();
+    let mut unique: Vec<&Dependency> = vec![];
+    for dependency in dependencies {
+        match by_key.get(&dependency.key()) {
+            Some(&index) => {
+                if unique[index].is_pinned() && !dependency.is_pinned() {
+                    unique[index] = dependency;
+                }
+            }
+            None => {
+                by_key.insert(dependency.key(), unique.len());
+                unique.push(dependency);
+            }
+        }
+    }
+    return unique;
+}
+
+/// Groups declarations of the same image or repository which select
+/// different versions of it (i.e. different tags or branches)
+pub fn find_conflicts(dependencies: &[Dependency]) -> Vec<Vec<&Dependency>> {
+    let mut by_identity: BTreeMap<String, Vec<&Dependency>> = BTreeMap::new();
+    for dependency in dependencies {
+        by_identity
+            .entry(dependency.identity())
+            .or_default()
+            .push(dependency);
+    }
+    return by_identity
+        .into_values()
+        .filter(|group| {
+            let keys: BTreeSet<_> = group.iter().map(|d| d.key()).collect();
+            keys.len() > 1
+        })
+        .collect();
+}
+
+/// Finds bindings which alias uptix (i.e. `let u = uptix; in ...`) or its
+/// functions (i.e. `inherit (uptix) dockerImage;`), so calls through them are
+/// picked up as well
+fn collect_aliases(context: &mut ParsingContext, root: &SyntaxNode) {
+    loop {
+        let mut found_alias = false;
+        for node in root.descendants() {
+            if let Some(binding) = ast::AttrpathValue::cast(node.clone()) {
+                let (alias, value) = match (binding.attrpath(), binding.value()) {
+                    (Some(key), Some(value)) => (key.to_string(), value),
+                    _ => continue,
+                };
+                if matches!(value, Expr::Ident(_))
+                    && context.is_prefix(&value.to_string())
+                    && !context.is_prefix(&alias)
+                {
+                    context.add_prefix(&alias);
+                    found_alias = true;
+                } else if matches!(value, Expr::Select(_))
+                    && context.function_name(&alias).is_none()
+                {
+                    if let Some(func) = context.function_name(&value.to_string()) {
+                        context.add_import(&alias, &func);
+                        found_alias = true;
+                    }
+                }
+            } else if let Some(inherit) = ast::Inherit::cast(node) {
+                let from = match inherit.from().and_then(|f| f.expr()) {
+                    Some(from) => unwrap_parens(from.syntax().clone()).to_string(),
+                    None => continue,
+                };
+                if !context.is_prefix(&from) {
+                    continue;
+                }
+                for attr in inherit.attrs() {
+                    let name = attr.to_string();
+                    if context.function_name(&name).is_none() {
+                        context.add_import(&name, &format!("uptix.{}", name));
+                        found_alias = true;
+                    }
+                }
+            }
+        }
+        // keep going until aliases of aliases are all found
+        if !found_alias {
+            return;
+        }
+    }
+}
+
+fn collect_root_dependencies(
+    context: &mut ParsingContext,
+    root: SyntaxNode,
+) -> Result<Vec<Dependency>, Error> {
+    collect_aliases(context, &root);
+    return collect_ast_dependencies(context, root);
 }
 
 fn collect_ast_dependencies(
     context: &ParsingContext,
     node: SyntaxNode,
 ) -> Result<Vec<Dependency>, Error> {
-    if node.kind() != SyntaxKind::NODE_SELECT {
-        return node
-            .children()
-            .map(|n| collect_ast_dependencies(&context, n))
-            .try_fold(Vec::new(), |mut acc, next| {
-                acc.extend_from_slice(&next?);
-                Ok(acc)
-            });
+    if node.kind() == SyntaxKind::NODE_APPLY {
+        if let Some(dependencies) = collect_call_dependencies(context, &node)? {
+            return Ok(dependencies);
+        }
     }
 
-    let func = node.text().to_string();
-    if !func.starts_with("uptix.") {
-        return Ok(vec![]);
+    // anything else (attrsets, rec attrsets, lists, let bindings, module
+    // function bodies, lib.mkIf blocks, etc) may have uptix calls nested
+    // somewhere inside of it
+    return node
+        .children()
+        .map(|n| collect_ast_dependencies(context, n))
+        .try_fold(Vec::new(), |mut acc, next| {
+            acc.extend_from_slice(&next?);
+            Ok(acc)
+        });
+}
+
+fn unwrap_parens(node: SyntaxNode) -> SyntaxNode {
+    let mut node = node;
+    while node.kind() == SyntaxKind::NODE_PAREN {
+        node = node.first_child().unwrap();
+    }
+    return node;
+}
+
+/// Resolves the uptix function being referred to by `node`, taking prefixes,
+/// imported bindings and enclosing `with uptix;` scopes into account
+fn resolve_function(context: &ParsingContext, node: &SyntaxNode) -> Option<String> {
+    match node.kind() {
+        SyntaxKind::NODE_SELECT => return context.function_name(&node.to_string()),
+        SyntaxKind::NODE_IDENT => (),
+        _ => return None,
+    }
+    let name = node.to_string();
+    if let Some(func) = context.function_name(&name) {
+        return Some(func);
+    }
+    let in_uptix_scope = node.ancestors().filter_map(ast::With::cast).any(|with| {
+        with.namespace()
+            .is_some_and(|ns| context.is_prefix(&unwrap_parens(ns.syntax().clone()).to_string()))
+    });
+    if in_uptix_scope {
+        return Some(format!("uptix.{}", name));
     }
+    return None;
+}
+
+/// Handles `uptix.<function> <argument>` calls. Returns `None` if the node
+/// is not a call to an uptix function which declares dependencies.
+fn collect_call_dependencies(
+    context: &ParsingContext,
+    node: &SyntaxNode,
+) -> Result<Option<Vec<Dependency>>, Error> {
+    let func_node = node.first_child().unwrap();
+    let func = match resolve_function(context, &func_node) {
+        Some(func) => func,
+        None => return Ok(None),
+    };
+    let argument = unwrap_parens(func_node.next_sibling().unwrap());
 
-    let value_node = node.next_sibling();
-    if value_node.is_none() {
-        return Ok(vec![]);
+    if func == "uptix.deps" {
+        let mut dependencies = collect_deps_block(context, &argument)?;
+        dependencies.extend(collect_ast_dependencies(context, argument)?);
+        return Ok(Some(dependencies));
     }
 
-    return match <Dependency>::new(&context, &func, &value_node.unwrap())? {
-        Some(dependency) => Ok(vec![dependency]),
-        None => Ok(vec![]),
+    return match <Dependency>::new(context, &func, &argument)? {
+        Some(dependency) => Ok(Some(
+            apply_directive(Some(dependency), &func_node)
+                .into_iter()
+                .collect(),
+        )),
+        None => Ok(None),
+    };
+}
+
+fn parse_directive(comment: &str) -> Option<Directive> {
+    let text = comment
+        .trim_start_matches('#')
+        .trim_start_matches("/*")
+        .trim_end_matches("*/")
+        .trim();
+    return match text.strip_prefix("uptix:")?.trim() {
+        "ignore" => Some(Directive::Ignore),
+        "pin" => Some(Directive::Pin),
+        _ => None,
     };
 }
 
+/// Looks for a directive comment right above or at the end of the line
+/// where the uptix function is called.
+fn find_directive(node: &SyntaxNode) -> Option<Directive> {
+    let mut anchor = node.clone();
+    while let Some(parent) = anchor.parent() {
+        match parent.kind() {
+            SyntaxKind::NODE_APPLY | SyntaxKind::NODE_PAREN => anchor = parent,
+            SyntaxKind::NODE_ATTRPATH_VALUE => {
+                anchor = parent;
+                break;
+            }
+            _ => break,
+        }
+    }
+
+    let mut previous = anchor.prev_sibling_or_token();
+    while let Some(element) = previous {
+        match element.kind() {
+            // a blank line separates the comment from the call
+            SyntaxKind::TOKEN_WHITESPACE if element.to_string().matches('\n').count() > 1 => break,
+            SyntaxKind::TOKEN_WHITESPACE => (),
+            SyntaxKind::TOKEN_COMMENT => {
+                let directive = parse_directive(element.as_token().unwrap().text());
+                if directive.is_some() {
+                    return directive;
+                }
+            }
+            _ => break,
+        }
+        previous = element.prev_sibling_or_token();
+    }
+
+    let mut next = anchor.next_sibling_or_token();
+    while let Some(element) = next {
+        let token = match element.as_token() {
+            Some(token) => token.clone(),
+            None => break,
+        };
+        match token.kind() {
+            SyntaxKind::TOKEN_WHITESPACE if !token.text().contains('\n') => (),
+            SyntaxKind::TOKEN_COMMENT => return parse_directive(token.text()),
+            _ => break,
+        }
+        next = element.next_sibling_or_token();
+    }
+
+    return None;
+}
+
+fn apply_directive(dependency: Option<Dependency>, node: &SyntaxNode) -> Option<Dependency> {
+    let mut dependency = dependency?;
+    match find_directive(node) {
+        Some(Directive::Ignore) => return None,
+        Some(Directive::Pin) => dependency.pinned = true,
+        None => (),
+    }
+    return Some(dependency);
+}
+
+fn collect_deps_block(
+    context: &ParsingContext,
+    node: &SyntaxNode,
+) -> Result<Vec<Dependency>, Error> {
+    let mut block = node.clone();
+    // allow for uptix.deps (with uptix; { ... }) so entries can use the
+    // unprefixed function names
+    while block.kind() == SyntaxKind::NODE_PAREN || block.kind() == SyntaxKind::NODE_WITH {
+        block = block.last_child().unwrap();
+    }
+    let attr_set = assert_kind(
+        context,
+        "uptix.deps",
+        &block,
+        SyntaxKind::NODE_ATTR_SET,
+        r#"here is an example of valid usage:
+
+  uptix.deps (with uptix; {
+    postgres = dockerImage "postgres:16";
+    grafana = dockerImage "grafana/grafana";
+  })"#,
+    )?;
+
+    // calls which resolve to uptix functions on their own (i.e. prefixed or
+    // inside of a `with uptix;` scope) are picked up by the regular traversal,
+    // so we only need to handle the remaining unprefixed ones here
+    let mut dependencies = vec![];
+    for entry in attr_set.children() {
+        let value = match entry.last_child() {
+            Some(value) if value.kind() == SyntaxKind::NODE_APPLY => value,
+            _ => continue,
+        };
+        let func_node = value.first_child().unwrap();
+        if func_node.kind() != SyntaxKind::NODE_IDENT
+            || resolve_function(context, &func_node).is_some()
+        {
+            continue;
+        }
+        let func = format!("uptix.{}", func_node.text());
+        let argument = unwrap_parens(func_node.next_sibling().unwrap());
+        let dependency = <Dependency>::new(context, &func, &argument)?;
+        dependencies.extend(apply_directive(dependency, &func_node));
+    }
+    return Ok(dependencies);
+}
+
 fn assert_kind<'a>(
     context: &ParsingContext,
     function: &str,
@@ -125,7 +696,18 @@ fn assert_kind<'a>(
 
 #[cfg(test)]
 mod tests {
+    use super::{
+        collect_file_dependencies, collect_source_dependencies, find_conflicts, unique,
+        RESOLVED_NAMESPACE,
+    };
+    use crate::cache::Cache;
+    use crate::config::{Config, GitHubHostConfig};
     use crate::deps::test_util;
+    use crate::error::Error;
+    use crate::lock::LockData;
+    use serde_json::json;
+    use std::fs;
+    use std::time::Duration;
 
     #[test]
     fn invalid_uptix_function() {
@@ -138,4 +720,393 @@ mod tests {
         .unwrap();
         assert_eq!(dependencies.len(), 0);
     }
+
+    #[test]
+    fn deps_block() {
+        let keys: Vec<_> = test_util::deps(
+            r#"{
+                deps = uptix.deps (with uptix; {
+                    postgres = dockerImage "postgres/postgres:16";
+                    grafana = uptix.dockerImage "grafana/grafana";
+                    uptix = githubRelease {
+                        owner = "luizribeiro";
+                        repo = "uptix";
+                    };
+                    notADependency = "foo";
+                });
+            }"#,
+        )
+        .unwrap()
+        .iter()
+        .map(|d| d.key())
+        .collect();
+        assert_eq!(
+            keys,
+            vec![
+                "postgres/postgres:16",
+                "grafana/grafana",
+                "$GITHUB_RELEASE$:luizribeiro/uptix$",
+            ],
+        );
+    }
+
+    #[test]
+    fn deps_block_requires_attr_set() {
+        let result = test_util::deps("{ deps = uptix.deps 42; }");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn invalid_argument() {
+        let source = "{ foo = uptix.githubBranch { owner = \"foo\"; repo = 42; }; }";
+        match test_util::deps(source) {
+            Err(Error::InvalidArgument {
+                function,
+                message,
+                argument_pos,
+                src: _,
+            }) => {
+                assert_eq!(function, "uptix.githubBranch");
+                assert!(message.contains("invalid type"), "{}", message);
+                assert_eq!(argument_pos, (27, 29).into());
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let source = "{ foo = uptix.dockerImage { image = \"foo/${bar}\"; }; }";
+        match test_util::deps(source) {
+            Err(Error::InvalidArgument { message, .. }) => {
+                assert_eq!(message, "String interpolation is not supported");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unreadable_file() {
+        let result = collect_file_dependencies("./does-not-exist.nix", &Config::default());
+        match result {
+            Err(Error::UnreadableFile { path, source: _ }) => {
+                assert_eq!(path, "./does-not-exist.nix");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn syntax_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("broken.nix");
+        fs::write(&path, "{ hass = uptix.dockerImage \"foo/bar\" }").unwrap();
+        let result = collect_file_dependencies(path.to_str().unwrap(), &Config::default());
+        match result {
+            Err(Error::NixSyntaxError {
+                src: _,
+                pos,
+                message: _,
+            }) => {
+                assert_eq!(pos, (37, 1).into());
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn directives() {
+        let dependencies = test_util::deps(
+            r#"{
+                # uptix: ignore
+                ignored = uptix.dockerImage "foo/ignored";
+                pinned = uptix.dockerImage "foo/pinned"; # uptix: pin
+                /* uptix: pin */
+                pinnedRelease = uptix.githubRelease {
+                    owner = "luizribeiro";
+                    repo = "uptix";
+                };
+                # some unrelated comment
+                regular = uptix.dockerImage "foo/regular";
+                # uptix: ignore
+
+                notAdjacent = uptix.dockerImage "foo/not-adjacent";
+                deps = uptix.deps (with uptix; {
+                    blockPinned = dockerImage "foo/block-pinned"; # uptix: pin
+                    # uptix: ignore
+                    blockIgnored = dockerImage "foo/block-ignored";
+                });
+            }"#,
+        )
+        .unwrap();
+        let keys: Vec<_> = dependencies
+            .iter()
+            .map(|d| (d.key(), d.is_pinned()))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("foo/pinned".to_string(), true),
+                ("$GITHUB_RELEASE$:luizribeiro/uptix$".to_string(), true),
+                ("foo/regular".to_string(), false),
+                ("foo/not-adjacent".to_string(), false),
+                ("foo/block-pinned".to_string(), true),
+            ],
+        );
+    }
+
+    #[test]
+    fn conflicts() {
+        let dependencies = test_util::deps(
+            r#"{
+                a = uptix.dockerImage "grafana/grafana:10";
+                b = uptix.dockerImage "grafana/grafana:9";
+                c = uptix.dockerImage "grafana/grafana:10";
+                d = uptix.dockerImage "foo/bar";
+                e = uptix.githubBranch {
+                    owner = "luizribeiro";
+                    repo = "uptix";
+                    branch = "main";
+                };
+                f = uptix.githubBranch {
+                    owner = "luizribeiro";
+                    repo = "uptix";
+                    branch = "main";
+                };
+            }"#,
+        )
+        .unwrap();
+        let conflicts: Vec<Vec<_>> = find_conflicts(&dependencies)
+            .iter()
+            .map(|group| group.iter().map(|d| d.key()).collect())
+            .collect();
+        assert_eq!(
+            conflicts,
+            vec![vec![
+                "grafana/grafana:10",
+                "grafana/grafana:9",
+                "grafana/grafana:10",
+            ]],
+        );
+    }
+
+    #[tokio::test]
+    async fn it_reuses_resolved_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path()).with_ttl(Duration::from_secs(3600));
+        // nothing listens on the registry, so only a cached lock can work
+        let dependencies = test_util::deps(
+            r#"{
+                a = uptix.dockerImage "127.0.0.1:1/foo/bar:1.0";
+            }"#,
+        )
+        .unwrap();
+        let key = dependencies[0].key();
+        cache.write(RESOLVED_NAMESPACE, &key, r#""sha256:foobar""#);
+        let lock = dependencies[0].lock_cached(&cache).await.unwrap();
+        assert_eq!(serde_json::to_value(lock).unwrap(), json!("sha256:foobar"));
+    }
+
+    #[tokio::test]
+    async fn it_keeps_going_when_history_cant_be_compared() {
+        let compare_mock = mockito::mock("GET", "/repos/luizribeiro/flaky/compare/old...new")
+            .with_status(403)
+            .with_body(r#"{ "message": "Forbidden" }"#)
+            .create();
+        let mut config = Config::default();
+        config.github.hosts.insert(
+            "github.com".to_string(),
+            GitHubHostConfig {
+                api_url: Some(mockito::server_url()),
+                ..Default::default()
+            },
+        );
+        let dependencies = collect_source_dependencies(
+            "./test.nix",
+            r#"{
+                a = uptix.githubBranch {
+                    owner = "luizribeiro";
+                    repo = "flaky";
+                    branch = "main";
+                };
+            }"#,
+            &config,
+        )
+        .unwrap();
+        let lock = |rev: &str| -> LockData {
+            return serde_json::from_value(json!({
+                "owner": "luizribeiro",
+                "repo": "flaky",
+                "rev": rev,
+                "sha256": "0000000000000000000000000000000000000000000000000000",
+                "fetchSubmodules": false,
+                "deepClone": false,
+                "leaveDotGit": false,
+            }))
+            .unwrap();
+        };
+        assert!(dependencies[0]
+            .rewritten_history(&lock("old"), &lock("new"))
+            .await
+            .is_none());
+        compare_mock.assert();
+
+        mockito::reset();
+    }
+
+    #[test]
+    fn it_drops_repeated_declarations() {
+        let dependencies = test_util::deps(
+            r#"{
+                a = uptix.dockerImage "library/redis:7";
+                b = uptix.dockerImage "grafana/grafana:10";
+                c = uptix.dockerImage "library/redis:7";
+                d = uptix.githubBranch {
+                    owner = "luizribeiro";
+                    repo = "uptix";
+                    branch = "main";
+                };
+                e = uptix.dockerImage "library/redis:7";
+            }"#,
+        )
+        .unwrap();
+        let keys: Vec<_> = unique(&dependencies).iter().map(|d| d.key()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "library/redis:7",
+                "grafana/grafana:10",
+                "$GITHUB_BRANCH$:luizribeiro/uptix:main$",
+            ],
+        );
+        assert_eq!(unique(&dependencies)[0].location().line, 2);
+    }
+
+    #[test]
+    fn locations() {
+        let locations: Vec<_> = test_util::deps(
+            r#"{
+  hass = uptix.dockerImage "homeassistant/home-assistant:stable";
+  deps = uptix.deps (with uptix; {
+      grafana = dockerImage "grafana/grafana";
+  });
+}"#,
+        )
+        .unwrap()
+        .iter()
+        .map(|d| d.location().to_string())
+        .collect();
+        assert_eq!(locations, vec!["./test.nix:2:10", "./test.nix:4:17"]);
+    }
+
+    #[test]
+    fn aliases() {
+        let keys: Vec<_> = test_util::deps(
+            r#"
+            let
+                u = uptix;
+                v = u;
+            in {
+                a = u.dockerImage "foo/a";
+                b = v.dockerImage "foo/b";
+                c = w.dockerImage "foo/c";
+                d = uptix.dockerImage "foo/d";
+            }"#,
+        )
+        .unwrap()
+        .iter()
+        .map(|d| d.key())
+        .collect();
+        assert_eq!(keys, vec!["foo/a", "foo/b", "foo/d"]);
+    }
+
+    #[test]
+    fn scoped_functions() {
+        let keys: Vec<_> = test_util::deps(
+            r#"
+            let
+                inherit (uptix) dockerImage;
+                inherit (pkgs) githubBranch;
+                image = uptix.dockerImage;
+            in {
+                a = dockerImage "foo/a";
+                b = image "foo/b";
+                c = githubBranch { owner = "foo"; repo = "c"; branch = "main"; };
+                d = with uptix; [ (dockerImage "foo/d") ];
+                e = with pkgs; githubRelease { owner = "foo"; repo = "e"; };
+                f = with (uptix); githubRelease { owner = "foo"; repo = "f"; };
+            }"#,
+        )
+        .unwrap()
+        .iter()
+        .map(|d| d.key())
+        .collect();
+        assert_eq!(
+            keys,
+            vec!["foo/a", "foo/b", "foo/d", "$GITHUB_RELEASE$:foo/f$"],
+        );
+    }
+
+    #[test]
+    fn configured_prefixes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("default.nix");
+        fs::write(&path, "{ a = u.dockerImage \"foo/a\"; }").unwrap();
+        let mut config = Config::default();
+        config.parsing.prefixes = vec!["u".to_string()];
+        let keys: Vec<_> = collect_file_dependencies(path.to_str().unwrap(), &config)
+            .unwrap()
+            .iter()
+            .map(|d| d.key())
+            .collect();
+        assert_eq!(keys, vec!["foo/a"]);
+    }
+
+    #[test]
+    fn nested_declarations() {
+        let keys: Vec<_> = test_util::deps(
+            r#"{ config, lib, uptix, ... }:
+            let
+                images = rec {
+                    base = uptix.dockerImage "foo/rec";
+                    other = base;
+                };
+            in {
+                a = [ (uptix.dockerImage "foo/list") "foo/not-an-image" ];
+                b = lib.mkIf config.foo.enable {
+                    c = uptix.dockerImage "foo/mkif";
+                };
+                d = lib.mkMerge [ { e = uptix.dockerImage "foo/mkmerge"; } ];
+                f = name: uptix.dockerImage "foo/lambda";
+                g = if config.foo.enable then uptix.dockerImage "foo/if" else null;
+                h = "${uptix.dockerImage "foo/interpolated"}";
+                i = uptix.dockerImage ("foo/parenthesized");
+            }"#,
+        )
+        .unwrap()
+        .iter()
+        .map(|d| d.key())
+        .collect();
+        assert_eq!(
+            keys,
+            vec![
+                "foo/rec",
+                "foo/list",
+                "foo/mkif",
+                "foo/mkmerge",
+                "foo/lambda",
+                "foo/if",
+                "foo/interpolated",
+                "foo/parenthesized",
+            ],
+        );
+    }
+
+    #[test]
+    fn references_without_calls() {
+        let dependencies = test_util::deps(
+            r#"{
+                a = [ uptix.dockerImage "foo/not-a-call" ];
+                b = map uptix.dockerImage [ "foo/mapped" ];
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(dependencies.len(), 0);
+    }
 }
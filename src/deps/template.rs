@@ -0,0 +1,74 @@
+use crate::config::Config;
+use crate::deps::docker::Docker;
+use crate::deps::{Dependency, DependencyKind};
+use crate::error::Error;
+use crate::util::{self, ParsingContext};
+use regex::Regex;
+
+/// Builds a regex matching `<prefix>.dockerImage "<name>"` for any of the
+/// accepted prefixes. Quotes may be escaped, since templates are often JSON.
+fn declaration_regex(config: &Config) -> Regex {
+    let prefixes = std::iter::once("uptix")
+        .chain(config.parsing.prefixes.iter().map(|p| p.as_str()))
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join("|");
+    let pattern = format!(r#"\b(?:{})\.dockerImage\s+\\?"([^"\\$]+)\\?""#, prefixes);
+    return Regex::new(&pattern).unwrap();
+}
+
+/// Scans files which can't be parsed as nix (i.e. `.nix.in` templates or
+/// Terranix JSON) for declarations of Docker images in their string form
+pub fn collect_template_dependencies(
+    file_path: &str,
+    config: &Config,
+) -> Result<Vec<Dependency>, Error> {
+    let content = util::read_source(file_path, config.discovery.max_file_size)?;
+    let context = ParsingContext::new(file_path, &content);
+    let mut dependencies = vec![];
+    for caps in declaration_regex(config).captures_iter(&content) {
+        let declaration = caps.get(0).unwrap();
+        let mut dependency = Dependency {
+            kind: DependencyKind::Docker(Docker::from(&caps[1])?),
+            location: context.location_at(declaration.start(), declaration.len()),
+            pinned: false,
+        };
+        dependency.configure(config);
+        dependencies.push(dependency);
+    }
+    return Ok(dependencies);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::collect_template_dependencies;
+    use crate::config::Config;
+    use std::fs;
+
+    #[test]
+    fn it_finds_declarations() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.tf.json");
+        fs::write(
+            &path,
+            r#"{
+  "resource": {
+    "docker_container": {
+      "grafana": { "image": "${uptix.dockerImage \"grafana/grafana\"}" },
+      "postgres": { "image": "${u.dockerImage \"postgres/postgres:16\"}" },
+      "redis": { "image": "${other.dockerImage \"redis:7\"}" },
+      "dynamic": { "image": "${uptix.dockerImage \"foo/${bar}\"}" }
+    }
+  }
+}"#,
+        )
+        .unwrap();
+        let mut config = Config::default();
+        config.parsing.prefixes = vec!["u".to_string()];
+        let dependencies = collect_template_dependencies(path.to_str().unwrap(), &config).unwrap();
+        let keys: Vec<_> = dependencies.iter().map(|d| d.key()).collect();
+        assert_eq!(keys, vec!["grafana/grafana", "postgres/postgres:16"]);
+        assert_eq!(dependencies[0].location().line, 4);
+        assert_eq!(dependencies[0].location().column, 32);
+    }
+}
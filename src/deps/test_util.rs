@@ -1,11 +1,9 @@
-use crate::deps::collect_ast_dependencies;
+use crate::deps::collect_root_dependencies;
 use crate::deps::Dependency;
 use crate::util::ParsingContext;
 
-#[cfg(test)]
-
 pub fn deps(source: &str) -> Result<Vec<Dependency>, crate::error::Error> {
-    let ast = rnix::parse(source);
-    let context = ParsingContext::new("./test.nix", source);
-    return collect_ast_dependencies(&context, ast.node());
+    let ast = rnix::Root::parse(source);
+    let mut context = ParsingContext::new("./test.nix", source);
+    return collect_root_dependencies(&mut context, ast.syntax());
 }
@@ -0,0 +1,84 @@
+use crate::config::Config;
+use crate::deps::github::action::GitHubAction;
+use crate::deps::{Dependency, DependencyKind};
+use crate::error::Error;
+use crate::util::{self, ParsingContext};
+use regex::Regex;
+
+lazy_static! {
+    /// Matches `uses: owner/repo[/path]@ref` steps and jobs, leaving out
+    /// local (`./`) and `docker://` actions
+    static ref USES_RE: Regex = Regex::new(
+        r#"(?m)^[ \t]*(?:-[ \t]+)?uses:[ \t]*["']?([A-Za-z0-9][A-Za-z0-9_.-]*)/([A-Za-z0-9_.-]+)(?:/[^@\s"']*)?@([^\s"'#]+)"#
+    )
+    .unwrap();
+}
+
+/// Scans a GitHub Actions workflow for the actions (and reusable workflows)
+/// it uses
+pub fn collect_workflow_dependencies(
+    file_path: &str,
+    config: &Config,
+) -> Result<Vec<Dependency>, Error> {
+    let content = util::read_source(file_path, config.discovery.max_file_size)?;
+    let context = ParsingContext::new(file_path, &content);
+    let mut dependencies = vec![];
+    for caps in USES_RE.captures_iter(&content) {
+        let start = caps.get(1).unwrap().start();
+        let end = caps.get(0).unwrap().end();
+        let mut dependency = Dependency {
+            kind: DependencyKind::GitHubAction(GitHubAction::new(&caps[1], &caps[2], &caps[3])),
+            location: context.location_at(start, end - start),
+            pinned: false,
+        };
+        dependency.configure(config);
+        dependencies.push(dependency);
+    }
+    return Ok(dependencies);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::collect_workflow_dependencies;
+    use crate::config::Config;
+    use std::fs;
+
+    #[test]
+    fn it_finds_actions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ci.yml");
+        fs::write(
+            &path,
+            r#"on: push
+jobs:
+  build:
+    runs-on: ubuntu-latest
+    steps:
+      - uses: actions/checkout@v4
+      - name: Cache
+        uses: "actions/cache/restore@v4.0.2"
+      - uses: ./.github/actions/local
+      - uses: docker://alpine:3.19
+      - uses: cachix/install-nix-action@8887e596b4ee1134dae06b98d573bd674693f47c # v26
+      - run: echo "uses: not/an-action@v1"
+  release:
+    uses: luizribeiro/workflows/.github/workflows/release.yml@main
+"#,
+        )
+        .unwrap();
+        let dependencies =
+            collect_workflow_dependencies(path.to_str().unwrap(), &Config::default()).unwrap();
+        let keys: Vec<_> = dependencies.iter().map(|d| d.key()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "$GITHUB_ACTION$:actions/checkout@v4",
+                "$GITHUB_ACTION$:actions/cache@v4.0.2",
+                "$GITHUB_ACTION$:cachix/install-nix-action@8887e596b4ee1134dae06b98d573bd674693f47c",
+                "$GITHUB_ACTION$:luizribeiro/workflows@main",
+            ]
+        );
+        assert_eq!(dependencies[0].location().line, 6);
+        assert_eq!(dependencies[0].location().column, 15);
+    }
+}
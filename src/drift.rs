@@ -0,0 +1,122 @@
+use crate::config::Config;
+use crate::deps::Docker;
+use crate::error::Error;
+use crate::util::{self, Location, ParsingContext};
+use regex::Regex;
+use rnix::ast;
+use rowan::ast::AstNode;
+
+lazy_static! {
+    /// Strings pinned to a digest, such as
+    /// `grafana/grafana:10.4.2@sha256:...`. The reference before the digest
+    /// is checked against docker's grammar when parsing it as an image.
+    static ref PINNED_RE: Regex = Regex::new(r"^([^\s@]+)@(sha256:[0-9a-f]{64})$").unwrap();
+}
+
+/// An image reference which was pinned to a digest by hand, rather than
+/// through uptix
+pub struct PinnedImage {
+    pub location: Location,
+    pub reference: String,
+    pub digest: String,
+    pub image: Docker,
+}
+
+impl PinnedImage {
+    fn parse(location: Location, text: &str) -> Option<PinnedImage> {
+        let caps = PINNED_RE.captures(text)?;
+        let reference = caps[1].to_string();
+        // official images on docker hub live under the library namespace
+        let name = reference.split(':').next().unwrap();
+        let image = match name.contains('/') {
+            true => Docker::from(&reference).ok()?,
+            false => Docker::from(&format!("library/{}", reference)).ok()?,
+        };
+        return Some(PinnedImage {
+            location,
+            reference,
+            digest: caps[2].to_string(),
+            image,
+        });
+    }
+}
+
+/// Finds string literals with image references pinned to a digest
+pub fn find_pinned_images(file_path: &str, config: &Config) -> Result<Vec<PinnedImage>, Error> {
+    let content = util::read_source(file_path, config.discovery.max_file_size)?;
+    let ast = rnix::Root::parse(&content);
+    let context = ParsingContext::new(file_path, &content);
+    if let Some(error) = ast.errors().first() {
+        return Err(context.syntax_error(error));
+    }
+
+    let mut images = vec![];
+    for string in ast.syntax().descendants().filter_map(ast::Str::cast) {
+        let node = string.syntax();
+        let text = match util::string_value(node) {
+            Some(text) => text,
+            None => continue,
+        };
+        if let Some(mut image) = PinnedImage::parse(context.location(node), &text) {
+            image.image.configure(&config.docker);
+            images.push(image);
+        }
+    }
+    return Ok(images);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_pinned_images;
+    use crate::config::Config;
+    use std::fs;
+
+    const DIGEST: &str = "sha256:4c5c2e8a2bbd3b8d3e5b2b58e2c1a6f6f1d1f2f2c2b6b8d8e3d4f6a7b8c9d0e1";
+
+    #[test]
+    fn it_finds_pinned_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("default.nix");
+        fs::write(
+            &path,
+            format!(
+                r#"{{
+  grafana = "grafana/grafana:10.4.2@{digest}";
+  postgres = "postgres:16@{digest}";
+  app = "ghcr.io/acme/team/app:v1.2_RC1@{digest}";
+  invalid = "not an image@{digest}";
+  unpinned = "grafana/grafana:10.4.2";
+  managed = uptix.dockerImage "grafana/grafana:10.4.2";
+}}"#,
+                digest = DIGEST,
+            ),
+        )
+        .unwrap();
+        let images = find_pinned_images(path.to_str().unwrap(), &Config::default()).unwrap();
+        let found: Vec<_> = images
+            .iter()
+            .map(|i| (i.reference.as_str(), i.image.identity(), i.location.line))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    "grafana/grafana:10.4.2",
+                    "registry-1.docker.io/grafana/grafana".to_string(),
+                    2,
+                ),
+                (
+                    "postgres:16",
+                    "registry-1.docker.io/library/postgres".to_string(),
+                    3,
+                ),
+                (
+                    "ghcr.io/acme/team/app:v1.2_RC1",
+                    "ghcr.io/acme/team/app".to_string(),
+                    4,
+                ),
+            ],
+        );
+        assert!(images.iter().all(|i| i.digest == DIGEST));
+    }
+}
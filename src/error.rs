@@ -1,10 +1,17 @@
 use miette::{Diagnostic, NamedSource, SourceSpan};
 
 #[derive(thiserror::Error, Diagnostic, Debug)]
+#[non_exhaustive]
 pub enum Error {
-    #[error("registry error")]
+    #[error("registry error: {0}")]
     #[diagnostic(code(uptix::error::registry))]
-    RegistryError(#[from] dkregistry::errors::Error),
+    RegistryError(String),
+    #[error("GitHub API error: {0}")]
+    #[diagnostic(code(uptix::error::github))]
+    GitHubError(String),
+    #[error("GitLab API error: {0}")]
+    #[diagnostic(code(uptix::error::gitlab))]
+    GitLabError(String),
     #[error("HTTP request error")]
     #[diagnostic(code(uptix::error::request_error))]
     RequestError(#[from] reqwest::Error),
@@ -17,11 +24,52 @@ pub enum Error {
     #[error("I/O error")]
     #[diagnostic(code(uptix::error::io_error))]
     IOError(#[from] std::io::Error),
+    #[error("config parsing error")]
+    #[diagnostic(code(uptix::error::config_error))]
+    ConfigError(#[from] toml::de::Error),
+    #[error("invalid glob pattern")]
+    #[diagnostic(code(uptix::error::glob_error))]
+    GlobError(#[from] globset::Error),
+    #[error("scan root {path} is not a directory")]
+    #[diagnostic(
+        code(uptix::error::missing_scan_root),
+        help("check the [[discovery.roots]] entries in uptix.toml")
+    )]
+    MissingScanRoot { path: String },
+    #[error("could not read {path}")]
+    #[diagnostic(code(uptix::error::unreadable_file))]
+    UnreadableFile {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{path} is larger than {max_size} bytes ({size} bytes)")]
+    #[diagnostic(
+        code(uptix::error::oversized_file),
+        help("raise max_file_size under [discovery] in uptix.toml to scan it anyway")
+    )]
+    OversizedFile {
+        path: String,
+        size: u64,
+        max_size: u64,
+    },
+    #[error("{path} does not look like a text file")]
+    #[diagnostic(code(uptix::error::binary_file))]
+    BinaryFile { path: String },
+    #[error("Nix syntax error")]
+    #[diagnostic(code(uptix::error::nix_syntax_error))]
+    NixSyntaxError {
+        #[source_code]
+        src: NamedSource,
+        #[label("{message}")]
+        pos: SourceSpan,
+        message: String,
+    },
     #[error("Nix parsing error")]
     #[diagnostic(code(uptix::error::nix_parsing_error))]
     NixParsingError(String),
     #[error("Unexpected argument for {function}")]
-    #[diagnostic(help("{help}"))]
+    #[diagnostic(code(uptix::error::unexpected_argument), help("{help}"))]
     UnexpectedArgument {
         function: String,
         #[source_code]
@@ -31,7 +79,361 @@ pub enum Error {
         expected_type: String,
         help: String,
     },
-    #[error("unknown error")]
-    #[diagnostic(code(uptix::error::unknown_error))]
-    StringError(String),
+    #[error("Opening {what} on {host} needs a token")]
+    #[diagnostic(code(uptix::error::missing_token))]
+    MissingToken {
+        what: String,
+        host: String,
+        #[help]
+        hint: String,
+    },
+    #[error("Invalid argument for {function}: {message}")]
+    #[diagnostic(
+        code(uptix::error::invalid_argument),
+        help("see the README for the options {function} takes")
+    )]
+    InvalidArgument {
+        function: String,
+        message: String,
+        #[source_code]
+        src: NamedSource,
+        #[label("{message}")]
+        argument_pos: SourceSpan,
+    },
+    #[error("Conflicting declarations for {identity}")]
+    #[diagnostic(
+        code(uptix::error::conflicting_declarations),
+        help("declared as:\n{declarations}")
+    )]
+    ConflictingDeclarations {
+        identity: String,
+        declarations: String,
+    },
+    #[error("The history of {name} was rewritten: {old_rev} is no longer in it ({location})")]
+    #[diagnostic(
+        code(uptix::error::rewritten_history),
+        help("make sure the force-push to {new_rev} was intended before deploying it")
+    )]
+    RewrittenHistory {
+        name: String,
+        old_rev: String,
+        new_rev: String,
+        location: String,
+    },
+    #[error("{name} uses the latest tag ({location})")]
+    #[diagnostic(
+        code(uptix::error::latest_tag),
+        help("pin a version tag instead, or set latest_tag under [lints] in uptix.toml")
+    )]
+    LatestTag { name: String, location: String },
+    #[error("{name} follows a release which is no longer supported: {reason} ({location})")]
+    #[diagnostic(
+        code(uptix::error::end_of_life),
+        help("move to a supported release, or set end_of_life under [lints] in uptix.toml")
+    )]
+    EndOfLife {
+        name: String,
+        reason: String,
+        location: String,
+    },
+    #[error("Could not verify the signature of {image}: {reason}")]
+    #[diagnostic(
+        code(uptix::error::signature_verification),
+        help("check the cosign settings for the image's registry on uptix.toml")
+    )]
+    SignatureVerification { image: String, reason: String },
+    #[error("{name} at {rev} has no verified signature: {reason}")]
+    #[diagnostic(
+        code(uptix::error::unverified_signature),
+        help("make sure upstream signed it, or drop verifySignature from the dependency")
+    )]
+    UnverifiedSignature {
+        name: String,
+        rev: String,
+        reason: String,
+    },
+    #[error("None of the last {count} commits of {name} on {branch} passed their checks")]
+    #[diagnostic(
+        code(uptix::error::failing_checks),
+        help("wait for upstream CI to pass, or drop requireChecks from the dependency")
+    )]
+    FailingChecks {
+        name: String,
+        branch: String,
+        count: usize,
+    },
+    #[error("{name} is deprecated: {reason} ({location})")]
+    #[diagnostic(
+        code(uptix::error::deprecated_image),
+        help("move to the image's replacement, as it will stop receiving updates")
+    )]
+    DeprecatedImage {
+        name: String,
+        reason: String,
+        location: String,
+    },
+    #[error("Interrupted before {skipped} dependencies were locked")]
+    #[diagnostic(
+        code(uptix::error::interrupted),
+        help("pass --save-partial to write the dependencies locked so far")
+    )]
+    Interrupted { skipped: usize },
+    #[error("{url} is larger than {limit} bytes")]
+    #[diagnostic(code(uptix::error::oversized_response))]
+    OversizedResponse { url: String, limit: usize },
+    #[error("{} errors found while parsing", .errors.len())]
+    #[diagnostic(code(uptix::error::parsing_errors))]
+    ParsingErrors {
+        #[related]
+        errors: Vec<Error>,
+    },
+    #[error("Could not update {name}")]
+    #[diagnostic(code(uptix::error::update_failed))]
+    UpdateFailed {
+        name: String,
+        #[source_code]
+        src: NamedSource,
+        #[label("declared here")]
+        pos: SourceSpan,
+        /// what went wrong, as its own diagnostic
+        #[related]
+        cause: Vec<Error>,
+    },
+    #[error("{} dependencies could not be updated", .errors.len())]
+    #[diagnostic(code(uptix::error::update_errors))]
+    UpdateErrors {
+        #[related]
+        errors: Vec<Error>,
+    },
+    #[error("{count} pinned images have drifted")]
+    #[diagnostic(
+        code(uptix::error::drifted_images),
+        help("pin the images to the digests their tags point to now")
+    )]
+    DriftedImages { count: usize },
+    #[error("{count} dependencies failed the {lint} lint")]
+    #[diagnostic(
+        code(uptix::error::denied_lint),
+        help("fix them, or set {lint} to warn under [lints] in uptix.toml")
+    )]
+    DeniedLint { lint: String, count: usize },
+    #[error("Unknown diagnostic code {code}")]
+    #[diagnostic(
+        code(uptix::error::unknown_code),
+        help("run `uptix explain` to list the available codes")
+    )]
+    UnknownCode { code: String },
+    #[error("Could not resolve {host}")]
+    #[diagnostic(
+        code(uptix::error::dns_failure),
+        help("check the spelling of the host (i.e. an image's registry), and your network's DNS")
+    )]
+    DnsFailure { host: String },
+    #[error("Could not establish a secure connection to {host}: {reason}")]
+    #[diagnostic(
+        code(uptix::error::tls_failure),
+        help("add the CA of hosts with their own certificates to the system's trust store, or set insecure = true for registries which only speak HTTP")
+    )]
+    TlsFailure { host: String, reason: String },
+    #[error("Request to {host} timed out")]
+    #[diagnostic(
+        code(uptix::error::timeout),
+        help("allow more time with --timeout, or timeout under [network] in uptix.toml")
+    )]
+    Timeout { host: String },
+    #[error("Locking {name} took longer than {seconds}s")]
+    #[diagnostic(
+        code(uptix::error::deadline_exceeded),
+        help("allow more time with deadline under [network] in uptix.toml")
+    )]
+    DeadlineExceeded { name: String, seconds: u64 },
+    #[error("{url} refused the request ({status})")]
+    #[diagnostic(code(uptix::error::unauthorized))]
+    Unauthorized {
+        url: String,
+        status: String,
+        #[help]
+        hint: String,
+    },
+    #[error("{url} was not found")]
+    #[diagnostic(code(uptix::error::not_found))]
+    NotFound {
+        url: String,
+        #[help]
+        hint: String,
+    },
+    #[error("{registry} is rate limiting requests{quota}")]
+    #[diagnostic(
+        code(uptix::error::registry_rate_limit),
+        help("try again later, or log in to the registry (i.e. with docker login) for a higher limit")
+    )]
+    RegistryRateLimited { registry: String, quota: String },
+    #[error("GitHub ({host}) is rate limiting requests {until}")]
+    #[diagnostic(
+        code(uptix::error::github_rate_limit),
+        help("set GITHUB_TOKEN (or token_env for the host on uptix.toml) for a higher limit")
+    )]
+    GitHubRateLimited { host: String, until: String },
+    #[error("Invalid version constraint: {constraint}")]
+    #[diagnostic(
+        code(uptix::error::invalid_constraint),
+        help("use comparators separated by commas, i.e. ~1.4 or >=2, <3")
+    )]
+    InvalidConstraint { constraint: String },
+    #[error("Could not find image {name} on registry")]
+    #[diagnostic(
+        code(uptix::error::image_not_found),
+        help("check the spelling of the image and its tag")
+    )]
+    ImageNotFound { name: String },
+    #[error("No tags of image {image} match {pattern}")]
+    #[diagnostic(code(uptix::error::no_matching_tag))]
+    NoMatchingTag { image: String, pattern: String },
+    #[error("Image {name} is not available for {platform} (available: {available})")]
+    #[diagnostic(
+        code(uptix::error::unsupported_platform),
+        help("pick one of the available platforms with the platform option")
+    )]
+    UnsupportedPlatform {
+        name: String,
+        platform: String,
+        available: String,
+    },
+    #[error("Image {name} is not a multi-platform image")]
+    #[diagnostic(
+        code(uptix::error::not_multi_platform),
+        help("use uptix.dockerImage instead of uptix.dockerImagePlatforms")
+    )]
+    NotMultiPlatform { name: String },
+    #[error("No release of {name} satisfies {constraint}")]
+    #[diagnostic(code(uptix::error::no_matching_release))]
+    NoMatchingRelease { name: String, constraint: String },
+    #[error("No asset of {name} {tag} matches {pattern}")]
+    #[diagnostic(code(uptix::error::no_matching_asset))]
+    NoMatchingAsset {
+        name: String,
+        tag: String,
+        pattern: String,
+    },
+    #[error("{tool} failed: {reason}")]
+    #[diagnostic(code(uptix::error::prefetch_failed))]
+    PrefetchFailed { tool: String, reason: String },
+    #[error("Could not notify {host}: {reason}")]
+    #[diagnostic(
+        code(uptix::error::notification_failed),
+        help("check the url (or url_env) of the notification on uptix.toml")
+    )]
+    NotificationFailed { host: String, reason: String },
+    #[error("git {command} failed: {reason}")]
+    #[diagnostic(code(uptix::error::git_failed))]
+    GitFailed { command: String, reason: String },
+    #[error(
+        "{mismatched} locked sources don't match their hash, and {failed} couldn't be fetched"
+    )]
+    #[diagnostic(
+        code(uptix::error::hash_mismatch),
+        help("run `uptix update` to lock them again, after making sure the change upstream is legitimate")
+    )]
+    HashMismatch { mismatched: usize, failed: usize },
+    #[error("uptix.lock is out of sync with the nix files: {missing} missing, {stale} stale and {invalid} invalid entries")]
+    #[diagnostic(
+        code(uptix::error::lock_out_of_sync),
+        help("run `uptix update` to lock what's missing and drop what's stale")
+    )]
+    LockOutOfSync {
+        missing: usize,
+        stale: usize,
+        invalid: usize,
+    },
+    #[error("No vulnerability database at {path}")]
+    #[diagnostic(
+        code(uptix::error::missing_vulnerability_database),
+        help("download one with `uptix db sync --db {path}` where there is network access")
+    )]
+    MissingVulnerabilityDatabase { path: String },
+    #[error("Could not sync the vulnerability database from {url}: {reason}")]
+    #[diagnostic(code(uptix::error::database_sync_failed))]
+    DatabaseSyncFailed { url: String, reason: String },
+    #[error("{count} known vulnerabilities affect what uptix.lock is locked to")]
+    #[diagnostic(
+        code(uptix::error::vulnerable_dependencies),
+        help(
+            "update the affected dependencies with `uptix update`, or pin them to a fixed version"
+        )
+    )]
+    VulnerableDependencies { count: usize },
+    #[error("Could not sign the provenance of the update: {reason}")]
+    #[diagnostic(
+        code(uptix::error::provenance_signing_failed),
+        help("--provenance-key takes a private key in PEM (RSA, ECDSA or Ed25519)")
+    )]
+    ProvenanceSigningFailed { reason: String },
+    #[error("Could not decrypt the credentials in {path}: {reason}")]
+    #[diagnostic(
+        code(uptix::error::credentials_decryption_failed),
+        help(
+            "check that the key to decrypt it with is available, i.e. with `sops --decrypt {path}`"
+        )
+    )]
+    CredentialsDecryptionFailed { path: String, reason: String },
+    #[error("The post-update hook `{hook}` failed ({status})")]
+    #[diagnostic(
+        code(uptix::error::post_update_hook_failed),
+        help("uptix.lock was restored to what it was before the update")
+    )]
+    PostUpdateHookFailed { hook: String, status: String },
+    #[error("{path} already exists and wasn't written by uptix")]
+    #[diagnostic(
+        code(uptix::error::hook_exists),
+        help("pass --force to replace it, or call `uptix hook run` from it instead")
+    )]
+    HookExists { path: String },
+}
+
+impl Error {
+    /// The status uptix exits with: 3 when `uptix check` found problems, so
+    /// that CI can tell them apart from uptix failing to run (1), and 130
+    /// when interrupted, as shells do for Ctrl-C
+    pub fn exit_code(&self) -> u8 {
+        return match self {
+            Error::DriftedImages { .. } | Error::DeniedLint { .. } => 3,
+            Error::LockOutOfSync { .. } | Error::VulnerableDependencies { .. } => 3,
+            Error::HashMismatch { mismatched, .. } if *mismatched > 0 => 3,
+            Error::Interrupted { .. } => 130,
+            _ => 1,
+        };
+    }
+
+    /// The diagnostic code of the error (i.e. `uptix::error::not_found`),
+    /// which stays the same across releases so that tools can match on it
+    pub fn diagnostic_code(&self) -> String {
+        return Diagnostic::code(self)
+            .map(|code| code.to_string())
+            .unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+
+    #[test]
+    fn it_has_exit_codes() {
+        let error = Error::ImageNotFound {
+            name: "foo/bar".to_string(),
+        };
+        assert_eq!(error.exit_code(), 1);
+        assert_eq!(Error::DriftedImages { count: 2 }.exit_code(), 3);
+        assert_eq!(Error::Interrupted { skipped: 1 }.exit_code(), 130);
+    }
+
+    #[test]
+    fn it_has_diagnostic_codes() {
+        let error = Error::NoMatchingTag {
+            image: "foo/bar".to_string(),
+            pattern: "1.*".to_string(),
+        };
+        assert_eq!(error.diagnostic_code(), "uptix::error::no_matching_tag");
+        assert_eq!(error.to_string(), "No tags of image foo/bar match 1.*");
+    }
 }
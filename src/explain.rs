@@ -0,0 +1,762 @@
+/// Prefix shared by all of the diagnostic codes in `Error`
+const CODE_PREFIX: &str = "uptix::error::";
+
+/// Extended guidance for each diagnostic code, printed by `uptix explain`
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "registry",
+        r#"A Docker registry returned an error while uptix was looking up the
+latest digest of an image.
+
+This usually means the image or tag doesn't exist, the registry requires
+credentials, or the registry is not reachable. Credentials for private
+registries can be set with `UPTIX_REGISTRY_AUTH_<HOST>=username:password`,
+under `[docker.registries."<host>"]` on uptix.toml, or with `docker login`
+(or `podman login`).
+
+Double check the image name on the declaration mentioned in the error, for
+instance:
+
+  uptix.dockerImage "grafana/grafana:latest"
+
+Images on Docker Hub without an organization live under `library/`, which
+uptix adds automatically when using the attrset form:
+
+  uptix.dockerImage { image = "postgres"; tag = "16"; }
+
+Docker Hub also limits how many images can be pulled anonymously. uptix
+retries rate limited requests a few times and prints the remaining quota at
+the end of each run; logging in with `docker login` raises the limit."#,
+    ),
+    (
+        "request_error",
+        r#"An HTTP request made by uptix failed, typically while talking to the
+GitHub API.
+
+Check your network connection. GitHub also rate limits unauthenticated
+requests, so running uptix many times in a row may cause requests to fail
+for a while."#,
+    ),
+    (
+        "url_error",
+        r#"uptix could not build a valid URL for a dependency.
+
+This happens when an owner, repository, branch or registry name contains
+characters which are not valid in a URL. Check the declaration mentioned in
+the error for typos."#,
+    ),
+    (
+        "json_parsing_error",
+        r#"uptix could not parse or produce a JSON document.
+
+This can come from an unexpected response from the GitHub API, from an
+uptix.lock file which is not valid JSON (i.e. after a bad merge), or from a
+declaration whose attributes have the wrong types, such as:
+
+  uptix.githubBranch { owner = "foo"; repo = "bar"; branch = 42; }
+
+If uptix.lock is broken, fix the conflict or delete it and run uptix again."#,
+    ),
+    (
+        "io_error",
+        r#"An operating system error happened while reading or writing files.
+
+Make sure uptix can read your nix files and write uptix.lock in the current
+directory."#,
+    ),
+    (
+        "config_error",
+        r#"uptix.toml could not be parsed.
+
+Besides TOML syntax errors, unknown keys are rejected so typos don't go
+unnoticed. A valid configuration looks like:
+
+  [discovery]
+  exclude = ["third_party/**"]
+  flake_inputs = true
+
+  [parsing]
+  prefixes = ["u"]"#,
+    ),
+    (
+        "glob_error",
+        r#"One of the glob patterns in uptix.toml is not valid.
+
+Patterns under `exclude` and `templates` use the usual glob syntax, where
+`*` matches within a directory and `**` matches across directories:
+
+  [discovery]
+  exclude = ["tests/fixtures/**", "*.generated.nix"]"#,
+    ),
+    (
+        "missing_scan_root",
+        r#"One of the `[[discovery.roots]]` configured in uptix.toml is not a
+directory.
+
+Root paths are relative to the directory uptix is run from:
+
+  [[discovery.roots]]
+  path = "hosts"
+
+Remove the root or fix its path."#,
+    ),
+    (
+        "unreadable_file",
+        r#"uptix found a file but could not read it.
+
+The file is skipped and the rest of the run continues. This usually means
+the file has restrictive permissions or was removed while uptix was running.
+If the file should not be scanned at all, exclude it in uptix.toml:
+
+  [discovery]
+  exclude = ["secrets/**"]"#,
+    ),
+    (
+        "oversized_file",
+        r#"A file is larger than the configured `max_file_size` and was skipped.
+
+Generated nix files (such as node2nix outputs) can be several megabytes and
+rarely contain uptix declarations, so files over 1 MiB are skipped by
+default. If a large file does declare dependencies, raise the limit (in
+bytes), or set it to 0 to disable it:
+
+  [discovery]
+  max_file_size = 4194304"#,
+    ),
+    (
+        "binary_file",
+        r#"A file looks like binary content (it contains NUL bytes or is not
+valid UTF-8) and was skipped.
+
+Exclude it in uptix.toml to silence the warning:
+
+  [discovery]
+  exclude = ["assets/**"]"#,
+    ),
+    (
+        "nix_syntax_error",
+        r#"A nix file could not be parsed.
+
+The file is skipped and the rest of the run continues. The error points at
+where parsing failed; `nix-instantiate --parse <file>` reports the same
+errors."#,
+    ),
+    (
+        "nix_parsing_error",
+        r#"The argument of an uptix function uses nix features which uptix can't
+evaluate.
+
+uptix reads declarations without evaluating nix, so arguments must be
+literals. String interpolation, `inherit` and references to other variables
+are not supported:
+
+  # not supported
+  uptix.dockerImage "grafana/grafana:${version}"
+
+  # supported
+  uptix.dockerImage "grafana/grafana:10.0.0""#,
+    ),
+    (
+        "unexpected_argument",
+        r#"An uptix function was called with the wrong kind of argument.
+
+uptix.dockerImage takes a string or an attrset, while uptix.githubBranch,
+uptix.githubRelease and uptix.deps take attrsets:
+
+  uptix.githubBranch {
+    owner = "luizribeiro";
+    repo = "uptix";
+    branch = "main";
+  }
+
+The help message on the error shows an example for the function being
+called."#,
+    ),
+    (
+        "conflicting_declarations",
+        r#"The same image or repository is declared with different versions
+(i.e. different tags or branches) in different places.
+
+This is reported as a warning, or as an error when running with --strict.
+Make the declarations agree, or add a directive to the one that is meant to
+differ:
+
+  # uptix: ignore
+  legacy = uptix.dockerImage "postgres/postgres:12";"#,
+    ),
+    (
+        "rewritten_history",
+        r#"A `uptix.githubBranch` dependency moved to a commit which doesn't
+descend from the one previously on uptix.lock, according to GitHub's compare
+API. This happens when upstream force-pushes to the branch (or deletes and
+recreates it), which may be an honest mistake or a sign that the repository
+was compromised.
+
+This is reported as a warning, or as an error when running with --strict (in
+which case uptix.lock is left alone). Look at what changed on the branch
+before deploying the new commit."#,
+    ),
+    (
+        "latest_tag",
+        r#"A Docker image uses the `latest` tag, either explicitly or by not
+setting a tag at all.
+
+Since `latest` moves to whatever was pushed last, uptix can't tell which
+version you are running, and rolling back to a previous version means
+digging up an old digest. Pin a version tag instead, or a tag pattern if
+you want to follow new releases:
+
+  uptix.dockerImage "grafana/grafana:10.*"
+
+The lint can be silenced, or made to fail `uptix check`, in uptix.toml:
+
+  [lints]
+  latest_tag = "allow"  # or "warn" (the default) or "deny""#,
+    ),
+    (
+        "deprecated_image",
+        r#"A Docker Hub image has been deprecated, according to the description
+or status of its repository on Docker Hub.
+
+Deprecated images (such as official images which moved to a new namespace)
+stop receiving updates, including security fixes, so uptix will keep
+locking the last version ever pushed. The description usually names the
+image to move to instead, i.e. `eclipse-temurin` for `openjdk`."#,
+    ),
+    (
+        "signature_verification",
+        r#"The cosign signature of an image could not be verified, so it was
+not written to uptix.lock.
+
+Registries with a `cosign` section on uptix.toml need every image resolved
+from them to be signed, either with a public key or (for keyless
+signatures) by the given identity:
+
+  [docker.registries."ghcr.io".cosign]
+  key = "cosign.pub"
+
+  [docker.registries."registry.example.com".cosign]
+  certificate_identity = "https://github.com/org/app/.github/workflows/release.yml@refs/heads/main"
+  certificate_oidc_issuer = "https://token.actions.githubusercontent.com"
+
+This usually means the image isn't signed, was signed by someone else, or
+`cosign` isn't installed. The error from cosign is included above."#,
+    ),
+    (
+        "unverified_signature",
+        r#"A GitHub dependency declared with `verifySignature = true` resolved
+to a commit (or tag) which GitHub doesn't show as verified, so it was not
+written to uptix.lock.
+
+GitHub verifies commits and annotated tags signed with a GPG, SSH or S/MIME
+key of their author. Releases pass when either their tag or the commit it
+points to is verified. The reason GitHub gives (i.e. `unsigned` or
+`unknown_key`) is included in the error.
+
+This may mean that upstream stopped signing their commits, or that the
+dependency was tampered with, so check the repository before dropping
+`verifySignature`."#,
+    ),
+    (
+        "failing_checks",
+        r#"A `uptix.githubBranch` dependency which requires passing checks (with
+`requireChecks = true`, or `require_checks` under `[github]` on uptix.toml)
+had no recent commit whose checks all passed, so its lock was left alone.
+
+A commit passes when its combined status is `success` and each of its check
+runs completed as `success`, `neutral` or `skipped`. Commits without any
+statuses or check runs don't pass, since nothing vouches for them. Only the
+latest commits of the branch are considered, starting from its tip.
+
+This usually means upstream CI is failing or still running. It passes again
+once a commit with green checks lands on the branch."#,
+    ),
+    (
+        "end_of_life",
+        r#"A Docker image follows a release cycle (i.e. `postgres:12`) which
+reached its end of life, according to https://endoflife.date.
+
+uptix keeps such images up to date with the latest digest for their tag,
+but no new versions will be released for them, security fixes included.
+Change the tag to a supported release instead.
+
+Images are looked up on endoflife.date by their name. Images named
+differently from their product, or another dataset in the same format, can
+be set in uptix.toml, as can the lint level:
+
+  [eol]
+  url = "https://endoflife.date/api"
+  products = { "bitnami/postgresql" = "postgresql" }
+
+  [lints]
+  end_of_life = "allow"  # or "warn" (the default) or "deny""#,
+    ),
+    (
+        "github",
+        r#"The GitHub API returned an error while uptix was looking up the latest
+commit or release of a repository.
+
+This usually means the repository (or branch) doesn't exist, or is private
+and the request wasn't authenticated with a token that can access it. Set
+`GITHUB_TOKEN` for github.com, or `token_env` for GitHub Enterprise hosts:
+
+  [github.hosts."ghe.example.com"]
+  token_env = "GHE_TOKEN"
+
+The message from GitHub is included in the error."#,
+    ),
+    (
+        "gitlab",
+        r#"The GitLab API returned an error while `uptix update --create-mr`
+was pushing uptix.lock or opening the merge request for it.
+
+This usually means the token can't write to the project, or the project or
+target branch doesn't exist. They can be set under [gitlab] on uptix.toml
+when they can't be told from `origin`:
+
+  [gitlab]
+  url = "https://gitlab.example.com"
+  project = "infra/nixos"
+  target_branch = "main"
+
+The message from GitLab is included in the error."#,
+    ),
+    (
+        "github_rate_limit",
+        r#"The GitHub API refused a request because its rate limit ran out.
+
+Anonymous requests are limited to 60 per hour, which is easily used up by
+a few dependencies. Authenticated requests get a much higher limit, so set
+a token on the environment:
+
+  GITHUB_TOKEN=... uptix
+
+For GitHub Enterprise, set the variable holding the token in uptix.toml:
+
+  [github.hosts."ghe.example.com"]
+  token_env = "GHE_TOKEN"
+
+Short-lived (secondary) rate limits are waited out and retried
+automatically. The error says when the limit resets."#,
+    ),
+    (
+        "interrupted",
+        r#"uptix was interrupted (i.e. with Ctrl-C) while locking dependencies.
+
+Requests and prefetches which were still running are cancelled, and
+uptix.lock is left as it was. To keep what was locked before the
+interruption, pass --save-partial: dependencies which were locked are
+written, while the ones which were skipped keep their previous entries:
+
+  uptix update --save-partial"#,
+    ),
+    (
+        "oversized_response",
+        r#"A server returned a much larger response than expected, such as a
+Docker image config blob of several megabytes.
+
+Responses which are read whole, rather than hashed as they are downloaded,
+are limited in size so that a misbehaving registry can't make uptix run out
+of memory. This usually means the registry (or a proxy in front of it) is
+serving something other than what was asked for."#,
+    ),
+    (
+        "parsing_errors",
+        r#"More than one file had errors while being parsed.
+
+Each of the errors is listed below this one, with its own code."#,
+    ),
+    (
+        "update_failed",
+        r#"A dependency couldn't be locked to its latest version.
+
+The error pointing at where the dependency is declared is caused by the one
+below it, which has its own code and explanation. uptix.lock isn't written
+when any dependency fails, so that it never ends up with only some of them
+updated."#,
+    ),
+    (
+        "update_errors",
+        r#"More than one dependency couldn't be locked to its latest version.
+
+Every dependency is still looked up, so that all of the failures are
+reported at once. Each of them is listed below this one."#,
+    ),
+    (
+        "drifted_images",
+        r#"`uptix check` found images pinned to a digest by hand (i.e.
+"grafana/grafana:10.4.2@sha256:...") whose tag now points at a different
+digest.
+
+Each of them is listed above the error. Pin them to the digest their tag
+points to now, or declare them with uptix.dockerImage so that uptix keeps
+them up to date. uptix exits with status 3, rather than 1, so that CI can
+tell drift apart from uptix failing to run."#,
+    ),
+    (
+        "denied_lint",
+        r#"`uptix check` found dependencies failing a lint which is set to
+"deny" under [lints] on uptix.toml, such as images using the latest tag:
+
+  [lints]
+  latest_tag = "deny"
+
+Each of them is reported above the error, with its own code. Fix them, or
+set the lint to "warn" to only report them. uptix exits with status 3, as
+for drifted images."#,
+    ),
+    (
+        "unknown_code",
+        r#"`uptix explain` was given a code which doesn't exist.
+
+Codes can be given with or without the uptix::error:: prefix. Run
+`uptix explain` on its own to list all of them."#,
+    ),
+    (
+        "dns_failure",
+        r#"The host of a request couldn't be resolved to an address.
+
+This usually means the host is misspelled, i.e. the registry of an image:
+
+  uptix.dockerImage "ghcr.oi/org/app:1.0"
+
+or that the network (or its DNS server) is unreachable. Hosts only reachable
+through a proxy need it set with HTTPS_PROXY, or proxy under [network] on
+uptix.toml."#,
+    ),
+    (
+        "tls_failure",
+        r#"A secure (HTTPS) connection to a host couldn't be established, i.e.
+because its certificate isn't trusted.
+
+Registries (or proxies) with certificates of their own need their CA added
+to the system's trust store. Local registries which only speak HTTP can be
+reached without TLS:
+
+  [docker.registries."localhost:5000"]
+  insecure = true"#,
+    ),
+    (
+        "timeout",
+        r#"A request didn't get a response in time, even after being retried.
+
+Slow registries or networks can be given more time, either for a single run
+or on uptix.toml:
+
+  uptix update --timeout 60
+
+  [network]
+  timeout = 60
+  retries = 5"#,
+    ),
+    (
+        "deadline_exceeded",
+        r#"A dependency took longer to lock than the deadline allows, across all of
+the requests (and retries) it took. Each request only times out when it stops
+making progress, so this is what bounds a dependency which keeps retrying or
+downloads something huge.
+
+The deadline can be raised on uptix.toml:
+
+  [network]
+  deadline = 600"#,
+    ),
+    (
+        "unauthorized",
+        r#"A server refused a request (401 Unauthorized or 403 Forbidden),
+usually because it needs credentials uptix doesn't have.
+
+For Docker registries, log in with `docker login <registry>`, set
+UPTIX_REGISTRY_AUTH_<HOST>=username:password, or set the credentials under
+[docker.registries."<host>"] on uptix.toml. For GitHub, set GITHUB_TOKEN (or
+token_env for the host on uptix.toml) to a token which can read the
+repository."#,
+    ),
+    (
+        "not_found",
+        r#"A server said what uptix asked for doesn't exist (404 Not Found).
+
+Double check the spelling of the image, repository or release on the
+declaration. Registries and GitHub also answer 404 for private images and
+repositories when the credentials given can't see them, rather than saying
+that access was denied."#,
+    ),
+    (
+        "registry_rate_limit",
+        r#"A Docker registry kept refusing requests with 429 Too Many
+Requests, even after uptix retried them.
+
+Docker Hub limits how many images can be pulled anonymously in a few hours,
+and uptix prints the remaining quota at the end of each run. Logging in with
+`docker login` raises the limit; otherwise, wait for it to reset."#,
+    ),
+    (
+        "missing_token",
+        r#"`uptix update --create-pr` and `--create-mr` need a token to open
+the pull (or merge) request, but none was found.
+
+For GitHub, set GITHUB_TOKEN to a token which can create pull requests (i.e.
+with the `pull-requests: write` permission on GitHub Actions), set token_env
+for the host on uptix.toml, or log in with `gh auth login`. Hosts with a
+GitHub App configured use an installation token of the app instead.
+
+For GitLab, set GITLAB_TOKEN (or the variable named by token_env under
+[gitlab] on uptix.toml) to a token with the `api` scope."#,
+    ),
+    (
+        "invalid_argument",
+        r#"An uptix function was given an argument of the right kind, but
+with a value uptix can't use: a required attribute is missing, an attribute
+has the wrong type (i.e. a number where a string is expected), or the value
+uses a nix feature which can't be read without evaluating it, such as string
+interpolation or `inherit`.
+
+The error points at the argument and says what is wrong with it. For
+instance, uptix.githubBranch needs at least:
+
+  uptix.githubBranch {
+    owner = "luizribeiro";
+    repo = "uptix";
+    branch = "main";
+  }"#,
+    ),
+    (
+        "invalid_constraint",
+        r#"The versionConstraint of a uptix.githubRelease (or the one given to
+`uptix pin`) isn't a valid constraint.
+
+Constraints are comparators separated by commas, following Cargo:
+
+  versionConstraint = "~1.4";
+  versionConstraint = ">=2, <3";"#,
+    ),
+    (
+        "image_not_found",
+        r#"A Docker registry has no image (or no digest for it) with the
+name and tag of a declaration.
+
+Double check the spelling of the image and its tag. Images on Docker Hub
+without an organization live under `library/`, i.e. `library/postgres`."#,
+    ),
+    (
+        "no_matching_tag",
+        r#"None of the tags of an image match the pattern given as its tag.
+
+List the tags of the image on its registry and make sure the pattern (i.e.
+`1.*`) matches at least one of them."#,
+    ),
+    (
+        "unsupported_platform",
+        r#"An image was locked for a platform (i.e. linux/arm64) which it
+isn't published for.
+
+The error lists the platforms the image is available for. Pick one of them
+with the platform option, or drop the option to lock the image as a whole."#,
+    ),
+    (
+        "not_multi_platform",
+        r#"uptix.dockerImagePlatforms was given an image which is published
+for a single platform, so it has no digest per platform to lock.
+
+Use uptix.dockerImage for the image instead."#,
+    ),
+    (
+        "no_matching_release",
+        r#"None of the releases of a repository satisfy the versionConstraint,
+tag filters or prerelease setting of its uptix.githubRelease.
+
+Pre-releases are skipped unless allowPrerelease is set, and tags which
+aren't versions don't satisfy any constraint."#,
+    ),
+    (
+        "no_matching_asset",
+        r#"The release picked for a uptix.githubRelease has no asset whose
+name matches the asset pattern of the declaration.
+
+Check the names of the assets on the release page and adjust the pattern,
+which is a glob such as `*-x86_64-linux.tar.gz`."#,
+    ),
+    (
+        "prefetch_failed",
+        r#"nix-prefetch-docker, which uptix uses to hash the images of
+uptix.dockerPullImage declarations, failed or isn't on PATH.
+
+Install it with `nix-shell -p nix-prefetch-docker` (or add it to the
+environment uptix runs in); the error has the output of the failed run."#,
+    ),
+    (
+        "notification_failed",
+        r#"Posting the summary of a run to one of the webhooks under
+[[notifications]] on uptix.toml failed.
+
+uptix only warns about it, so the run itself still succeeds. Check that the
+webhook still exists and that its format matches the service, i.e.:
+
+  [[notifications]]
+  url_env = "SLACK_WEBHOOK_URL"
+  format = "slack"
+
+The error only mentions the host of the webhook, since its URL is secret."#,
+    ),
+    (
+        "git_failed",
+        r#"A git operation on the repository uptix runs in failed, i.e. while
+pushing the branch of `uptix update --create-pr` or committing uptix.lock
+for `uptix update --commit`.
+
+Make sure uptix runs inside a git checkout whose `origin` remote points at
+the repository, that `HEAD` is on a branch, and that git can push to it
+without prompting for credentials. Commits need user.name and user.email
+to be set on the git configuration. The error has what git reported."#,
+    ),
+    (
+        "hash_mismatch",
+        r#"`uptix verify` fetched locked sources again and got a different hash
+than the one on uptix.lock, or couldn't fetch some of them.
+
+A mismatch means what a revision points at changed upstream, i.e. a tag
+was moved to another commit or a release tarball was rebuilt. nix would
+fail to build with the hash on uptix.lock, or worse, it could be serving
+a cached copy of something which no longer exists. Check the change is
+legitimate before locking it again with `uptix update`.
+
+Each mismatch is listed above the error, with both hashes. uptix exits
+with status 3 when any hash doesn't match, as for drifted images, and 1
+when sources only couldn't be fetched."#,
+    ),
+    (
+        "lock_out_of_sync",
+        r#"`uptix hook run` found uptix.lock doesn't match the dependencies declared
+on the nix files: some have no entry yet (missing), some entries belong
+to dependencies which aren't declared anymore (stale), or some entries
+can't be read by this version of uptix (invalid).
+
+This is checked without any request, before commits (or pushes) when the
+hook installed by `uptix hook install` is in place, so that nix files and
+uptix.lock are always committed together. Run `uptix update` and stage
+uptix.lock again, or skip the hook once with `git commit --no-verify`."#,
+    ),
+    (
+        "missing_vulnerability_database",
+        r#"`uptix audit` was pointed (with --db) at a directory which
+`uptix db sync` didn't write a vulnerability database to.
+
+The database is downloaded from OSV ahead of time, so that audits can run
+without network access (i.e. in air-gapped CI). Run `uptix db sync --db`
+with the same directory where there is network access, and make the
+directory available to the audit (i.e. as a CI cache or artifact)."#,
+    ),
+    (
+        "database_sync_failed",
+        r#"`uptix db sync` couldn't download the advisories of an ecosystem from
+OSV, or couldn't read what it downloaded.
+
+Check the name of the ecosystem (as OSV spells it, i.e. GIT) and that
+osv-vulnerabilities.storage.googleapis.com is reachable. The advisories
+synced before for that ecosystem are left as they were."#,
+    ),
+    (
+        "vulnerable_dependencies",
+        r#"`uptix audit` found advisories on the vulnerability database listing the
+tag (or commit) an entry of uptix.lock is locked to as affected.
+
+Each advisory is listed above the error, along with the entry it affects.
+Update the dependency to a version with a fix (i.e. with `uptix update`),
+or pin it to one by hand. uptix exits with status 3 in this case, so that
+CI can tell it apart from the audit failing to run."#,
+    ),
+    (
+        "provenance_signing_failed",
+        r#"`uptix update --provenance-key` couldn't sign the provenance of the
+update with the key it was given.
+
+The key must be a private key in PEM, i.e. as written by
+`openssl genpkey -algorithm ed25519` or `openssl ecparam -genkey`, and
+not encrypted with a passphrase. uptix.lock was already written when this
+happens; only the provenance is missing."#,
+    ),
+    (
+        "credentials_decryption_failed",
+        r#"The credentials file set on `[credentials]` in uptix.toml couldn't be
+decrypted.
+
+uptix decrypts it by running `sops --decrypt`, or `age --decrypt` for files
+ending in `.age` (or with `format = "age"`), so the tool must be on PATH and
+the key the file was encrypted for must be available to it: for sops, the
+age key, PGP key or cloud KMS access it expects; for age, the identity set
+with `identity`, or else sops' `$SOPS_AGE_KEY_FILE` or
+`~/.config/sops/age/keys.txt`.
+
+  [credentials]
+  file = "secrets.env.age"
+  identity = "/run/secrets/uptix-age-key""#,
+    ),
+    (
+        "post_update_hook_failed",
+        r#"One of the commands run after `uptix update` wrote uptix.lock, from
+`post_update` under `[hooks]` in uptix.toml or from `--hook`, exited with an
+error.
+
+Hooks check that an update doesn't break anything before it is kept, so
+uptix.lock was put back the way it was before the update, and neither
+committed nor proposed in a pull request. Run the hook yourself after
+`uptix update` to see what broke; `$UPTIX_UPDATED` lists what the update
+changed:
+
+  [hooks]
+  post_update = ["nix flake check", "nixos-rebuild build --flake .#myhost"]"#,
+    ),
+    (
+        "hook_exists",
+        r#"`uptix hook install` found a hook which it didn't write where it would
+write its own, and left it alone.
+
+Pass --force to replace it, or keep it and add `uptix hook run` to it,
+which exits with a non-zero status when uptix.lock is out of sync."#,
+    ),
+];
+
+/// Returns the extended explanation for a diagnostic code, which can be
+/// given with or without the `uptix::error::` prefix
+pub fn explanation(code: &str) -> Option<&'static str> {
+    let code = code.strip_prefix(CODE_PREFIX).unwrap_or(code);
+    return EXPLANATIONS
+        .iter()
+        .find(|(c, _)| *c == code)
+        .map(|(_, text)| *text);
+}
+
+pub fn codes() -> impl Iterator<Item = String> {
+    return EXPLANATIONS
+        .iter()
+        .map(|(code, _)| format!("{}{}", CODE_PREFIX, code));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{codes, explanation};
+    use regex::Regex;
+
+    #[test]
+    fn it_explains_every_error() {
+        let re = Regex::new(r"code\((uptix::error::\w+)\)").unwrap();
+        let error_codes: Vec<_> = re
+            .captures_iter(include_str!("error.rs"))
+            .map(|caps| caps[1].to_string())
+            .collect();
+        assert!(!error_codes.is_empty());
+        for code in &error_codes {
+            assert!(explanation(code).is_some(), "{} is not explained", code);
+        }
+        assert_eq!(codes().count(), error_codes.len());
+    }
+
+    #[test]
+    fn it_accepts_short_codes() {
+        assert_eq!(
+            explanation("binary_file"),
+            explanation("uptix::error::binary_file"),
+        );
+        assert!(explanation("does_not_exist").is_none());
+    }
+}
@@ -0,0 +1,495 @@
+use crate::error::Error;
+use crate::http;
+use crate::plan::{Change, Plan};
+use crate::process;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Remote the branches of pull and merge requests are pushed to
+pub const REMOTE: &str = "origin";
+/// Commit subjects longer than this only say how many entries changed,
+/// leaving the list of them to the body
+const MAX_SUBJECT_LENGTH: usize = 72;
+
+/// Runs git on the repository uptix runs in, returning what it printed
+pub async fn run(args: &[&str], env: &[(&str, &Path)]) -> Result<String, Error> {
+    let mut command = Command::new("git");
+    command
+        .args(args)
+        .envs(env.iter().copied())
+        .envs(http::proxy_env())
+        .env("GIT_TERMINAL_PROMPT", "0");
+    let output = process::output(&mut command).await?;
+    if !output.status.success() {
+        return Err(Error::GitFailed {
+            command: args[0].to_string(),
+            reason: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+}
+
+/// The branch `HEAD` is on. CI checkouts (i.e. `actions/checkout`) often
+/// leave `HEAD` detached, in which case it is the branch the CI says it is
+/// building.
+pub async fn current_branch() -> Result<String, Error> {
+    return match run(&["symbolic-ref", "--short", "HEAD"], &[]).await {
+        Ok(branch) => Ok(branch),
+        Err(error) => ci_branch(|name| std::env::var(name).ok()).ok_or(error),
+    };
+}
+
+/// The branch GitHub Actions or GitLab CI is building, from the variables
+/// they set (which `var` looks up)
+fn ci_branch(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    // GitHub sets GITHUB_HEAD_REF to nothing outside of pull requests
+    let var = |name: &str| var(name).filter(|value| !value.is_empty());
+    return var("GITHUB_HEAD_REF")
+        .or_else(|| match var("GITHUB_REF_TYPE").as_deref() {
+            Some("branch") => var("GITHUB_REF_NAME"),
+            _ => None,
+        })
+        .or_else(|| var("CI_COMMIT_BRANCH"))
+        .or_else(|| var("CI_MERGE_REQUEST_SOURCE_BRANCH_NAME"));
+}
+
+/// The URL of `REMOTE`
+pub async fn remote_url() -> Result<String, Error> {
+    return run(&["remote", "get-url", REMOTE], &[]).await;
+}
+
+/// The host and path of a repository from the URL of a remote, i.e.
+/// `git@gitlab.com:group/subgroup/project.git` or
+/// `https://github.com/owner/repo`
+pub fn parse_remote(url: &str) -> Option<(String, String)> {
+    let rest = match url.split_once("://") {
+        Some((_, rest)) => rest.to_string(),
+        // scp-like syntax, i.e. git@github.com:owner/repo.git
+        None => url.replacen(':', "/", 1),
+    };
+    let rest = rest
+        .rsplit_once('@')
+        .map_or(rest.as_str(), |(_, rest)| rest);
+    let (host, path) = rest.trim_end_matches('/').split_once('/')?;
+    let host = host.split(':').next()?;
+    let path = path.trim_end_matches(".git");
+    if host.is_empty() || !path.contains('/') || path.split('/').any(str::is_empty) {
+        return None;
+    }
+    return Some((host.to_string(), path.to_string()));
+}
+
+/// A conventional commit message for the changes of a plan, i.e.
+/// `uptix: bump postgres 15.6 -> 15.7, grafana 10.4.2 -> 10.4.3`
+pub fn commit_message(plan: &Plan) -> String {
+    let sections = [
+        ("bump", &plan.changed),
+        ("add", &plan.added),
+        ("remove", &plan.removed),
+    ];
+    let mut parts = vec![];
+    let mut lines = vec![];
+    for (action, changes) in sections {
+        if changes.is_empty() {
+            continue;
+        }
+        let described: Vec<_> = changes.iter().map(Change::summary).collect();
+        parts.push(format!("{} {}", action, described.join(", ")));
+        lines.extend(described.iter().map(|d| format!("- {} {}", action, d)));
+    }
+    let subject = format!("uptix: {}", parts.join("; "));
+    if subject.len() <= MAX_SUBJECT_LENGTH {
+        return subject;
+    }
+    let count = plan.changed.len() + plan.added.len() + plan.removed.len();
+    return format!(
+        "uptix: update {} dependencies\n\n{}\n",
+        count,
+        lines.join("\n")
+    );
+}
+
+fn git_error(command: &'static str) -> impl Fn(git2::Error) -> Error {
+    return move |error| Error::GitFailed {
+        command: command.to_string(),
+        reason: error.message().to_string(),
+    };
+}
+
+/// Stages `paths` on the repository `root` is in, and commits them on top of
+/// `HEAD` without git having to be installed. Only those paths go into the
+/// commit; anything else which was staged stays staged. Returns the id of
+/// the commit.
+pub fn commit(root: &Path, paths: &[&Path], message: &str) -> Result<String, Error> {
+    let error = git_error("commit");
+    let repo = git2::Repository::discover(root).map_err(&error)?;
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::GitFailed {
+            command: "commit".to_string(),
+            reason: "the repository has no working tree".to_string(),
+        })?
+        .canonicalize()?;
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit().map_err(&error)?),
+        // the first commit of a repository
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => None,
+        Err(e) => return Err(error(e)),
+    };
+    let mut index = repo.index().map_err(&error)?;
+    let mut tree_index = git2::Index::new().map_err(&error)?;
+    if let Some(parent) = &parent {
+        let tree = parent.tree().map_err(&error)?;
+        tree_index.read_tree(&tree).map_err(&error)?;
+    }
+    for path in paths {
+        let path = fs::canonicalize(path)?;
+        let relative = path.strip_prefix(&workdir).map_err(|_| Error::GitFailed {
+            command: "commit".to_string(),
+            reason: format!("{} is outside of the repository", path.display()),
+        })?;
+        index.add_path(relative).map_err(&error)?;
+        if let Some(entry) = index.get_path(relative, 0) {
+            tree_index.add(&entry).map_err(&error)?;
+        }
+    }
+    index.write().map_err(&error)?;
+    let tree = tree_index.write_tree_to(&repo).map_err(&error)?;
+    let tree = repo.find_tree(tree).map_err(&error)?;
+    let signature = repo.signature().map_err(&error)?;
+    let parents: Vec<_> = parent.iter().collect();
+    let id = repo
+        .commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .map_err(&error)?;
+    return Ok(id.to_string());
+}
+
+/// Writes what is staged under `root` to `target`, so that hooks can check
+/// what is being committed rather than the working tree. Files which aren't
+/// staged are left out, and so are symlinks and submodules. Returns where
+/// `root` ends up within `target`.
+pub fn write_index(root: &Path, target: &Path) -> Result<PathBuf, Error> {
+    let error = git_error("checkout-index");
+    let repo = git2::Repository::discover(root).map_err(&error)?;
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::GitFailed {
+            command: "checkout-index".to_string(),
+            reason: "the repository has no working tree".to_string(),
+        })?
+        .canonicalize()?;
+    let prefix = fs::canonicalize(root)?
+        .strip_prefix(&workdir)
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let index = repo.index().map_err(&error)?;
+    for entry in index.iter() {
+        let path = Path::new(OsStr::from_bytes(&entry.path));
+        let is_file = entry.mode & 0o170000 == 0o100000;
+        if !is_file || !path.starts_with(&prefix) {
+            continue;
+        }
+        let blob = repo.find_blob(entry.id).map_err(&error)?;
+        let path = target.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, blob.content())?;
+    }
+    return Ok(target.join(prefix));
+}
+
+/// Where the hooks of the repository `root` is in go, honouring
+/// `core.hooksPath`, along with the path of `root` relative to the top of
+/// the repository, which hooks run from
+pub fn hooks_dir(root: &Path) -> Result<(PathBuf, PathBuf), Error> {
+    let error = git_error("rev-parse");
+    let repo = git2::Repository::discover(root).map_err(&error)?;
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::GitFailed {
+            command: "rev-parse".to_string(),
+            reason: "the repository has no working tree".to_string(),
+        })?
+        .canonicalize()?;
+    let prefix = fs::canonicalize(root)?
+        .strip_prefix(&workdir)
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let config = repo.config().map_err(&error)?;
+    let hooks = match config.get_path("core.hooksPath") {
+        // relative to the top of the repository, as git runs hooks from it
+        Ok(path) => workdir.join(path),
+        Err(_) => repo.commondir().join("hooks"),
+    };
+    return Ok((hooks, prefix));
+}
+
+/// When a file was committed, in seconds since the epoch, along with what it
+/// contained then (or `None` if it didn't exist)
+pub type Version = (i64, Option<Vec<u8>>);
+
+/// What `path` contained over the first-parent history of `HEAD`, newest
+/// first, along with when it was committed. Consecutive commits which left
+/// the file as it was are collapsed into the oldest of them, so that each
+/// time is when those contents were first committed.
+pub fn history(root: &Path, path: &Path) -> Result<Vec<Version>, Error> {
+    let error = git_error("log");
+    let repo = git2::Repository::discover(root).map_err(&error)?;
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| Error::GitFailed {
+            command: "log".to_string(),
+            reason: "the repository has no working tree".to_string(),
+        })?
+        .canonicalize()?;
+    let path = fs::canonicalize(path)?;
+    let relative = path.strip_prefix(&workdir).map_err(|_| Error::GitFailed {
+        command: "log".to_string(),
+        reason: format!("{} is outside of the repository", path.display()),
+    })?;
+    let head = match repo.head() {
+        Ok(head) => head.peel_to_commit().map_err(&error)?,
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch => return Ok(vec![]),
+        Err(e) => return Err(error(e)),
+    };
+    let mut walk = repo.revwalk().map_err(&error)?;
+    walk.push(head.id()).map_err(&error)?;
+    walk.simplify_first_parent().map_err(&error)?;
+    let mut versions: Vec<(i64, Option<git2::Oid>)> = vec![];
+    for id in walk {
+        let commit = repo.find_commit(id.map_err(&error)?).map_err(&error)?;
+        let tree = commit.tree().map_err(&error)?;
+        let blob = tree.get_path(relative).ok().map(|entry| entry.id());
+        let time = commit.time().seconds();
+        match versions.last_mut() {
+            Some(last) if last.1 == blob => last.0 = time,
+            _ => versions.push((time, blob)),
+        }
+    }
+    return versions
+        .into_iter()
+        .map(|(time, blob)| {
+            let content = match blob {
+                Some(id) => Some(repo.find_blob(id).map_err(&error)?.content().to_vec()),
+                None => None,
+            };
+            return Ok((time, content));
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ci_branch, commit, commit_message, history, hooks_dir, parse_remote, write_index};
+    use crate::lock::{LockData, LockFile};
+    use crate::plan::Plan;
+    use serde_json::json;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn it_parses_remotes() {
+        assert_eq!(
+            parse_remote("git@gitlab.com:group/subgroup/project.git"),
+            Some((
+                "gitlab.com".to_string(),
+                "group/subgroup/project".to_string()
+            )),
+        );
+        assert_eq!(
+            parse_remote("https://gitlab.example.com/group/project/"),
+            Some((
+                "gitlab.example.com".to_string(),
+                "group/project".to_string()
+            )),
+        );
+        assert_eq!(parse_remote("https://github.com/luizribeiro"), None);
+        assert_eq!(parse_remote("/srv/git/uptix"), None);
+    }
+
+    #[test]
+    fn it_tells_which_branch_ci_is_building() {
+        let ci_branch_with = |vars: &[(&str, &str)]| {
+            let vars: Vec<_> = vars.to_vec();
+            ci_branch(|name| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            })
+        };
+        assert_eq!(
+            ci_branch_with(&[("GITHUB_REF_TYPE", "branch"), ("GITHUB_REF_NAME", "main")]),
+            Some("main".to_string())
+        );
+        // pull_request events check out a merge commit
+        assert_eq!(
+            ci_branch_with(&[
+                ("GITHUB_HEAD_REF", "feature"),
+                ("GITHUB_REF_TYPE", "branch"),
+                ("GITHUB_REF_NAME", "12/merge"),
+            ]),
+            Some("feature".to_string())
+        );
+        assert_eq!(
+            ci_branch_with(&[("GITHUB_REF_TYPE", "tag"), ("GITHUB_REF_NAME", "v1.0")]),
+            None
+        );
+        assert_eq!(
+            ci_branch_with(&[("GITHUB_HEAD_REF", ""), ("CI_COMMIT_BRANCH", "main")]),
+            Some("main".to_string())
+        );
+        assert_eq!(ci_branch_with(&[]), None);
+    }
+
+    #[test]
+    fn it_writes_commit_messages() {
+        let image = |tag: &str| LockData::from(json!({ "tag": tag, "digest": "sha256:foo" }));
+        let previous = LockFile::from([
+            ("postgres:15".to_string(), image("15.6")),
+            ("grafana:10.*".to_string(), image("10.4.2")),
+        ]);
+        let lock_file = LockFile::from([
+            ("postgres:15".to_string(), image("15.7")),
+            ("grafana:10.*".to_string(), image("10.4.3")),
+        ]);
+        assert_eq!(
+            commit_message(&Plan::diff(&previous, lock_file.clone())),
+            "uptix: bump grafana 10.4.2 -> 10.4.3, postgres 15.6 -> 15.7",
+        );
+
+        let mut previous = previous;
+        previous.insert(
+            "old/image".to_string(),
+            LockData::Digest("sha256:bar".to_string()),
+        );
+        previous.insert(
+            "$GITHUB_BRANCH$:torvalds/linux:master$".to_string(),
+            LockData::from(json!({
+                "owner": "torvalds",
+                "repo": "linux",
+                "rev": "1234567890abcdef",
+                "sha256": "",
+                "fetchSubmodules": false,
+                "deepClone": false,
+                "leaveDotGit": false,
+            })),
+        );
+        assert_eq!(
+            commit_message(&Plan::diff(&previous, lock_file)),
+            "uptix: update 4 dependencies\n\n\
+             - bump grafana 10.4.2 -> 10.4.3\n\
+             - bump postgres 15.6 -> 15.7\n\
+             - remove torvalds/linux\n\
+             - remove old/image\n"
+        );
+    }
+
+    #[test]
+    fn it_commits_without_touching_the_rest_of_the_index() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(root.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "uptix").unwrap();
+        config.set_str("user.email", "uptix@example.com").unwrap();
+        fs::write(root.path().join("uptix.lock"), "{}").unwrap();
+        fs::write(root.path().join("staged.nix"), "{ }").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("staged.nix")).unwrap();
+        index.write().unwrap();
+
+        let lock_file = root.path().join("uptix.lock");
+        let id = commit(root.path(), &[&lock_file], "uptix: bump foo").unwrap();
+        let commit = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(commit.id().to_string(), id);
+        assert_eq!(commit.message(), Some("uptix: bump foo"));
+        let tree = commit.tree().unwrap();
+        assert!(tree.get_name("uptix.lock").is_some());
+        assert!(tree.get_name("staged.nix").is_none());
+        // still staged for whatever the user commits next
+        let index = repo.index().unwrap();
+        assert!(index.get_path(Path::new("staged.nix"), 0).is_some());
+    }
+
+    #[test]
+    fn it_writes_what_is_staged() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(root.path()).unwrap();
+        let infra = root.path().join("infra");
+        fs::create_dir_all(infra.join("hosts")).unwrap();
+        fs::write(infra.join("uptix.lock"), "staged").unwrap();
+        fs::write(infra.join("hosts/web.nix"), "{ }").unwrap();
+        fs::write(root.path().join("outside.nix"), "{ }").unwrap();
+        let mut index = repo.index().unwrap();
+        for path in ["infra/uptix.lock", "infra/hosts/web.nix", "outside.nix"] {
+            index.add_path(Path::new(path)).unwrap();
+        }
+        index.write().unwrap();
+        fs::write(infra.join("uptix.lock"), "unstaged").unwrap();
+        fs::write(infra.join("untracked.nix"), "{ }").unwrap();
+
+        let target = tempfile::tempdir().unwrap();
+        let staged = write_index(&infra, target.path()).unwrap();
+        assert_eq!(staged, target.path().join("infra"));
+        assert_eq!(
+            fs::read_to_string(staged.join("uptix.lock")).unwrap(),
+            "staged"
+        );
+        assert!(staged.join("hosts/web.nix").exists());
+        assert!(!staged.join("untracked.nix").exists());
+        assert!(!target.path().join("outside.nix").exists());
+    }
+
+    #[test]
+    fn it_reads_the_history_of_files() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(root.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "uptix").unwrap();
+        config.set_str("user.email", "uptix@example.com").unwrap();
+        let lock_file = root.path().join("uptix.lock");
+        fs::write(&lock_file, "{}").unwrap();
+        assert_eq!(history(root.path(), &lock_file).unwrap(), vec![]);
+
+        commit(root.path(), &[&lock_file], "uptix: add").unwrap();
+        commit(root.path(), &[&lock_file], "uptix: nothing changed").unwrap();
+        fs::write(&lock_file, "{ \"foo\": \"bar\" }").unwrap();
+        commit(root.path(), &[&lock_file], "uptix: bump foo").unwrap();
+        let contents: Vec<_> = history(root.path(), &lock_file)
+            .unwrap()
+            .into_iter()
+            .map(|(_, content)| String::from_utf8(content.unwrap()).unwrap())
+            .collect();
+        assert_eq!(contents, vec!["{ \"foo\": \"bar\" }", "{}"]);
+    }
+
+    #[test]
+    fn it_finds_where_hooks_go() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(root.path()).unwrap();
+        let top = root.path().canonicalize().unwrap();
+        let nested = root.path().join("infra");
+        fs::create_dir(&nested).unwrap();
+        let (hooks, prefix) = hooks_dir(&nested).unwrap();
+        assert!(hooks.ends_with(".git/hooks"));
+        assert_eq!(prefix, Path::new("infra"));
+
+        repo.config()
+            .unwrap()
+            .set_str("core.hooksPath", ".githooks")
+            .unwrap();
+        let (hooks, prefix) = hooks_dir(root.path()).unwrap();
+        assert_eq!(hooks, top.join(".githooks"));
+        assert_eq!(prefix, Path::new(""));
+    }
+}
@@ -0,0 +1,357 @@
+use crate::config::GitLabConfig;
+use crate::deps::github::pull;
+use crate::error::Error;
+use crate::git;
+use crate::http;
+use crate::lock::LOCK_FILE_PATH;
+use crate::plan::Plan;
+use crate::secrets;
+use crate::util;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+
+/// Branch the update is pushed to, so that later runs update the same merge
+/// request instead of opening new ones
+pub const BRANCH: &str = "uptix/update";
+const DEFAULT_TOKEN_ENV: &str = "GITLAB_TOKEN";
+const TITLE: &str = "Update dependencies locked by uptix";
+const HINTS: http::Hints = http::Hints {
+    unauthorized: "set GITLAB_TOKEN (or token_env under [gitlab] on uptix.toml) to a token \
+                   with the api scope on the project",
+    not_found: "check the project and target branch, or set them under [gitlab] on uptix.toml",
+};
+
+#[derive(Serialize)]
+struct CommitAction<'a> {
+    action: &'a str,
+    file_path: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct NewCommit<'a> {
+    branch: &'a str,
+    start_branch: &'a str,
+    commit_message: &'a str,
+    actions: Vec<CommitAction<'a>>,
+    /// replaces whatever an earlier run pushed to the branch
+    force: bool,
+}
+
+#[derive(Serialize)]
+struct NewMergeRequest<'a> {
+    source_branch: &'a str,
+    target_branch: &'a str,
+    title: &'a str,
+    description: &'a str,
+    remove_source_branch: bool,
+}
+
+#[derive(Serialize)]
+struct UpdatedMergeRequest<'a> {
+    description: &'a str,
+}
+
+#[derive(Deserialize)]
+struct MergeRequest {
+    iid: u64,
+    web_url: String,
+}
+
+/// Where to reach a GitLab project, and how to authenticate with it
+#[derive(Default, PartialEq, Clone, Debug)]
+pub struct GitLabProject {
+    /// URL of the instance (i.e. https://gitlab.com)
+    pub url: String,
+    /// path of the project, including its groups
+    pub path: String,
+    pub token: Option<String>,
+}
+
+impl GitLabProject {
+    /// The instance and project come from uptix.toml, falling back to the
+    /// ones `origin` points at. The token comes from GITLAB_TOKEN, unless
+    /// another variable is configured.
+    pub async fn new(config: &GitLabConfig) -> Result<GitLabProject, Error> {
+        let (url, path) = match (&config.url, &config.project) {
+            (Some(url), Some(project)) => (url.clone(), project.clone()),
+            _ => {
+                let remote = git::remote_url().await?;
+                let (host, path) = git::parse_remote(&remote).ok_or_else(|| {
+                    Error::GitLabError(format!("{} is not a project on GitLab", remote))
+                })?;
+                (
+                    config
+                        .url
+                        .clone()
+                        .unwrap_or_else(|| format!("https://{}", host)),
+                    config.project.clone().unwrap_or(path),
+                )
+            }
+        };
+        let token_env = config.token_env.as_deref().unwrap_or(DEFAULT_TOKEN_ENV);
+        return Ok(GitLabProject {
+            url: url.trim_end_matches('/').to_string(),
+            path: path.trim_matches('/').to_string(),
+            token: secrets::var(token_env),
+        });
+    }
+
+    /// The API endpoint of the project, which is addressed by its path with
+    /// the slashes escaped
+    fn api_url(&self, path: &str) -> String {
+        let project: String = url::form_urlencoded::byte_serialize(self.path.as_bytes()).collect();
+        return format!("{}/api/v4/projects/{}/{}", self.url, project, path);
+    }
+
+    fn token(&self) -> Result<&str, Error> {
+        return self.token.as_deref().ok_or_else(|| Error::MissingToken {
+            what: "merge requests".to_string(),
+            host: self.url.clone(),
+            hint: "set GITLAB_TOKEN (or token_env under [gitlab] on uptix.toml)".to_string(),
+        });
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, Error> {
+        let request = request
+            .header(reqwest::header::USER_AGENT, util::user_agent())
+            .header("PRIVATE-TOKEN", self.token()?);
+        return http::send(request, http::transient).await;
+    }
+
+    /// Whether a file exists on a branch of the project
+    async fn has_file(&self, branch: &str, file_path: &str) -> Result<bool, Error> {
+        let file: String = url::form_urlencoded::byte_serialize(file_path.as_bytes()).collect();
+        let url = self.api_url(&format!("repository/files/{}", file));
+        let request = http::client()?.head(url).query(&[("ref", branch)]);
+        let response = self.send(request).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        if let Some(error) = http::status_error(&response, &HINTS) {
+            return Err(error);
+        }
+        return Ok(true);
+    }
+
+    /// Commits a file on top of `target` and force pushes it to `BRANCH`,
+    /// all through the API, so that no git credentials are needed
+    pub async fn push(&self, target: &str, file_path: &str, content: &str) -> Result<(), Error> {
+        let action = match self.has_file(target, file_path).await? {
+            true => "update",
+            false => "create",
+        };
+        let request = http::client()?
+            .post(self.api_url("repository/commits"))
+            .json(&NewCommit {
+                branch: BRANCH,
+                start_branch: target,
+                commit_message: TITLE,
+                actions: vec![CommitAction {
+                    action,
+                    file_path,
+                    content,
+                }],
+                force: true,
+            });
+        let response = self.send(request).await?;
+        if let Some(error) = http::status_error(&response, &HINTS) {
+            return Err(error);
+        }
+        if !response.status().is_success() {
+            return Err(Error::GitLabError(format!(
+                "could not push to {} ({}): {}",
+                BRANCH,
+                response.status(),
+                response.text().await.unwrap_or_default(),
+            )));
+        }
+        return Ok(());
+    }
+
+    /// Opens a merge request from `BRANCH`, or updates the description of
+    /// the one already open for it, and returns its URL
+    pub async fn open(&self, target: &str, description: &str) -> Result<String, Error> {
+        let url = self.api_url("merge_requests");
+        let request = http::client()?.post(&url).json(&NewMergeRequest {
+            source_branch: BRANCH,
+            target_branch: target,
+            title: TITLE,
+            description,
+            remove_source_branch: true,
+        });
+        let response = self.send(request).await?;
+        // GitLab refuses to open a second merge request for the same branch
+        if response.status() == StatusCode::CONFLICT {
+            let request = http::client()?.get(&url).query(&[
+                ("source_branch", BRANCH),
+                ("target_branch", target),
+                ("state", "opened"),
+            ]);
+            let response = self.send(request).await?;
+            if let Some(error) = http::status_error(&response, &HINTS) {
+                return Err(error);
+            }
+            let open: Vec<MergeRequest> = response.json().await?;
+            let merge_request = open.into_iter().next().ok_or_else(|| {
+                Error::GitLabError(format!(
+                    "GitLab refused to open a merge request from {} to {}",
+                    BRANCH, target
+                ))
+            })?;
+            // its list of updates is from an earlier run
+            let request = http::client()?
+                .put(format!("{}/{}", url, merge_request.iid))
+                .json(&UpdatedMergeRequest { description });
+            let response = self.send(request).await?;
+            if let Some(error) = http::status_error(&response, &HINTS) {
+                return Err(error);
+            }
+            return Ok(merge_request.web_url);
+        }
+        if let Some(error) = http::status_error(&response, &HINTS) {
+            return Err(error);
+        }
+        if !response.status().is_success() {
+            return Err(Error::GitLabError(format!(
+                "could not open a merge request ({}): {}",
+                response.status(),
+                response.text().await.unwrap_or_default(),
+            )));
+        }
+        let merge_request: MergeRequest = response.json().await?;
+        return Ok(merge_request.web_url);
+    }
+}
+
+/// Pushes the uptix.lock of the repository uptix runs in to `BRANCH` and
+/// opens a merge request for it against the configured branch (or the
+/// current one), returning its URL
+pub async fn create(
+    config: &GitLabConfig,
+    plan: &Plan,
+    links: &BTreeMap<String, String>,
+) -> Result<String, Error> {
+    let project = GitLabProject::new(config).await?;
+    let target = match &config.target_branch {
+        Some(branch) => branch.clone(),
+        None => git::current_branch().await?,
+    };
+    // uptix may run in a subdirectory of the repository
+    let prefix = git::run(&["rev-parse", "--show-prefix"], &[]).await?;
+    let content = fs::read_to_string(LOCK_FILE_PATH)?;
+    let file_path = format!("{}{}", prefix, LOCK_FILE_PATH);
+    project.push(&target, &file_path, &content).await?;
+    return project.open(&target, &pull::body(plan, links)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GitLabProject, BRANCH};
+    use crate::config::GitLabConfig;
+    use serde_json::json;
+
+    fn mock_project(token: Option<&str>) -> GitLabProject {
+        return GitLabProject {
+            url: mockito::server_url(),
+            path: "infra/nixos".to_string(),
+            token: token.map(str::to_string),
+        };
+    }
+
+    #[tokio::test]
+    async fn it_uses_configured_projects() {
+        let config = GitLabConfig {
+            url: Some("https://gitlab.example.com/".to_string()),
+            project: Some("infra/nixos".to_string()),
+            token_env: Some("UPTIX_TEST_GITLAB_TOKEN_UNSET".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            GitLabProject::new(&config).await.unwrap(),
+            GitLabProject {
+                url: "https://gitlab.example.com".to_string(),
+                path: "infra/nixos".to_string(),
+                token: None,
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn it_pushes_through_the_api() {
+        let project = mock_project(Some("hunter2"));
+        let _file_mock = mockito::mock(
+            "HEAD",
+            "/api/v4/projects/infra%2Fnixos/repository/files/uptix.lock",
+        )
+        .match_query(mockito::Matcher::UrlEncoded("ref".into(), "main".into()))
+        .with_status(404)
+        .create();
+        let _commit_mock =
+            mockito::mock("POST", "/api/v4/projects/infra%2Fnixos/repository/commits")
+                .match_header("private-token", "hunter2")
+                .match_body(mockito::Matcher::PartialJson(json!({
+                    "branch": BRANCH,
+                    "start_branch": "main",
+                    "force": true,
+                    "actions": [{ "action": "create", "file_path": "uptix.lock", "content": "{}" }],
+                })))
+                .with_status(201)
+                .with_body("{}")
+                .create();
+        project.push("main", "uptix.lock", "{}").await.unwrap();
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_opens_merge_requests() {
+        let project = mock_project(Some("hunter2"));
+        let _create_mock = mockito::mock("POST", "/api/v4/projects/infra%2Fnixos/merge_requests")
+            .match_body(mockito::Matcher::PartialJson(
+                json!({ "source_branch": BRANCH, "target_branch": "main" }),
+            ))
+            .with_status(201)
+            .with_body(
+                r#"{ "iid": 1, "web_url": "https://gitlab.com/infra/nixos/-/merge_requests/1" }"#,
+            )
+            .create();
+        assert_eq!(
+            project.open("main", "body").await.unwrap(),
+            "https://gitlab.com/infra/nixos/-/merge_requests/1"
+        );
+
+        // the branch already has a merge request open
+        let _existing_mock = mockito::mock("POST", "/api/v4/projects/infra%2Fnixos/merge_requests")
+            .match_body(mockito::Matcher::PartialJson(
+                json!({ "target_branch": "release" }),
+            ))
+            .with_status(409)
+            .create();
+        let _list_mock = mockito::mock("GET", "/api/v4/projects/infra%2Fnixos/merge_requests")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("source_branch".into(), BRANCH.into()),
+                mockito::Matcher::UrlEncoded("state".into(), "opened".into()),
+            ]))
+            .with_status(200)
+            .with_body(
+                r#"[{ "iid": 7, "web_url": "https://gitlab.com/infra/nixos/-/merge_requests/7" }]"#,
+            )
+            .create();
+        let update_mock = mockito::mock("PUT", "/api/v4/projects/infra%2Fnixos/merge_requests/7")
+            .match_header("private-token", "hunter2")
+            .match_body(mockito::Matcher::Json(json!({ "description": "new body" })))
+            .with_status(200)
+            .with_body("{}")
+            .create();
+        assert_eq!(
+            project.open("release", "new body").await.unwrap(),
+            "https://gitlab.com/infra/nixos/-/merge_requests/7"
+        );
+        update_mock.assert();
+
+        assert!(mock_project(None).open("main", "body").await.is_err());
+        mockito::reset();
+    }
+}
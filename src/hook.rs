@@ -0,0 +1,145 @@
+use crate::deps::Dependency;
+use crate::error::Error;
+use crate::lock::{LockData, LockFile};
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Marks hooks written by uptix, which may be replaced without `--force`
+const MARKER: &str = "# Written by `uptix hook install`";
+
+/// How uptix.lock disagrees with the nix files
+#[derive(Default, PartialEq, Debug)]
+pub struct Inconsistencies {
+    /// declared dependencies without an entry
+    pub missing: Vec<String>,
+    /// entries of dependencies which aren't declared anymore
+    pub stale: Vec<String>,
+    /// entries of declared dependencies which uptix can't read
+    pub invalid: Vec<String>,
+}
+
+impl Inconsistencies {
+    pub fn is_empty(&self) -> bool {
+        return self.missing.is_empty() && self.stale.is_empty() && self.invalid.is_empty();
+    }
+}
+
+/// Compares the dependencies declared on the nix files with uptix.lock,
+/// without any request, so that it can run before every commit
+pub fn check(dependencies: &[Dependency], lock_file: &LockFile) -> Inconsistencies {
+    let declared: BTreeSet<String> = dependencies.iter().map(|d| d.key()).collect();
+    let mut inconsistencies = Inconsistencies::default();
+    for key in &declared {
+        match lock_file.get(key) {
+            None => inconsistencies.missing.push(key.clone()),
+            Some(LockData::Unknown(_)) => inconsistencies.invalid.push(key.clone()),
+            Some(_) => (),
+        }
+    }
+    inconsistencies.stale = lock_file
+        .keys()
+        .filter(|key| !declared.contains(*key))
+        .cloned()
+        .collect();
+    return inconsistencies;
+}
+
+/// The script of a hook running `uptix hook run` from `prefix` (where
+/// uptix.lock is, relative to the root of the repository), with `args`
+pub fn script(prefix: &Path, args: &[String]) -> String {
+    let mut command = "uptix hook run".to_string();
+    for arg in args {
+        command.push_str(&format!(" '{}'", arg.replace('\'', "'\\''")));
+    }
+    let directory = prefix.to_string_lossy().replace('\'', "'\\''");
+    return format!(
+        "#!/bin/sh\n\
+         {}: blocks commits while uptix.lock is out\n\
+         # of sync with the nix files. Skip it once with --no-verify.\n\
+         cd \"$(git rev-parse --show-toplevel)\"/'{}' || exit 1\n\
+         exec {}\n",
+        MARKER, directory, command
+    );
+}
+
+/// Writes `script` as the given hook, refusing to replace a hook which
+/// wasn't written by uptix unless forced to. Returns where it was written.
+pub fn install(hooks_dir: &Path, hook: &str, script: &str, force: bool) -> Result<PathBuf, Error> {
+    let path = hooks_dir.join(hook);
+    if let Ok(existing) = fs::read_to_string(&path) {
+        if !force && !existing.contains(MARKER) {
+            return Err(Error::HookExists {
+                path: path.display().to_string(),
+            });
+        }
+    }
+    fs::create_dir_all(hooks_dir)?;
+    fs::write(&path, script)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    return Ok(path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, install, script, Inconsistencies};
+    use crate::deps::test_util;
+    use crate::error::Error;
+    use crate::lock::LockFile;
+    use serde_json::json;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    #[test]
+    fn it_finds_inconsistencies() {
+        let dependencies = test_util::deps(
+            r#"{
+            db = uptix.dockerImage "library/postgres:15";
+            cache = uptix.dockerImage "library/redis:7";
+            proxy = uptix.dockerImage "library/nginx:1";
+        }"#,
+        )
+        .unwrap();
+        let lock_file: LockFile = serde_json::from_value(json!({
+            "library/postgres:15": "sha256:abc",
+            "library/nginx:1": { "something": "else" },
+            "library/mysql:8": "sha256:def",
+        }))
+        .unwrap();
+        assert_eq!(
+            check(&dependencies, &lock_file),
+            Inconsistencies {
+                missing: vec!["library/redis:7".to_string()],
+                stale: vec!["library/mysql:8".to_string()],
+                invalid: vec!["library/nginx:1".to_string()],
+            },
+        );
+    }
+
+    #[test]
+    fn it_installs_hooks() {
+        let dir = tempfile::tempdir().unwrap();
+        let hooks = dir.path().join("hooks");
+        let hook = script(
+            Path::new("infra"),
+            &["--config".to_string(), "ci.toml".to_string()],
+        );
+        assert!(hook.contains("cd \"$(git rev-parse --show-toplevel)\"/'infra' || exit 1\n"));
+        assert!(hook.ends_with("exec uptix hook run '--config' 'ci.toml'\n"));
+
+        let path = install(&hooks, "pre-commit", &hook, false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), hook);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o111, 0o111);
+        // hooks written by uptix are replaced, others aren't unless forced
+        install(&hooks, "pre-commit", &hook, false).unwrap();
+        fs::write(hooks.join("pre-push"), "#!/bin/sh\nmake test\n").unwrap();
+        assert!(matches!(
+            install(&hooks, "pre-push", &hook, false),
+            Err(Error::HookExists { .. })
+        ));
+        install(&hooks, "pre-push", &hook, true).unwrap();
+    }
+}
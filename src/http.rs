@@ -0,0 +1,574 @@
+use crate::config::NetworkConfig;
+use crate::error::Error;
+use crate::netrc::{self, Login};
+use base64::Engine;
+use openssl::hash::MessageDigest;
+use reqwest::{Request, RequestBuilder, Response, StatusCode};
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::RwLock;
+use std::time::Duration;
+use tracing::Instrument;
+
+/// Longest we are willing to wait before retrying a request
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    /// Network settings from uptix.toml, shared by every HTTP client
+    static ref NETWORK: RwLock<NetworkConfig> = RwLock::new(NetworkConfig::default());
+}
+
+/// Network settings of a single `Updater`, along with the client a library
+/// user set up for it (i.e. with custom TLS roots), if any
+pub struct Scope {
+    pub network: NetworkConfig,
+    pub client: Option<reqwest::Client>,
+}
+
+tokio::task_local! {
+    /// Settings of the `Updater` being planned, which take the place of the
+    /// ones configured for the whole process
+    static SCOPE: Scope;
+}
+
+pub fn configure(config: &NetworkConfig) {
+    *NETWORK.write().unwrap() = config.clone();
+}
+
+/// Runs `future` with network settings (and possibly a client) of its own,
+/// rather than the ones given to `configure`
+pub async fn scoped<F: Future>(scope: Scope, future: F) -> F::Output {
+    return SCOPE.scope(scope, future).await;
+}
+
+fn network<T>(read: fn(&NetworkConfig) -> T) -> T {
+    return SCOPE
+        .try_with(|scope| read(&scope.network))
+        .unwrap_or_else(|_| read(&NETWORK.read().unwrap()));
+}
+
+/// How many times failed requests are retried
+pub fn retries() -> u32 {
+    return network(|config| config.retries);
+}
+
+/// How long locking a single dependency may take, across all of its requests
+pub fn deadline() -> Duration {
+    return Duration::from_secs(network(|config| config.deadline));
+}
+
+/// How many dependencies may be locked at once
+pub fn jobs() -> usize {
+    return network(|config| config.jobs.max(1));
+}
+
+/// How many repositories may be cloned at once
+pub fn prefetch_jobs() -> usize {
+    return network(|config| config.prefetch_jobs.max(1));
+}
+
+/// Whether repositories may be hashed from shallow clones
+pub fn shallow_clone() -> bool {
+    return network(|config| config.shallow_clone);
+}
+
+/// Builds an HTTP client with the proxy from uptix.toml, if any. Otherwise
+/// the `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` environment variables are
+/// honored. Within a scope with a client of its own, that one is used
+/// instead.
+pub fn client() -> Result<reqwest::Client, Error> {
+    if let Ok(Some(client)) = SCOPE.try_with(|scope| scope.client.clone()) {
+        return Ok(client);
+    }
+    return network(build_client);
+}
+
+/// Environment variables passing the configured proxy on to the tools uptix
+/// runs, such as nix-prefetch-git
+pub fn proxy_env() -> Vec<(&'static str, String)> {
+    return network(|config| {
+        let proxy = match &config.proxy {
+            Some(proxy) => proxy,
+            None => return vec![],
+        };
+        let no_proxy = config.no_proxy.join(",");
+        return vec![
+            ("http_proxy", proxy.clone()),
+            ("https_proxy", proxy.clone()),
+            ("no_proxy", no_proxy),
+        ];
+    });
+}
+
+/// What to do about a response which came back
+pub enum Retry {
+    /// The response is final, whether it succeeded or not
+    No,
+    /// The request may succeed if sent again, after the given delay if the
+    /// server asked for one
+    After(Option<Duration>),
+}
+
+/// Retries responses which are usually transient: server errors, timeouts
+/// and rate limits, honoring `Retry-After` when present
+pub fn transient(response: &Response) -> Retry {
+    let status = response.status();
+    if !status.is_server_error()
+        && status != StatusCode::REQUEST_TIMEOUT
+        && status != StatusCode::TOO_MANY_REQUESTS
+    {
+        return Retry::No;
+    }
+    return Retry::After(retry_after(response));
+}
+
+/// The delay a server asked for through `Retry-After`, in seconds
+pub fn retry_after(response: &Response) -> Option<Duration> {
+    return response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|seconds| seconds.trim().parse().ok())
+        .map(Duration::from_secs);
+}
+
+/// Whether a request which never got a response is worth sending again, as
+/// opposed to i.e. an invalid URL or a TLS error
+fn is_transient(error: &reqwest::Error) -> bool {
+    return error.is_timeout() || error.is_connect();
+}
+
+/// Tells apart why a request never got a response, so that the error can
+/// suggest a fix (i.e. a misspelled registry, or a self-signed certificate)
+fn request_error(error: reqwest::Error, host: &str) -> Error {
+    let host = host.to_string();
+    if error.is_timeout() {
+        return Error::Timeout { host };
+    }
+    let mut causes = vec![];
+    let mut source = std::error::Error::source(&error);
+    while let Some(cause) = source {
+        causes.push(cause.to_string());
+        source = cause.source();
+    }
+    let chain = causes.join(": ").to_lowercase();
+    if chain.contains("dns error") || chain.contains("failed to lookup address") {
+        return Error::DnsFailure { host };
+    }
+    if ["certificate", "tls", "ssl"]
+        .iter()
+        .any(|s| chain.contains(s))
+    {
+        return Error::TlsFailure {
+            host,
+            reason: causes.pop().unwrap_or_default(),
+        };
+    }
+    return Error::RequestError(error);
+}
+
+/// What to suggest when a server refuses a request, or doesn't have what was
+/// asked for
+pub struct Hints {
+    pub unauthorized: &'static str,
+    pub not_found: &'static str,
+}
+
+/// Explains responses which were refused or not found, leaving other
+/// statuses to the caller
+pub fn status_error(response: &Response, hints: &Hints) -> Option<Error> {
+    let url = response.url().to_string();
+    return match response.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Some(Error::Unauthorized {
+            url,
+            status: response.status().to_string(),
+            hint: hints.unauthorized.to_string(),
+        }),
+        StatusCode::NOT_FOUND => Some(Error::NotFound {
+            url,
+            hint: hints.not_found.to_string(),
+        }),
+        _ => None,
+    };
+}
+
+/// Exponential backoff with jitter, so that concurrent requests which failed
+/// together don't all retry at the same time
+pub fn backoff(attempt: u32) -> Duration {
+    let delay = Duration::from_secs(1 << attempt.min(6)).min(MAX_RETRY_DELAY);
+    let random = RandomState::new().build_hasher().finish();
+    let jitter = delay.as_millis() as u64 / 2;
+    return delay / 2 + Duration::from_millis(random % (jitter + 1));
+}
+
+/// Sends a request with the retry policy shared by every network operation:
+/// transport failures and the responses `classify` deems retryable are sent
+/// again, up to the configured number of retries. Once they run out, the
+/// last response is returned for the caller to make sense of.
+pub async fn send(
+    request: RequestBuilder,
+    classify: impl Fn(&Response) -> Retry,
+) -> Result<Response, Error> {
+    let (client, request) = request.build_split();
+    let mut request = request?;
+    if let Some(host) = request.url().host_str() {
+        let login = netrc::lookup(host);
+        authorize(&mut request, login.as_ref());
+    }
+    let span = tracing::debug_span!("request", method = %request.method(), url = %request.url());
+    return send_with_retries(&client, request, classify)
+        .instrument(span)
+        .await;
+}
+
+/// Authenticates a request with the credentials from .netrc, as curl and
+/// nix do, unless it already carries some (i.e. a GitHub token, or GitLab's
+/// `PRIVATE-TOKEN`)
+fn authorize(request: &mut Request, login: Option<&Login>) {
+    let headers = request.headers_mut();
+    if headers.contains_key(reqwest::header::AUTHORIZATION) || headers.contains_key("private-token")
+    {
+        return;
+    }
+    let password = match login.and_then(|login| login.password.as_deref()) {
+        Some(password) => password,
+        None => return,
+    };
+    let user = login.and_then(|login| login.login.as_deref()).unwrap_or("");
+    let credentials =
+        base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password));
+    if let Ok(mut value) = format!("Basic {}", credentials).parse::<reqwest::header::HeaderValue>()
+    {
+        value.set_sensitive(true);
+        headers.insert(reqwest::header::AUTHORIZATION, value);
+    }
+}
+
+async fn send_with_retries(
+    client: &reqwest::Client,
+    request: Request,
+    classify: impl Fn(&Response) -> Retry,
+) -> Result<Response, Error> {
+    let retries = retries();
+    let host = request.url().host_str().unwrap_or_default().to_string();
+    let mut request = request;
+    let mut attempt = 0;
+    loop {
+        let retry = request.try_clone().filter(|_| attempt < retries);
+        let (reason, delay) = match client.execute(request).await {
+            Ok(response) => {
+                tracing::debug!(status = %response.status(), attempt, "received response");
+                match (classify(&response), &retry) {
+                    (Retry::After(delay), Some(_)) => (response.status().to_string(), delay),
+                    _ => return Ok(response),
+                }
+            }
+            Err(error) if is_transient(&error) && retry.is_some() => {
+                tracing::debug!(%error, attempt, "request failed");
+                let reason = match error.is_timeout() {
+                    true => "timed out".to_string(),
+                    false => "connection failed".to_string(),
+                };
+                (reason, None)
+            }
+            Err(error) => return Err(request_error(error, &host)),
+        };
+        let delay = delay
+            .unwrap_or_else(|| backoff(attempt))
+            .min(MAX_RETRY_DELAY);
+        tracing::warn!(
+            "request to {} failed ({}), retrying in {}s",
+            host,
+            reason,
+            delay.as_secs_f32().ceil(),
+        );
+        tokio::time::sleep(delay).await;
+        request = retry.unwrap();
+        attempt += 1;
+    }
+}
+
+/// Reads a response body chunk by chunk, failing as soon as it grows past
+/// `limit` bytes rather than buffering all of it
+pub async fn read_limited(response: Response, limit: usize) -> Result<Vec<u8>, Error> {
+    let url = response.url().to_string();
+    let mut response = response;
+    let mut body = vec![];
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            return Err(Error::OversizedResponse { url, limit });
+        }
+        body.extend_from_slice(&chunk);
+    }
+    return Ok(body);
+}
+
+/// Hashes a response body as it downloads, so that memory use stays the same
+/// regardless of how large it is
+pub async fn sha256(response: Response) -> Result<Vec<u8>, Error> {
+    let mut response = response;
+    let mut hasher =
+        openssl::hash::Hasher::new(MessageDigest::sha256()).map_err(std::io::Error::from)?;
+    while let Some(chunk) = response.chunk().await? {
+        hasher.update(&chunk).map_err(std::io::Error::from)?;
+    }
+    let hash = hasher.finish().map_err(std::io::Error::from)?;
+    return Ok(hash.to_vec());
+}
+
+/// Builds a client whose requests time out when they stop making progress,
+/// rather than after a fixed time, so that large downloads on slow links
+/// aren't cut short. How long a dependency may take overall is bounded by
+/// the deadline instead.
+pub(crate) fn build_client(config: &NetworkConfig) -> Result<reqwest::Client, Error> {
+    let timeout = Duration::from_secs(config.timeout);
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(timeout)
+        .read_timeout(timeout);
+    if let Some(proxy) = &config.proxy {
+        let no_proxy = reqwest::NoProxy::from_string(&config.no_proxy.join(","));
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?.no_proxy(no_proxy));
+    }
+    return Ok(builder.build()?);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        authorize, backoff, build_client, client, jobs, read_limited, request_error, scoped, send,
+        sha256, status_error, transient, Hints, Scope, MAX_RETRY_DELAY,
+    };
+    use crate::config::NetworkConfig;
+    use crate::error::Error;
+    use crate::netrc::Login;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    struct NoSuchHost;
+
+    impl reqwest::dns::Resolve for NoSuchHost {
+        fn resolve(&self, _name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+            return Box::pin(async {
+                return Err(
+                    "failed to lookup address information: Name or service not known".into(),
+                );
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn it_uses_the_configured_proxy() {
+        let proxy_mock = mockito::mock("GET", "http://api.github.invalid/zen")
+            .with_status(200)
+            .with_body("Keep it logically awesome.")
+            .create();
+        let client = build_client(&NetworkConfig {
+            proxy: Some(format!("http://{}", mockito::server_address())),
+            ..Default::default()
+        })
+        .unwrap();
+        let response = client
+            .get("http://api.github.invalid/zen")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "Keep it logically awesome.");
+        proxy_mock.assert();
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_uses_injected_clients() {
+        let mock = mockito::mock("GET", "/injected")
+            .match_header("x-injected", "yes")
+            .with_status(200)
+            .create();
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-injected", "yes".parse().unwrap());
+        let scope = Scope {
+            network: NetworkConfig::default(),
+            client: Some(
+                reqwest::Client::builder()
+                    .default_headers(headers)
+                    .build()
+                    .unwrap(),
+            ),
+        };
+        let url = format!("{}/injected", mockito::server_url());
+        let request = async { send(client().unwrap().get(&url), transient).await };
+        let response = scoped(scope, request).await;
+        assert_eq!(response.unwrap().status(), 200);
+        mock.assert();
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_scopes_settings() {
+        let config = NetworkConfig {
+            jobs: 7,
+            ..Default::default()
+        };
+        let scope = Scope {
+            network: config,
+            client: None,
+        };
+        assert_eq!(scoped(scope, async { jobs() }).await, 7);
+        // nothing else is configured for the whole process by the tests
+        assert_eq!(jobs(), NetworkConfig::default().jobs);
+    }
+
+    #[test]
+    fn it_backs_off_with_jitter() {
+        for attempt in 0..3 {
+            let delay = backoff(attempt);
+            assert!(delay >= Duration::from_millis(500 << attempt));
+            assert!(delay <= Duration::from_secs(1 << attempt));
+        }
+        assert!(backoff(100) <= MAX_RETRY_DELAY);
+        assert!(backoff(100) >= MAX_RETRY_DELAY / 2);
+    }
+
+    #[test]
+    fn it_authenticates_from_netrc() {
+        let login = Login {
+            login: Some("deploy".to_string()),
+            password: Some("hunter2".to_string()),
+        };
+        let client = reqwest::Client::new();
+        let mut request = client.get("https://artifacts.example.com").build().unwrap();
+        authorize(&mut request, Some(&login));
+        assert_eq!(
+            request.headers()[reqwest::header::AUTHORIZATION],
+            "Basic ZGVwbG95Omh1bnRlcjI="
+        );
+
+        // tokens set by the caller win
+        let mut request = client
+            .get("https://ghe.example.com")
+            .bearer_auth("ghp_token")
+            .build()
+            .unwrap();
+        authorize(&mut request, Some(&login));
+        assert_eq!(
+            request.headers()[reqwest::header::AUTHORIZATION],
+            "Bearer ghp_token"
+        );
+        let mut request = client
+            .get("https://gitlab.example.com")
+            .header("PRIVATE-TOKEN", "glpat_token")
+            .build()
+            .unwrap();
+        authorize(&mut request, Some(&login));
+        assert!(!request
+            .headers()
+            .contains_key(reqwest::header::AUTHORIZATION));
+    }
+
+    #[tokio::test]
+    async fn it_retries_transient_failures() {
+        let failing_mock = mockito::mock("GET", "/flaky")
+            .with_status(502)
+            .with_header("retry-after", "0")
+            .expect(1)
+            .create();
+        let _ok_mock = mockito::mock("GET", "/flaky").with_status(200).create();
+        let missing_mock = mockito::mock("GET", "/missing")
+            .with_status(404)
+            .expect(1)
+            .create();
+
+        let client = build_client(&NetworkConfig::default()).unwrap();
+        let url = |path: &str| format!("{}{}", mockito::server_url(), path);
+        let response = send(client.get(url("/flaky")), transient).await.unwrap();
+        assert_eq!(response.status(), 200);
+        failing_mock.assert();
+        let response = send(client.get(url("/missing")), transient).await.unwrap();
+        assert_eq!(response.status(), 404);
+        missing_mock.assert();
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_explains_failed_requests() {
+        // hosts are looked up by a resolver which knows none, rather than
+        // by whatever DNS the machine running the tests has
+        let offline = reqwest::Client::builder()
+            .no_proxy()
+            .dns_resolver(Arc::new(NoSuchHost))
+            .build()
+            .unwrap();
+        let error = offline.get("http://uptix.invalid/v2/").send().await;
+        assert!(matches!(
+            request_error(error.unwrap_err(), "uptix.invalid"),
+            Error::DnsFailure { .. }
+        ));
+        let client = build_client(&NetworkConfig::default()).unwrap();
+        // the mock server doesn't speak TLS
+        let url = format!("https://{}/v2/", mockito::server_address());
+        let error = client.get(&url).send().await;
+        assert!(matches!(
+            request_error(error.unwrap_err(), "localhost"),
+            Error::TlsFailure { .. }
+        ));
+
+        let _denied_mock = mockito::mock("GET", "/denied").with_status(401).create();
+        let _gone_mock = mockito::mock("GET", "/gone").with_status(404).create();
+        let _broken_mock = mockito::mock("GET", "/broken").with_status(500).create();
+        let hints = Hints {
+            unauthorized: "log in",
+            not_found: "check the spelling",
+        };
+        let get = |path: &str| {
+            client
+                .get(format!("{}{}", mockito::server_url(), path))
+                .send()
+        };
+        match status_error(&get("/denied").await.unwrap(), &hints) {
+            Some(Error::Unauthorized { status, hint, .. }) => {
+                assert_eq!(status, "401 Unauthorized");
+                assert_eq!(hint, "log in");
+            }
+            _ => panic!("expected an unauthorized error"),
+        }
+        assert!(matches!(
+            status_error(&get("/gone").await.unwrap(), &hints),
+            Some(Error::NotFound { .. })
+        ));
+        assert!(status_error(&get("/broken").await.unwrap(), &hints).is_none());
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_streams_bodies() {
+        let _blob_mock = mockito::mock("GET", "/blob")
+            .with_status(200)
+            .with_body("a".repeat(100_000))
+            .create();
+
+        let client = build_client(&NetworkConfig::default()).unwrap();
+        let url = format!("{}/blob", mockito::server_url());
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(
+            sha256(response).await.unwrap(),
+            openssl::sha::sha256("a".repeat(100_000).as_bytes()).to_vec()
+        );
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(
+            read_limited(response, 100_000).await.unwrap().len(),
+            100_000
+        );
+        let response = client.get(&url).send().await.unwrap();
+        assert!(read_limited(response, 1000).await.is_err());
+        mockito::reset();
+    }
+
+    #[test]
+    fn it_rejects_invalid_proxies() {
+        assert!(build_client(&NetworkConfig {
+            proxy: Some("not a url".to_string()),
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,95 @@
+//! uptix finds the dependencies declared in nix files (i.e. with
+//! `uptix.dockerImage` or `uptix.githubBranch`) and locks them to their
+//! latest version on uptix.lock.
+//!
+//! [`Updater`] is what other tools should use to do so without shelling out
+//! to the CLI, or [`blocking`] for those which don't use async. The other
+//! modules are what the CLI is built from, and may change between any two
+//! versions.
+//!
+//! Reading nix files (`util`), the lock model ([`LockData`], in `lock`) and
+//! comparing lock files ([`Plan`]) never touch the network. Resolving
+//! dependencies (`deps`, on top of `http` and `cache`) is layered on top of
+//! them, so that tools which only read uptix.lock needn't make requests.
+
+// explicit returns are the style of this codebase, and errors carry the
+// source they point at, which makes them large
+#![allow(clippy::needless_return, clippy::result_large_err)]
+
+#[macro_use]
+extern crate lazy_static;
+
+#[doc(hidden)]
+pub mod audit;
+pub mod blocking;
+#[doc(hidden)]
+pub mod cache;
+#[doc(hidden)]
+pub mod ci;
+#[doc(hidden)]
+pub mod config;
+#[doc(hidden)]
+pub mod convert;
+#[doc(hidden)]
+pub mod deps;
+#[doc(hidden)]
+pub mod drift;
+#[doc(hidden)]
+pub mod error;
+#[doc(hidden)]
+pub mod explain;
+#[doc(hidden)]
+pub mod git;
+#[doc(hidden)]
+pub mod gitlab;
+#[doc(hidden)]
+pub mod hook;
+#[doc(hidden)]
+pub mod http;
+#[doc(hidden)]
+pub mod lock;
+#[doc(hidden)]
+pub mod lsp;
+#[doc(hidden)]
+pub mod nar;
+#[doc(hidden)]
+pub mod netrc;
+#[doc(hidden)]
+pub mod notify;
+#[doc(hidden)]
+pub mod overlay;
+mod plan;
+#[doc(hidden)]
+pub mod post_update;
+#[doc(hidden)]
+pub mod process;
+#[doc(hidden)]
+pub mod provenance;
+#[doc(hidden)]
+pub mod renovate;
+#[doc(hidden)]
+pub mod report;
+#[doc(hidden)]
+pub mod secrets;
+#[doc(hidden)]
+pub mod semver;
+#[doc(hidden)]
+pub mod serve;
+#[doc(hidden)]
+pub mod timings;
+mod updater;
+#[doc(hidden)]
+pub mod util;
+#[doc(hidden)]
+pub mod verify;
+
+pub use config::Config;
+pub use deps::github::branch::GitHubBranch;
+pub use deps::github::release::GitHubRelease;
+pub use deps::{Docker, Lockable};
+pub use error::Error;
+pub use lock::LockData;
+pub use plan::{Change, Plan};
+#[doc(hidden)]
+pub use updater::{Files, Resolution, Resolved};
+pub use updater::{Progress, Updater};
@@ -0,0 +1,299 @@
+use crate::error::Error;
+use crate::semver::{Bump, Version};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const LOCK_FILE_PATH: &str = "uptix.lock";
+
+/// The nix library reading uptix.lock, as this version of uptix writes it
+const NIX_LIBRARY: &str = include_str!("../modules/default.nix");
+
+pub type LockFile = BTreeMap<String, LockData>;
+
+/// What a dependency is locked to on uptix.lock. Entries aren't tagged with
+/// their kind (uptix.nix reads them as they are), so they are told apart by
+/// their fields. Anything else, such as entries written by other versions
+/// of uptix, is kept as it was.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(untagged)]
+#[non_exhaustive]
+pub enum LockData {
+    /// An image locked to nothing but its digest
+    Digest(String),
+    Docker(DockerLock),
+    /// A release along with one of its assets
+    Release(ReleaseLock),
+    GitHub(GitHubLock),
+    Action(GitHubActionLock),
+    Unknown(serde_json::Value),
+}
+
+impl LockData {
+    /// The commit (or tag) a repository is locked to
+    pub fn rev(&self) -> Option<&str> {
+        return match self {
+            LockData::Release(lock) => Some(&lock.lock.rev),
+            LockData::GitHub(lock) => Some(&lock.rev),
+            LockData::Action(lock) => Some(&lock.rev),
+            LockData::Unknown(value) => value["rev"].as_str(),
+            LockData::Digest(_) | LockData::Docker(_) => None,
+        };
+    }
+
+    /// The tag (or the tag a pattern resolved to, for images) an entry is
+    /// locked to, as it was written, if it looks like a version
+    pub fn version_tag(&self) -> Option<&str> {
+        let tag = match self {
+            LockData::Docker(DockerLock { tag: Some(tag), .. }) => tag,
+            lock => lock.rev()?,
+        };
+        Version::parse(tag)?;
+        return Some(tag);
+    }
+
+    /// The version an entry is locked to, if its tag (or the tag a pattern
+    /// resolved to, for images) looks like one
+    pub fn version(&self) -> Option<Version> {
+        return Version::parse(self.version_tag()?);
+    }
+
+    /// What uptix reports about the version an entry is locked to
+    pub fn metadata(&self) -> Option<&LockMetadata> {
+        return match self {
+            LockData::Docker(lock) => lock.metadata.as_ref(),
+            LockData::GitHub(lock) | LockData::Release(ReleaseLock { lock, .. }) => {
+                lock.metadata.as_ref()
+            }
+            _ => None,
+        };
+    }
+
+    /// How big of an update going from `previous` to this entry is, when
+    /// both are locked to versions
+    pub fn bump_from(&self, previous: &LockData) -> Option<Bump> {
+        return self.version()?.bump_from(&previous.version()?);
+    }
+}
+
+/// The version an entry is locked to, i.e. a digest or a commit
+impl std::fmt::Display for LockData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(rev) = self.rev() {
+            return write!(f, "{}", rev);
+        }
+        return match self {
+            LockData::Digest(digest) => write!(f, "{}", digest),
+            LockData::Docker(DockerLock {
+                tag: Some(tag),
+                digest,
+                ..
+            }) => write!(f, "{}@{}", tag, digest),
+            LockData::Docker(lock) => write!(f, "{}", lock.digest),
+            lock => write!(f, "{}", serde_json::to_string(lock).unwrap_or_default()),
+        };
+    }
+}
+
+impl From<serde_json::Value> for LockData {
+    fn from(value: serde_json::Value) -> Self {
+        return serde_json::from_value(value.clone()).unwrap_or(LockData::Unknown(value));
+    }
+}
+
+/// Lock entry for images which need more than a digest, such as the tag a
+/// pattern was resolved to or the digest of the image for each platform
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct DockerLock {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) tag: Option<String>,
+    pub(crate) digest: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) platforms: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) metadata: Option<LockMetadata>,
+}
+
+/// Details about what an entry is locked to which are only reported on (i.e.
+/// by `uptix update`), kept so that they aren't looked up again. uptix.nix
+/// doesn't read them.
+#[derive(Serialize, Deserialize, Default, PartialEq, Clone, Debug)]
+pub struct LockMetadata {
+    /// compressed size of an image in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layers: Option<usize>,
+    /// link to the release notes of a release
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// the first lines of the release notes of a release
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+/// A repository locked to a commit, as given to `fetchFromGitHub`
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[allow(non_snake_case)]
+pub struct GitHubLock {
+    pub(crate) owner: String,
+    pub(crate) repo: String,
+    pub(crate) rev: String,
+    pub(crate) sha256: String,
+    pub(crate) fetchSubmodules: bool,
+    pub(crate) deepClone: bool,
+    pub(crate) leaveDotGit: bool,
+    /// host of GitHub Enterprise instances, as `fetchFromGitHub` takes it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) githubBase: Option<String>,
+    /// what each submodule resolved to, for dependencies with
+    /// `lockSubmodules`. uptix.nix leaves it out of `fetchFromGitHub`'s
+    /// arguments.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) submodules: Option<Vec<SubmoduleLock>>,
+    /// what uptix reports about releases. uptix.nix leaves it out of
+    /// `fetchFromGitHub`'s arguments as well.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) metadata: Option<LockMetadata>,
+}
+
+/// The revision a submodule is pinned to by its parent repository
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct SubmoduleLock {
+    pub(crate) path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) url: Option<String>,
+    pub(crate) rev: String,
+}
+
+impl std::fmt::Display for SubmoduleLock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match &self.url {
+            Some(url) => write!(f, "{}: {}@{}", self.path, url, self.rev),
+            None => write!(f, "{}: {}", self.path, self.rev),
+        };
+    }
+}
+
+/// The lock of a release along with one of its assets
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ReleaseLock {
+    #[serde(flatten)]
+    pub(crate) lock: GitHubLock,
+    pub(crate) asset: AssetLock,
+}
+
+/// A release asset, as given to `fetchurl`
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct AssetLock {
+    pub(crate) name: String,
+    pub(crate) url: String,
+    pub(crate) sha256: String,
+}
+
+/// The commit a GitHub Action's ref resolved to
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct GitHubActionLock {
+    pub(crate) owner: String,
+    pub(crate) repo: String,
+    #[serde(rename = "ref")]
+    pub(crate) reference: String,
+    pub(crate) rev: String,
+}
+
+/// The nix library built into this version of uptix, for projects which
+/// vendor it instead of importing it from the flake
+pub fn nix_library() -> String {
+    return format!(
+        "# Generated by `uptix generate nix-lib` from uptix {}, do not edit.\n\
+         # Import it with: import ./uptix.nix {{ lockFile = ./uptix.lock; }}\n\
+         {}",
+        env!("CARGO_PKG_VERSION"),
+        NIX_LIBRARY
+    );
+}
+
+pub fn read(path: &Path) -> Result<LockFile, Error> {
+    if !path.exists() {
+        return Ok(LockFile::new());
+    }
+    let content = fs::read_to_string(path)?;
+    return Ok(serde_json::from_str(&content)?);
+}
+
+pub fn write(path: &Path, lock_file: &LockFile) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(lock_file)?;
+    return replace(path, &json);
+}
+
+/// Writes a lock file through a temporary file next to it, so that it is
+/// never left half written (i.e. when uptix is killed midway)
+pub fn replace(path: &Path, json: &str) -> Result<(), Error> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, json)?;
+    fs::rename(&temporary, path)?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{nix_library, LockData};
+    use serde_json::json;
+
+    #[test]
+    fn it_types_locks() {
+        let github = json!({
+            "owner": "luizribeiro",
+            "repo": "uptix",
+            "rev": "b4ffde65f46336ab88eb53be808477a3936bae11",
+            "sha256": "1vxzg4wdjvfnc7fjqr9flza5y7gh69w0bpf7mhyf06ddcvq3p00j",
+            "fetchSubmodules": false,
+            "deepClone": false,
+            "leaveDotGit": false,
+        });
+        let mut release = github.clone();
+        release["asset"] =
+            json!({ "name": "uptix.tar.gz", "url": "https://example.com", "sha256": "abc" });
+        let entries = [
+            (json!("sha256:foobar"), "Digest"),
+            (json!({ "tag": "1.2", "digest": "sha256:foobar" }), "Docker"),
+            (release, "Release"),
+            (github, "GitHub"),
+            (
+                json!({ "owner": "actions", "repo": "checkout", "ref": "v4", "rev": "b4ff" }),
+                "Action",
+            ),
+            // i.e. written before GitHub locks had all of their flags
+            (json!({ "owner": "luizribeiro", "rev": "b4ff" }), "Unknown"),
+        ];
+        for (value, kind) in entries {
+            let lock = LockData::from(value.clone());
+            assert!(format!("{:?}", lock).starts_with(kind), "{:?}", lock);
+            // entries are written back as they were read
+            assert_eq!(serde_json::to_value(&lock).unwrap(), value);
+        }
+        assert_eq!(
+            LockData::from(json!({ "owner": "luizribeiro", "rev": "b4ff" })).rev(),
+            Some("b4ff")
+        );
+        assert_eq!(LockData::from(json!("sha256:foobar")).rev(), None);
+        assert_eq!(
+            LockData::from(json!({ "tag": "1.2", "digest": "sha256:foobar" })).to_string(),
+            "1.2@sha256:foobar"
+        );
+    }
+
+    #[test]
+    fn it_ships_the_nix_library() {
+        let library = nix_library();
+        assert!(library.starts_with("# Generated by `uptix generate nix-lib` from uptix "));
+        assert!(library.contains("{ lockFile }:"));
+        // the prefixes GitHub dependencies are keyed by
+        assert!(library.contains("$GITHUB_BRANCH$:"));
+        assert!(library.contains("$GITHUB_RELEASE$:"));
+    }
+}
@@ -0,0 +1,550 @@
+use crate::config::Config;
+use crate::deps::{self, Dependency};
+use crate::error::Error;
+use crate::git;
+use crate::lock;
+use crate::plan::Change;
+use crate::report::{self, DependencyReport};
+use crate::serve::{self, RpcError};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Command of the code actions updating a single dependency, which takes
+/// the URI of the document and the key of the dependency
+pub const UPDATE_COMMAND: &str = "uptix.update";
+
+/// Answers Language Server Protocol requests from editors about the uptix
+/// calls of the nix files open on them: hovers with what a dependency is
+/// locked to and what its latest version is, and code actions updating it
+pub struct Server {
+    root: PathBuf,
+    config: Config,
+    /// contents of the open documents, by URI
+    documents: BTreeMap<String, String>,
+    shutdown: bool,
+}
+
+/// Byte offset of an LSP position (a 0-based line, and a character in
+/// UTF-16 code units) on a text
+fn offset(text: &str, position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let mut start = 0;
+    for _ in 0..line {
+        start += text[start..].find('\n')? + 1;
+    }
+    let mut units = 0;
+    for (i, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(start + i);
+        }
+        units += c.len_utf16();
+    }
+    return Some(text.len());
+}
+
+/// Whether a dependency was declared between two byte offsets
+fn overlaps(dependency: &Dependency, start: usize, end: usize) -> bool {
+    let span = dependency.location().span;
+    return span.offset() <= end && start <= span.offset() + span.len();
+}
+
+/// What a hover over a dependency shows, in markdown
+fn hover_text(report: &DependencyReport) -> String {
+    let mut lines = vec![format!("**{}** ({})", report.key, report.kind)];
+    lines.push(match (&report.locked, report.age_days) {
+        (Some(locked), Some(age)) => format!("Locked to `{}`, {} days ago", locked, age),
+        (Some(locked), None) => format!("Locked to `{}`", locked),
+        (None, _) => "Not locked yet".to_string(),
+    });
+    lines.push(
+        match (&report.latest, report.days_behind_latest, &report.error) {
+            (_, _, Some(error)) => format!("Latest version unavailable: {}", error),
+            (Some(latest), Some(days), _) if days > 0 => {
+                format!("Latest is `{}`, out for {} days", latest, days)
+            }
+            (Some(latest), _, _) => format!("Latest is `{}`", latest),
+            (None, _, _) => "Latest version unknown".to_string(),
+        },
+    );
+    return lines.join("\n\n");
+}
+
+impl Server {
+    pub fn new(root: impl AsRef<Path>, config: Config) -> Server {
+        return Server {
+            root: root.as_ref().to_path_buf(),
+            config,
+            documents: BTreeMap::new(),
+            shutdown: false,
+        };
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        return self.root.join(lock::LOCK_FILE_PATH);
+    }
+
+    /// The dependencies declared on an open document, or none when it
+    /// doesn't parse (i.e. while it is being edited)
+    fn dependencies(&self, uri: &str) -> Vec<Dependency> {
+        let text = match self.documents.get(uri) {
+            Some(text) => text,
+            None => return vec![],
+        };
+        let path = url::Url::parse(uri)
+            .ok()
+            .and_then(|url| url.to_file_path().ok())
+            .map_or(uri.to_string(), |path| path.display().to_string());
+        return deps::collect_source_dependencies(&path, text, &self.config).unwrap_or_default();
+    }
+
+    /// The dependency declared at a position of a document
+    fn dependency_at(&self, params: &Value) -> Option<Dependency> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let at = offset(self.documents.get(uri)?, &params["position"])?;
+        return self
+            .dependencies(uri)
+            .into_iter()
+            .find(|d| overlaps(d, at, at));
+    }
+
+    async fn hover(&self, params: &Value) -> Result<Value, RpcError> {
+        let dependency = match self.dependency_at(params) {
+            Some(dependency) => dependency,
+            None => return Ok(Value::Null),
+        };
+        let lock_file = lock::read(&self.lock_path())?;
+        let history = git::history(&self.root, &self.lock_path());
+        let since = match &history {
+            Ok(history) => report::locked_since(&lock_file, history),
+            Err(_) => BTreeMap::new(),
+        };
+        let mut report =
+            DependencyReport::lookup(&dependency, &lock_file, &since, report::now()).await;
+        // without history, how long it has been locked is unknown
+        if history.is_err() {
+            report.age_days = None;
+            report.days_behind_latest = None;
+        }
+        return Ok(json!({
+            "contents": { "kind": "markdown", "value": hover_text(&report) },
+        }));
+    }
+
+    fn code_actions(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let text = match self.documents.get(uri) {
+            Some(text) => text,
+            None => return json!([]),
+        };
+        let range = &params["range"];
+        let (start, end) = match (offset(text, &range["start"]), offset(text, &range["end"])) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return json!([]),
+        };
+        let mut keys: Vec<String> = self
+            .dependencies(uri)
+            .iter()
+            .filter(|d| overlaps(d, start, end))
+            .map(|d| d.key())
+            .collect();
+        keys.dedup();
+        let actions: Vec<Value> = keys
+            .into_iter()
+            .map(|key| {
+                let title = format!("Update {} on uptix.lock", key);
+                return json!({
+                    "title": title,
+                    "kind": "quickfix",
+                    "command": {
+                        "title": title,
+                        "command": UPDATE_COMMAND,
+                        "arguments": [uri, key],
+                    },
+                });
+            })
+            .collect();
+        return Value::from(actions);
+    }
+
+    /// Locks a single dependency of a document to its latest version,
+    /// leaving the rest of uptix.lock as it was. Pinned dependencies which
+    /// are already locked are left alone, as `uptix update` does.
+    async fn update(&self, arguments: &Value) -> Result<Change, RpcError> {
+        let (uri, key) = match (arguments[0].as_str(), arguments[1].as_str()) {
+            (Some(uri), Some(key)) => (uri, key),
+            _ => return Err(serve::invalid_params("expected a document and a key")),
+        };
+        let dependency = self
+            .dependencies(uri)
+            .into_iter()
+            .find(|d| d.key() == key)
+            .ok_or_else(|| serve::invalid_params(&format!("{} isn't declared", key)))?;
+        let mut lock_file = lock::read(&self.lock_path())?;
+        if dependency.is_pinned() && lock_file.contains_key(key) {
+            return Err(serve::invalid_params(&format!(
+                "{} is pinned with `# uptix: pin`",
+                key
+            )));
+        }
+        deps::remember_hashes(&lock_file);
+        let current = dependency
+            .lock()
+            .await
+            .map_err(|error| dependency.failure(error))?;
+        let previous = lock_file.insert(key.to_string(), current.clone());
+        lock::write(&self.lock_path(), &lock_file)?;
+        return Ok(Change {
+            key: key.to_string(),
+            previous,
+            current: Some(current),
+        });
+    }
+
+    /// Handles a message from the editor, returning the messages to send
+    /// back to it: the response to a request, and any notification
+    pub async fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let params = &message["params"];
+        let method = message["method"].as_str().unwrap_or_default();
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => {
+                self.notify(method, params);
+                return vec![];
+            }
+        };
+        let mut notifications = vec![];
+        let result = match method {
+            // nothing but exiting is expected after shutting down
+            _ if self.shutdown => Err(RpcError {
+                code: serve::INVALID_REQUEST,
+                message: "uptix lsp is shutting down".to_string(),
+                data: None,
+            }),
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    // documents are sent whole on every change
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "codeActionProvider": true,
+                    "executeCommandProvider": { "commands": [UPDATE_COMMAND] },
+                },
+                "serverInfo": { "name": "uptix", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/hover" => self.hover(params).await,
+            "textDocument/codeAction" => Ok(self.code_actions(params)),
+            "workspace/executeCommand" if params["command"] == UPDATE_COMMAND => {
+                let result = self.update(&params["arguments"]).await;
+                let message = match &result {
+                    Ok(change) if change.previous == change.current => {
+                        format!("{} is up to date", change.key)
+                    }
+                    Ok(change) => format!("Updated {}", change.summary()),
+                    Err(error) => error.message.clone(),
+                };
+                notifications.push(json!({
+                    "jsonrpc": "2.0",
+                    "method": "window/showMessage",
+                    // errors are 1 and information 3
+                    "params": { "type": if result.is_ok() { 3 } else { 1 }, "message": message },
+                }));
+                result.map(|_| Value::Null)
+            }
+            _ => Err(RpcError {
+                code: serve::METHOD_NOT_FOUND,
+                message: format!("{} isn't supported by uptix lsp", method),
+                data: None,
+            }),
+        };
+        let mut messages = vec![serve::response(id, result)];
+        messages.append(&mut notifications);
+        return messages;
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.to_string(), text.to_string());
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+            }
+            _ => (),
+        }
+    }
+
+    /// Serves an editor over the given streams (i.e. stdin and stdout) until
+    /// it exits
+    pub async fn run(
+        &mut self,
+        input: impl AsyncBufRead + Unpin,
+        output: impl AsyncWrite + Unpin,
+    ) -> Result<(), Error> {
+        let mut input = input;
+        let mut output = output;
+        while let Some(body) = read_message(&mut input).await? {
+            let message = body.and_then(|body| {
+                return serde_json::from_slice::<Value>(&body).map_err(|error| RpcError {
+                    code: serve::PARSE_ERROR,
+                    message: error.to_string(),
+                    data: None,
+                });
+            });
+            let message = match message {
+                Ok(message) => message,
+                Err(error) => {
+                    write_message(&mut output, &serve::response(Value::Null, Err(error))).await?;
+                    continue;
+                }
+            };
+            if message["method"] == "exit" {
+                break;
+            }
+            for reply in self.handle(&message).await {
+                write_message(&mut output, &reply).await?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Reads the body of a message framed by a Content-Length header, or
+/// `None` once the input is closed. Bodies larger than `MAX_BODY_SIZE` are
+/// skipped rather than read, and answered with an error.
+async fn read_message(
+    input: &mut (impl AsyncBufRead + Unpin),
+) -> Result<Option<Result<Vec<u8>, RpcError>>, Error> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+        if line.trim().is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.unwrap_or(0);
+    if length > serve::MAX_BODY_SIZE {
+        tokio::io::copy(&mut input.take(length as u64), &mut tokio::io::sink()).await?;
+        return Ok(Some(Err(RpcError {
+            code: serve::INVALID_REQUEST,
+            message: format!("messages of {} bytes are too large to be read", length),
+            data: None,
+        })));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).await?;
+    return Ok(Some(Ok(body)));
+}
+
+async fn write_message(
+    output: &mut (impl AsyncWrite + Unpin),
+    message: &Value,
+) -> Result<(), Error> {
+    let body = message.to_string();
+    let framed = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    output.write_all(framed.as_bytes()).await?;
+    output.flush().await?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{offset, Server, UPDATE_COMMAND};
+    use crate::config::Config;
+    use crate::lock::{self, LockData};
+    use crate::serve;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn it_converts_positions_to_offsets() {
+        let text = "{\n  é = \"𝔸\"; x = 1;\n}";
+        let at = |line: u64, character: u64| {
+            return offset(text, &json!({ "line": line, "character": character }));
+        };
+        assert_eq!(at(0, 0), Some(0));
+        assert_eq!(at(1, 2), Some(4));
+        // é is a single UTF-16 unit but two bytes, and 𝔸 two units and four
+        assert_eq!(at(1, 3), Some(6));
+        assert_eq!(at(1, 9), Some(14));
+        assert_eq!(at(5, 0), None);
+    }
+
+    #[tokio::test]
+    async fn it_serves_editors() {
+        let registry = mockito::server_address().to_string();
+        let _ping_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _manifest_mock = mockito::mock("HEAD", "/v2/library/lsp/manifests/1.0")
+            .with_status(200)
+            .with_header("docker-content-digest", "sha256:latest")
+            .create();
+        let _image_mock = mockito::mock("GET", "/v2/library/lsp/manifests/sha256:latest")
+            .with_status(404)
+            .create();
+        let project = tempfile::tempdir().unwrap();
+        let config: Config = toml::from_str(&format!(
+            "[docker.registries.\"{}\"]\ninsecure = true",
+            registry
+        ))
+        .unwrap();
+        let key = format!("{}/library/lsp:1.0", registry);
+        lock::write(
+            &project.path().join("uptix.lock"),
+            &[(key.clone(), LockData::Digest("sha256:old".to_string()))].into(),
+        )
+        .unwrap();
+        let uri = "file:///project/default.nix";
+        let text = format!("{{\n  image = uptix.dockerImage \"{}\";\n}}", key);
+        let mut server = Server::new(project.path(), config);
+        let open = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": uri, "text": text } },
+        });
+        assert_eq!(server.handle(&open).await, Vec::<Value>::new());
+
+        let hover = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "textDocument/hover",
+            "params": {
+                "textDocument": { "uri": uri },
+                "position": { "line": 1, "character": 14 },
+            },
+        });
+        let replies = server.handle(&hover).await;
+        let contents = replies[0]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(contents.starts_with(&format!("**{}** (docker)", key)));
+        assert!(contents.contains("Locked to `sha256:old`"));
+        let outside = json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "textDocument/hover",
+            "params": {
+                "textDocument": { "uri": uri },
+                "position": { "line": 0, "character": 0 },
+            },
+        });
+        assert_eq!(server.handle(&outside).await[0]["result"], Value::Null);
+
+        let actions = json!({
+            "jsonrpc": "2.0",
+            "id": 3,
+            "method": "textDocument/codeAction",
+            "params": {
+                "textDocument": { "uri": uri },
+                "range": {
+                    "start": { "line": 1, "character": 20 },
+                    "end": { "line": 1, "character": 20 },
+                },
+            },
+        });
+        let replies = server.handle(&actions).await;
+        let command = &replies[0]["result"][0]["command"];
+        assert_eq!(command["command"], json!(UPDATE_COMMAND));
+
+        let execute = json!({
+            "jsonrpc": "2.0",
+            "id": 4,
+            "method": "workspace/executeCommand",
+            "params": command,
+        });
+        let replies = server.handle(&execute).await;
+        assert_eq!(replies[0]["result"], Value::Null);
+        assert_eq!(replies[1]["method"], json!("window/showMessage"));
+        let lock_file = lock::read(&project.path().join("uptix.lock")).unwrap();
+        assert_eq!(
+            lock_file[&key],
+            LockData::Digest("sha256:latest".to_string())
+        );
+
+        let pinned_uri = "file:///project/pinned.nix";
+        let pinned_text = format!(
+            "{{\n  image = uptix.dockerImage \"{}\"; # uptix: pin\n}}",
+            key
+        );
+        let open = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": pinned_uri, "text": pinned_text } },
+        });
+        server.handle(&open).await;
+        let execute = json!({
+            "jsonrpc": "2.0",
+            "id": 5,
+            "method": "workspace/executeCommand",
+            "params": { "command": UPDATE_COMMAND, "arguments": [pinned_uri, key] },
+        });
+        let replies = server.handle(&execute).await;
+        assert_eq!(replies[0]["error"]["code"], json!(serve::INVALID_PARAMS));
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_frames_messages() {
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "method": "shutdown" }).to_string();
+        let exit = json!({ "jsonrpc": "2.0", "method": "exit" }).to_string();
+        let input = format!(
+            "Content-Length: {}\r\n\r\n{}Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body,
+            exit.len(),
+            exit
+        );
+        let mut output = vec![];
+        let mut server = Server::new(".", Config::default());
+        server.run(input.as_bytes(), &mut output).await.unwrap();
+        let reply = json!({ "jsonrpc": "2.0", "id": 1, "result": null }).to_string();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!("Content-Length: {}\r\n\r\n{}", reply.len(), reply)
+        );
+    }
+
+    #[tokio::test]
+    async fn it_skips_oversized_messages() {
+        let oversized = "x".repeat(serve::MAX_BODY_SIZE + 1);
+        let exit = json!({ "jsonrpc": "2.0", "method": "exit" }).to_string();
+        let input = format!(
+            "Content-Length: {}\r\n\r\n{}Content-Length: {}\r\n\r\n{}",
+            oversized.len(),
+            oversized,
+            exit.len(),
+            exit
+        );
+        let mut output = vec![];
+        let mut server = Server::new(".", Config::default());
+        server.run(input.as_bytes(), &mut output).await.unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let (_, reply) = output.split_once("\r\n\r\n").unwrap();
+        let reply: Value = serde_json::from_str(reply).unwrap();
+        assert_eq!(reply["error"]["code"], json!(serve::INVALID_REQUEST));
+    }
+}
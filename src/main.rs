@@ -1,51 +1,1317 @@
-mod deps;
-mod error;
-mod util;
+#![allow(clippy::needless_return, clippy::result_large_err)]
 
-#[macro_use]
-extern crate lazy_static;
-
-use crate::deps::collect_file_dependencies;
-use crate::deps::Dependency;
-use miette::{IntoDiagnostic, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use futures::stream::{self, StreamExt};
 use std::collections::BTreeMap;
-use std::fs;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tracing_subscriber::EnvFilter;
+use uptix::config::{Config, LintLevel};
+use uptix::deps::github::pull;
+use uptix::deps::Dependency;
+use uptix::drift::PinnedImage;
+use uptix::error::Error;
+use uptix::lock::{LockData, LockFile};
+use uptix::semver::Bump;
+use uptix::{
+    audit, cache, ci, config, convert, deps, drift, explain, git, gitlab, hook, http, lock, lsp,
+    notify, overlay, post_update, provenance, renovate, report, secrets, serve, timings, util,
+    verify, Change, Files, Plan, Resolution, Resolved, Updater,
+};
+
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Args {
+    /// Path to the uptix configuration file
+    #[arg(long, global = true, default_value = config::DEFAULT_CONFIG_PATH)]
+    config: PathBuf,
+    /// Only scan nix files reachable through imports from this file
+    #[arg(long, global = true)]
+    entry: Option<PathBuf>,
+    /// Follow symlinks while discovering files (links into the nix store are
+    /// always skipped)
+    #[arg(long, global = true)]
+    follow_symlinks: bool,
+    /// Seconds before a single network request is abandoned
+    #[arg(long, global = true)]
+    timeout: Option<u64>,
+    /// How many times requests which failed transiently are retried
+    #[arg(long, global = true)]
+    retries: Option<u32>,
+    /// How many dependencies are locked at once
+    #[arg(long, short, global = true)]
+    jobs: Option<usize>,
+    /// Report how long each phase of the run took
+    #[arg(long, global = true)]
+    timings: bool,
+    /// Log each dependency being resolved and each request made for it.
+    /// RUST_LOG takes precedence, i.e. RUST_LOG=uptix=trace.
+    #[arg(long, short, global = true)]
+    verbose: bool,
+    /// Format of what is logged to stderr. `json` writes one object per
+    /// line, including an event for each dependency resolved, each error
+    /// and a summary of the run.
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    update: UpdateArgs,
+}
+
+#[derive(ValueEnum, PartialEq, Clone, Copy, Debug)]
+enum ReportFormat {
+    Json,
+    /// the text format of Prometheus, i.e. for node_exporter's textfile
+    /// collector
+    Prometheus,
+}
+
+#[derive(ValueEnum, PartialEq, Clone, Copy, Debug)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Target of the events meant for log aggregation, which are only logged
+/// by default along with `--log-format json`
+const EVENTS: &str = "uptix::events";
+
+#[derive(clap::Args)]
+struct UpdateArgs {
+    /// Fail instead of warning when a dependency is declared with
+    /// conflicting versions, or when a branch's history was rewritten
+    #[arg(long)]
+    strict: bool,
+    /// Skip looking up the size and release notes of updated dependencies,
+    /// which `uptix list` shows on demand
+    #[arg(long)]
+    no_metadata: bool,
+    /// When interrupted (i.e. with Ctrl-C), write the dependencies locked
+    /// so far, keeping the previous entries of the rest
+    #[arg(long)]
+    save_partial: bool,
+    /// Print how uptix.lock would change, without writing it
+    #[arg(long)]
+    dry_run: bool,
+    /// After writing uptix.lock, push it to the uptix/update branch on
+    /// origin and open a pull request for it on GitHub
+    #[arg(long, conflicts_with = "dry_run")]
+    create_pr: bool,
+    /// After writing uptix.lock, push it to the uptix/update branch through
+    /// the GitLab API and open a merge request for it (see [gitlab] on
+    /// uptix.toml)
+    #[arg(long, conflicts_with_all = ["dry_run", "create_pr"])]
+    create_mr: bool,
+    /// After writing uptix.lock, commit it on the current branch with a
+    /// message listing what was bumped
+    #[arg(long, conflicts_with_all = ["dry_run", "create_pr", "create_mr"])]
+    commit: bool,
+    /// Also write the updates found to this file, in the shape of Renovate's
+    /// report (i.e. for an org-wide dependency dashboard)
+    #[arg(long)]
+    renovate_report: Option<PathBuf>,
+    /// Also write the provenance of the update to this file (what each entry
+    /// was resolved to, where from and when), as an in-toto statement with
+    /// SLSA provenance in a DSSE envelope
+    #[arg(long, conflicts_with = "dry_run")]
+    provenance: Option<PathBuf>,
+    /// Sign the provenance with this private key, in PEM
+    #[arg(long, requires = "provenance")]
+    provenance_key: Option<PathBuf>,
+    /// Run this command after writing uptix.lock, on top of the post_update
+    /// hooks on uptix.toml, restoring uptix.lock if it fails
+    #[arg(long = "hook", value_name = "COMMAND", conflicts_with = "dry_run")]
+    hooks: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Update uptix.lock with the latest version of each dependency (default)
+    Update(UpdateArgs),
+    /// Suggest uptix functions to replace raw fetchers such as fetchFromGitHub
+    Convert {
+        /// Rewrite the nix files and add the pinned versions to uptix.lock
+        #[arg(long)]
+        write: bool,
+        /// Commit the rewritten nix files along with uptix.lock
+        #[arg(long, requires = "write")]
+        commit: bool,
+    },
+    /// List the dependencies along with details about their latest version
+    List,
+    /// Report how long each dependency has been locked, and how far behind
+    /// its latest version it is
+    Report {
+        #[arg(long, value_enum, default_value = "json")]
+        format: ReportFormat,
+        /// Write the report to this file instead of printing it
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Report images pinned to a digest (i.e. "foo/bar:1.0@sha256:...") whose
+    /// tag now points at a different digest
+    Check,
+    /// Fetch the sources on uptix.lock again and check they still match
+    /// their hash (i.e. that no tag was moved upstream)
+    Verify {
+        /// Fetch them with nix-prefetch-url and nix-prefetch-git, as nix
+        /// would, instead of the way uptix does
+        #[arg(long)]
+        nix: bool,
+    },
+    /// Check what uptix.lock is locked to against known vulnerabilities, on a
+    /// database downloaded ahead of time with `uptix db sync`
+    Audit {
+        /// Directory of the vulnerability database
+        #[arg(long)]
+        db: PathBuf,
+    },
+    /// Manage the vulnerability database `uptix audit` reads
+    Db {
+        #[command(subcommand)]
+        action: DbAction,
+    },
+    /// Generate files from uptix.lock, for projects which don't use the uptix
+    /// nix module
+    Generate {
+        #[command(subcommand)]
+        target: GenerateTarget,
+    },
+    /// Answer JSON-RPC requests (list, show, plan and update) POSTed to a
+    /// unix socket, keeping caches warm between them
+    Serve {
+        /// Socket to listen on
+        #[arg(long, default_value = serve::DEFAULT_SOCKET_PATH)]
+        socket: PathBuf,
+        /// Also serve /metrics for Prometheus on this TCP address (i.e.
+        /// 127.0.0.1:9184)
+        #[arg(long)]
+        metrics_address: Option<String>,
+    },
+    /// Serve editors over the Language Server Protocol on stdin and stdout,
+    /// with hovers and code actions on the uptix calls of nix files
+    Lsp,
+    /// Check uptix.lock is in sync with the nix files from a git hook
+    Hook {
+        #[command(subcommand)]
+        action: HookAction,
+    },
+    /// Print extended guidance for a diagnostic code (i.e. uptix::error::binary_file)
+    Explain {
+        /// Code to explain, with or without the uptix::error:: prefix. All
+        /// codes are listed when omitted.
+        code: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum GenerateTarget {
+    /// A nix overlay exposing each locked dependency under `uptixPins`
+    Overlay {
+        /// File to write the overlay to, i.e. overlay.nix
+        path: PathBuf,
+    },
+    /// A GitHub Actions workflow opening pull requests with updates and
+    /// checking hand-pinned images on a schedule
+    Workflow {
+        /// File to write the workflow to
+        #[arg(default_value = ci::DEFAULT_WORKFLOW_PATH)]
+        path: PathBuf,
+        /// When to run, as a cron expression in UTC
+        #[arg(long, default_value = ci::DEFAULT_SCHEDULE, value_parser = ci::parse_schedule)]
+        schedule: String,
+        /// Path of uptix.lock, relative to the root of the repository. uptix
+        /// runs from its directory.
+        #[arg(long, default_value = lock::LOCK_FILE_PATH, value_parser = ci::parse_lock_path)]
+        lock_path: PathBuf,
+    },
+    /// The nix library reading uptix.lock, matching this version of uptix
+    NixLib {
+        /// File to write the library to, i.e. nix/uptix.nix
+        path: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbAction {
+    /// Download the advisories published by OSV
+    Sync {
+        /// Directory to store the vulnerability database in
+        #[arg(long)]
+        db: PathBuf,
+        /// OSV ecosystems to download, which can be repeated
+        #[arg(long = "ecosystem", default_value = audit::DEFAULT_ECOSYSTEM)]
+        ecosystems: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum HookAction {
+    /// Write a pre-commit hook running `uptix hook run`
+    Install {
+        /// Write a pre-push hook instead
+        #[arg(long)]
+        pre_push: bool,
+        /// Replace the hook even if it wasn't written by uptix
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check, without any request, that every declared dependency has a
+    /// valid entry on uptix.lock and that no entry is stale
+    Run,
+}
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let all_files = util::discover_nix_files(".");
-    println!("Found {} nix files", all_files.len());
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    init_tracing(args.verbose, args.log_format);
+    let result = run(&args).await;
+    if args.timings {
+        print!("{}", timings::report());
+    }
+    return match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            let code = error.exit_code();
+            tracing::error!(
+                target: EVENTS,
+                code = %error.diagnostic_code(),
+                error = %describe(&error),
+                "exited"
+            );
+            eprintln!("Error: {:?}", miette::Report::new(error));
+            ExitCode::from(code)
+        }
+    };
+}
+
+async fn run(args: &Args) -> Result<(), Error> {
+    let update_args = match &args.command {
+        Some(Command::Update(update_args)) => update_args,
+        _ => &args.update,
+    };
+    return match &args.command {
+        None | Some(Command::Update(_)) => {
+            let config = load_config_with_credentials(args)?;
+            let mut summary = notify::Summary::new("update");
+            let result = update(args, &config, update_args, &mut summary).await;
+            if update_args.dry_run {
+                return result;
+            }
+            send_notifications(&config, summary, result).await
+        }
+        Some(Command::Convert { write, commit }) => {
+            convert(args, &load_config(args)?, *write, *commit)
+        }
+        Some(Command::List) => list(args, &load_config(args)?).await,
+        Some(Command::Report { format, output }) => {
+            report(
+                args,
+                &load_config_with_credentials(args)?,
+                *format,
+                output.as_deref(),
+            )
+            .await
+        }
+        Some(Command::Check) => {
+            let config = load_config_with_credentials(args)?;
+            let mut summary = notify::Summary::new("check");
+            let result = check(args, &config, &mut summary).await;
+            send_notifications(&config, summary, result).await
+        }
+        Some(Command::Verify { nix }) => verify(&load_config_with_credentials(args)?, *nix).await,
+        Some(Command::Audit { db }) => audit(db),
+        Some(Command::Db {
+            action: DbAction::Sync { db, ecosystems },
+        }) => {
+            load_config(args)?;
+            println!("Downloading advisories from {}...", audit::OSV_URL);
+            let metadata = audit::sync(db, ecosystems, audit::OSV_URL).await?;
+            for (ecosystem, count) in &metadata.ecosystems {
+                println!(" - {}: {} advisories", ecosystem, count);
+            }
+            println!("Synced to {}", db.display());
+            Ok(())
+        }
+        Some(Command::Generate { target }) => generate(target),
+        Some(Command::Hook { action }) => hook(args, action),
+        Some(Command::Lsp) => {
+            // stdout carries the protocol, so nothing else may be printed
+            let mut server = lsp::Server::new(".", load_config_with_credentials(args)?);
+            let stdin = tokio::io::BufReader::new(tokio::io::stdin());
+            server.run(stdin, tokio::io::stdout()).await
+        }
+        Some(Command::Serve {
+            socket,
+            metrics_address,
+        }) => {
+            serve(
+                load_config_with_credentials(args)?,
+                socket,
+                metrics_address.as_deref(),
+            )
+            .await
+        }
+        // explaining a config error shouldn't require a valid config
+        Some(Command::Explain { code }) => explain(code.as_deref()),
+    };
+}
+
+/// Posts the summary of a run to the webhooks configured on uptix.toml,
+/// returning what the run did. A webhook failing only warns, rather than
+/// failing the run.
+async fn send_notifications(
+    config: &Config,
+    summary: notify::Summary,
+    result: Result<(), Error>,
+) -> Result<(), Error> {
+    if config.notifications.is_empty() {
+        return result;
+    }
+    let mut summary = summary;
+    if let Err(error) = &result {
+        summary.error = Some(describe(error));
+    }
+    for error in notify::send(&config.notifications, &summary).await {
+        println!("Warning:");
+        println!("{:?}", miette::Report::new(error));
+    }
+    return result;
+}
+
+/// Logs to stderr, so that it doesn't get mixed up with what commands print
+fn init_tracing(verbose: bool, format: LogFormat) {
+    let default = match (verbose, format) {
+        (true, _) => "uptix=debug".to_string(),
+        (false, LogFormat::Text) => format!("warn,{}=off", EVENTS),
+        (false, LogFormat::Json) => format!("warn,{}=info", EVENTS),
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default));
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().flatten_event(true).init(),
+    }
+}
+
+/// An error along with what caused it, on a single line
+fn describe(error: &dyn std::error::Error) -> String {
+    let mut description = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        // some errors already repeat what caused them
+        let cause_description = cause.to_string();
+        if !description.contains(&cause_description) {
+            description.push_str(&format!(": {}", cause_description));
+        }
+        source = cause.source();
+    }
+    return description;
+}
+
+fn load_config(args: &Args) -> Result<Config, Error> {
+    let mut config = Config::load(&args.config)?;
+    config.discovery.follow_symlinks |= args.follow_symlinks;
+    if let Some(timeout) = args.timeout {
+        config.network.timeout = timeout;
+    }
+    if let Some(retries) = args.retries {
+        config.network.retries = retries;
+    }
+    if let Some(jobs) = args.jobs {
+        config.network.jobs = jobs;
+    }
+    http::configure(&config.network);
+    cache::configure(&config.cache);
+    return Ok(config);
+}
+
+/// Like `load_config`, for commands which reach registries and so may need
+/// the secrets on the credentials file. Commands which stay offline (i.e.
+/// `list` and the pre-commit hook) leave it alone, so that they never run
+/// sops.
+fn load_config_with_credentials(args: &Args) -> Result<Config, Error> {
+    let config = load_config(args)?;
+    secrets::configure(&config.credentials, Path::new("."));
+    return Ok(config);
+}
+
+/// Finds the files to scan under `root`, which the paths on `args` and
+/// `config` are relative to
+fn discover_files(root: &Path, args: &Args, config: &Config) -> Result<Files, Error> {
+    let _timer = timings::start("discovery");
+    return match &args.entry {
+        Some(entry) => Ok(Files {
+            nix: util::discover_imported_nix_files(&root.join(entry))?,
+            ..Default::default()
+        }),
+        None => Files::discover(root, config),
+    };
+}
+
+fn collect_dependencies(args: &Args, config: &Config) -> Result<Vec<Dependency>, Error> {
+    return collect_dependencies_under(Path::new(""), args, config);
+}
+
+fn collect_dependencies_under(
+    root: &Path,
+    args: &Args,
+    config: &Config,
+) -> Result<Vec<Dependency>, Error> {
+    let (dependencies, _) = collect_updater(root, args, config)?.into_parts();
+    return Ok(dependencies);
+}
+
+/// Finds and parses the files to scan under `root`, reporting the ones
+/// which had to be skipped
+fn collect_updater(root: &Path, args: &Args, config: &Config) -> Result<Updater, Error> {
+    let files = discover_files(root, args, config)?;
+    println!("Found {} nix files", files.nix.len());
+    if !files.templates.is_empty() {
+        println!("Found {} template files", files.templates.len());
+    }
+    if !files.workflows.is_empty() {
+        println!("Found {} workflow files", files.workflows.len());
+    }
 
     print!("Parsing files... ");
-    std::io::stdout().flush().into_diagnostic()?;
-    let mut all_dependencies: Vec<Dependency> = vec![];
-    for f in all_files {
-        let mut deps = collect_file_dependencies(f.to_str().unwrap())?;
-        all_dependencies.append(&mut deps);
+    std::io::stdout().flush()?;
+    let timer = timings::start("parsing");
+    let updater = Updater::with_files(root, config, files);
+    drop(timer);
+    let mut updater = match updater {
+        Ok(updater) => updater,
+        Err(error) => {
+            println!("Failed.");
+            return Err(error);
+        }
+    };
+    for (f, error) in updater.take_skipped() {
+        println!();
+        println!("Skipping {}:", f.display());
+        println!("{:?}", miette::Report::new(error));
     }
     println!("Done.");
-    println!("Found {} uptix dependencies", all_dependencies.len());
+    println!("Found {} uptix dependencies", updater.declared().len());
+    return Ok(updater);
+}
+
+async fn update(
+    args: &Args,
+    config: &Config,
+    update_args: &UpdateArgs,
+    summary: &mut notify::Summary,
+) -> Result<(), Error> {
+    let started_at = report::now();
+    let updater = collect_updater(Path::new(""), args, config)?;
+    let all_dependencies = updater.declared();
+
+    for conflict in deps::find_conflicts(all_dependencies) {
+        let declarations = conflict
+            .iter()
+            .map(|d| format!(" - {} ({})", d.key(), d.location()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let error = Error::ConflictingDeclarations {
+            identity: conflict[0].identity(),
+            declarations,
+        };
+        if update_args.strict {
+            return Err(error);
+        }
+        println!("Warning:");
+        println!("{:?}", miette::Report::new(error));
+    }
+
+    for warning in lint(all_dependencies, config) {
+        println!("Warning:");
+        println!("{:?}", miette::Report::new(warning));
+    }
 
     print!("Looking for updates... ");
-    std::io::stdout().flush().into_diagnostic()?;
-    let mut lock_file = BTreeMap::new();
-    for dependency in all_dependencies {
-        let lock = dependency.lock().await.into_diagnostic();
-        if lock.is_err() {
-            println!("Error while updating dependency {}", dependency.key());
-            println!("{:?}", lock.err().unwrap());
+    std::io::stdout().flush()?;
+    let ctrl_c = async {
+        // without a handler, there's nothing to be interrupted by
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    let resolution = updater.resolve(ctrl_c).await?;
+    let lock_file = resolution.lock_file();
+    let Resolution {
+        resolved,
+        skipped,
+        cancelled: interrupted,
+        previous,
+    } = resolution;
+    let previous_lock_file = &previous;
+    if interrupted {
+        println!("Interrupted.");
+        println!("Skipped {} dependencies:", skipped.len());
+        for dependency in &skipped {
+            println!(" - {} ({})", dependency.key(), dependency.location());
+        }
+        if !update_args.save_partial {
+            return Err(Error::Interrupted {
+                skipped: skipped.len(),
+            });
+        }
+    }
+    let locked = resolved.len();
+    let mut updated = vec![];
+    // every dependency is still handled after one fails, so that all of the
+    // failures get reported together
+    let mut failures = vec![];
+    for (dependency, resolved) in resolved {
+        let (lock, warning) = match resolved {
+            Resolved::Locked { lock, warning } => (*lock, warning),
+            Resolved::Failed(error) => {
+                tracing::error!(
+                    target: EVENTS,
+                    key = %dependency.key(),
+                    location = %dependency.location(),
+                    code = %error.diagnostic_code(),
+                    error = %describe(&error),
+                    "failed"
+                );
+                summary
+                    .failures
+                    .push(format!("{}: {}", dependency.key(), describe(&error)));
+                failures.push(dependency.failure(error));
+                continue;
+            }
+            Resolved::Kept => continue,
+        };
+        if let Some(warning) = warning {
+            if update_args.strict {
+                return Err(warning);
+            }
+            println!("Warning:");
+            println!("{:?}", miette::Report::new(warning));
+        }
+        let changed = previous_lock_file.get(&dependency.key()) != Some(&lock);
+        tracing::info!(
+            target: EVENTS,
+            key = %dependency.key(),
+            location = %dependency.location(),
+            lock = %lock,
+            updated = changed,
+            "resolved"
+        );
+        if changed {
+            updated.push(dependency);
+        }
+    }
+    tracing::info!(
+        target: EVENTS,
+        updated = updated.len(),
+        up_to_date = locked - updated.len() - failures.len(),
+        failed = failures.len(),
+        skipped = skipped.len(),
+        "summary"
+    );
+    if !failures.is_empty() {
+        println!("Failed.");
+        print_rate_limits();
+        if failures.len() == 1 {
+            return Err(failures.remove(0));
+        }
+        return Err(Error::UpdateErrors { errors: failures });
+    }
+    if !interrupted {
+        println!("Done.");
+    }
+    print_rate_limits();
+    if update_args.dry_run {
+        let plan = Plan::diff(previous_lock_file, lock_file);
+        print_plan(&plan);
+        write_renovate_report(update_args, all_dependencies, previous_lock_file, &plan)?;
+        return Ok(());
+    }
+    let bumps = updated
+        .iter()
+        .filter_map(|dependency| {
+            let key = dependency.key();
+            let bump = lock_file[&key].bump_from(previous_lock_file.get(&key)?)?;
+            return Some((key, bump));
+        })
+        .collect();
+    let links = print_updates(&updated, &lock_file, &bumps, !update_args.no_metadata);
+    let up_to_date = locked - updated.len();
+    if up_to_date > 0 {
+        println!("{} dependencies are up to date", up_to_date);
+    }
+
+    let _timer = timings::start("writing");
+    let lock_path = Path::new(lock::LOCK_FILE_PATH);
+    let previous_content = match lock_path.exists() {
+        true => Some(std::fs::read_to_string(lock_path)?),
+        false => None,
+    };
+    lock::write(lock_path, &lock_file)?;
+    match interrupted {
+        true => println!("Wrote the dependencies locked so far to uptix.lock"),
+        false => println!("Wrote uptix.lock successfully"),
+    }
+    let plan = Plan::diff(previous_lock_file, lock_file);
+    run_post_update_hooks(config, update_args, &plan, previous_content).await?;
+    write_renovate_report(update_args, all_dependencies, previous_lock_file, &plan)?;
+    write_provenance(update_args, &plan, started_at)?;
+    summary.updated = plan
+        .added
+        .iter()
+        .chain(&plan.changed)
+        .map(Change::summary)
+        .collect();
+    if update_args.commit {
+        if plan.is_empty() {
+            println!("uptix.lock didn't change, so nothing was committed");
             return Ok(());
         }
-        lock_file.insert(dependency.key().to_string(), lock.unwrap());
+        let id = git::commit(Path::new("."), &[lock_path], &git::commit_message(&plan))?;
+        println!("Committed uptix.lock as {}", &id[..7]);
+        return Ok(());
     }
-    println!("Done.");
+    if update_args.create_pr || update_args.create_mr {
+        let request = match update_args.create_pr {
+            true => "pull request",
+            false => "merge request",
+        };
+        if plan.is_empty() {
+            println!("uptix.lock didn't change, so no {} was opened", request);
+            return Ok(());
+        }
+        let url = match update_args.create_pr {
+            true => pull::create(&config.github, &plan, &links).await?,
+            false => gitlab::create(&config.gitlab, &plan, &links).await?,
+        };
+        println!("Opened {}", url);
+    }
+
+    return Ok(());
+}
+
+/// Lists the dependencies whose lock changed, along with the image size and
+/// release notes kept on their new lock, so that large bumps stand out before
+/// deploying and reviewers see what changed. Printing those can be skipped
+/// with `--no-metadata`. Major version bumps are called out, since they are
+/// the likeliest to break things. Returns the links to the release notes, by
+/// key.
+fn print_updates(
+    updated: &[&Dependency],
+    lock_file: &LockFile,
+    bumps: &BTreeMap<String, Bump>,
+    with_metadata: bool,
+) -> BTreeMap<String, String> {
+    let mut links = BTreeMap::new();
+    if updated.is_empty() {
+        return links;
+    }
+    println!("Updated {} dependencies:", updated.len());
+    for dependency in updated {
+        let key = dependency.key();
+        match bumps.get(&key) {
+            Some(bump) => println!(" - {} ({}, {} bump)", key, dependency.location(), bump),
+            None => println!(" - {} ({})", key, dependency.location()),
+        }
+        let metadata = match lock_file.get(&key).and_then(LockData::metadata) {
+            Some(metadata) => metadata,
+            None => continue,
+        };
+        if let (Some(url), Some(_)) = (&metadata.url, &metadata.notes) {
+            links.insert(key.clone(), url.clone());
+        }
+        if !with_metadata {
+            continue;
+        }
+        if let Some(size) = metadata.size {
+            println!(
+                "   size: {} ({} layers)",
+                deps::format_size(size),
+                metadata.layers.unwrap_or_default(),
+            );
+        }
+        if let Some(notes) = &metadata.notes {
+            if let Some(url) = &metadata.url {
+                println!("   release notes: {}", url);
+            }
+            for line in notes.lines() {
+                println!("     {}", line);
+            }
+        }
+    }
+    return links;
+}
+
+/// Writes the updates of a plan for `--renovate-report`, if it was given
+fn write_renovate_report(
+    update_args: &UpdateArgs,
+    dependencies: &[Dependency],
+    previous_lock_file: &LockFile,
+    plan: &Plan,
+) -> Result<(), Error> {
+    let path = match &update_args.renovate_report {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let report = renovate::Report::new(dependencies, previous_lock_file, plan);
+    let json = serde_json::to_string_pretty(&report)?;
+    lock::replace(path, &format!("{}\n", json))?;
+    println!("Wrote the updates found to {}", path.display());
+    return Ok(());
+}
+
+/// Runs the post-update hooks from uptix.toml and `--hook`, unless the
+/// update didn't change anything. If one fails, uptix.lock is put back the
+/// way it was before the update (`previous_content`).
+async fn run_post_update_hooks(
+    config: &Config,
+    update_args: &UpdateArgs,
+    plan: &Plan,
+    previous_content: Option<String>,
+) -> Result<(), Error> {
+    let hooks = [config.hooks.post_update.clone(), update_args.hooks.clone()].concat();
+    if hooks.is_empty() || plan.is_empty() {
+        return Ok(());
+    }
+    let _timer = timings::start("post-update hooks");
+    let error = match post_update::run(&hooks, plan).await {
+        Ok(()) => return Ok(()),
+        Err(error) => error,
+    };
+    let lock_path = Path::new(lock::LOCK_FILE_PATH);
+    match previous_content {
+        Some(content) => lock::replace(lock_path, &content)?,
+        None => std::fs::remove_file(lock_path)?,
+    }
+    println!("Restored uptix.lock");
+    return Err(error);
+}
+
+/// Writes the provenance of an update for `--provenance`, if it was given
+fn write_provenance(update_args: &UpdateArgs, plan: &Plan, started_at: i64) -> Result<(), Error> {
+    let path = match &update_args.provenance {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let lock_file = std::fs::read_to_string(lock::LOCK_FILE_PATH)?;
+    let statement = provenance::statement(plan, &lock_file, started_at, report::now());
+    let private_key = match &update_args.provenance_key {
+        Some(path) => Some(std::fs::read_to_string(path)?),
+        None => None,
+    };
+    let envelope = provenance::envelope(&statement, private_key.as_deref())?;
+    let json = serde_json::to_string_pretty(&envelope)?;
+    lock::replace(path, &format!("{}\n", json))?;
+    match private_key {
+        Some(_) => println!(
+            "Wrote the signed provenance of the update to {}",
+            path.display()
+        ),
+        None => println!("Wrote the provenance of the update to {}", path.display()),
+    }
+    return Ok(());
+}
+
+/// Prints how uptix.lock would change, for `--dry-run`
+fn print_plan(plan: &Plan) {
+    let sections = [
+        ("add", &plan.added),
+        ("update", &plan.changed),
+        ("remove", &plan.removed),
+    ];
+    for (action, changes) in sections {
+        if changes.is_empty() {
+            continue;
+        }
+        println!("Would {} {} entries:", action, changes.len());
+        for change in changes {
+            match (&change.previous, &change.current) {
+                (Some(previous), Some(current)) => match change.bump() {
+                    Some(bump) => println!(
+                        " - {}: {} -> {} ({} bump)",
+                        change.key, previous, current, bump
+                    ),
+                    None => println!(" - {}: {} -> {}", change.key, previous, current),
+                },
+                (Some(lock), None) | (None, Some(lock)) => println!(" - {}: {}", change.key, lock),
+                (None, None) => {}
+            }
+        }
+    }
+    if !plan.unchanged.is_empty() {
+        println!("{} dependencies are up to date", plan.unchanged.len());
+    }
+    println!("Left uptix.lock as it was (--dry-run)");
+}
 
-    let mut file = fs::File::create("uptix.lock").expect("Error creating uptix.lock");
-    let json = serde_json::to_string_pretty(&lock_file).into_diagnostic()?;
-    file.write_all(json.as_bytes())
-        .expect("Error writing JSON to uptix.lock");
-    println!("Wrote uptix.lock successfully");
+/// Flags declarations which go against the lints configured in uptix.toml
+fn lint(dependencies: &[Dependency], config: &Config) -> Vec<Error> {
+    if config.lints.latest_tag == LintLevel::Allow {
+        return vec![];
+    }
+    return dependencies
+        .iter()
+        .filter(|d| d.uses_latest_tag())
+        .map(|d| Error::LatestTag {
+            name: d.key(),
+            location: d.location().to_string(),
+        })
+        .collect();
+}
+
+/// Prints the pull quota left on registries which report one (i.e. Docker
+/// Hub), so that running out of it doesn't come as a surprise
+fn print_rate_limits() {
+    for (registry, rate_limit) in deps::rate_limits() {
+        match rate_limit.limit {
+            Some(limit) => println!(
+                "Pull quota on {}: {} of {} remaining",
+                registry, rate_limit.remaining, limit,
+            ),
+            None => println!(
+                "Pull quota on {}: {} remaining",
+                registry, rate_limit.remaining,
+            ),
+        }
+    }
+}
 
+fn convert(args: &Args, config: &Config, write: bool, commit: bool) -> Result<(), Error> {
+    let all_files = discover_files(Path::new(""), args, config)?.nix;
+    let mut all_conversions = vec![];
+    let mut rewritten = vec![];
+    for f in all_files {
+        let file_path = f.to_str().unwrap();
+        let conversions = match convert::find_conversions(file_path, config) {
+            Ok(conversions) => conversions,
+            Err(
+                e @ (Error::UnreadableFile { .. }
+                | Error::NixSyntaxError { .. }
+                | Error::OversizedFile { .. }
+                | Error::BinaryFile { .. }),
+            ) => {
+                println!("Skipping {}:", f.display());
+                println!("{:?}", miette::Report::new(e));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        for conversion in &conversions {
+            println!("{}:", conversion.location);
+            println!("{}", conversion.original);
+            println!("can be replaced with:");
+            println!("{}", conversion.replacement);
+            println!(
+                "along with the following entry in {}:",
+                lock::LOCK_FILE_PATH
+            );
+            println!(
+                "\"{}\": {}",
+                conversion.lock_key,
+                serde_json::to_string_pretty(&conversion.lock_entry)?,
+            );
+            if let Some(note) = &conversion.note {
+                println!("Note: {}", note);
+            }
+            println!();
+        }
+        if write && !conversions.is_empty() && convert::apply(file_path, &conversions)? > 0 {
+            rewritten.push(f.clone());
+        }
+        all_conversions.extend(conversions);
+    }
+
+    let applicable = all_conversions.iter().filter(|c| c.in_place).count();
+    if !write {
+        println!(
+            "Found {} fetchers, {} of which can be converted with --write",
+            all_conversions.len(),
+            applicable,
+        );
+        return Ok(());
+    }
+    convert::update_lock_file(Path::new(lock::LOCK_FILE_PATH), &all_conversions)?;
+    println!(
+        "Converted {} of {} fetchers and updated {}",
+        applicable,
+        all_conversions.len(),
+        lock::LOCK_FILE_PATH,
+    );
+    if commit && applicable > 0 {
+        let lock_path = PathBuf::from(lock::LOCK_FILE_PATH);
+        let paths: Vec<_> = rewritten
+            .iter()
+            .chain([&lock_path])
+            .map(|p| p.as_path())
+            .collect();
+        let message = format!("uptix: convert {} fetchers to uptix", applicable);
+        let id = git::commit(Path::new("."), &paths, &message)?;
+        println!("Committed the converted files as {}", &id[..7]);
+    }
     return Ok(());
 }
+
+/// Finds the images pinned to a digest by hand on the files uptix scans
+fn find_pinned_images(args: &Args, config: &Config) -> Result<Vec<PinnedImage>, Error> {
+    let all_files = discover_files(Path::new(""), args, config)?.nix;
+    let mut pinned_images = vec![];
+    for f in all_files {
+        match drift::find_pinned_images(f.to_str().unwrap(), config) {
+            Ok(images) => pinned_images.extend(images),
+            Err(
+                e @ (Error::UnreadableFile { .. }
+                | Error::NixSyntaxError { .. }
+                | Error::OversizedFile { .. }
+                | Error::BinaryFile { .. }),
+            ) => {
+                println!("Skipping {}:", f.display());
+                println!("{:?}", miette::Report::new(e));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    return Ok(pinned_images);
+}
+
+async fn list(args: &Args, config: &Config) -> Result<(), Error> {
+    for dependency in collect_dependencies(args, config)? {
+        println!();
+        println!("{} ({})", dependency.key(), dependency.location());
+        match dependency.metadata().await {
+            Ok(metadata) => print!("{}", metadata),
+            Err(e) => println!("  metadata unavailable: {}", e),
+        }
+        if let Ok(Some(reason)) = dependency.end_of_life(&config.eol).await {
+            println!("  end of life: {}", reason);
+        }
+    }
+    // images pinned by hand aren't updated, but drifting is worth knowing
+    for pinned in find_pinned_images(args, config)? {
+        println!();
+        println!("{} ({})", pinned.reference, pinned.location);
+        println!("  pinned to: {}", pinned.digest);
+        match pinned.image.drift(&pinned.digest).await {
+            Ok(Some(digest)) => println!("  drifted to: {}", digest),
+            Ok(None) => (),
+            Err(e) => println!("  drift unavailable: {}", e),
+        }
+    }
+    return Ok(());
+}
+
+async fn verify(config: &Config, with_nix: bool) -> Result<(), Error> {
+    let lock_file = lock::read(Path::new(lock::LOCK_FILE_PATH))?;
+    let entries: Vec<_> = lock_file
+        .iter()
+        .filter(|(_, lock)| verify::is_verifiable(lock))
+        .collect();
+    println!(
+        "Verifying {} entries of {}...",
+        entries.len(),
+        lock::LOCK_FILE_PATH
+    );
+    let results: Vec<_> = stream::iter(&entries)
+        .map(|(_, lock)| verify::verify(&config.github, lock, with_nix))
+        .buffered(http::jobs())
+        .collect()
+        .await;
+    let (mut mismatched, mut failed) = (0, 0);
+    for ((key, _), result) in entries.iter().zip(results) {
+        match result {
+            Ok(mismatches) if mismatches.is_empty() => println!(" - {}: ok", key),
+            Ok(mismatches) => {
+                mismatched += 1;
+                for mismatch in mismatches {
+                    println!(" - {}: {}", key, mismatch);
+                }
+            }
+            Err(error) => {
+                failed += 1;
+                println!(" - {}: couldn't be fetched: {}", key, describe(&error));
+            }
+        }
+    }
+    if mismatched > 0 || failed > 0 {
+        return Err(Error::HashMismatch { mismatched, failed });
+    }
+    println!("Every source matches its hash");
+    return Ok(());
+}
+
+async fn serve(config: Config, socket: &Path, metrics_address: Option<&str>) -> Result<(), Error> {
+    let server = serve::Server::new(".", config);
+    println!("Listening on {}", socket.display());
+    let metrics = async {
+        return match metrics_address {
+            Some(address) => {
+                println!("Serving metrics on http://{}/metrics", address);
+                server.listen_metrics(address).await
+            }
+            None => std::future::pending().await,
+        };
+    };
+    let result = tokio::select! {
+        result = server.listen(socket) => result,
+        result = metrics => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+    std::fs::remove_file(socket).ok();
+    return result;
+}
+
+fn hook(args: &Args, action: &HookAction) -> Result<(), Error> {
+    return match action {
+        HookAction::Install { pre_push, force } => {
+            let (hooks_dir, prefix) = git::hooks_dir(Path::new("."))?;
+            let mut hook_args = vec![];
+            if args.config != Path::new(config::DEFAULT_CONFIG_PATH) {
+                hook_args.push("--config".to_string());
+                hook_args.push(args.config.display().to_string());
+            }
+            if let Some(entry) = &args.entry {
+                hook_args.push("--entry".to_string());
+                hook_args.push(entry.display().to_string());
+            }
+            let name = if *pre_push { "pre-push" } else { "pre-commit" };
+            let script = hook::script(&prefix, &hook_args);
+            let path = hook::install(&hooks_dir, name, &script, *force)?;
+            println!("Installed the {} hook at {}", name, path.display());
+            Ok(())
+        }
+        HookAction::Run => {
+            let config = load_config(args)?;
+            // what is about to be committed, rather than the working tree
+            let staged = tempfile::tempdir()?;
+            let root = git::write_index(Path::new("."), staged.path())?;
+            let dependencies = collect_dependencies_under(&root, args, &config)?;
+            let lock_file = lock::read(&root.join(lock::LOCK_FILE_PATH))?;
+            let inconsistencies = hook::check(&dependencies, &lock_file);
+            if inconsistencies.is_empty() {
+                println!("{} is in sync with the nix files", lock::LOCK_FILE_PATH);
+                return Ok(());
+            }
+            for (problem, keys) in [
+                ("not locked", &inconsistencies.missing),
+                ("locked but not declared", &inconsistencies.stale),
+                ("locked with an unreadable entry", &inconsistencies.invalid),
+            ] {
+                for key in keys {
+                    println!(" - {}: {}", key, problem);
+                }
+            }
+            Err(Error::LockOutOfSync {
+                missing: inconsistencies.missing.len(),
+                stale: inconsistencies.stale.len(),
+                invalid: inconsistencies.invalid.len(),
+            })
+        }
+    };
+}
+
+fn audit(db: &Path) -> Result<(), Error> {
+    let metadata = audit::read_metadata(db)?;
+    let age = (report::now() - metadata.synced_at).max(0) / 86400;
+    println!(
+        "Auditing {} against advisories synced {} days ago...",
+        lock::LOCK_FILE_PATH,
+        age
+    );
+    let lock_file = lock::read(Path::new(lock::LOCK_FILE_PATH))?;
+    let findings = audit::audit(db, &lock_file)?;
+    if findings.is_empty() {
+        println!("No known vulnerabilities");
+        return Ok(());
+    }
+    for finding in &findings {
+        let mut ids = vec![finding.id.clone()];
+        ids.extend(finding.aliases.iter().cloned());
+        println!(
+            " - {} ({}): {}{}",
+            finding.key,
+            finding.version,
+            ids.join(", "),
+            finding
+                .summary
+                .as_ref()
+                .map_or(String::new(), |summary| format!(" - {}", summary))
+        );
+    }
+    return Err(Error::VulnerableDependencies {
+        count: findings.len(),
+    });
+}
+
+fn generate(target: &GenerateTarget) -> Result<(), Error> {
+    return match target {
+        GenerateTarget::Overlay { path } => {
+            let lock_file = lock::read(Path::new(lock::LOCK_FILE_PATH))?;
+            lock::replace(path, &overlay::generate(&lock_file))?;
+            println!(
+                "Wrote {} entries of {} to {}",
+                lock_file.len(),
+                lock::LOCK_FILE_PATH,
+                path.display()
+            );
+            Ok(())
+        }
+        GenerateTarget::Workflow {
+            path,
+            schedule,
+            lock_path,
+        } => {
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                std::fs::create_dir_all(parent)?;
+            }
+            lock::replace(path, &ci::workflow(schedule, lock_path))?;
+            println!("Wrote a workflow running uptix to {}", path.display());
+            Ok(())
+        }
+        GenerateTarget::NixLib { path } => {
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                std::fs::create_dir_all(parent)?;
+            }
+            lock::replace(path, &lock::nix_library())?;
+            println!("Wrote the uptix nix library to {}", path.display());
+            Ok(())
+        }
+    };
+}
+
+async fn report(
+    args: &Args,
+    config: &Config,
+    format: ReportFormat,
+    output: Option<&Path>,
+) -> Result<(), Error> {
+    let dependencies = collect_dependencies(args, config)?;
+    let lock_path = Path::new(lock::LOCK_FILE_PATH);
+    let lock_file = lock::read(lock_path)?;
+    // outside of a git repository every entry counts as locked just now
+    let history = git::history(Path::new("."), lock_path).unwrap_or_default();
+    let since = report::locked_since(&lock_file, &history);
+    let now = report::now();
+    let entries = stream::iter(&dependencies)
+        .map(|dependency| report::DependencyReport::lookup(dependency, &lock_file, &since, now))
+        .buffered(http::jobs())
+        .collect()
+        .await;
+    let report = report::Report::new(now, entries);
+    let text = match format {
+        ReportFormat::Json => format!("{}\n", serde_json::to_string_pretty(&report)?),
+        ReportFormat::Prometheus => report.prometheus(),
+    };
+    match output {
+        // written through a temporary file, so that scrapers never read
+        // half of it
+        Some(path) => lock::replace(path, &text)?,
+        None => print!("{}", text),
+    }
+    return Ok(());
+}
+
+async fn check(args: &Args, config: &Config, summary: &mut notify::Summary) -> Result<(), Error> {
+    let dependencies = collect_dependencies(args, config)?;
+    let lint_errors = lint(&dependencies, config);
+    let lint_count = lint_errors.len();
+    for error in lint_errors {
+        summary.findings.push(error.to_string());
+        println!("{:?}", miette::Report::new(error));
+    }
+    for dependency in &dependencies {
+        // Docker Hub's API being unreachable shouldn't fail the check
+        let reason = match dependency.deprecation().await {
+            Ok(Some(reason)) => reason,
+            Ok(None) => continue,
+            Err(error) => {
+                tracing::debug!(%error, "couldn't tell whether {} is deprecated", dependency.key());
+                continue;
+            }
+        };
+        let warning = Error::DeprecatedImage {
+            name: dependency.key(),
+            reason,
+            location: dependency.location().to_string(),
+        };
+        summary.findings.push(warning.to_string());
+        println!("{:?}", miette::Report::new(warning));
+    }
+    let mut eol_count = 0;
+    if config.lints.end_of_life != LintLevel::Allow {
+        for dependency in &dependencies {
+            if let Ok(Some(reason)) = dependency.end_of_life(&config.eol).await {
+                let warning = Error::EndOfLife {
+                    name: dependency.key(),
+                    reason,
+                    location: dependency.location().to_string(),
+                };
+                summary.findings.push(warning.to_string());
+                println!("{:?}", miette::Report::new(warning));
+                eol_count += 1;
+            }
+        }
+    }
+    let pinned_images = find_pinned_images(args, config)?;
+    let mut drifted = 0;
+    for pinned in &pinned_images {
+        if let Some(digest) = pinned.image.drift(&pinned.digest).await? {
+            println!("{}:", pinned.location);
+            println!(
+                "{} is pinned to {}, but now points to {}",
+                pinned.reference, pinned.digest, digest,
+            );
+            summary.findings.push(format!(
+                "{} has drifted from {} to {} ({})",
+                pinned.reference, pinned.digest, digest, pinned.location,
+            ));
+            drifted += 1;
+        }
+    }
+    println!(
+        "Found {} images pinned to a digest, {} of which have drifted",
+        pinned_images.len(),
+        drifted,
+    );
+    if drifted > 0 {
+        return Err(Error::DriftedImages { count: drifted });
+    }
+    if config.lints.latest_tag == LintLevel::Deny && lint_count > 0 {
+        return Err(Error::DeniedLint {
+            lint: "latest_tag".to_string(),
+            count: lint_count,
+        });
+    }
+    if config.lints.end_of_life == LintLevel::Deny && eol_count > 0 {
+        return Err(Error::DeniedLint {
+            lint: "end_of_life".to_string(),
+            count: eol_count,
+        });
+    }
+    return Ok(());
+}
+
+fn explain(code: Option<&str>) -> Result<(), Error> {
+    let code = match code {
+        Some(code) => code,
+        None => {
+            println!("Available diagnostic codes:");
+            for code in explain::codes() {
+                println!(" - {}", code);
+            }
+            return Ok(());
+        }
+    };
+    return match explain::explanation(code) {
+        Some(text) => {
+            println!("{}", text);
+            Ok(())
+        }
+        None => Err(Error::UnknownCode {
+            code: code.to_string(),
+        }),
+    };
+}
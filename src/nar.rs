@@ -0,0 +1,332 @@
+use openssl::hash::{Hasher, MessageDigest};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// Alphabet of nix's base32, which leaves out `e`, `o`, `u` and `t`
+const NIX_BASE32: &[u8] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+/// A file system tree, as nix archives (NARs) see it
+#[derive(PartialEq, Clone, Debug)]
+pub enum Node {
+    Regular { executable: bool, contents: Vec<u8> },
+    Symlink(String),
+    Directory(BTreeMap<String, Node>),
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        return Node::Directory(BTreeMap::new());
+    }
+}
+
+fn write_str(out: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u64).to_le_bytes())?;
+    out.write_all(data)?;
+    let padding = (8 - data.len() % 8) % 8;
+    return out.write_all(&[0; 8][..padding]);
+}
+
+fn write_node(out: &mut impl Write, node: &Node) -> io::Result<()> {
+    write_str(out, b"(")?;
+    write_str(out, b"type")?;
+    match node {
+        Node::Regular {
+            executable,
+            contents,
+        } => {
+            write_str(out, b"regular")?;
+            if *executable {
+                write_str(out, b"executable")?;
+                write_str(out, b"")?;
+            }
+            write_str(out, b"contents")?;
+            write_str(out, contents)?;
+        }
+        Node::Symlink(target) => {
+            write_str(out, b"symlink")?;
+            write_str(out, b"target")?;
+            write_str(out, target.as_bytes())?;
+        }
+        Node::Directory(entries) => {
+            write_str(out, b"directory")?;
+            // BTreeMap iterates by name, which is the order NARs expect
+            for (name, entry) in entries {
+                write_str(out, b"entry")?;
+                write_str(out, b"(")?;
+                write_str(out, b"name")?;
+                write_str(out, name.as_bytes())?;
+                write_str(out, b"node")?;
+                write_node(out, entry)?;
+                write_str(out, b")")?;
+            }
+        }
+    }
+    return write_str(out, b")");
+}
+
+/// Serializes a tree the way `nix-store --dump` does
+pub fn write(out: &mut impl Write, node: &Node) -> io::Result<()> {
+    write_str(out, b"nix-archive-1")?;
+    return write_node(out, node);
+}
+
+/// Writes the contents of a file as a string, copying them over rather
+/// than reading them into memory
+fn write_contents(out: &mut impl Write, path: &Path, len: u64) -> io::Result<()> {
+    out.write_all(&len.to_le_bytes())?;
+    let copied = io::copy(&mut fs::File::open(path)?.take(len), out)?;
+    if copied != len {
+        return Err(io::Error::other(format!(
+            "{} changed while it was read",
+            path.display()
+        )));
+    }
+    let padding = ((8 - len % 8) % 8) as usize;
+    return out.write_all(&[0; 8][..padding]);
+}
+
+fn dump_node(out: &mut impl Write, path: &Path, excluded: &[&str]) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    write_str(out, b"(")?;
+    write_str(out, b"type")?;
+    if metadata.file_type().is_symlink() {
+        write_str(out, b"symlink")?;
+        write_str(out, b"target")?;
+        write_str(out, fs::read_link(path)?.as_os_str().as_bytes())?;
+    } else if metadata.is_dir() {
+        write_str(out, b"directory")?;
+        let mut names = vec![];
+        for entry in fs::read_dir(path)? {
+            let name = entry?.file_name();
+            if !excluded.iter().any(|excluded| name == OsStr::new(excluded)) {
+                names.push(name);
+            }
+        }
+        // entries are ordered by the bytes of their name
+        names.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+        for name in names {
+            write_str(out, b"entry")?;
+            write_str(out, b"(")?;
+            write_str(out, b"name")?;
+            write_str(out, name.as_bytes())?;
+            write_str(out, b"node")?;
+            dump_node(out, &path.join(&name), excluded)?;
+            write_str(out, b")")?;
+        }
+    } else {
+        write_str(out, b"regular")?;
+        if metadata.permissions().mode() & 0o100 != 0 {
+            write_str(out, b"executable")?;
+            write_str(out, b"")?;
+        }
+        write_str(out, b"contents")?;
+        write_contents(out, path, metadata.len())?;
+    }
+    return write_str(out, b")");
+}
+
+/// Serializes a tree on disk the way `nix-store --dump` does, as it walks
+/// it, so that only one file is read at a time. Entries with one of the
+/// `excluded` names (i.e. `.git`) are left out, wherever they are.
+pub fn dump(out: &mut impl Write, path: &Path, excluded: &[&str]) -> io::Result<()> {
+    write_str(out, b"nix-archive-1")?;
+    return dump_node(out, path, excluded);
+}
+
+/// Encodes a hash with nix's base32, which is what `nix-prefetch-*` print
+pub fn to_nix_base32(hash: &[u8]) -> String {
+    let len = (hash.len() * 8 - 1) / 5 + 1;
+    return (0..len)
+        .rev()
+        .map(|n| {
+            let bit = n * 5;
+            let (byte, shift) = (bit / 8, bit % 8);
+            let low = (hash[byte] as u16) >> shift;
+            let high = hash.get(byte + 1).map_or(0, |b| (*b as u16) << (8 - shift));
+            return NIX_BASE32[((low | high) & 0x1f) as usize] as char;
+        })
+        .collect();
+}
+
+/// The sha256 of a tree's NAR, as nix would compute it for a fixed-output
+/// derivation such as `fetchzip`
+pub fn sha256(node: &Node) -> io::Result<String> {
+    let mut hasher = Hasher::new(MessageDigest::sha256())?;
+    write(&mut hasher, node)?;
+    return Ok(to_nix_base32(&hasher.finish()?));
+}
+
+/// Like `sha256`, but for a tree on disk, which is hashed as it's walked
+/// (as `nix hash path --base32` does)
+pub fn sha256_path(path: &Path, excluded: &[&str]) -> io::Result<String> {
+    let mut hasher = Hasher::new(MessageDigest::sha256())?;
+    dump(&mut hasher, path, excluded)?;
+    return Ok(to_nix_base32(&hasher.finish()?));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sha256, sha256_path, to_nix_base32, write, Node};
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn it_encodes_nix_base32() {
+        let empty = openssl::sha::sha256(b"");
+        assert_eq!(
+            to_nix_base32(&empty),
+            "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73"
+        );
+        assert_eq!(to_nix_base32(&[0xff]), "7z");
+    }
+
+    #[test]
+    fn it_writes_nars() {
+        let tree = Node::Directory(BTreeMap::from([
+            (
+                "run".to_string(),
+                Node::Regular {
+                    executable: true,
+                    contents: b"hi".to_vec(),
+                },
+            ),
+            ("link".to_string(), Node::Symlink("run".to_string())),
+        ]));
+        let mut nar = vec![];
+        write(&mut nar, &tree).unwrap();
+        let mut expected = vec![];
+        for token in [
+            "nix-archive-1",
+            "(",
+            "type",
+            "directory",
+            "entry",
+            "(",
+            "name",
+            "link",
+            "node",
+            "(",
+            "type",
+            "symlink",
+            "target",
+            "run",
+            ")",
+            ")",
+            "entry",
+            "(",
+            "name",
+            "run",
+            "node",
+            "(",
+            "type",
+            "regular",
+            "executable",
+            "",
+            "contents",
+            "hi",
+            ")",
+            ")",
+            ")",
+        ] {
+            expected.extend((token.len() as u64).to_le_bytes());
+            expected.extend(token.as_bytes());
+            expected.extend(vec![0; (8 - token.len() % 8) % 8]);
+        }
+        assert_eq!(nar, expected);
+        assert_eq!(sha256(&tree).unwrap().len(), 52);
+        assert_ne!(sha256(&tree).unwrap(), sha256(&Node::default()).unwrap());
+    }
+
+    #[test]
+    fn it_hashes_trees_as_they_are_walked() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("bin")).unwrap();
+        fs::write(dir.path().join("bin/run"), "hi").unwrap();
+        fs::set_permissions(
+            dir.path().join("bin/run"),
+            fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        fs::write(dir.path().join("README.md"), "# uptix").unwrap();
+        std::os::unix::fs::symlink("README.md", dir.path().join("docs")).unwrap();
+
+        assert_eq!(
+            sha256_path(dir.path(), &[]).unwrap(),
+            sha256(&Node::Directory(BTreeMap::from([
+                (
+                    "bin".to_string(),
+                    Node::Directory(BTreeMap::from([(
+                        "run".to_string(),
+                        Node::Regular {
+                            executable: true,
+                            contents: b"hi".to_vec(),
+                        },
+                    )])),
+                ),
+                (
+                    "README.md".to_string(),
+                    Node::Regular {
+                        executable: false,
+                        contents: b"# uptix".to_vec(),
+                    },
+                ),
+                ("docs".to_string(), Node::Symlink("README.md".to_string())),
+            ])))
+            .unwrap(),
+        );
+    }
+
+    #[test]
+    fn it_hashes_paths() {
+        // computed with a separate implementation of the NAR format (as laid
+        // out in the Nix thesis), since nix isn't needed to run the tests
+        let dir = tempfile::tempdir().unwrap();
+        let tree = dir.path().join("tree");
+        fs::create_dir_all(tree.join("bin")).unwrap();
+        fs::create_dir(tree.join("empty")).unwrap();
+        fs::write(tree.join("bin/run"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(tree.join("bin/run"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(tree.join("README.md"), "# uptix\n").unwrap();
+        std::os::unix::fs::symlink("README.md", tree.join("docs")).unwrap();
+        fs::create_dir(tree.join(".git")).unwrap();
+        fs::write(tree.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        assert_eq!(
+            sha256_path(&tree, &[".git"]).unwrap(),
+            "10qlid5sqf0jxfc7y8c89zg5myafcb7iz6shjv5l7pxk5bga6x1w"
+        );
+        assert_eq!(
+            sha256_path(&tree.join("empty"), &[]).unwrap(),
+            "0sjjj9z1dhilhpc8pq4154czrb79z9cm044jvn75kxcjv6v5l2m5"
+        );
+
+        let file = dir.path().join("hello");
+        fs::write(&file, "hello\n").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(
+            sha256_path(&file, &[]).unwrap(),
+            "04zwf782yjwnh3q6hz5izfd6jyip8kgw6g6yj43fiqhbyhdd0dqw"
+        );
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(
+            sha256_path(&file, &[]).unwrap(),
+            "1yhh619m3cy1mird7f0l7zsmiv9rayqn075zinda2g7rscwn0hv5"
+        );
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink("hello", &link).unwrap();
+        assert_eq!(
+            sha256_path(&link, &[]).unwrap(),
+            "09c1ai5wvx5pm61qnwh152s2y1fi1bcavnv5fsrbppchynnm7ca6"
+        );
+        // the same tree built in memory hashes the same
+        assert_eq!(
+            sha256(&Node::Symlink("hello".to_string())).unwrap(),
+            sha256_path(&link, &[]).unwrap()
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+lazy_static! {
+    /// Entries of the user's .netrc, read once per run
+    static ref ENTRIES: Vec<Entry> = match netrc_path() {
+        Some(path) => parse(&fs::read_to_string(path).unwrap_or_default()),
+        None => vec![],
+    };
+}
+
+/// Credentials a .netrc holds for a host
+#[derive(PartialEq, Clone, Default)]
+pub struct Login {
+    pub login: Option<String>,
+    pub password: Option<String>,
+}
+
+impl fmt::Debug for Login {
+    // keeps passwords out of logs and error messages
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f
+            .debug_struct("Login")
+            .field("login", &self.login)
+            .finish_non_exhaustive();
+    }
+}
+
+#[derive(PartialEq, Debug)]
+struct Entry {
+    /// the host the entry is for, or `None` for the `default` entry
+    machine: Option<String>,
+    login: Login,
+}
+
+/// Where the .netrc is, which `NETRC` overrides as it does for curl
+fn netrc_path() -> Option<PathBuf> {
+    // the credentials of whoever runs the tests would leak into requests
+    // made to mock servers
+    if cfg!(test) {
+        return None;
+    }
+    if let Some(path) = env::var_os("NETRC") {
+        return Some(PathBuf::from(path));
+    }
+    return Some(PathBuf::from(env::var_os("HOME")?).join(".netrc"));
+}
+
+/// Splits a line into whitespace separated tokens, which may be quoted to
+/// hold spaces (i.e. `password "correct horse"`)
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut token = String::new();
+        if c == '"' {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => token.extend(chars.next()),
+                    c => token.push(c),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+        }
+        tokens.push(token);
+    }
+    return tokens;
+}
+
+/// Parses a .netrc, which looks like:
+///
+/// ```text
+/// machine ghe.example.com login luiz password ghp_...
+/// default login anonymous password hunter2
+/// ```
+///
+/// Macros (`macdef`) run until the next blank line and are skipped, as are
+/// tokens only ftp cares about.
+fn parse(content: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = vec![];
+    let mut in_macro = false;
+    let mut tokens = vec![];
+    for line in content.lines() {
+        if in_macro {
+            in_macro = !line.trim().is_empty();
+            continue;
+        }
+        let mut line_tokens = tokenize(line);
+        if let Some(position) = line_tokens.iter().position(|t| t == "macdef") {
+            line_tokens.truncate(position);
+            in_macro = true;
+        }
+        tokens.extend(line_tokens);
+    }
+    let mut tokens = tokens.into_iter();
+    while let Some(token) = tokens.next() {
+        match token.as_str() {
+            "machine" => entries.push(Entry {
+                machine: tokens.next(),
+                login: Login::default(),
+            }),
+            "default" => entries.push(Entry {
+                machine: None,
+                login: Login::default(),
+            }),
+            "login" | "password" | "account" => {
+                let value = tokens.next();
+                let entry = match entries.last_mut() {
+                    Some(entry) => entry,
+                    None => continue,
+                };
+                match token.as_str() {
+                    "login" => entry.login.login = value,
+                    "password" => entry.login.password = value,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    return entries;
+}
+
+/// The entry for `host`. The `default` entry is left out: it is meant for
+/// anonymous ftp logins, and would otherwise be sent to every host uptix
+/// talks to (and taken for a GitHub token).
+fn find<'a>(entries: &'a [Entry], host: &str) -> Option<&'a Login> {
+    return entries
+        .iter()
+        .find(|entry| entry.machine.as_deref() == Some(host))
+        .map(|entry| &entry.login);
+}
+
+/// The credentials the user's .netrc holds for `host`, which is matched
+/// without its port, as curl and nix do
+pub fn lookup(host: &str) -> Option<Login> {
+    let host = host.split(':').next().unwrap_or(host);
+    return find(&ENTRIES, host).cloned();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, parse, Login};
+
+    #[test]
+    fn it_parses_netrc() {
+        let entries = parse(
+            "machine ghe.example.com login luiz password ghp_secret\n\
+             macdef init\n\
+             machine evil.example.com password leaked\n\
+             \n\
+             machine artifacts.example.com\n\
+             \tlogin deploy\n\
+             \tpassword \"correct horse\"\n\
+             default login anonymous password guest\n",
+        );
+        assert_eq!(
+            find(&entries, "ghe.example.com"),
+            Some(&Login {
+                login: Some("luiz".to_string()),
+                password: Some("ghp_secret".to_string()),
+            }),
+        );
+        assert_eq!(
+            find(&entries, "artifacts.example.com").unwrap().password,
+            Some("correct horse".to_string()),
+        );
+        assert_eq!(find(&entries, "evil.example.com"), None);
+        assert_eq!(find(&entries, "api.github.com"), None);
+        assert_eq!(find(&parse("machine a password b"), "c"), None);
+    }
+
+    #[test]
+    fn it_redacts_passwords() {
+        let login = Login {
+            login: Some("luiz".to_string()),
+            password: Some("hunter2".to_string()),
+        };
+        assert!(!format!("{:?}", login).contains("hunter2"));
+    }
+}
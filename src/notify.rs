@@ -0,0 +1,210 @@
+use crate::config::{NotificationConfig, NotificationFormat};
+use crate::error::Error;
+use crate::http;
+use crate::secrets;
+use crate::util;
+use serde::Serialize;
+use serde_json::json;
+
+/// Discord rejects messages longer than this many characters
+const DISCORD_MAX_LENGTH: usize = 2000;
+
+/// What a run of `uptix update` or `uptix check` did, as posted to webhooks
+#[derive(Serialize, Default, PartialEq, Debug)]
+pub struct Summary {
+    /// the command which ran, i.e. `update`
+    pub command: String,
+    /// what each updated dependency moved between, i.e. `postgres 15.6 -> 15.7`
+    pub updated: Vec<String>,
+    /// dependencies which couldn't be updated, along with why
+    pub failures: Vec<String>,
+    /// problems `uptix check` found, such as drifted images
+    pub findings: Vec<String>,
+    /// what the run failed with, if it did
+    pub error: Option<String>,
+}
+
+impl Summary {
+    pub fn new(command: &str) -> Summary {
+        return Summary {
+            command: command.to_string(),
+            ..Default::default()
+        };
+    }
+
+    /// Whether the run neither changed nor found anything, and didn't fail
+    pub fn is_empty(&self) -> bool {
+        return self.updated.is_empty()
+            && self.failures.is_empty()
+            && self.findings.is_empty()
+            && self.error.is_none();
+    }
+
+    /// The summary as a chat message
+    pub fn text(&self) -> String {
+        let mut counts = vec![];
+        if !self.updated.is_empty() {
+            counts.push(format!("{} updated", self.updated.len()));
+        }
+        if !self.failures.is_empty() {
+            counts.push(format!("{} failed", self.failures.len()));
+        }
+        if !self.findings.is_empty() {
+            counts.push(format!("{} found", self.findings.len()));
+        }
+        let status = match (&self.error, counts.is_empty()) {
+            (Some(_), _) => "failed".to_string(),
+            (None, true) => "nothing to report".to_string(),
+            (None, false) => counts.join(", "),
+        };
+        let mut text = format!("uptix {}: {}\n", self.command, status);
+        let sections = [
+            ("Updated", &self.updated),
+            ("Failed", &self.failures),
+            ("Found", &self.findings),
+        ];
+        for (title, lines) in sections {
+            if lines.is_empty() {
+                continue;
+            }
+            text.push_str(&format!("{}:\n", title));
+            for line in lines {
+                text.push_str(&format!("- {}\n", line));
+            }
+        }
+        if let Some(error) = &self.error {
+            text.push_str(&format!("Error: {}\n", error));
+        }
+        return text;
+    }
+
+    /// The payload posted to a webhook of the given format
+    pub fn payload(&self, format: NotificationFormat) -> serde_json::Value {
+        return match format {
+            NotificationFormat::Json => json!(self),
+            NotificationFormat::Slack => json!({ "text": self.text() }),
+            NotificationFormat::Discord => {
+                let text: String = self.text().chars().take(DISCORD_MAX_LENGTH).collect();
+                json!({ "content": text })
+            }
+        };
+    }
+}
+
+/// Posts a summary to a webhook. Errors only mention the host, since the
+/// URL of a webhook is its secret.
+async fn post(url: &str, format: NotificationFormat, summary: &Summary) -> Result<(), Error> {
+    let host = reqwest::Url::parse(url)?
+        .host_str()
+        .unwrap_or_default()
+        .to_string();
+    let request = http::client()?
+        .post(url)
+        .header(reqwest::header::USER_AGENT, util::user_agent())
+        .json(&summary.payload(format));
+    let response =
+        http::send(request, http::transient)
+            .await
+            .map_err(|e| Error::NotificationFailed {
+                host: host.clone(),
+                reason: e.to_string(),
+            })?;
+    if !response.status().is_success() {
+        return Err(Error::NotificationFailed {
+            host,
+            reason: response.status().to_string(),
+        });
+    }
+    return Ok(());
+}
+
+/// Posts a summary to every configured webhook, returning the errors of the
+/// ones which failed rather than stopping at the first
+pub async fn send(notifications: &[NotificationConfig], summary: &Summary) -> Vec<Error> {
+    let mut errors = vec![];
+    for notification in notifications {
+        if notification.skip_empty && summary.is_empty() {
+            continue;
+        }
+        let url = notification
+            .url_env
+            .as_deref()
+            .and_then(secrets::var)
+            .or_else(|| notification.url.clone());
+        let url = match url {
+            Some(url) => url,
+            // i.e. a webhook whose URL is only set on CI
+            None => continue,
+        };
+        if let Err(error) = post(&url, notification.format, summary).await {
+            errors.push(error);
+        }
+    }
+    return errors;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{send, Summary};
+    use crate::config::{NotificationConfig, NotificationFormat};
+    use serde_json::json;
+
+    fn summary() -> Summary {
+        return Summary {
+            updated: vec!["postgres 15.6 -> 15.7".to_string()],
+            failures: vec!["grafana/grafana: not found".to_string()],
+            ..Summary::new("update")
+        };
+    }
+
+    #[test]
+    fn it_writes_messages() {
+        assert_eq!(
+            summary().text(),
+            "uptix update: 1 updated, 1 failed\n\
+             Updated:\n\
+             - postgres 15.6 -> 15.7\n\
+             Failed:\n\
+             - grafana/grafana: not found\n"
+        );
+        assert_eq!(
+            Summary::new("check").text(),
+            "uptix check: nothing to report\n"
+        );
+        assert_eq!(
+            summary().payload(NotificationFormat::Json)["updated"],
+            json!(["postgres 15.6 -> 15.7"]),
+        );
+        assert!(summary().payload(NotificationFormat::Discord)["content"].is_string());
+    }
+
+    #[tokio::test]
+    async fn it_posts_to_webhooks() {
+        let _slack_mock = mockito::mock("POST", "/slack")
+            .match_body(mockito::Matcher::PartialJson(
+                json!({ "text": summary().text() }),
+            ))
+            .with_status(200)
+            .create();
+        let _broken_mock = mockito::mock("POST", "/broken").with_status(404).create();
+        let notifications = vec![
+            NotificationConfig {
+                url: Some(format!("{}/slack", mockito::server_url())),
+                format: NotificationFormat::Slack,
+                ..Default::default()
+            },
+            NotificationConfig {
+                url: Some(format!("{}/broken", mockito::server_url())),
+                skip_empty: true,
+                ..Default::default()
+            },
+        ];
+        assert!(send(&notifications[1..], &Summary::new("update"))
+            .await
+            .is_empty());
+        let errors = send(&notifications, &summary()).await;
+        assert_eq!(errors.len(), 1);
+        assert!(!errors[0].to_string().contains("/broken"));
+        mockito::reset();
+    }
+}
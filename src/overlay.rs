@@ -0,0 +1,228 @@
+use crate::lock::{DockerLock, GitHubLock, LockData, LockFile};
+use crate::plan::short_name;
+use crate::util::nix_string;
+use std::collections::BTreeMap;
+
+/// Attribute of the package set the overlay puts the dependencies under
+pub const ATTRIBUTE: &str = "uptixPins";
+
+/// The reference an image key stands for, without the platform or the kind
+/// of entry (i.e. `$pullImage`) on it
+pub(crate) fn image_ref(key: &str) -> &str {
+    return key.split('$').next().unwrap_or(key);
+}
+
+/// The reference of an image with the tag a pattern resolved to, as
+/// uptix.nix builds it
+pub(crate) fn locked_image_ref(key: &str, lock: &DockerLock) -> String {
+    let reference = image_ref(key);
+    let tag = match &lock.tag {
+        Some(tag) => tag,
+        None => return reference.to_string(),
+    };
+    return match reference.rfind(':') {
+        Some(i) if !reference[i..].contains('/') => format!("{}:{}", &reference[..i], tag),
+        _ => format!("{}:{}", reference, tag),
+    };
+}
+
+/// A call to `fetchFromGitHub` for a locked repository
+fn fetch_from_github(lock: &GitHubLock, indent: &str) -> String {
+    let mut arguments = vec![
+        ("owner", nix_string(&lock.owner)),
+        ("repo", nix_string(&lock.repo)),
+        ("rev", nix_string(&lock.rev)),
+        ("sha256", nix_string(&lock.sha256)),
+    ];
+    // fetchFromGitHub fetches differently with any of these set, even to
+    // false, so only the ones which are true are passed
+    for (name, value) in [
+        ("fetchSubmodules", lock.fetchSubmodules),
+        ("deepClone", lock.deepClone),
+        ("leaveDotGit", lock.leaveDotGit),
+    ] {
+        if value {
+            arguments.push((name, "true".to_string()));
+        }
+    }
+    if let Some(base) = &lock.githubBase {
+        arguments.push(("githubBase", nix_string(base)));
+    }
+    return call("prev.fetchFromGitHub", &arguments, indent);
+}
+
+fn call(function: &str, arguments: &[(&str, String)], indent: &str) -> String {
+    let mut text = format!("{} {{\n", function);
+    for (name, value) in arguments {
+        text.push_str(&format!("{}  {} = {};\n", indent, name, value));
+    }
+    text.push_str(&format!("{}}}", indent));
+    return text;
+}
+
+/// The nix expression exposing a locked dependency, or `None` for entries
+/// this version of uptix doesn't understand
+fn expression(key: &str, lock: &LockData, indent: &str) -> Option<String> {
+    return Some(match lock {
+        LockData::Digest(digest) => nix_string(&format!("{}@{}", image_ref(key), digest)),
+        LockData::Docker(docker) => {
+            let reference = locked_image_ref(key, docker);
+            match (&docker.platforms, &docker.sha256) {
+                (Some(platforms), _) => {
+                    let mut text = "{\n".to_string();
+                    for (platform, digest) in platforms {
+                        text.push_str(&format!(
+                            "{}  {} = {};\n",
+                            indent,
+                            nix_string(platform),
+                            nix_string(&format!("{}@{}", reference, digest))
+                        ));
+                    }
+                    text.push_str(&format!("{}}}", indent));
+                    text
+                }
+                (None, Some(sha256)) => {
+                    let (name, tag) = match reference.rfind(':') {
+                        Some(i) if !reference[i..].contains('/') => {
+                            (&reference[..i], &reference[i + 1..])
+                        }
+                        _ => (reference.as_str(), "latest"),
+                    };
+                    call(
+                        "prev.dockerTools.pullImage",
+                        &[
+                            ("imageName", nix_string(name)),
+                            ("imageDigest", nix_string(&docker.digest)),
+                            ("sha256", nix_string(sha256)),
+                            ("finalImageTag", nix_string(tag)),
+                        ],
+                        indent,
+                    )
+                }
+                (None, None) => nix_string(&format!("{}@{}", reference, docker.digest)),
+            }
+        }
+        LockData::GitHub(github) => fetch_from_github(github, indent),
+        LockData::Release(release) => call(
+            "prev.fetchurl",
+            &[
+                ("name", nix_string(&release.asset.name)),
+                ("url", nix_string(&release.asset.url)),
+                ("sha256", nix_string(&release.asset.sha256)),
+            ],
+            indent,
+        ),
+        LockData::Action(action) => {
+            nix_string(&format!("{}/{}@{}", action.owner, action.repo, action.rev))
+        }
+        LockData::Unknown(_) => return None,
+    });
+}
+
+/// Names each entry by its dependency (i.e. `postgres` or `owner/repo`),
+/// falling back to the whole key for the entries whose names clash
+fn attribute_names(lock_file: &LockFile) -> BTreeMap<&str, &str> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for key in lock_file.keys() {
+        *counts.entry(short_name(key)).or_insert(0) += 1;
+    }
+    return lock_file
+        .keys()
+        .map(|key| {
+            let name = short_name(key);
+            return match counts[name] {
+                1 => (key.as_str(), name),
+                _ => (key.as_str(), key.as_str()),
+            };
+        })
+        .collect();
+}
+
+/// A nix overlay exposing every entry of a lock file under `ATTRIBUTE`:
+/// images as references pinned to their digest (or `dockerTools.pullImage`
+/// calls), repositories as `fetchFromGitHub` calls and release assets as
+/// `fetchurl` calls, so that the pins can be used without the uptix module
+pub fn generate(lock_file: &LockFile) -> String {
+    let names = attribute_names(lock_file);
+    let mut text = String::from(
+        "# Generated by `uptix generate overlay` from uptix.lock, do not edit\n\
+         final: prev: {\n",
+    );
+    text.push_str(&format!("  {} = {{\n", ATTRIBUTE));
+    for (key, lock) in lock_file {
+        if let Some(expression) = expression(key, lock, "    ") {
+            text.push_str(&format!(
+                "    {} = {};\n",
+                nix_string(names[key.as_str()]),
+                expression
+            ));
+        }
+    }
+    text.push_str("  };\n}\n");
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate;
+    use crate::lock::LockFile;
+    use serde_json::json;
+
+    #[test]
+    fn it_generates_overlays() {
+        let lock_file: LockFile = serde_json::from_value(json!({
+            "postgres:15": "sha256:abc",
+            "grafana/grafana:10.*": { "tag": "10.4.2", "digest": "sha256:def" },
+            "redis:7$pullImage": { "digest": "sha256:123", "sha256": "sha256-hash" },
+            "$GITHUB_BRANCH$:luizribeiro/uptix:main$f": {
+                "owner": "luizribeiro",
+                "repo": "uptix",
+                "rev": "0123456",
+                "sha256": "sha256-src",
+                "fetchSubmodules": true,
+                "deepClone": false,
+                "leaveDotGit": false,
+            },
+            "$GITHUB_BRANCH$:luizribeiro/uptix:dev$": {
+                "owner": "luizribeiro",
+                "repo": "uptix",
+                "rev": "789abcd",
+                "sha256": "sha256-dev",
+                "fetchSubmodules": false,
+                "deepClone": false,
+                "leaveDotGit": false,
+            },
+        }))
+        .unwrap();
+        assert_eq!(
+            generate(&lock_file),
+            r#"# Generated by `uptix generate overlay` from uptix.lock, do not edit
+final: prev: {
+  uptixPins = {
+    "$GITHUB_BRANCH$:luizribeiro/uptix:dev$" = prev.fetchFromGitHub {
+      owner = "luizribeiro";
+      repo = "uptix";
+      rev = "789abcd";
+      sha256 = "sha256-dev";
+    };
+    "$GITHUB_BRANCH$:luizribeiro/uptix:main$f" = prev.fetchFromGitHub {
+      owner = "luizribeiro";
+      repo = "uptix";
+      rev = "0123456";
+      sha256 = "sha256-src";
+      fetchSubmodules = true;
+    };
+    "grafana/grafana" = "grafana/grafana:10.4.2@sha256:def";
+    "postgres" = "postgres:15@sha256:abc";
+    "redis" = prev.dockerTools.pullImage {
+      imageName = "redis";
+      imageDigest = "sha256:123";
+      sha256 = "sha256-hash";
+      finalImageTag = "7";
+    };
+  };
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use crate::lock::{LockData, LockFile};
+use crate::semver::Bump;
+
+/// How uptix.lock changes, without having written anything yet
+#[non_exhaustive]
+pub struct Plan {
+    /// Entries of dependencies which weren't locked before
+    pub added: Vec<Change>,
+    /// Entries whose lock changed
+    pub changed: Vec<Change>,
+    /// Keys of the entries which stay as they were
+    pub unchanged: Vec<String>,
+    /// Entries of dependencies which aren't declared anymore
+    pub removed: Vec<Change>,
+    lock_file: LockFile,
+}
+
+/// An entry of uptix.lock which is added, changed or removed
+#[non_exhaustive]
+#[derive(PartialEq, Clone, Debug)]
+pub struct Change {
+    /// The dependency's key on uptix.lock (i.e. `postgres:15`)
+    pub key: String,
+    /// What the dependency was locked to, unless it is being added
+    pub previous: Option<LockData>,
+    /// What the dependency is locked to, unless it is being removed
+    pub current: Option<LockData>,
+}
+
+impl Change {
+    /// How big of an update the change is, when the entry is locked to
+    /// versions before and after it (i.e. the tags of releases)
+    pub fn bump(&self) -> Option<Bump> {
+        return self.current.as_ref()?.bump_from(self.previous.as_ref()?);
+    }
+
+    /// What changed about the entry, i.e. `postgres 15.6 -> 15.7`, falling
+    /// back to the commits it moved between when it isn't locked to versions
+    pub fn summary(&self) -> String {
+        let (previous, current) = match (&self.previous, &self.current) {
+            (Some(previous), Some(current)) => (previous, current),
+            _ => return self.name().to_string(),
+        };
+        if let (Some(from), Some(to)) = (previous.version_tag(), current.version_tag()) {
+            return format!("{} {} -> {}", self.name(), from, to);
+        }
+        if let (Some(from), Some(to)) = (previous.rev(), current.rev()) {
+            let short = |rev: &str| rev.chars().take(7).collect::<String>();
+            return format!("{} {} -> {}", self.name(), short(from), short(to));
+        }
+        return self.name().to_string();
+    }
+
+    /// A short name for the dependency, without the tag, branch or options
+    /// on its key (i.e. `postgres` for `postgres:15`, or `owner/repo` for a
+    /// GitHub branch)
+    pub fn name(&self) -> &str {
+        return short_name(&self.key);
+    }
+}
+
+/// The name of the dependency a lock key is for, as `Change::name` returns it
+pub(crate) fn short_name(key: &str) -> &str {
+    // keys of GitHub dependencies start with their kind, i.e. $GITHUB_BRANCH$:
+    let name = match key.strip_prefix('$') {
+        Some(rest) => rest.split_once("$:").map_or(rest, |(_, name)| name),
+        None => key,
+    };
+    let name = name.split(['$', '@']).next().unwrap_or(name);
+    // a tag, rather than the port of a registry
+    return match name.rfind(':') {
+        Some(i) if !name[i..].contains('/') => &name[..i],
+        _ => name,
+    };
+}
+
+impl Plan {
+    /// Compares the entries of a lock file with the ones replacing them
+    pub fn diff(previous: &LockFile, lock_file: LockFile) -> Plan {
+        let mut plan = Plan {
+            added: vec![],
+            changed: vec![],
+            unchanged: vec![],
+            removed: vec![],
+            lock_file: LockFile::new(),
+        };
+        for (key, lock) in &lock_file {
+            let change = Change {
+                key: key.clone(),
+                previous: previous.get(key).cloned(),
+                current: Some(lock.clone()),
+            };
+            match previous.get(key) {
+                None => plan.added.push(change),
+                Some(previous) if previous != lock => plan.changed.push(change),
+                Some(_) => plan.unchanged.push(key.clone()),
+            }
+        }
+        for (key, lock) in previous {
+            if !lock_file.contains_key(key) {
+                plan.removed.push(Change {
+                    key: key.clone(),
+                    previous: Some(lock.clone()),
+                    current: None,
+                });
+            }
+        }
+        plan.lock_file = lock_file;
+        return plan;
+    }
+
+    /// Whether uptix.lock stays as it was
+    pub fn is_empty(&self) -> bool {
+        return self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty();
+    }
+
+    /// The lock file as planned
+    pub fn lock_file(&self) -> &LockFile {
+        return &self.lock_file;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Change, Plan};
+    use crate::lock::{LockData, LockFile};
+    use crate::semver::Bump;
+    use serde_json::json;
+
+    #[test]
+    fn it_diffs_lock_files() {
+        let lock = |digest: &str| LockData::Digest(digest.to_string());
+        let previous = LockFile::from([
+            ("changed".to_string(), lock("sha256:old")),
+            ("removed".to_string(), lock("sha256:gone")),
+            ("unchanged".to_string(), lock("sha256:same")),
+        ]);
+        let plan = Plan::diff(
+            &previous,
+            LockFile::from([
+                ("added".to_string(), lock("sha256:new")),
+                ("changed".to_string(), lock("sha256:newer")),
+                ("unchanged".to_string(), lock("sha256:same")),
+            ]),
+        );
+        assert_eq!(
+            plan.added,
+            vec![Change {
+                key: "added".to_string(),
+                previous: None,
+                current: Some(lock("sha256:new")),
+            }]
+        );
+        assert_eq!(
+            plan.changed,
+            vec![Change {
+                key: "changed".to_string(),
+                previous: Some(lock("sha256:old")),
+                current: Some(lock("sha256:newer")),
+            }]
+        );
+        assert_eq!(plan.unchanged, vec!["unchanged"]);
+        assert_eq!(
+            plan.removed,
+            vec![Change {
+                key: "removed".to_string(),
+                previous: Some(lock("sha256:gone")),
+                current: None,
+            }]
+        );
+        assert!(!plan.is_empty());
+        assert_eq!(plan.lock_file().len(), 3);
+    }
+
+    #[test]
+    fn it_tells_bumps_apart() {
+        let image = |tag: &str| LockData::from(json!({ "tag": tag, "digest": "sha256:foo" }));
+        let change = |previous, current| Change {
+            key: "grafana/grafana:*".to_string(),
+            previous: Some(previous),
+            current: Some(current),
+        };
+        assert_eq!(
+            change(image("10.4.3"), image("11.0.0")).bump(),
+            Some(Bump::Major)
+        );
+        assert_eq!(
+            change(image("10.4.3"), image("10.4.4")).bump(),
+            Some(Bump::Patch)
+        );
+        assert_eq!(change(image("latest"), image("11.0.0")).bump(), None);
+        let digest = LockData::Digest("sha256:foo".to_string());
+        assert_eq!(change(digest.clone(), digest).bump(), None);
+    }
+
+    #[test]
+    fn it_names_changes() {
+        let name = |key: &str| {
+            Change {
+                key: key.to_string(),
+                previous: None,
+                current: None,
+            }
+            .name()
+            .to_string()
+        };
+        assert_eq!(name("postgres:15"), "postgres");
+        assert_eq!(
+            name("localhost:5000/foo/bar:1.0$linux/arm64"),
+            "localhost:5000/foo/bar"
+        );
+        assert_eq!(
+            name("$GITHUB_BRANCH$:torvalds/linux:master$"),
+            "torvalds/linux"
+        );
+        assert_eq!(
+            name("$GITHUB_RELEASE$:luizribeiro/uptix$@~1.4"),
+            "luizribeiro/uptix"
+        );
+        assert_eq!(
+            name("$GITHUB_ACTION$:actions/checkout@v4"),
+            "actions/checkout"
+        );
+    }
+}
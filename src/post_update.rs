@@ -0,0 +1,104 @@
+use crate::error::Error;
+use crate::git;
+use crate::lock::LOCK_FILE_PATH;
+use crate::plan::{Change, Plan};
+use tokio::process::Command;
+
+/// Environment variables describing an update to its hooks: the keys which
+/// were updated (added or changed) and removed, one per line, a summary of
+/// each update such as `postgres 15.6 -> 15.7`, and the message `--commit`
+/// would commit uptix.lock with
+pub fn env(plan: &Plan) -> Vec<(&'static str, String)> {
+    let keys = |changes: &mut dyn Iterator<Item = &Change>| {
+        return changes
+            .map(|change| change.key.clone())
+            .collect::<Vec<_>>()
+            .join("\n");
+    };
+    let summary = plan
+        .added
+        .iter()
+        .chain(&plan.changed)
+        .map(Change::summary)
+        .collect::<Vec<_>>()
+        .join("\n");
+    return vec![
+        ("UPTIX_LOCK_FILE", LOCK_FILE_PATH.to_string()),
+        (
+            "UPTIX_UPDATED",
+            keys(&mut plan.added.iter().chain(&plan.changed)),
+        ),
+        ("UPTIX_REMOVED", keys(&mut plan.removed.iter())),
+        ("UPTIX_SUMMARY", summary),
+        ("UPTIX_COMMIT_MESSAGE", git::commit_message(plan)),
+    ];
+}
+
+/// Runs each hook with `sh -c`, in order, stopping at the first one which
+/// fails. Their output goes straight to the terminal, since hooks such as
+/// `nixos-rebuild build` take a while and report progress as they go.
+pub async fn run(hooks: &[String], plan: &Plan) -> Result<(), Error> {
+    let env = env(plan);
+    for hook in hooks {
+        tracing::info!("running post-update hook {}", hook);
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(hook)
+            .envs(env.clone())
+            .status()
+            .await?;
+        if !status.success() {
+            return Err(Error::PostUpdateHookFailed {
+                hook: hook.clone(),
+                status: status.to_string(),
+            });
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use crate::error::Error;
+    use crate::lock::LockFile;
+    use crate::plan::Plan;
+    use serde_json::json;
+    use std::fs;
+
+    #[tokio::test]
+    async fn it_runs_hooks() {
+        let previous: LockFile = serde_json::from_value(json!({
+            "postgres:15": "sha256:old",
+            "redis:7": "sha256:gone",
+        }))
+        .unwrap();
+        let current: LockFile = serde_json::from_value(json!({
+            "postgres:15": "sha256:new",
+            "grafana/grafana:10": "sha256:added",
+        }))
+        .unwrap();
+        let plan = Plan::diff(&previous, current);
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("output");
+        let hooks = vec![
+            format!(
+                "printf '%s|%s' \"$UPTIX_UPDATED\" \"$UPTIX_REMOVED\" > {}",
+                output.display()
+            ),
+            "exit 3".to_string(),
+            format!("touch {}", dir.path().join("unreachable").display()),
+        ];
+        let error = run(&hooks, &plan).await.unwrap_err();
+        assert!(matches!(
+            error,
+            Error::PostUpdateHookFailed { ref hook, .. } if hook == "exit 3"
+        ));
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "grafana/grafana:10\npostgres:15|redis:7"
+        );
+        assert!(!dir.path().join("unreachable").exists());
+        assert!(run(&hooks[..1], &plan).await.is_ok());
+    }
+}
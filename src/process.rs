@@ -0,0 +1,81 @@
+use std::io;
+use std::process::{Output, Stdio};
+use tokio::process::Command;
+
+/// Kills a process group when dropped, unless it finished first
+struct ProcessGroup(Option<u32>);
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if let Some(id) = self.0 {
+            unsafe {
+                libc::killpg(id as libc::pid_t, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+/// Runs a command to completion, like `Command::output`. The command gets a
+/// process group of its own, so that dropping the future (i.e. on a timeout
+/// or Ctrl-C) kills every process it started too, such as the `git`
+/// processes `nix-prefetch-git` runs, rather than leaving them behind.
+pub async fn output(command: &mut Command) -> io::Result<Output> {
+    let child = command
+        .process_group(0)
+        .kill_on_drop(true)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut group = ProcessGroup(child.id());
+    let output = child.wait_with_output().await;
+    group.0 = None;
+    return output;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::output;
+    use std::time::Duration;
+    use tokio::process::Command;
+
+    /// Whether a process is alive, rather than gone or a zombie
+    fn is_running(pid: &str) -> bool {
+        return match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(stat) => !stat.contains(") Z "),
+            Err(_) => false,
+        };
+    }
+
+    #[tokio::test]
+    async fn it_runs_commands() {
+        let result = output(Command::new("sh").args(["-c", "echo out; echo err >&2"]))
+            .await
+            .unwrap();
+        assert!(result.status.success());
+        assert_eq!(result.stdout, b"out\n");
+        assert_eq!(result.stderr, b"err\n");
+    }
+
+    #[tokio::test]
+    async fn it_kills_children_when_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!("sleep 60 & echo $! > {}; wait", pid_file.display());
+        let mut command = Command::new("sh");
+        command.args(["-c", &script]);
+        let result = tokio::time::timeout(Duration::from_secs(1), output(&mut command)).await;
+        assert!(result.is_err());
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let pid = pid.trim();
+        // signals are delivered asynchronously, which can take a moment
+        for _ in 0..50 {
+            if !is_running(pid) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("sleep {} was left running", pid);
+    }
+}
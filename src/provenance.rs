@@ -0,0 +1,269 @@
+use crate::error::Error;
+use crate::lock::{GitHubLock, LockData};
+use crate::overlay::{image_ref, locked_image_ref};
+use crate::plan::Plan;
+use crate::report;
+use crate::util;
+use base64::Engine;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{Id, PKey};
+use openssl::sign::Signer;
+use serde_json::{json, Map, Value};
+
+/// Payload type of the DSSE envelope around the statement
+pub const PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+
+const BUILD_TYPE: &str = "https://github.com/luizribeiro/uptix/update/v1";
+const BUILDER_ID: &str = "https://github.com/luizribeiro/uptix";
+
+/// A resource descriptor of the SLSA provenance format
+fn descriptor(name: &str, uri: String, digest: Map<String, Value>) -> Value {
+    return json!({ "name": name, "uri": uri, "digest": digest });
+}
+
+fn digest(entries: &[(&str, &str)]) -> Map<String, Value> {
+    return entries
+        .iter()
+        .map(|(algorithm, value)| (algorithm.to_string(), Value::from(*value)))
+        .collect();
+}
+
+/// Whether a revision is a full commit, rather than a tag
+fn is_commit(rev: &str) -> bool {
+    return rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit());
+}
+
+fn repository(name: &str, lock: &GitHubLock) -> Value {
+    let host = lock.githubBase.as_deref().unwrap_or("github.com");
+    let uri = format!(
+        "git+https://{}/{}/{}@{}",
+        host, lock.owner, lock.repo, lock.rev
+    );
+    let mut entries = vec![("nix-sha256", lock.sha256.as_str())];
+    if is_commit(&lock.rev) {
+        entries.push(("gitCommit", &lock.rev));
+    }
+    return descriptor(name, uri, digest(&entries));
+}
+
+/// What an entry was resolved to, and where from, as resource descriptors
+fn resolved(key: &str, lock: &LockData) -> Vec<Value> {
+    return match lock {
+        LockData::Digest(image_digest) => {
+            let (algorithm, hex) = image_digest
+                .split_once(':')
+                .unwrap_or(("sha256", image_digest));
+            let uri = format!("docker://{}@{}", image_ref(key), image_digest);
+            vec![descriptor(key, uri, digest(&[(algorithm, hex)]))]
+        }
+        LockData::Docker(docker) => {
+            let (algorithm, hex) = docker
+                .digest
+                .split_once(':')
+                .unwrap_or(("sha256", &docker.digest));
+            let uri = format!(
+                "docker://{}@{}",
+                locked_image_ref(key, docker),
+                docker.digest
+            );
+            let mut entries = vec![(algorithm, hex)];
+            if let Some(sha256) = &docker.sha256 {
+                entries.push(("nix-sha256", sha256));
+            }
+            vec![descriptor(key, uri, digest(&entries))]
+        }
+        LockData::GitHub(lock) => vec![repository(key, lock)],
+        LockData::Release(release) => vec![
+            repository(key, &release.lock),
+            descriptor(
+                &release.asset.name,
+                release.asset.url.clone(),
+                digest(&[("nix-sha256", &release.asset.sha256)]),
+            ),
+        ],
+        LockData::Action(action) => {
+            let uri = format!(
+                "git+https://github.com/{}/{}@{}",
+                action.owner, action.repo, action.reference
+            );
+            vec![descriptor(key, uri, digest(&[("gitCommit", &action.rev)]))]
+        }
+        LockData::Unknown(_) => vec![],
+    };
+}
+
+/// An in-toto statement with SLSA provenance for the uptix.lock an update
+/// wrote (whose contents are `lock_file`): what each added or changed entry
+/// was resolved to and where from, by which version of uptix and when
+pub fn statement(plan: &Plan, lock_file: &str, started_at: i64, finished_at: i64) -> Value {
+    let changes: Vec<_> = plan.added.iter().chain(&plan.changed).collect();
+    let mut dependencies = vec![];
+    for change in &changes {
+        if let Some(current) = &change.current {
+            dependencies.extend(resolved(&change.key, current));
+        }
+    }
+    let sha256 = util::hex(&openssl::sha::sha256(lock_file.as_bytes()));
+    return json!({
+        "_type": "https://in-toto.io/Statement/v1",
+        "subject": [{ "name": crate::lock::LOCK_FILE_PATH, "digest": { "sha256": sha256 } }],
+        "predicateType": "https://slsa.dev/provenance/v1",
+        "predicate": {
+            "buildDefinition": {
+                "buildType": BUILD_TYPE,
+                "externalParameters": {
+                    "updated": changes.iter().map(|change| &change.key).collect::<Vec<_>>(),
+                    "removed": plan.removed.iter().map(|change| &change.key).collect::<Vec<_>>(),
+                },
+                "resolvedDependencies": dependencies,
+            },
+            "runDetails": {
+                "builder": {
+                    "id": BUILDER_ID,
+                    "version": { "uptix": env!("CARGO_PKG_VERSION") },
+                },
+                "metadata": {
+                    "startedOn": report::format_timestamp(started_at),
+                    "finishedOn": report::format_timestamp(finished_at),
+                },
+            },
+        },
+    });
+}
+
+/// The pre-authentication encoding DSSE signs, rather than the payload
+/// alone, so that the payload type is signed too
+pub fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut encoded = format!(
+        "DSSEv1 {} {} {} ",
+        payload_type.len(),
+        payload_type,
+        payload.len()
+    )
+    .into_bytes();
+    encoded.extend(payload);
+    return encoded;
+}
+
+/// Wraps a statement in a DSSE envelope, signed with a private key in PEM
+/// (RSA, ECDSA or Ed25519) when one is given. The key id is the SHA-256 of
+/// the public key, in DER.
+pub fn envelope(statement: &Value, private_key: Option<&str>) -> Result<Value, Error> {
+    let payload = statement.to_string();
+    let encode = |data: &[u8]| base64::engine::general_purpose::STANDARD.encode(data);
+    let mut signatures = vec![];
+    if let Some(private_key) = private_key {
+        let sign = || -> Result<(String, Vec<u8>), openssl::error::ErrorStack> {
+            let key = PKey::private_key_from_pem(private_key.as_bytes())?;
+            let keyid = util::hex(&openssl::sha::sha256(&key.public_key_to_der()?));
+            let message = pae(PAYLOAD_TYPE, payload.as_bytes());
+            // Ed25519 hashes what it signs itself
+            let signature = match key.id() {
+                Id::ED25519 => Signer::new_without_digest(&key)?.sign_oneshot_to_vec(&message)?,
+                _ => {
+                    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+                    signer.update(&message)?;
+                    signer.sign_to_vec()?
+                }
+            };
+            return Ok((keyid, signature));
+        };
+        let (keyid, signature) = sign().map_err(|error| Error::ProvenanceSigningFailed {
+            reason: error.to_string(),
+        })?;
+        signatures.push(json!({ "keyid": keyid, "sig": encode(&signature) }));
+    }
+    return Ok(json!({
+        "payloadType": PAYLOAD_TYPE,
+        "payload": encode(payload.as_bytes()),
+        "signatures": signatures,
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{envelope, pae, statement, PAYLOAD_TYPE};
+    use crate::lock::LockFile;
+    use crate::plan::Plan;
+    use base64::Engine;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use openssl::sign::Verifier;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn it_attests_updates() {
+        let previous: LockFile = serde_json::from_value(json!({
+            "postgres:15": "sha256:old",
+            "redis:7": "sha256:same",
+        }))
+        .unwrap();
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let current: LockFile = serde_json::from_value(json!({
+            "postgres:15": "sha256:new",
+            "redis:7": "sha256:same",
+            "$GITHUB_BRANCH$:luizribeiro/uptix:main$": {
+                "owner": "luizribeiro",
+                "repo": "uptix",
+                "rev": commit,
+                "sha256": "sha256-src",
+                "fetchSubmodules": false,
+                "deepClone": false,
+                "leaveDotGit": false,
+            },
+        }))
+        .unwrap();
+        let plan = Plan::diff(&previous, current);
+        let statement = statement(&plan, "{}", 1709294400, 1709294460);
+        assert_eq!(
+            statement["subject"][0]["digest"]["sha256"],
+            json!("44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a")
+        );
+        let predicate = &statement["predicate"];
+        assert_eq!(
+            predicate["buildDefinition"]["resolvedDependencies"],
+            json!([
+                {
+                    "name": "$GITHUB_BRANCH$:luizribeiro/uptix:main$",
+                    "uri": format!("git+https://github.com/luizribeiro/uptix@{}", commit),
+                    "digest": { "nix-sha256": "sha256-src", "gitCommit": commit },
+                },
+                {
+                    "name": "postgres:15",
+                    "uri": "docker://postgres:15@sha256:new",
+                    "digest": { "sha256": "new" },
+                },
+            ])
+        );
+        assert_eq!(
+            predicate["runDetails"]["metadata"]["startedOn"],
+            json!("2024-03-01T12:00:00Z")
+        );
+
+        let key = PKey::from_ec_key(
+            EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap(),
+        )
+        .unwrap();
+        let pem = String::from_utf8(key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        let signed = envelope(&statement, Some(&pem)).unwrap();
+        let decode = |value: &Value| {
+            base64::engine::general_purpose::STANDARD
+                .decode(value.as_str().unwrap())
+                .unwrap()
+        };
+        let payload = decode(&signed["payload"]);
+        assert_eq!(
+            serde_json::from_slice::<Value>(&payload).unwrap(),
+            statement
+        );
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &key).unwrap();
+        verifier.update(&pae(PAYLOAD_TYPE, &payload)).unwrap();
+        assert!(verifier
+            .verify(&decode(&signed["signatures"][0]["sig"]))
+            .unwrap());
+        assert_eq!(envelope(&statement, None).unwrap()["signatures"], json!([]));
+        assert!(envelope(&statement, Some("not a key")).is_err());
+    }
+}
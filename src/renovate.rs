@@ -0,0 +1,197 @@
+use crate::deps::Dependency;
+use crate::lock::{LockData, LockFile};
+use crate::plan::{short_name, Change, Plan};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Name of the manager the dependencies are reported under, as Renovate
+/// names its own (i.e. `nix` or `dockerfile`)
+pub const MANAGER: &str = "uptix";
+
+/// An update available for a dependency
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Update {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_digest: Option<String>,
+    /// `major`, `minor` or `patch` between versions, or `digest` otherwise
+    pub update_type: String,
+}
+
+/// A dependency along with the update found for it, if any
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Dep {
+    pub dep_name: String,
+    pub datasource: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_digest: Option<String>,
+    pub updates: Vec<Update>,
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PackageFile {
+    pub package_file: String,
+    pub deps: Vec<Dep>,
+}
+
+/// Updates in the shape of the `packageFiles` of Renovate's report, so that
+/// dashboards built on it can show uptix's findings too
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub package_files: BTreeMap<String, Vec<PackageFile>>,
+}
+
+/// The Renovate datasource closest to how a dependency is looked up
+fn datasource(dependency: &Dependency) -> &'static str {
+    return match dependency.type_label() {
+        "docker" => "docker",
+        "github_release" => "github-releases",
+        "github_action" => "github-tags",
+        _ => "git-refs",
+    };
+}
+
+/// The digest (or commit) an entry is locked to
+fn digest(lock: &LockData) -> Option<String> {
+    return match lock {
+        LockData::Digest(digest) => Some(digest.clone()),
+        LockData::Docker(docker) => Some(docker.digest.clone()),
+        lock => lock.rev().map(str::to_string),
+    };
+}
+
+/// The update a change makes, if it changes an existing entry: entries
+/// which are added weren't locked to anything to update from
+fn update(change: &Change) -> Option<Update> {
+    let previous = change.previous.as_ref()?;
+    let current = change.current.as_ref()?;
+    let update_type = current
+        .bump_from(previous)
+        .map_or("digest".to_string(), |bump| bump.to_string());
+    return Some(Update {
+        new_version: current.version_tag().map(str::to_string),
+        new_digest: digest(current),
+        update_type,
+    });
+}
+
+impl Report {
+    /// Reports every declared dependency along with what `plan` would
+    /// change its entry to. Dependencies are grouped by the file they are
+    /// declared on. Those which `plan` adds are reported as what they are
+    /// locked to, with no updates.
+    pub fn new(dependencies: &[Dependency], previous: &LockFile, plan: &Plan) -> Report {
+        let changes: BTreeMap<&str, &Change> = plan
+            .added
+            .iter()
+            .chain(&plan.changed)
+            .map(|change| (change.key.as_str(), change))
+            .collect();
+        let mut files: BTreeMap<String, Vec<Dep>> = BTreeMap::new();
+        for dependency in dependencies {
+            let key = dependency.key();
+            let change = changes.get(key.as_str());
+            let current = match change {
+                Some(change) => change.previous.as_ref().or(change.current.as_ref()),
+                None => previous.get(&key),
+            };
+            files
+                .entry(dependency.location().file_path.clone())
+                .or_default()
+                .push(Dep {
+                    dep_name: short_name(&key).to_string(),
+                    datasource: datasource(dependency).to_string(),
+                    current_version: current.and_then(LockData::version_tag).map(str::to_string),
+                    current_digest: current.and_then(digest),
+                    updates: change
+                        .and_then(|change| update(change))
+                        .into_iter()
+                        .collect(),
+                });
+        }
+        let package_files = files
+            .into_iter()
+            .map(|(package_file, deps)| PackageFile { package_file, deps })
+            .collect();
+        return Report {
+            package_files: BTreeMap::from([(MANAGER.to_string(), package_files)]),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Report;
+    use crate::deps::test_util;
+    use crate::lock::LockFile;
+    use crate::plan::Plan;
+    use serde_json::json;
+
+    #[test]
+    fn it_reports_updates() {
+        let dependencies = test_util::deps(
+            r#"{
+            db = uptix.dockerImage "library/postgres:15.*";
+            cache = uptix.dockerImage "library/redis:7";
+            proxy = uptix.dockerImage "library/nginx:1";
+        }"#,
+        )
+        .unwrap();
+        let previous: LockFile = serde_json::from_value(json!({
+            "library/postgres:15.*": { "tag": "15.6", "digest": "sha256:old" },
+            "library/redis:7": "sha256:same",
+        }))
+        .unwrap();
+        let current: LockFile = serde_json::from_value(json!({
+            "library/postgres:15.*": { "tag": "15.7", "digest": "sha256:new" },
+            "library/redis:7": "sha256:same",
+            "library/nginx:1": { "tag": "1.27", "digest": "sha256:added" },
+        }))
+        .unwrap();
+        let plan = Plan::diff(&previous, current);
+        let report = Report::new(&dependencies, &previous, &plan);
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "packageFiles": {
+                    "uptix": [{
+                        "packageFile": "./test.nix",
+                        "deps": [
+                            {
+                                "depName": "library/postgres",
+                                "datasource": "docker",
+                                "currentVersion": "15.6",
+                                "currentDigest": "sha256:old",
+                                "updates": [{
+                                    "newVersion": "15.7",
+                                    "newDigest": "sha256:new",
+                                    "updateType": "minor",
+                                }],
+                            },
+                            {
+                                "depName": "library/redis",
+                                "datasource": "docker",
+                                "currentDigest": "sha256:same",
+                                "updates": [],
+                            },
+                            {
+                                "depName": "library/nginx",
+                                "datasource": "docker",
+                                "currentVersion": "1.27",
+                                "currentDigest": "sha256:added",
+                                "updates": [],
+                            },
+                        ],
+                    }],
+                },
+            }),
+        );
+    }
+}
@@ -0,0 +1,411 @@
+use crate::deps::Dependency;
+use crate::lock::{LockData, LockFile};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+lazy_static! {
+    static ref TIMESTAMP: Regex = Regex::new(
+        r"^(\d{4})-(\d{2})-(\d{2})[Tt ](\d{2}):(\d{2}):(\d{2})(?:\.\d+)?(?:[Zz]|([+-])(\d{2}):(\d{2}))$"
+    )
+    .unwrap();
+}
+
+/// How stale a single dependency is
+#[derive(Serialize, PartialEq, Debug)]
+pub struct DependencyReport {
+    pub key: String,
+    /// what kind of dependency it is, i.e. `docker`
+    pub kind: String,
+    /// what uptix.lock has it locked to
+    pub locked: Option<String>,
+    /// when it was locked to that, in seconds since the epoch
+    pub locked_at: Option<i64>,
+    pub age_days: Option<i64>,
+    /// the newest version available
+    pub latest: Option<String>,
+    /// when the newest version was made, in seconds since the epoch
+    pub latest_at: Option<i64>,
+    /// for how long a newer version than the locked one has been out
+    pub days_behind_latest: Option<i64>,
+    /// why the newest version couldn't be looked up, if it couldn't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Reads the value of a Prometheus gauge off the report of a dependency
+type Gauge = fn(&DependencyReport) -> Option<i64>;
+
+/// How stale every dependency is, as printed by `uptix report`
+#[derive(Serialize, PartialEq, Debug)]
+pub struct Report {
+    /// when the report was made, in seconds since the epoch
+    pub generated_at: i64,
+    /// how many dependencies there are of each kind
+    pub counts: BTreeMap<String, usize>,
+    pub dependencies: Vec<DependencyReport>,
+}
+
+/// Seconds since the epoch
+pub fn now() -> i64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+}
+
+/// How many days a month (counted from 1) of the proleptic Gregorian
+/// calendar has
+fn days_in_month(year: i64, month: i64) -> i64 {
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    return match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+}
+
+/// Seconds since the epoch of an RFC 3339 timestamp, i.e.
+/// `2024-03-01T12:00:00Z`, as GitHub and OCI annotations write them
+pub fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    let captures = TIMESTAMP.captures(timestamp.trim())?;
+    let field = |i: usize| -> i64 {
+        return captures
+            .get(i)
+            .map_or(0, |m| m.as_str().parse().unwrap_or(0));
+    };
+    let (year, month, day) = (field(1), field(2), field(3));
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return None;
+    }
+    // days since 1970-01-01 of a proleptic Gregorian date, from Howard
+    // Hinnant's days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    let offset = match captures.get(7).map(|m| m.as_str()) {
+        Some("-") => -(field(8) * 3600 + field(9) * 60),
+        Some(_) => field(8) * 3600 + field(9) * 60,
+        None => 0,
+    };
+    return Some(days * SECONDS_PER_DAY + field(4) * 3600 + field(5) * 60 + field(6) - offset);
+}
+
+/// An RFC 3339 timestamp in UTC for seconds since the epoch, i.e.
+/// `2024-03-01T12:00:00Z`, the other way around from `parse_timestamp`
+pub fn format_timestamp(seconds: i64) -> String {
+    let days = seconds.div_euclid(SECONDS_PER_DAY);
+    let time = seconds.rem_euclid(SECONDS_PER_DAY);
+    // Howard Hinnant's civil_from_days
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    return format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    );
+}
+
+/// When each entry of `lock_file` was locked to what it is locked to now,
+/// given the history of uptix.lock (as `git::history` reads it): the oldest
+/// commit since which it hasn't changed. Entries which aren't committed yet
+/// are left out.
+pub fn locked_since(
+    lock_file: &LockFile,
+    history: &[(i64, Option<Vec<u8>>)],
+) -> BTreeMap<String, i64> {
+    let mut since = BTreeMap::new();
+    let mut pending: BTreeSet<&String> = lock_file.keys().collect();
+    for (time, content) in history {
+        let committed: LockFile = content
+            .as_deref()
+            .and_then(|content| serde_json::from_slice(content).ok())
+            .unwrap_or_default();
+        pending.retain(|key| committed.get(*key) == lock_file.get(*key));
+        if pending.is_empty() {
+            break;
+        }
+        for key in &pending {
+            since.insert(key.to_string(), *time);
+        }
+    }
+    return since;
+}
+
+/// Whether an entry is locked to the given version, which may be a tag or
+/// an abbreviated commit
+fn is_locked_to(locked: &LockData, version: &str) -> bool {
+    return locked.rev().is_some_and(|rev| rev.starts_with(version))
+        || locked.version_tag() == Some(version);
+}
+
+impl DependencyReport {
+    /// Reports on a dependency from what it is locked to and when, and from
+    /// its newest version (or why it couldn't be looked up)
+    pub fn new(
+        key: String,
+        kind: &str,
+        locked: Option<&LockData>,
+        locked_at: Option<i64>,
+        latest: Result<(Option<String>, Option<i64>), String>,
+        now: i64,
+    ) -> DependencyReport {
+        let age_days = locked_at.map(|at| (now - at).max(0) / SECONDS_PER_DAY);
+        let (latest, latest_at, error) = match latest {
+            Ok((version, at)) => (version, at, None),
+            Err(error) => (None, None, Some(error)),
+        };
+        let up_to_date = match (locked, &latest) {
+            (Some(locked), Some(version)) => is_locked_to(locked, version),
+            _ => false,
+        };
+        let days_behind_latest = match (locked_at, latest_at) {
+            _ if up_to_date => Some(0),
+            (Some(locked_at), Some(latest_at)) if latest_at > locked_at => {
+                Some((now - latest_at).max(0) / SECONDS_PER_DAY)
+            }
+            (Some(_), Some(_)) => Some(0),
+            _ => None,
+        };
+        return DependencyReport {
+            key,
+            kind: kind.to_string(),
+            locked: locked.map(|lock| lock.to_string()),
+            locked_at,
+            age_days,
+            latest,
+            latest_at,
+            days_behind_latest,
+            error,
+        };
+    }
+
+    /// Looks up the newest version of a dependency to report on it.
+    /// Entries which aren't committed yet count as locked `now`.
+    pub async fn lookup(
+        dependency: &Dependency,
+        lock_file: &LockFile,
+        since: &BTreeMap<String, i64>,
+        now: i64,
+    ) -> DependencyReport {
+        let key = dependency.key();
+        let locked = lock_file.get(&key);
+        let locked_at = since.get(&key).copied().or_else(|| locked.map(|_| now));
+        let latest = dependency
+            .metadata()
+            .await
+            .map(|metadata| {
+                let at = metadata.timestamp.as_deref().and_then(parse_timestamp);
+                (metadata.resolved_version, at)
+            })
+            .map_err(|error| error.to_string());
+        return DependencyReport::new(key, dependency.type_label(), locked, locked_at, latest, now);
+    }
+}
+
+/// Escapes a Prometheus label value
+fn label(value: &str) -> String {
+    return value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+}
+
+impl Report {
+    pub fn new(generated_at: i64, dependencies: Vec<DependencyReport>) -> Report {
+        let mut counts = BTreeMap::new();
+        for dependency in &dependencies {
+            *counts.entry(dependency.kind.clone()).or_insert(0) += 1;
+        }
+        return Report {
+            generated_at,
+            counts,
+            dependencies,
+        };
+    }
+
+    /// The report in the text format of Prometheus, i.e. for the textfile
+    /// collector of node_exporter
+    pub fn prometheus(&self) -> String {
+        let mut text = String::new();
+        let gauges: [(&str, &str, Gauge); 2] = [
+            (
+                "uptix_dependency_age_days",
+                "Days since the dependency was locked to its current version",
+                |d| d.age_days,
+            ),
+            (
+                "uptix_dependency_days_behind_latest",
+                "Days since a newer version than the locked one came out",
+                |d| d.days_behind_latest,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            text.push_str(&format!(
+                "# HELP {} {}\n# TYPE {} gauge\n",
+                name, help, name
+            ));
+            for dependency in &self.dependencies {
+                if let Some(value) = value(dependency) {
+                    text.push_str(&format!(
+                        "{}{{key=\"{}\",kind=\"{}\"}} {}\n",
+                        name,
+                        label(&dependency.key),
+                        label(&dependency.kind),
+                        value
+                    ));
+                }
+            }
+        }
+        text.push_str("# HELP uptix_dependencies Dependencies of each kind\n");
+        text.push_str("# TYPE uptix_dependencies gauge\n");
+        for (kind, count) in &self.counts {
+            text.push_str(&format!(
+                "uptix_dependencies{{kind=\"{}\"}} {}\n",
+                label(kind),
+                count
+            ));
+        }
+        text.push_str("# HELP uptix_report_timestamp_seconds When the report was made\n");
+        text.push_str("# TYPE uptix_report_timestamp_seconds gauge\n");
+        text.push_str(&format!(
+            "uptix_report_timestamp_seconds {}\n",
+            self.generated_at
+        ));
+        return text;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_timestamp, locked_since, parse_timestamp, DependencyReport, Report};
+    use crate::lock::{LockData, LockFile};
+    use serde_json::json;
+
+    const DAY: i64 = 86400;
+
+    #[test]
+    fn it_parses_timestamps() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2024-03-01T12:00:00Z"), Some(1709294400));
+        assert_eq!(
+            parse_timestamp("2024-03-01T14:00:00.123+02:00"),
+            Some(1709294400)
+        );
+        assert_eq!(parse_timestamp("2024-13-01T00:00:00Z"), None);
+        assert_eq!(parse_timestamp("2024-02-31T00:00:00Z"), None);
+        assert_eq!(parse_timestamp("2023-02-29T00:00:00Z"), None);
+        assert_eq!(parse_timestamp("1900-02-29T00:00:00Z"), None);
+        assert_eq!(parse_timestamp("2024-04-31T00:00:00Z"), None);
+        assert!(parse_timestamp("2000-02-29T00:00:00Z").is_some());
+        assert_eq!(parse_timestamp("yesterday"), None);
+        for timestamp in [
+            "1970-01-01T00:00:00Z",
+            "2024-02-29T23:59:59Z",
+            "2100-03-01T01:02:03Z",
+        ] {
+            assert_eq!(
+                format_timestamp(parse_timestamp(timestamp).unwrap()),
+                timestamp
+            );
+        }
+    }
+
+    #[test]
+    fn it_finds_when_entries_were_locked() {
+        let lock_file: LockFile = serde_json::from_value(json!({
+            "postgres:15": "sha256:new",
+            "grafana:10": "sha256:same",
+            "redis:7": "sha256:uncommitted",
+        }))
+        .unwrap();
+        let history = vec![
+            (
+                300,
+                Some(br#"{ "postgres:15": "sha256:new", "grafana:10": "sha256:same" }"#.to_vec()),
+            ),
+            (
+                200,
+                Some(br#"{ "postgres:15": "sha256:old", "grafana:10": "sha256:same" }"#.to_vec()),
+            ),
+            (100, None),
+        ];
+        let since = locked_since(&lock_file, &history);
+        assert_eq!(since.get("postgres:15"), Some(&300));
+        assert_eq!(since.get("grafana:10"), Some(&200));
+        assert_eq!(since.get("redis:7"), None);
+    }
+
+    #[test]
+    fn it_reports_staleness() {
+        let locked = LockData::from(json!({
+            "owner": "luizribeiro",
+            "repo": "uptix",
+            "rev": "v1.0.0",
+            "sha256": "",
+            "fetchSubmodules": false,
+            "deepClone": false,
+            "leaveDotGit": false,
+        }));
+        let now = 100 * DAY;
+        let behind = DependencyReport::new(
+            "uptix".to_string(),
+            "github_release",
+            Some(&locked),
+            Some(10 * DAY),
+            Ok((Some("v1.1.0".to_string()), Some(40 * DAY))),
+            now,
+        );
+        assert_eq!(behind.age_days, Some(90));
+        assert_eq!(behind.days_behind_latest, Some(60));
+        let current = DependencyReport::new(
+            "uptix".to_string(),
+            "github_release",
+            Some(&locked),
+            Some(10 * DAY),
+            Ok((Some("v1.0.0".to_string()), Some(5 * DAY))),
+            now,
+        );
+        assert_eq!(current.days_behind_latest, Some(0));
+        let failed = DependencyReport::new(
+            "uptix".to_string(),
+            "github_release",
+            Some(&locked),
+            None,
+            Err("not found".to_string()),
+            now,
+        );
+        assert_eq!(failed.days_behind_latest, None);
+
+        let report = Report::new(now, vec![behind, current, failed]);
+        assert_eq!(report.counts.get("github_release"), Some(&3));
+        let text = report.prometheus();
+        assert!(text.contains(
+            "uptix_dependency_days_behind_latest{key=\"uptix\",kind=\"github_release\"} 60\n"
+        ));
+        assert!(text.contains("uptix_dependencies{kind=\"github_release\"} 3\n"));
+        assert!(text.contains(&format!("uptix_report_timestamp_seconds {}\n", now)));
+    }
+}
@@ -0,0 +1,277 @@
+use crate::config::{CredentialsConfig, CredentialsFormat};
+use crate::error::Error;
+use crate::process;
+use std::collections::BTreeMap;
+use std::env;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use tokio::process::Command;
+
+/// A credentials file, and the directory it is relative to
+type Source = (CredentialsConfig, PathBuf);
+
+/// Secrets decrypted from a credentials file, keyed by variable name, along
+/// with the file they were decrypted from
+type Decrypted = (Source, BTreeMap<String, String>);
+
+lazy_static! {
+    /// The credentials file to decrypt
+    static ref CREDENTIALS: RwLock<Option<Source>> = RwLock::new(None);
+    /// Secrets decrypted from the credentials file, once a secret has been
+    /// looked up
+    static ref SECRETS: Mutex<Option<Decrypted>> = Mutex::new(None);
+}
+
+fn decryption_error(path: &Path, reason: String) -> Error {
+    return Error::CredentialsDecryptionFailed {
+        path: path.display().to_string(),
+        reason,
+    };
+}
+
+/// How a credentials file is encrypted, when uptix.toml doesn't say: age
+/// files end in `.age`, and anything else is assumed to be sops'
+fn format_of(config: &CredentialsConfig, path: &Path) -> CredentialsFormat {
+    if let Some(format) = config.format {
+        return format;
+    }
+    return match path.extension().and_then(|e| e.to_str()) {
+        Some("age") => CredentialsFormat::Age,
+        _ => CredentialsFormat::Sops,
+    };
+}
+
+/// The age identity to decrypt with, which is the one sops uses unless
+/// uptix.toml names another
+fn age_identity(config: &CredentialsConfig, root: &Path) -> Option<PathBuf> {
+    if let Some(identity) = &config.identity {
+        return Some(root.join(identity));
+    }
+    if let Some(path) = env::var_os("SOPS_AGE_KEY_FILE") {
+        return Some(PathBuf::from(path));
+    }
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(env::var_os("HOME")?).join(".config")))?;
+    return Some(config_home.join("sops/age/keys.txt"));
+}
+
+/// Arguments to decrypt a credentials file with, for either sops or age
+fn command(config: &CredentialsConfig, root: &Path, path: &Path) -> (&'static str, Vec<String>) {
+    let format = format_of(config, path);
+    let path = path.to_string_lossy().to_string();
+    return match format {
+        CredentialsFormat::Sops => (
+            "sops",
+            vec![
+                "--decrypt".to_string(),
+                "--output-type".to_string(),
+                "dotenv".to_string(),
+                path,
+            ],
+        ),
+        CredentialsFormat::Age => {
+            let mut args = vec!["--decrypt".to_string()];
+            if let Some(identity) = age_identity(config, root) {
+                args.extend([
+                    "--identity".to_string(),
+                    identity.to_string_lossy().to_string(),
+                ]);
+            }
+            args.push(path);
+            ("age", args)
+        }
+    };
+}
+
+/// Parses decrypted credentials, which are `NAME=value` lines as sops
+/// writes them for dotenv files. Blank lines and comments are skipped.
+fn parse(content: &str) -> BTreeMap<String, String> {
+    return content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (name, value) = line.split_once('=')?;
+            return Some((name.trim().to_string(), value.to_string()));
+        })
+        .collect();
+}
+
+/// Runs sops (or age) on a thread and runtime of its own, since secrets are
+/// looked up from synchronous code which may itself be running on a runtime
+fn run(program: &str, args: &[String]) -> std::io::Result<std::process::Output> {
+    let mut command = Command::new(program);
+    command.args(args);
+    return std::thread::scope(|scope| {
+        return scope
+            .spawn(|| {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?;
+                return runtime.block_on(process::output(&mut command));
+            })
+            .join()
+            .unwrap();
+    });
+}
+
+fn decrypt(config: &CredentialsConfig, root: &Path) -> Result<BTreeMap<String, String>, Error> {
+    let path = match &config.file {
+        Some(file) => root.join(file),
+        None => return Ok(BTreeMap::new()),
+    };
+    let (program, args) = command(config, root, &path);
+    let output = run(program, &args).map_err(|e| match e.kind() {
+        ErrorKind::NotFound => decryption_error(
+            &path,
+            format!("{} is needed to decrypt it, but it isn't on PATH", program),
+        ),
+        _ => Error::IOError(e),
+    })?;
+    if !output.status.success() {
+        return Err(decryption_error(
+            &path,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    let content = String::from_utf8(output.stdout)
+        .map_err(|_| decryption_error(&path, "it isn't valid UTF-8".to_string()))?;
+    return Ok(parse(&content));
+}
+
+/// Points at the credentials file uptix.toml names, if any, so that the
+/// secrets on it are used for the variables `*_env` settings name. Relative
+/// paths are relative to `root`. The file is only decrypted once a secret
+/// the environment doesn't set is looked up, and nothing is written to
+/// disk: the decrypted file only ever lives in memory.
+pub fn configure(config: &CredentialsConfig, root: &Path) {
+    *CREDENTIALS.write().unwrap() = Some((config.clone(), root.to_path_buf()));
+}
+
+/// The secrets decrypted from `source`, which are decrypted again when the
+/// ones kept came from another file (i.e. of another project)
+fn decrypted(
+    kept: &mut Option<Decrypted>,
+    source: Source,
+    decrypt: impl FnOnce(&Source) -> BTreeMap<String, String>,
+) -> &BTreeMap<String, String> {
+    if kept.as_ref().map(|(from, _)| from) != Some(&source) {
+        let secrets = decrypt(&source);
+        *kept = Some((source, secrets));
+    }
+    return &kept.as_ref().unwrap().1;
+}
+
+/// The value of a secret from the environment or else from the credentials
+/// file, which only fills in what the environment doesn't set. Each file is
+/// decrypted once, unless another one is configured in between, and a file
+/// which can't be decrypted only warns, as if it were empty.
+pub fn var(name: &str) -> Option<String> {
+    if let Ok(value) = env::var(name) {
+        return Some(value);
+    }
+    let source = CREDENTIALS.read().unwrap().clone()?;
+    source.0.file.as_ref()?;
+    let mut kept = SECRETS.lock().unwrap();
+    let secrets = decrypted(&mut kept, source, |(config, root)| {
+        return decrypt(config, root).unwrap_or_else(|error| {
+            tracing::warn!("{}", error);
+            return BTreeMap::new();
+        });
+    });
+    return secrets.get(name).cloned();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{command, decrypted, parse, run};
+    use crate::config::{CredentialsConfig, CredentialsFormat};
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn it_parses_credentials() {
+        let secrets =
+            parse("# registry tokens\nGHCR_TOKEN=ghp_abc\n\nexport GITHUB_TOKEN=ghp_a=b\nbogus\n");
+        assert_eq!(secrets.len(), 2);
+        assert_eq!(secrets["GHCR_TOKEN"], "ghp_abc");
+        assert_eq!(secrets["GITHUB_TOKEN"], "ghp_a=b");
+    }
+
+    #[test]
+    fn it_decrypts_with_sops_or_age() {
+        let config = CredentialsConfig::default();
+        let (program, args) = command(&config, Path::new("."), Path::new("./secrets.env"));
+        assert_eq!(program, "sops");
+        assert_eq!(
+            args,
+            vec!["--decrypt", "--output-type", "dotenv", "./secrets.env"]
+        );
+
+        let config = CredentialsConfig {
+            identity: Some("keys.txt".to_string()),
+            ..Default::default()
+        };
+        let (program, args) = command(&config, Path::new("ci"), Path::new("ci/secrets.env.age"));
+        assert_eq!(program, "age");
+        assert_eq!(
+            args,
+            vec![
+                "--decrypt",
+                "--identity",
+                "ci/keys.txt",
+                "ci/secrets.env.age"
+            ]
+        );
+
+        let config = CredentialsConfig {
+            format: Some(CredentialsFormat::Age),
+            identity: Some("/keys.txt".to_string()),
+            ..Default::default()
+        };
+        let (program, _) = command(&config, Path::new("."), Path::new("secrets.enc"));
+        assert_eq!(program, "age");
+    }
+
+    #[tokio::test]
+    async fn it_decrypts_from_async_code() {
+        let script = "echo GHCR_TOKEN=ghp_abc".to_string();
+        let output = run("sh", &["-c".to_string(), script]).unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            parse(&String::from_utf8(output.stdout).unwrap())["GHCR_TOKEN"],
+            "ghp_abc"
+        );
+    }
+
+    #[test]
+    fn it_decrypts_each_file_once() {
+        let file = |name: &str| {
+            return (
+                CredentialsConfig {
+                    file: Some(name.to_string()),
+                    ..Default::default()
+                },
+                PathBuf::from("."),
+            );
+        };
+        let secrets = |value: &str| BTreeMap::from([("TOKEN".to_string(), value.to_string())]);
+        let mut kept = None;
+        let mut runs = 0;
+        for _ in 0..2 {
+            let found = decrypted(&mut kept, file("a.env"), |_| {
+                runs += 1;
+                return secrets("a");
+            });
+            assert_eq!(found["TOKEN"], "a");
+        }
+        assert_eq!(runs, 1);
+
+        // i.e. an updater for another project
+        let found = decrypted(&mut kept, file("b.env"), |_| secrets("b"));
+        assert_eq!(found["TOKEN"], "b");
+    }
+}
@@ -0,0 +1,233 @@
+use crate::error::Error;
+use regex::Regex;
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Deref;
+
+lazy_static! {
+    /// A `v` in front of a version in a requirement, i.e. in `~v1.4`
+    static ref V_PREFIX_RE: Regex = Regex::new(r"(^|[\s,<>=~^])v(\d)").unwrap();
+}
+
+/// A version number as found on tags, i.e. `v1.4.2` or `2.0.0-rc.1`, parsed
+/// by the `semver` crate. Tags are more lenient than semver: they may start
+/// with `v` and leave components out (as in `v1.4`), which are taken to be
+/// zero.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Version(::semver::Version);
+
+/// Fills in the components a version leaves out, so that semver parses it
+fn complete(text: &str) -> Option<String> {
+    let text = text.strip_prefix('v').unwrap_or(text);
+    // pre-releases and build metadata start at the first `-` or `+`
+    let end = text.find(['-', '+']).unwrap_or(text.len());
+    let (numbers, rest) = text.split_at(end);
+    let mut parts: Vec<_> = numbers.split('.').collect();
+    if parts.len() > 3 || parts.iter().any(|p| p.is_empty()) {
+        return None;
+    }
+    parts.resize(3, "0");
+    return Some(format!("{}{}", parts.join("."), rest));
+}
+
+impl Version {
+    pub fn parse(text: &str) -> Option<Version> {
+        let version = ::semver::Version::parse(&complete(text)?).ok()?;
+        return Some(Version(version));
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        return !self.0.pre.is_empty();
+    }
+
+    /// How big of an update going from `previous` to this version is, if
+    /// it is newer at all. Below 1.0.0, minor versions are the major ones
+    /// (and patch versions below 0.1.0), as caret requirements have it.
+    /// Build metadata alone doesn't make for an update.
+    pub fn bump_from(&self, previous: &Version) -> Option<Bump> {
+        let (v, p) = (&self.0, &previous.0);
+        if v.cmp_precedence(p) != Ordering::Greater {
+            return None;
+        }
+        if v.major != p.major {
+            return Some(Bump::Major);
+        }
+        if v.minor != p.minor {
+            return Some(match v.major {
+                0 => Bump::Major,
+                _ => Bump::Minor,
+            });
+        }
+        if v.patch != p.patch && v.major == 0 && v.minor == 0 {
+            return Some(Bump::Major);
+        }
+        return Some(Bump::Patch);
+    }
+}
+
+impl Deref for Version {
+    type Target = ::semver::Version;
+
+    fn deref(&self) -> &::semver::Version {
+        return &self.0;
+    }
+}
+
+/// Writes versions canonically, i.e. `1.4.0` for `v1.4`
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return self.0.fmt(f);
+    }
+}
+
+/// Orders versions the way semver does, except that build metadata (which
+/// semver says to ignore) only breaks ties
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return self
+            .0
+            .cmp_precedence(&other.0)
+            .then_with(|| self.0.cmp(&other.0));
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+/// Which component of a version an update changes
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Bump {
+    Major,
+    Minor,
+    Patch,
+}
+
+impl fmt::Display for Bump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str(match self {
+            Bump::Major => "major",
+            Bump::Minor => "minor",
+            Bump::Patch => "patch",
+        });
+    }
+}
+
+/// A version range such as `~1.4` or `>=2, <3`, where every comma separated
+/// requirement must hold, as `semver` (and cargo) read them. Versions in it
+/// may start with `v`, as tags do.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Constraint(::semver::VersionReq);
+
+impl Constraint {
+    pub fn parse(text: &str) -> Result<Constraint, Error> {
+        let requirement = V_PREFIX_RE.replace_all(text.trim(), "$1$2");
+        return match ::semver::VersionReq::parse(&requirement) {
+            Ok(requirement) => Ok(Constraint(requirement)),
+            Err(_) => Err(Error::InvalidConstraint {
+                constraint: text.to_string(),
+            }),
+        };
+    }
+
+    /// Whether the version satisfies the constraint. Pre-releases only do
+    /// when the constraint asks for one of the same version (i.e.
+    /// `>=2.1.0-alpha` for `2.1.0-beta`).
+    pub fn matches(&self, version: &Version) -> bool {
+        return self.0.matches(&version.0);
+    }
+
+    /// Like `matches`, but lets pre-releases through when their release
+    /// would satisfy the constraint
+    pub fn matches_prerelease(&self, version: &Version) -> bool {
+        let release = ::semver::Version::new(version.major, version.minor, version.patch);
+        return self.0.matches(&release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bump, Constraint, Version};
+
+    fn matches(constraint: &str, version: &str) -> bool {
+        return Constraint::parse(constraint)
+            .unwrap()
+            .matches(&Version::parse(version).unwrap());
+    }
+
+    #[test]
+    fn it_parses_versions() {
+        let version = Version::parse("v1.4.2").unwrap();
+        assert_eq!((version.major, version.minor, version.patch), (1, 4, 2));
+        assert!(!version.is_prerelease());
+        assert_eq!(Version::parse("2.0").unwrap().patch, 0);
+        assert_eq!(Version::parse("2.0.0-rc.1").unwrap().pre.as_str(), "rc.1");
+        let version = Version::parse("1.0.0-rc.1+build.5").unwrap();
+        assert_eq!(version.pre.as_str(), "rc.1");
+        assert_eq!(version.build.as_str(), "build.5");
+        let version = Version::parse("v1.0.0+build").unwrap();
+        assert!(!version.is_prerelease());
+        assert_eq!(version.build.as_str(), "build");
+        assert_eq!(
+            Version::parse("1.2+build").unwrap().to_string(),
+            "1.2.0+build"
+        );
+        assert_eq!(Version::parse("nightly"), None);
+        assert_eq!(Version::parse("1.2.3.4"), None);
+        assert_eq!(Version::parse("1..2"), None);
+        assert_eq!(Version::parse("v"), None);
+        assert!(Version::parse("1.10.0") > Version::parse("1.9.9"));
+        assert!(Version::parse("2.0.0") > Version::parse("2.0.0-rc.1"));
+        assert!(Version::parse("2.0.0-rc.10") > Version::parse("2.0.0-rc.2"));
+        assert!(Version::parse("2.0.0-rc.1") > Version::parse("2.0.0-rc"));
+        assert!(Version::parse("2.0.0-beta") > Version::parse("2.0.0-1"));
+        assert!(Version::parse("1.0.1") > Version::parse("1.0.0+build"));
+        assert_eq!(Version::parse("v1.4").unwrap().to_string(), "1.4.0");
+        assert_eq!(
+            Version::parse("2.0-rc.1").unwrap().to_string(),
+            "2.0.0-rc.1"
+        );
+    }
+
+    #[test]
+    fn it_tells_bumps_apart() {
+        let bump = |from: &str, to: &str| {
+            Version::parse(to)
+                .unwrap()
+                .bump_from(&Version::parse(from).unwrap())
+        };
+        assert_eq!(bump("1.4.2", "2.0.0"), Some(Bump::Major));
+        assert_eq!(bump("v1.4.2", "v1.5"), Some(Bump::Minor));
+        assert_eq!(bump("1.4.2", "1.4.3"), Some(Bump::Patch));
+        assert_eq!(bump("1.4.3-rc.1", "1.4.3"), Some(Bump::Patch));
+        assert_eq!(bump("1.4.3", "1.4.2"), None);
+        assert_eq!(bump("0.2.1", "0.3.0"), Some(Bump::Major));
+        assert_eq!(bump("0.2.1", "0.2.2"), Some(Bump::Patch));
+        assert_eq!(bump("0.0.1", "0.0.2"), Some(Bump::Major));
+        assert_eq!(bump("1.0.0", "1.0.0+build"), None);
+    }
+
+    #[test]
+    fn it_matches_constraints() {
+        assert!(matches("~1.4", "1.4.9"));
+        assert!(!matches("~1.4", "1.5.0"));
+        assert!(matches("~1", "1.9.0"));
+        assert!(matches(">=2, <3", "v2.7.1"));
+        assert!(!matches(">=2, <3", "3.0.0"));
+        assert!(matches("^1.2", "1.9.0"));
+        assert!(!matches("^0.2", "0.3.0"));
+        assert!(matches("1.2", "1.3.0"));
+        assert!(matches("1.*", "1.3.0"));
+        assert!(!matches("1.*", "2.0.0"));
+        assert!(matches("=1.2.3", "1.2.3"));
+        assert!(!matches(">=2", "2.1.0-beta"));
+        assert!(matches(">=2.1.0-alpha", "2.1.0-beta"));
+        assert!(matches(">=v2, <v3", "2.0.0+build"));
+        assert!(Constraint::parse("~v1.4")
+            .unwrap()
+            .matches_prerelease(&Version::parse("1.4.1-rc.1").unwrap()));
+        assert!(Constraint::parse(">=two").is_err());
+    }
+}
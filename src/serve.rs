@@ -0,0 +1,692 @@
+use crate::config::Config;
+use crate::deps::{self, Dependency};
+use crate::error::Error;
+use crate::git;
+use crate::http;
+use crate::lock;
+use crate::plan::{Change, Plan};
+use crate::report::{self, DependencyReport, Report};
+use crate::updater::Updater;
+use futures::stream::{self, FuturesUnordered, Stream, StreamExt};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::Mutex;
+
+/// Socket `uptix serve` listens on, relative to the project
+pub const DEFAULT_SOCKET_PATH: &str = ".uptix.sock";
+
+/// Requests larger than this are refused rather than read
+pub(crate) const MAX_BODY_SIZE: usize = 1024 * 1024;
+/// Request lines and headers longer than this (altogether) are refused
+const MAX_HEADER_SIZE: u64 = 16 * 1024;
+/// How long a client has to send its request before it is hung up on
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to pause after failing to accept a connection (i.e. when out of
+/// file descriptors), rather than failing again right away
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// error codes defined by JSON-RPC 2.0
+pub(crate) const PARSE_ERROR: i64 = -32700;
+pub(crate) const INVALID_REQUEST: i64 = -32600;
+pub(crate) const METHOD_NOT_FOUND: i64 = -32601;
+pub(crate) const INVALID_PARAMS: i64 = -32602;
+/// Code of the errors uptix itself fails with, whose diagnostic code (i.e.
+/// `uptix::error::not_found`) goes in their data
+const UPTIX_ERROR: i64 = -32000;
+
+/// Answers JSON-RPC requests about a project, scanning its nix files again
+/// on each request but keeping what was looked up (i.e. hashes, tokens and
+/// connections) warm between them
+pub struct Server {
+    root: PathBuf,
+    config: Config,
+    // requests are handled one at a time, so that an update never races
+    // with another one writing uptix.lock
+    requests: Mutex<()>,
+    // only held to read or write it, so that scrapes don't wait on plans
+    state: std::sync::Mutex<State>,
+    // built on the first request and shared by the rest, so that their
+    // connections are reused
+    client: std::sync::Mutex<Option<reqwest::Client>>,
+}
+
+/// What the server found out while answering requests, for `/metrics`
+#[derive(Default, Clone)]
+struct State {
+    /// how many dependencies of each kind had a newer version as of the
+    /// last plan (or update)
+    outdated: BTreeMap<String, usize>,
+    planned_at: Option<i64>,
+    updated_at: Option<i64>,
+}
+
+/// A request read off a connection
+enum Request {
+    /// a JSON-RPC request, POSTed to any path
+    Rpc(Vec<u8>),
+    /// `GET /metrics`
+    Metrics,
+    /// anything else, along with the status to refuse it with
+    Refused(&'static str),
+}
+
+pub(crate) struct RpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl From<Error> for RpcError {
+    fn from(error: Error) -> RpcError {
+        return RpcError {
+            code: UPTIX_ERROR,
+            message: error.to_string(),
+            data: Some(json!({ "code": error.diagnostic_code() })),
+        };
+    }
+}
+
+pub(crate) fn invalid_params(message: &str) -> RpcError {
+    return RpcError {
+        code: INVALID_PARAMS,
+        message: message.to_string(),
+        data: None,
+    };
+}
+
+fn change(change: &Change) -> Value {
+    return json!({
+        "key": change.key,
+        "summary": change.summary(),
+        "previous": change.previous,
+        "current": change.current,
+    });
+}
+
+/// A plan as the `plan` and `update` methods return it
+fn plan(plan: &Plan) -> Value {
+    return json!({
+        "added": plan.added.iter().map(change).collect::<Vec<_>>(),
+        "changed": plan.changed.iter().map(change).collect::<Vec<_>>(),
+        "unchanged": plan.unchanged,
+        "removed": plan.removed.iter().map(change).collect::<Vec<_>>(),
+    });
+}
+
+fn dependency(dependency: &Dependency) -> Value {
+    return json!({
+        "key": dependency.key(),
+        "kind": dependency.type_label(),
+        "location": dependency.location().to_string(),
+    });
+}
+
+impl Server {
+    pub fn new(root: impl AsRef<Path>, config: Config) -> Server {
+        return Server {
+            root: root.as_ref().to_path_buf(),
+            config,
+            requests: Mutex::new(()),
+            state: std::sync::Mutex::new(State::default()),
+            client: std::sync::Mutex::new(None),
+        };
+    }
+
+    /// The client requests are sent through, with the network settings of
+    /// the server's configuration
+    fn client(&self) -> Result<reqwest::Client, Error> {
+        let mut client = self.client.lock().unwrap();
+        if client.is_none() {
+            *client = Some(http::build_client(&self.config.network)?);
+        }
+        return Ok(client.clone().unwrap());
+    }
+
+    /// Plans an update, remembering how many dependencies of each kind are
+    /// outdated
+    async fn plan(&self, updater: &Updater) -> Result<Plan, Error> {
+        let planned = updater.plan().await?;
+        let outdated: Vec<&String> = planned
+            .added
+            .iter()
+            .chain(&planned.changed)
+            .map(|change| &change.key)
+            .collect();
+        let mut counts = BTreeMap::new();
+        for dependency in deps::unique(updater.declared()) {
+            let count = counts
+                .entry(dependency.type_label().to_string())
+                .or_insert(0);
+            if outdated.contains(&&dependency.key()) {
+                *count += 1;
+            }
+        }
+        let mut state = self.state.lock().unwrap();
+        state.outdated = counts;
+        state.planned_at = Some(report::now());
+        return Ok(planned);
+    }
+
+    async fn call(&self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        let updater = Updater::with_config(&self.root, &self.config)?.with_client(http::client()?);
+        return match method {
+            "list" => Ok(Value::from(
+                deps::unique(updater.declared())
+                    .into_iter()
+                    .map(dependency)
+                    .collect::<Vec<_>>(),
+            )),
+            "show" => {
+                let key = params["key"]
+                    .as_str()
+                    .ok_or_else(|| invalid_params("show needs the key of a dependency"))?;
+                let found = updater.declared().iter().find(|d| d.key() == key);
+                let found =
+                    found.ok_or_else(|| invalid_params(&format!("{} isn't declared", key)))?;
+                let lock_file = lock::read(&self.root.join(lock::LOCK_FILE_PATH))?;
+                let mut value = dependency(found);
+                value["locked"] = json!(lock_file.get(key));
+                value["metadata"] = json!(found.metadata().await?);
+                Ok(value)
+            }
+            "plan" => Ok(plan(&self.plan(&updater).await?)),
+            "update" => {
+                let planned = self.plan(&updater).await?;
+                updater.apply(&planned)?;
+                let mut state = self.state.lock().unwrap();
+                state.updated_at = Some(report::now());
+                // what was outdated was just updated
+                state.outdated.values_mut().for_each(|count| *count = 0);
+                Ok(plan(&planned))
+            }
+            _ => Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("{} isn't a method of uptix serve", method),
+                data: None,
+            }),
+        };
+    }
+
+    /// Answers a JSON-RPC 2.0 request, i.e.
+    /// `{"jsonrpc": "2.0", "id": 1, "method": "show", "params": {"key": "postgres:15"}}`
+    pub async fn handle(&self, body: &[u8]) -> Value {
+        let request: Value = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(error) => {
+                return response(
+                    Value::Null,
+                    Err(RpcError {
+                        code: PARSE_ERROR,
+                        message: error.to_string(),
+                        data: None,
+                    }),
+                )
+            }
+        };
+        let id = request["id"].clone();
+        let method = match request["method"].as_str() {
+            Some(method) => method,
+            None => {
+                return response(
+                    id,
+                    Err(RpcError {
+                        code: INVALID_REQUEST,
+                        message: "requests need a method".to_string(),
+                        data: None,
+                    }),
+                )
+            }
+        };
+        let _request = self.requests.lock().await;
+        let result = match self.client() {
+            Ok(client) => {
+                let scope = http::Scope {
+                    network: self.config.network.clone(),
+                    client: Some(client),
+                };
+                http::scoped(scope, self.call(method, &request["params"])).await
+            }
+            Err(error) => Err(error.into()),
+        };
+        return response(id, result);
+    }
+
+    /// Gauges on how stale the pins are, in the text format of Prometheus:
+    /// how long each dependency has been locked (from the git history of
+    /// uptix.lock), how many of each kind were outdated as of the last plan,
+    /// and when uptix.lock was last updated. Nothing is looked up upstream,
+    /// nor does it wait for requests being answered, so it is cheap to
+    /// scrape.
+    pub async fn metrics(&self) -> Result<String, Error> {
+        let state = self.state.lock().unwrap().clone();
+        let updater = Updater::with_config(&self.root, &self.config)?;
+        let lock_path = self.root.join(lock::LOCK_FILE_PATH);
+        let lock_file = lock::read(&lock_path)?;
+        let since = match git::history(&self.root, &lock_path) {
+            Ok(history) => report::locked_since(&lock_file, &history),
+            Err(_) => BTreeMap::new(),
+        };
+        let now = report::now();
+        let dependencies = deps::unique(updater.declared())
+            .into_iter()
+            .map(|dependency| {
+                let key = dependency.key();
+                let locked_at = since.get(&key).copied();
+                return DependencyReport::new(
+                    key.clone(),
+                    dependency.type_label(),
+                    lock_file.get(&key),
+                    locked_at,
+                    Ok((None, None)),
+                    now,
+                );
+            })
+            .collect();
+        let mut text = Report::new(now, dependencies).prometheus();
+        text.push_str("# HELP uptix_outdated_dependencies Dependencies of each kind with a newer version, as of the last plan\n");
+        text.push_str("# TYPE uptix_outdated_dependencies gauge\n");
+        for (kind, count) in &state.outdated {
+            text.push_str(&format!(
+                "uptix_outdated_dependencies{{kind=\"{}\"}} {}\n",
+                kind, count
+            ));
+        }
+        // before any update through the server, the last time uptix.lock
+        // was written stands for the last update
+        let written_at = std::fs::metadata(&lock_path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|elapsed| elapsed.as_secs() as i64);
+        for (name, help, value) in [
+            (
+                "uptix_last_plan_timestamp_seconds",
+                "When updates were last looked for",
+                state.planned_at,
+            ),
+            (
+                "uptix_last_update_timestamp_seconds",
+                "When uptix.lock was last updated",
+                state.updated_at.or(written_at),
+            ),
+        ] {
+            if let Some(value) = value {
+                text.push_str(&format!(
+                    "# HELP {} {}\n# TYPE {} gauge\n{} {}\n",
+                    name, help, name, name, value
+                ));
+            }
+        }
+        return Ok(text);
+    }
+
+    /// Serves requests on a unix socket until the future is dropped. Each
+    /// connection takes a single request: JSON-RPC POSTed as HTTP/1.1, or
+    /// `GET /metrics`.
+    pub async fn listen(&self, socket: &Path) -> Result<(), Error> {
+        // a socket left behind by a server which didn't shut down cleanly
+        // would fail binding
+        if socket.exists() {
+            std::fs::remove_file(socket)?;
+        }
+        let listener = UnixListener::bind(socket)?;
+        let incoming = stream::unfold(listener, |listener| async move {
+            let accepted = listener.accept().await.map(|(stream, _)| stream);
+            return Some((accepted, listener));
+        });
+        return self.serve(incoming, true).await;
+    }
+
+    /// Serves `GET /metrics` on a TCP address (i.e. `127.0.0.1:9184`) until
+    /// the future is dropped, for Prometheus to scrape. JSON-RPC requests
+    /// are refused there, since anyone able to connect could update.
+    pub async fn listen_metrics(&self, address: &str) -> Result<(), Error> {
+        let listener = TcpListener::bind(address).await?;
+        return self.serve_metrics(listener).await;
+    }
+
+    /// Like [`Server::listen_metrics`], on a listener which is already bound
+    pub async fn serve_metrics(&self, listener: TcpListener) -> Result<(), Error> {
+        let incoming = stream::unfold(listener, |listener| async move {
+            let accepted = listener.accept().await.map(|(stream, _)| stream);
+            return Some((accepted, listener));
+        });
+        return self.serve(incoming, false).await;
+    }
+
+    /// Answers the connections coming in all at once, so that a slow client
+    /// (or a long update) doesn't keep the others waiting. Connections which
+    /// can't be accepted only warn, so that the daemon outlives them.
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        incoming: impl Stream<Item = io::Result<S>>,
+        rpc: bool,
+    ) -> Result<(), Error> {
+        let incoming = incoming.then(|accepted| async {
+            if accepted.is_err() {
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+            }
+            return accepted;
+        });
+        let mut incoming = pin!(incoming);
+        let mut connections = FuturesUnordered::new();
+        loop {
+            tokio::select! {
+                accepted = incoming.next() => match accepted {
+                    Some(Ok(stream)) => connections.push(self.serve_connection(stream, rpc)),
+                    Some(Err(error)) => tracing::warn!("failed to accept a connection: {}", error),
+                    None => return Ok(()),
+                },
+                Some(result) = connections.next() => {
+                    if let Err(error) = result {
+                        tracing::warn!("failed to answer a request: {}", error);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn serve_connection(
+        &self,
+        stream: impl AsyncRead + AsyncWrite + Unpin,
+        rpc: bool,
+    ) -> Result<(), Error> {
+        let mut reader = BufReader::new(stream);
+        let json = "application/json";
+        let request = tokio::time::timeout(READ_TIMEOUT, read_request(&mut reader))
+            .await
+            .unwrap_or(Ok(Request::Refused("408 Request Timeout")))?;
+        let (status, content_type, body) = match request {
+            Request::Rpc(body) if rpc => ("200 OK", json, self.handle(&body).await.to_string()),
+            Request::Rpc(_) => ("404 Not Found", json, String::new()),
+            Request::Metrics => match self.metrics().await {
+                Ok(text) => ("200 OK", "text/plain; version=0.0.4", text),
+                Err(error) => ("500 Internal Server Error", "text/plain", error.to_string()),
+            },
+            Request::Refused(status) => (status, json, String::new()),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        );
+        let mut stream = reader.into_inner();
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await?;
+        return Ok(());
+    }
+}
+
+/// The JSON-RPC 2.0 response to the request with the given id
+pub(crate) fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    return match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => {
+            let mut value = json!({ "code": error.code, "message": error.message });
+            if let Some(data) = error.data {
+                value["data"] = data;
+            }
+            json!({ "jsonrpc": "2.0", "id": id, "error": value })
+        }
+    };
+}
+
+/// Reads an HTTP request off a connection
+async fn read_request(reader: &mut BufReader<impl AsyncRead + Unpin>) -> Result<Request, Error> {
+    let mut head = reader.take(MAX_HEADER_SIZE);
+    let mut line = String::new();
+    head.read_line(&mut line).await?;
+    let is_post = line.starts_with("POST ");
+    let is_metrics = line.starts_with("GET /metrics ") || line.starts_with("GET /metrics?");
+    let mut content_length = None;
+    loop {
+        line.clear();
+        let read = head.read_line(&mut line).await?;
+        if head.limit() == 0 && (read == 0 || !line.ends_with('\n')) {
+            return Ok(Request::Refused("431 Request Header Fields Too Large"));
+        }
+        if read == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    if is_metrics {
+        return Ok(Request::Metrics);
+    }
+    if !is_post {
+        return Ok(Request::Refused("405 Method Not Allowed"));
+    }
+    let length = match content_length {
+        Some(length) if length <= MAX_BODY_SIZE => length,
+        Some(_) => return Ok(Request::Refused("413 Payload Too Large")),
+        None => return Ok(Request::Refused("411 Length Required")),
+    };
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    return Ok(Request::Rpc(body));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_request, Request, Server, READ_TIMEOUT};
+    use crate::config::Config;
+    use futures::stream::{self, StreamExt};
+    use serde_json::{json, Value};
+    use std::fs;
+    use std::io;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::UnixStream;
+
+    fn project(registry: &str) -> (tempfile::TempDir, Config) {
+        let project = tempfile::tempdir().unwrap();
+        fs::write(
+            project.path().join("default.nix"),
+            format!(
+                "{{ uptix, ... }}: {{ image = uptix.dockerImage \"{}/library/serve:1.0\"; }}",
+                registry
+            ),
+        )
+        .unwrap();
+        let config: Config = toml::from_str(&format!(
+            "[docker.registries.\"{}\"]\ninsecure = true",
+            registry
+        ))
+        .unwrap();
+        return (project, config);
+    }
+
+    async fn call(server: &Server, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": 7, "method": method, "params": params });
+        return server.handle(request.to_string().as_bytes()).await;
+    }
+
+    #[tokio::test]
+    async fn it_answers_requests() {
+        let registry = mockito::server_address().to_string();
+        let _ping_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _manifest_mock = mockito::mock("HEAD", "/v2/library/serve/manifests/1.0")
+            .with_status(200)
+            .with_header("docker-content-digest", "sha256:served")
+            .create();
+        let _image_mock = mockito::mock("GET", "/v2/library/serve/manifests/sha256:served")
+            .with_status(404)
+            .create();
+        let (project, config) = project(&registry);
+        let server = Server::new(project.path(), config);
+        let key = format!("{}/library/serve:1.0", registry);
+
+        let list = call(&server, "list", Value::Null).await;
+        assert_eq!(list["id"], json!(7));
+        assert_eq!(list["result"][0]["key"], json!(key));
+        assert_eq!(list["result"][0]["kind"], json!("docker"));
+
+        let plan = call(&server, "plan", Value::Null).await;
+        assert_eq!(
+            plan["result"]["added"][0]["current"],
+            json!("sha256:served")
+        );
+        assert!(!project.path().join("uptix.lock").exists());
+        let update = call(&server, "update", Value::Null).await;
+        assert_eq!(update["result"], plan["result"]);
+        let plan = call(&server, "plan", Value::Null).await;
+        assert_eq!(plan["result"]["unchanged"], json!([key]));
+        let metrics = server.metrics().await.unwrap();
+        assert!(metrics.contains("uptix_outdated_dependencies{kind=\"docker\"} 0\n"));
+        assert!(metrics.contains("uptix_last_update_timestamp_seconds "));
+        assert!(metrics.contains("uptix_dependencies{kind=\"docker\"} 1\n"));
+
+        let missing = call(&server, "show", json!({ "key": "nope" })).await;
+        assert_eq!(missing["error"]["code"], json!(-32602));
+        let unknown = call(&server, "frobnicate", Value::Null).await;
+        assert_eq!(unknown["error"]["code"], json!(-32601));
+        let malformed = server.handle(b"{").await;
+        assert_eq!(malformed["error"]["code"], json!(-32700));
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_listens_on_a_socket() {
+        let (project, config) = project("registry.example.com");
+        let socket = project.path().join("uptix.sock");
+        let server = Server::new(project.path(), config);
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "method": "list" }).to_string();
+        let client = async {
+            while !socket.exists() {
+                tokio::task::yield_now().await;
+            }
+            // a client which never sends its request holds no one else up
+            let _silent = UnixStream::connect(&socket).await.unwrap();
+            let mut stream = UnixStream::connect(&socket).await.unwrap();
+            let request = format!(
+                "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            return response;
+        };
+        let response = tokio::select! {
+            response = client => response,
+            result = server.listen(&socket) => panic!("stopped listening: {:?}", result.err()),
+        };
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let body: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(
+            body["result"][0]["key"],
+            json!("registry.example.com/library/serve:1.0")
+        );
+    }
+
+    #[tokio::test]
+    async fn it_refuses_oversized_headers() {
+        let request = format!(
+            "GET /metrics HTTP/1.1\r\nX-Padding: {}\r\n\r\n",
+            "a".repeat(20000)
+        );
+        let mut reader = BufReader::new(request.as_bytes());
+        assert!(matches!(
+            read_request(&mut reader).await.unwrap(),
+            Request::Refused("431 Request Header Fields Too Large")
+        ));
+        let mut reader = BufReader::new("GET /metrics HTTP/1.1\r\n\r\n".as_bytes());
+        assert!(matches!(
+            read_request(&mut reader).await.unwrap(),
+            Request::Metrics
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_hangs_up_on_silent_clients() {
+        let (project, config) = project("registry.example.com");
+        let server = Server::new(project.path(), config);
+        let (client, connection) = tokio::io::duplex(1024);
+        let started = tokio::time::Instant::now();
+        server.serve_connection(connection, true).await.unwrap();
+        assert!(started.elapsed() >= READ_TIMEOUT);
+        let mut response = String::new();
+        let mut client = client;
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_keeps_accepting_after_errors() {
+        let (project, config) = project("registry.example.com");
+        let server = Server::new(project.path(), config);
+        let (mut client, connection) = tokio::io::duplex(1024);
+        let incoming = stream::iter(vec![
+            Err(io::Error::from_raw_os_error(libc::EMFILE)),
+            Ok(connection),
+        ])
+        .chain(stream::pending());
+        let request = async {
+            client
+                .write_all(b"GET /metrics HTTP/1.1\r\n\r\n")
+                .await
+                .unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).await.unwrap();
+            return response;
+        };
+        let response = tokio::select! {
+            response = request => response,
+            result = server.serve(incoming, false) => panic!("stopped serving: {:?}", result.err()),
+        };
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[tokio::test]
+    async fn it_serves_metrics() {
+        let (project, config) = project("registry.example.com");
+        let server = Server::new(project.path(), config);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let get = |request: &'static str| {
+            return async move {
+                let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+                stream.write_all(request.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+                return response;
+            };
+        };
+        let client = async {
+            let metrics = get("GET /metrics HTTP/1.1\r\n\r\n").await;
+            let rpc = get("POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}").await;
+            return (metrics, rpc);
+        };
+        let (metrics, rpc) = tokio::select! {
+            responses = client => responses,
+            result = server.serve_metrics(listener) => panic!("stopped listening: {:?}", result.err()),
+        };
+        assert!(
+            metrics.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n")
+        );
+        assert!(metrics.contains("uptix_dependencies{kind=\"docker\"} 1\n"));
+        // the entry isn't locked, let alone committed
+        assert!(!metrics.contains("uptix_dependency_age_days{"));
+        // updates can't be requested over TCP
+        assert!(rpc.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        // scrapes don't wait for the request being answered
+        let _request = server.requests.lock().await;
+        let metrics = tokio::time::timeout(Duration::from_secs(5), server.metrics()).await;
+        assert!(metrics.unwrap().is_ok());
+    }
+}
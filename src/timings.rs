@@ -0,0 +1,110 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    /// Time spent on each phase of a run, for `--timings`
+    static ref PHASES: Mutex<Vec<Phase>> = Mutex::new(vec![]);
+}
+
+#[derive(PartialEq, Clone, Debug)]
+struct Phase {
+    name: String,
+    started: Instant,
+    elapsed: Duration,
+    count: usize,
+}
+
+/// Measures a phase until it is dropped
+pub struct Timer {
+    name: String,
+    started: Instant,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        record(&self.name, self.started, self.started.elapsed());
+    }
+}
+
+/// Starts timing a phase. Phases which run several times (i.e. hashing each
+/// repository) add up.
+pub fn start(name: &str) -> Timer {
+    return Timer {
+        name: name.to_string(),
+        started: Instant::now(),
+    };
+}
+
+fn record(name: &str, started: Instant, elapsed: Duration) {
+    let mut phases = PHASES.lock().unwrap();
+    match phases.iter_mut().find(|phase| phase.name == name) {
+        Some(phase) => {
+            phase.started = phase.started.min(started);
+            phase.elapsed += elapsed;
+            phase.count += 1;
+        }
+        None => phases.push(Phase {
+            name: name.to_string(),
+            started,
+            elapsed,
+            count: 1,
+        }),
+    }
+}
+
+fn format(phases: &[Phase]) -> String {
+    let mut phases = phases.to_vec();
+    // listed in the order they began, so that nested phases follow the
+    // phase they are part of
+    phases.sort_by_key(|phase| phase.started);
+    let width = phases
+        .iter()
+        .map(|phase| phase.name.len())
+        .max()
+        .unwrap_or(0);
+    let mut report = "Timings:\n".to_string();
+    for phase in phases {
+        let count = match phase.count {
+            1 => "".to_string(),
+            n => format!(" ({} times)", n),
+        };
+        report.push_str(&format!(
+            "  {:<width$}  {:>8.2}s{}\n",
+            phase.name,
+            phase.elapsed.as_secs_f64(),
+            count,
+            width = width,
+        ));
+    }
+    return report;
+}
+
+/// How long each phase took so far
+pub fn report() -> String {
+    return format(&PHASES.lock().unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format, Phase};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn it_formats_phases() {
+        let now = Instant::now();
+        let phase = |name: &str, offset: u64, millis: u64, count: usize| Phase {
+            name: name.to_string(),
+            started: now + Duration::from_millis(offset),
+            elapsed: Duration::from_millis(millis),
+            count,
+        };
+        assert_eq!(
+            format(&[
+                phase("hashing", 3, 4000, 2),
+                phase("discovery", 0, 10, 1),
+                phase("resolution", 2, 12340, 1),
+            ]),
+            "Timings:\n  discovery       0.01s\n  resolution     12.34s\n  hashing         4.00s (2 times)\n",
+        );
+    }
+}
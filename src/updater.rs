@@ -0,0 +1,685 @@
+use crate::cache;
+use crate::config::{self, CacheConfig, Config, NetworkConfig, RootConfig};
+use crate::deps::{self, Dependency};
+use crate::error::Error;
+use crate::http;
+use crate::lock::{self, LockData, LockFile};
+use crate::plan::Plan;
+use crate::secrets;
+use crate::timings;
+use crate::util;
+use futures::stream::{self, StreamExt};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+
+/// Finds the dependencies declared in a project and locks them on its
+/// uptix.lock, as `uptix update` does
+///
+/// ```no_run
+/// # async fn run() -> Result<(), uptix::Error> {
+/// let updater = uptix::Updater::discover("path/to/project")?;
+/// let plan = updater.plan().await?;
+/// for change in &plan.changed {
+///     println!("{} changed", change.key);
+/// }
+/// updater.apply(&plan)?;
+/// # return Ok(());
+/// # }
+/// ```
+pub struct Updater {
+    root: PathBuf,
+    dependencies: Vec<Dependency>,
+    skipped: Vec<(PathBuf, Error)>,
+    progress: Box<dyn Progress>,
+    /// kept to plan with, rather than configured for the whole process, so
+    /// that updaters with different settings can run side by side
+    network: NetworkConfig,
+    cache: CacheConfig,
+    client: Option<reqwest::Client>,
+}
+
+/// Gets told how planning an update goes, i.e. to render progress in a GUI
+/// rather than scraping what the CLI prints. Every method does nothing
+/// unless implemented, and may be called from several threads at once.
+pub trait Progress: Send + Sync {
+    /// A dependency will be looked up. Every dependency is reported before
+    /// any of them starts being resolved.
+    fn on_discovered(&self, _key: &str) {}
+    /// A dependency started being resolved
+    fn on_resolving(&self, _key: &str) {}
+    /// A dependency was resolved to what it will be locked to, or kept as
+    /// it was when pinned
+    fn on_resolved(&self, _key: &str, _lock: &LockData) {}
+    /// A dependency couldn't be resolved
+    fn on_error(&self, _key: &str, _error: &Error) {}
+    /// A dependency was resolved, but to something worth a look before
+    /// deploying it, such as a branch which was force-pushed
+    fn on_warning(&self, _key: &str, _warning: &Error) {}
+}
+
+/// How resolving a dependency went
+#[doc(hidden)]
+pub enum Resolved {
+    /// pinned, so kept as it was locked before
+    Kept,
+    /// along with why it may need a look before deploying, if it does (i.e.
+    /// its branch was force-pushed)
+    Locked {
+        lock: Box<LockData>,
+        warning: Option<Error>,
+    },
+    Failed(Error),
+}
+
+/// How each dependency was resolved, in the order they were declared
+#[doc(hidden)]
+pub struct Resolution<'a> {
+    pub resolved: Vec<(&'a Dependency, Resolved)>,
+    /// dependencies which weren't resolved before resolving was cancelled
+    pub skipped: Vec<&'a Dependency>,
+    pub cancelled: bool,
+    /// uptix.lock as it was before
+    pub previous: LockFile,
+}
+
+impl Resolution<'_> {
+    /// uptix.lock as resolved so far. Dependencies which failed are left
+    /// out, while pinned and skipped ones stay as they were.
+    pub fn lock_file(&self) -> LockFile {
+        let mut lock_file = LockFile::new();
+        for (dependency, resolved) in &self.resolved {
+            let lock = match resolved {
+                Resolved::Kept => &self.previous[&dependency.key()],
+                Resolved::Locked { lock, .. } => lock.as_ref(),
+                Resolved::Failed(_) => continue,
+            };
+            lock_file.insert(dependency.key(), lock.clone());
+        }
+        for dependency in &self.skipped {
+            if let Some(previous) = self.previous.get(&dependency.key()) {
+                lock_file.insert(dependency.key(), previous.clone());
+            }
+        }
+        return lock_file;
+    }
+
+    /// How uptix.lock would change, failing with every dependency which
+    /// couldn't be locked
+    pub fn into_plan(self) -> Result<Plan, Error> {
+        let lock_file = self.lock_file();
+        let mut failures: Vec<Error> = self
+            .resolved
+            .into_iter()
+            .filter_map(|(dependency, resolved)| match resolved {
+                Resolved::Failed(error) => Some(dependency.failure(error)),
+                _ => None,
+            })
+            .collect();
+        if failures.len() == 1 {
+            return Err(failures.remove(0));
+        }
+        if !failures.is_empty() {
+            return Err(Error::UpdateErrors { errors: failures });
+        }
+        return Ok(Plan::diff(&self.previous, lock_file));
+    }
+}
+
+/// The files dependencies may be declared in
+#[doc(hidden)]
+#[derive(Default)]
+pub struct Files {
+    pub nix: Vec<PathBuf>,
+    pub templates: Vec<PathBuf>,
+    pub workflows: Vec<PathBuf>,
+}
+
+impl Files {
+    /// Finds nix files, templates and GitHub Actions workflows under the
+    /// roots configured on uptix.toml, which are relative to `root`
+    pub fn discover(root: &Path, config: &Config) -> Result<Files, Error> {
+        let roots: Vec<_> = config
+            .discovery
+            .scan_roots()
+            .into_iter()
+            .map(|r| RootConfig {
+                path: root.join(&r.path).to_string_lossy().to_string(),
+                ..r
+            })
+            .collect();
+        return Ok(Files {
+            nix: util::discover_root_files(&roots, util::discover_nix_files)?,
+            templates: util::discover_root_files(&roots, |r| {
+                util::discover_template_files(r, &config.discovery.templates)
+            })?,
+            workflows: match config.discovery.github_actions {
+                true => util::discover_root_files(&roots, util::discover_workflow_files)?,
+                false => vec![],
+            },
+        });
+    }
+}
+
+struct NoProgress;
+
+impl Progress for NoProgress {}
+
+impl Updater {
+    /// Finds the dependencies under a project's directory, configured by its
+    /// uptix.toml (if it has one)
+    pub fn discover(root: impl AsRef<Path>) -> Result<Updater, Error> {
+        let root = root.as_ref();
+        let config = Config::load(&root.join(config::DEFAULT_CONFIG_PATH))?;
+        return Updater::with_config(root, &config);
+    }
+
+    /// Like [`Updater::discover`], but with a configuration of its own
+    /// rather than the project's uptix.toml
+    pub fn with_config(root: impl AsRef<Path>, config: &Config) -> Result<Updater, Error> {
+        secrets::configure(&config.credentials, root.as_ref());
+        let files = Files::discover(root.as_ref(), config)?;
+        return Updater::with_files(root, config, files);
+    }
+
+    /// Like [`Updater::with_config`], but with the files to scan already
+    /// found (i.e. only those imported from an entry point)
+    #[doc(hidden)]
+    pub fn with_files(
+        root: impl AsRef<Path>,
+        config: &Config,
+        files: Files,
+    ) -> Result<Updater, Error> {
+        let root = root.as_ref().to_path_buf();
+        let mut dependencies = vec![];
+        let mut skipped = vec![];
+        let mut errors = vec![];
+        let results = files
+            .nix
+            .into_iter()
+            .map(|f| {
+                let result = deps::collect_file_dependencies(f.to_str().unwrap(), config);
+                (f, result)
+            })
+            .chain(files.templates.into_iter().map(|f| {
+                let result = deps::collect_template_dependencies(f.to_str().unwrap(), config);
+                (f, result)
+            }))
+            .chain(files.workflows.into_iter().map(|f| {
+                let result = deps::collect_workflow_dependencies(f.to_str().unwrap(), config);
+                (f, result)
+            }));
+        for (f, result) in results {
+            match result {
+                Ok(mut deps) => dependencies.append(&mut deps),
+                Err(
+                    e @ (Error::UnreadableFile { .. }
+                    | Error::NixSyntaxError { .. }
+                    | Error::OversizedFile { .. }
+                    | Error::BinaryFile { .. }),
+                ) => skipped.push((f, e)),
+                Err(e) => errors.push(e),
+            }
+        }
+        if errors.len() == 1 {
+            return Err(errors.remove(0));
+        }
+        if !errors.is_empty() {
+            return Err(Error::ParsingErrors { errors });
+        }
+        return Ok(Updater {
+            root,
+            dependencies,
+            skipped,
+            progress: Box::new(NoProgress),
+            network: config.network.clone(),
+            cache: config.cache.clone(),
+            client: None,
+        });
+    }
+
+    /// Sends every request through `client` rather than one built from
+    /// uptix.toml, i.e. for custom TLS roots, client certificates for
+    /// internal registries or recording requests in tests. The configured
+    /// proxy and timeout are then up to the client.
+    pub fn with_client(self, client: reqwest::Client) -> Updater {
+        return Updater {
+            client: Some(client),
+            ..self
+        };
+    }
+
+    /// Reports how planning goes to `progress`
+    pub fn with_progress(self, progress: impl Progress + 'static) -> Updater {
+        return Updater {
+            progress: Box::new(progress),
+            ..self
+        };
+    }
+
+    /// The keys (as on uptix.lock) of the dependencies which were found
+    pub fn dependencies(&self) -> Vec<String> {
+        return deps::unique(&self.dependencies)
+            .iter()
+            .map(|d| d.key())
+            .collect();
+    }
+
+    /// Every declaration found, including repeated ones
+    #[doc(hidden)]
+    pub fn declared(&self) -> &[Dependency] {
+        return &self.dependencies;
+    }
+
+    /// Takes the files which were skipped, along with why, i.e. to report
+    /// them
+    #[doc(hidden)]
+    pub fn take_skipped(&mut self) -> Vec<(PathBuf, Error)> {
+        return std::mem::take(&mut self.skipped);
+    }
+
+    /// Every declaration found, along with the files which were skipped
+    #[doc(hidden)]
+    pub fn into_parts(self) -> (Vec<Dependency>, Vec<(PathBuf, Error)>) {
+        return (self.dependencies, self.skipped);
+    }
+
+    /// Files which couldn't be scanned (i.e. with syntax errors), along with
+    /// why. Their dependencies are left out, as `uptix update` does.
+    pub fn skipped(&self) -> &[(PathBuf, Error)] {
+        return &self.skipped;
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        return self.root.join(lock::LOCK_FILE_PATH);
+    }
+
+    /// Looks up the latest version of each dependency, without writing
+    /// anything. Fails with every dependency which couldn't be locked.
+    pub async fn plan(&self) -> Result<Plan, Error> {
+        return self.resolve(std::future::pending()).await?.into_plan();
+    }
+
+    /// Like [`Updater::plan`], but stopping once `cancel` completes (i.e. on
+    /// Ctrl-C), and with how each dependency was resolved rather than only
+    /// the resulting plan
+    #[doc(hidden)]
+    pub async fn resolve(&self, cancel: impl Future<Output = ()>) -> Result<Resolution<'_>, Error> {
+        let resolving = cache::scoped(self.cache.clone(), self.resolve_until(cancel));
+        let scope = http::Scope {
+            network: self.network.clone(),
+            client: self.client.clone(),
+        };
+        return http::scoped(scope, resolving).await;
+    }
+
+    async fn resolve_until(
+        &self,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<Resolution<'_>, Error> {
+        let previous_lock_file = lock::read(&self.lock_path())?;
+        deps::remember_hashes(&previous_lock_file);
+        // declarations of the same dependency share a single entry on the
+        // lock file, so each one only needs to be resolved once
+        let unique_dependencies = deps::unique(&self.dependencies);
+        deps::plan_sessions(&unique_dependencies);
+        for dependency in &unique_dependencies {
+            self.progress.on_discovered(&dependency.key());
+        }
+        let timer = timings::start("resolution");
+        let previous = &previous_lock_file;
+        let progress = self.progress.as_ref();
+        // dependencies are locked concurrently, but their results are kept
+        // in order so that output stays the same across runs
+        let mut pending = stream::iter(unique_dependencies.iter().copied())
+            .map(|dependency| async move {
+                let key = dependency.key();
+                if dependency.is_pinned() && previous.contains_key(&key) {
+                    progress.on_resolved(&key, &previous[&key]);
+                    return Resolved::Kept;
+                }
+                progress.on_resolving(&key);
+                let _timer = timings::start(&format!("resolution of {}", dependency.type_name()));
+                let lock = match dependency.lock().await {
+                    Ok(lock) => lock,
+                    Err(error) => {
+                        progress.on_error(&key, &error);
+                        return Resolved::Failed(error);
+                    }
+                };
+                let warning = match previous.get(&key) {
+                    Some(previous) => dependency.rewritten_history(previous, &lock).await,
+                    None => None,
+                };
+                if let Some(warning) = &warning {
+                    progress.on_warning(&key, warning);
+                }
+                progress.on_resolved(&key, &lock);
+                return Resolved::Locked {
+                    lock: Box::new(lock),
+                    warning,
+                };
+            })
+            .buffered(http::jobs());
+        let mut resolved = vec![];
+        let mut cancel = pin!(cancel);
+        let cancelled = loop {
+            tokio::select! {
+                biased;
+                _ = &mut cancel => break true,
+                next = pending.next() => match next {
+                    Some(next) => resolved.push(next),
+                    None => break false,
+                },
+            }
+        };
+        // cancels whatever is still in flight, killing any prefetch processes
+        drop(pending);
+        drop(timer);
+        let skipped = unique_dependencies[resolved.len()..].to_vec();
+        return Ok(Resolution {
+            resolved: unique_dependencies.into_iter().zip(resolved).collect(),
+            skipped,
+            cancelled,
+            previous: previous_lock_file,
+        });
+    }
+
+    /// Writes uptix.lock as planned
+    pub fn apply(&self, plan: &Plan) -> Result<(), Error> {
+        return lock::write(&self.lock_path(), plan.lock_file());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Progress, Updater};
+    use crate::config::Config;
+    use crate::error::Error;
+    use crate::lock::LockData;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Events(Arc<Mutex<Vec<String>>>);
+
+    impl Progress for Events {
+        fn on_discovered(&self, key: &str) {
+            self.0.lock().unwrap().push(format!("discovered {}", key));
+        }
+
+        fn on_resolving(&self, key: &str) {
+            self.0.lock().unwrap().push(format!("resolving {}", key));
+        }
+
+        fn on_resolved(&self, key: &str, lock: &LockData) {
+            let lock = serde_json::to_string(lock).unwrap();
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("resolved {} to {}", key, lock));
+        }
+
+        fn on_error(&self, key: &str, _error: &Error) {
+            self.0.lock().unwrap().push(format!("failed {}", key));
+        }
+    }
+
+    #[tokio::test]
+    async fn it_updates_projects() {
+        let registry = mockito::server_address().to_string();
+        let _ping_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _manifest_mock = mockito::mock("HEAD", "/v2/library/updater/manifests/1.0")
+            .with_status(200)
+            .with_header("docker-content-digest", "sha256:updated")
+            .create();
+        // images are measured when locked, which isn't needed here
+        let _image_mock = mockito::mock("GET", "/v2/library/updater/manifests/sha256:updated")
+            .with_status(404)
+            .create();
+        let project = tempfile::tempdir().unwrap();
+        fs::write(
+            project.path().join("default.nix"),
+            format!(
+                "{{ uptix, ... }}: {{ image = uptix.dockerImage \"{}/library/updater:1.0\"; }}",
+                registry
+            ),
+        )
+        .unwrap();
+        fs::write(project.path().join("broken.nix"), "{ uptix, ... }: {").unwrap();
+        let config: Config = toml::from_str(&format!(
+            "[docker.registries.\"{}\"]\ninsecure = true",
+            registry
+        ))
+        .unwrap();
+
+        let updater = Updater::with_config(project.path(), &config).unwrap();
+        assert_eq!(updater.skipped().len(), 1);
+        assert!(updater.skipped()[0].0.ends_with("broken.nix"));
+        let key = format!("{}/library/updater:1.0", registry);
+        assert_eq!(updater.dependencies(), vec![key.clone()]);
+        let plan = updater.plan().await.unwrap();
+        assert!(plan.unchanged.is_empty());
+        assert_eq!(plan.added.len(), 1);
+        assert_eq!(plan.added[0].key, key);
+        assert_eq!(plan.added[0].previous, None);
+        assert_eq!(
+            plan.added[0].current,
+            Some(LockData::Digest("sha256:updated".to_string()))
+        );
+        // nothing is written until the plan is applied
+        assert!(!project.path().join("uptix.lock").exists());
+
+        updater.apply(&plan).unwrap();
+        let plan = updater.plan().await.unwrap();
+        assert_eq!(plan.unchanged, vec![key]);
+        assert!(plan.is_empty());
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_sends_requests_through_its_client() {
+        let registry = mockito::server_address().to_string();
+        let _ping_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let manifest_mock = mockito::mock("HEAD", "/v2/library/client/manifests/1.0")
+            .match_header("x-injected", "yes")
+            .with_status(200)
+            .with_header("docker-content-digest", "sha256:client")
+            .create();
+        let _image_mock = mockito::mock("GET", "/v2/library/client/manifests/sha256:client")
+            .with_status(404)
+            .create();
+        let project = tempfile::tempdir().unwrap();
+        fs::write(
+            project.path().join("default.nix"),
+            format!(
+                "{{ uptix, ... }}: {{ image = uptix.dockerImage \"{}/library/client:1.0\"; }}",
+                registry
+            ),
+        )
+        .unwrap();
+        let config: Config = toml::from_str(&format!(
+            "[docker.registries.\"{}\"]\ninsecure = true",
+            registry
+        ))
+        .unwrap();
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-injected", "yes".parse().unwrap());
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap();
+
+        let updater = Updater::with_config(project.path(), &config)
+            .unwrap()
+            .with_client(client);
+        let plan = updater.plan().await.unwrap();
+        assert_eq!(
+            plan.added[0].current,
+            Some(LockData::Digest("sha256:client".to_string()))
+        );
+        manifest_mock.assert();
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_reports_every_failure() {
+        let registry = mockito::server_address().to_string();
+        let _ping_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _manifest_mock = mockito::mock(
+            "HEAD",
+            mockito::Matcher::Regex(r"^/v2/library/missing\d/manifests/1\.0$".to_string()),
+        )
+        .with_status(404)
+        .create();
+        let project = tempfile::tempdir().unwrap();
+        let content = format!(
+            "{{ uptix, ... }}: {{\n  a = uptix.dockerImage \"{0}/library/missing1:1.0\";\n  b = uptix.dockerImage \"{0}/library/missing2:1.0\";\n}}",
+            registry
+        );
+        fs::write(project.path().join("default.nix"), &content).unwrap();
+        let config: Config = toml::from_str(&format!(
+            "[docker.registries.\"{}\"]\ninsecure = true",
+            registry
+        ))
+        .unwrap();
+
+        let updater = Updater::with_config(project.path(), &config).unwrap();
+        let errors = match updater.plan().await {
+            Err(Error::UpdateErrors { errors }) => errors,
+            _ => panic!("expected every failure to be reported"),
+        };
+        assert_eq!(errors.len(), 2);
+        match &errors[1] {
+            Error::UpdateFailed { name, pos, .. } => {
+                assert_eq!(name, &format!("{}/library/missing2:1.0", registry));
+                // points at the image on the second declaration
+                assert_eq!(pos.offset(), content.rfind("uptix.dockerImage").unwrap());
+            }
+            _ => panic!("expected failures to point at their declaration"),
+        }
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_keeps_unverified_images_off_the_lock() {
+        let registry = mockito::server_address().to_string();
+        let _ping_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _manifest_mock = mockito::mock("HEAD", "/v2/library/unsigned/manifests/1.0")
+            .with_status(200)
+            .with_header("docker-content-digest", "sha256:unsigned")
+            .create();
+        let project = tempfile::tempdir().unwrap();
+        fs::write(
+            project.path().join("default.nix"),
+            format!(
+                "{{ uptix, ... }}: {{ image = uptix.dockerImage \"{}/library/unsigned:1.0\"; }}",
+                registry
+            ),
+        )
+        .unwrap();
+        let key = format!("{}/library/unsigned:1.0", registry);
+        let previous = format!("{{\n  \"{}\": \"sha256:signed\"\n}}\n", key);
+        fs::write(project.path().join("uptix.lock"), &previous).unwrap();
+        // there is no signature to verify, whether cosign is installed or not
+        let config: Config = toml::from_str(&format!(
+            "[docker.registries.\"{}\"]\ninsecure = true\ncosign.key = \"cosign.pub\"",
+            registry
+        ))
+        .unwrap();
+
+        let updater = Updater::with_config(project.path(), &config).unwrap();
+        match updater.plan().await {
+            Err(Error::UpdateFailed { name, cause, .. }) => {
+                assert_eq!(name, key);
+                assert!(matches!(cause[..], [Error::SignatureVerification { .. }]));
+            }
+            _ => panic!("expected the verification to fail"),
+        }
+        // without a plan, there is nothing to write
+        assert_eq!(
+            fs::read_to_string(project.path().join("uptix.lock")).unwrap(),
+            previous
+        );
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_reports_progress() {
+        let registry = mockito::server_address().to_string();
+        let _ping_mock = mockito::mock("GET", "/v2/").with_status(200).create();
+        let _found_mock = mockito::mock("HEAD", "/v2/library/progress/manifests/1.0")
+            .with_status(200)
+            .with_header("docker-content-digest", "sha256:progress")
+            .create();
+        let _image_mock = mockito::mock("GET", "/v2/library/progress/manifests/sha256:progress")
+            .with_status(404)
+            .create();
+        let _missing_mock = mockito::mock("HEAD", "/v2/library/progress/manifests/2.0")
+            .with_status(404)
+            .create();
+        let project = tempfile::tempdir().unwrap();
+        fs::write(
+            project.path().join("default.nix"),
+            format!(
+                "{{ uptix, ... }}: {{\n  a = uptix.dockerImage \"{0}/library/progress:1.0\";\n  b = uptix.dockerImage \"{0}/library/progress:2.0\";\n}}",
+                registry
+            ),
+        )
+        .unwrap();
+        let config: Config = toml::from_str(&format!(
+            "[docker.registries.\"{}\"]\ninsecure = true",
+            registry
+        ))
+        .unwrap();
+
+        let events = Events::default();
+        let updater = Updater::with_config(project.path(), &config)
+            .unwrap()
+            .with_progress(events.clone());
+        assert!(updater.plan().await.is_err());
+        let mut events = events.0.lock().unwrap().clone();
+        // the order in which they are resolved can vary
+        events[2..].sort();
+        let found = format!("{}/library/progress:1.0", registry);
+        let missing = format!("{}/library/progress:2.0", registry);
+        assert_eq!(
+            events,
+            vec![
+                format!("discovered {}", found),
+                format!("discovered {}", missing),
+                format!("failed {}", missing),
+                format!("resolved {} to \"sha256:progress\"", found),
+                format!("resolving {}", found),
+                format!("resolving {}", missing),
+            ]
+        );
+        mockito::reset();
+    }
+
+    #[tokio::test]
+    async fn it_stops_when_cancelled() {
+        let project = tempfile::tempdir().unwrap();
+        fs::write(
+            project.path().join("default.nix"),
+            "{ uptix, ... }: { image = uptix.dockerImage \"registry.example.com/library/stop:1.0\"; }",
+        )
+        .unwrap();
+        let key = "registry.example.com/library/stop:1.0";
+        fs::write(
+            project.path().join("uptix.lock"),
+            format!("{{\"{}\": \"sha256:old\"}}", key),
+        )
+        .unwrap();
+
+        let updater = Updater::with_config(project.path(), &Config::default()).unwrap();
+        let resolution = updater.resolve(std::future::ready(())).await.unwrap();
+        assert!(resolution.cancelled);
+        assert!(resolution.resolved.is_empty());
+        assert_eq!(resolution.skipped.len(), 1);
+        // what wasn't looked up yet stays as it was
+        let plan = resolution.into_plan().unwrap();
+        assert!(plan.is_empty());
+        assert_eq!(plan.unchanged, vec![key.to_string()]);
+    }
+}
@@ -1,38 +1,320 @@
+use crate::config::RootConfig;
 use crate::error::Error;
-use rnix::{SyntaxKind, SyntaxNode};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::{DirEntry, WalkBuilder, WalkState};
+use rnix::ast::{self, Expr, HasEntry, InterpolPart, LiteralKind, UnaryOpKind};
+use rnix::{ParseError, SyntaxNode};
+use rowan::ast::AstNode;
 use serde_json::{Map, Value};
-use std::path::PathBuf;
-use walkdir::{DirEntry, WalkDir};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-fn is_not_hidden(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| entry.depth() == 0 || !s.starts_with("."))
-        .unwrap_or(false)
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    return Ok(builder.build()?);
+}
+
+const NIX_STORE: &str = "/nix/store";
+
+/// Whether a symlink should be walked into, which is never the case for links
+/// into the nix store (i.e. `result` links from nix-build)
+fn is_followable_link(entry: &DirEntry) -> bool {
+    return fs::canonicalize(entry.path())
+        .map(|target| !target.starts_with(NIX_STORE))
+        .unwrap_or(false);
+}
+
+/// Symlink loops are only reported when following links, and are safe to
+/// skip since their target is being walked already
+fn is_loop(error: &ignore::Error) -> bool {
+    return match error {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. } | ignore::Error::WithDepth { err, .. } => is_loop(err),
+        _ => false,
+    };
+}
+
+/// Walks a root with one thread per core, which makes a difference on large
+/// trees such as nixpkgs checkouts. Files are sorted, since they are found in
+/// no particular order.
+fn discover_files(
+    root: &RootConfig,
+    is_wanted: impl Fn(&Path) -> bool + Sync,
+) -> Result<Vec<PathBuf>, Error> {
+    let excluded = build_glob_set(&root.exclude)?;
+    let root_path = PathBuf::from(&root.path);
+    let follow_symlinks = root.follow_symlinks;
+    let is_excluded = move |e: &DirEntry| {
+        e.path()
+            .strip_prefix(&root_path)
+            .map(|p| excluded.is_match(p))
+            .unwrap_or(false)
+    };
+    let is_allowed_link = move |e: &DirEntry| {
+        !e.path_is_symlink() || e.depth() == 0 || (follow_symlinks && is_followable_link(e))
+    };
+    let walker = WalkBuilder::new(&root.path)
+        // .gitignore and friends aren't honored, only the exclude rules
+        .standard_filters(false)
+        .hidden(true)
+        .follow_links(root.follow_symlinks)
+        .filter_entry(move |e| !is_excluded(e) && is_allowed_link(e))
+        .build_parallel();
+    let files = Mutex::new(Vec::new());
+    let failure = Mutex::new(None);
+    walker.run(|| {
+        return Box::new(|entry| {
+            let e = match entry {
+                Ok(e) => e,
+                Err(err) if is_loop(&err) => return WalkState::Continue,
+                Err(err) => {
+                    let error = err
+                        .into_io_error()
+                        .unwrap_or_else(|| io::Error::other("walk failed"));
+                    *failure.lock().unwrap() = Some(error);
+                    return WalkState::Quit;
+                }
+            };
+            let is_file = e.file_type().is_some_and(|t| t.is_file());
+            if is_file && is_wanted(e.path()) {
+                files.lock().unwrap().push(e.into_path());
+            }
+            return WalkState::Continue;
+        });
+    });
+    if let Some(error) = failure.into_inner().unwrap() {
+        return Err(Error::IOError(error));
+    }
+    let mut files = files.into_inner().unwrap();
+    files.sort();
+    return Ok(files);
 }
 
-pub fn discover_nix_files(root_path: &str) -> Vec<PathBuf> {
+fn is_nix_file(path: &Path) -> bool {
+    return path.extension().and_then(|x| x.to_str()) == Some("nix");
+}
+
+pub fn discover_nix_files(root: &RootConfig) -> Result<Vec<PathBuf>, Error> {
+    return discover_files(root, is_nix_file);
+}
+
+/// Discovers non-nix files matching any of the given glob patterns, relative
+/// to the root
+pub fn discover_template_files(
+    root: &RootConfig,
+    patterns: &[String],
+) -> Result<Vec<PathBuf>, Error> {
+    let templates = build_glob_set(patterns)?;
+    return discover_files(root, |path| {
+        !is_nix_file(path)
+            && path
+                .strip_prefix(&root.path)
+                .map(|p| templates.is_match(p))
+                .unwrap_or(false)
+    });
+}
+
+/// Discovers GitHub Actions workflows, which live under `.github/workflows`
+/// and would otherwise be skipped for being hidden
+pub fn discover_workflow_files(root: &RootConfig) -> Result<Vec<PathBuf>, Error> {
+    let dir = Path::new(&root.path).join(".github").join("workflows");
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
     let mut files = Vec::new();
-    let walker = WalkDir::new(root_path).into_iter();
-    for entry in walker.filter_entry(|e| is_not_hidden(e)) {
-        let e = entry.unwrap();
-        let path = e.path();
-        if path.extension().and_then(|x| x.to_str()) != Some("nix") {
-            continue;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_yaml = matches!(
+            path.extension().and_then(|x| x.to_str()),
+            Some("yml" | "yaml")
+        );
+        if is_yaml && path.is_file() {
+            files.push(path);
         }
-        files.push(PathBuf::from(path));
     }
-    return files;
+    files.sort();
+    return Ok(files);
+}
+
+/// How much of a file is sniffed for NUL bytes to tell if it's binary
+const SNIFF_LENGTH: usize = 8000;
+
+/// Reads a file which is about to be scanned, refusing files which are too
+/// large (unless `max_size` is 0) or which don't look like text
+pub fn read_source(file_path: &str, max_size: u64) -> Result<String, Error> {
+    let unreadable = |source| Error::UnreadableFile {
+        path: file_path.to_string(),
+        source,
+    };
+    let size = fs::metadata(file_path).map_err(unreadable)?.len();
+    if max_size > 0 && size > max_size {
+        return Err(Error::OversizedFile {
+            path: file_path.to_string(),
+            size,
+            max_size,
+        });
+    }
+    let bytes = fs::read(file_path).map_err(unreadable)?;
+    if bytes.iter().take(SNIFF_LENGTH).any(|b| *b == 0) {
+        return Err(Error::BinaryFile {
+            path: file_path.to_string(),
+        });
+    }
+    return String::from_utf8(bytes).map_err(|_| Error::BinaryFile {
+        path: file_path.to_string(),
+    });
+}
+
+/// Runs `discover` on each of the given roots, skipping files which were
+/// already found through a previous (overlapping) root
+pub fn discover_root_files(
+    roots: &[RootConfig],
+    discover: impl Fn(&RootConfig) -> Result<Vec<PathBuf>, Error>,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    for root in roots {
+        if !Path::new(&root.path).is_dir() {
+            return Err(Error::MissingScanRoot {
+                path: root.path.clone(),
+            });
+        }
+        for file in discover(root)? {
+            if seen.insert(fs::canonicalize(&file)?) {
+                files.push(file);
+            }
+        }
+    }
+    return Ok(files);
+}
+
+fn nix_path_literal(node: &SyntaxNode) -> Option<String> {
+    // search paths (<nixpkgs>) and home paths are outside of the project
+    let parts = match ast::Path::cast(node.clone())? {
+        ast::Path::PathAbs(path) => path.parts(),
+        ast::Path::PathRel(path) => path.parts(),
+        _ => return None,
+    };
+    return parts
+        .iter()
+        .map(|part| match part {
+            InterpolPart::Literal(content) => Some(content.text()),
+            InterpolPart::Interpolation(_) => None,
+        })
+        .collect();
+}
+
+fn imported_paths(root: &SyntaxNode) -> Vec<String> {
+    let mut paths = vec![];
+    for node in root.descendants() {
+        if let Some(apply) = ast::Apply::cast(node.clone()) {
+            let func_name = match apply.lambda() {
+                Some(func) => func.to_string(),
+                None => continue,
+            };
+            if func_name != "import" && func_name != "builtins.import" {
+                continue;
+            }
+            if let Some(path) = apply.argument().and_then(|a| nix_path_literal(a.syntax())) {
+                paths.push(path);
+            }
+        } else if let Some(binding) = ast::AttrpathValue::cast(node) {
+            let is_imports = binding
+                .attrpath()
+                .is_some_and(|key| key.to_string() == "imports");
+            if let (true, Some(Expr::List(list))) = (is_imports, binding.value()) {
+                paths.extend(list.items().filter_map(|c| nix_path_literal(c.syntax())));
+            }
+        }
+    }
+    return paths;
+}
+
+/// Finds the nix files reachable from `entry` through imports. Imports which
+/// can't be followed (i.e. a directory without a default.nix) are skipped
+/// with a warning naming the file which imports them.
+pub fn discover_imported_nix_files(entry: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending: Vec<(PathBuf, Option<PathBuf>)> = vec![(entry.to_path_buf(), None)];
+    while let Some((path, importer)) = pending.pop() {
+        let path = if path.is_dir() {
+            path.join("default.nix")
+        } else {
+            path
+        };
+        let content = fs::canonicalize(&path).and_then(|canonical| {
+            if !seen.insert(canonical) {
+                return Ok(None);
+            }
+            return fs::read_to_string(&path).map(Some);
+        });
+        let content = match (content, importer) {
+            (Ok(Some(content)), _) => content,
+            (Ok(None), _) => continue,
+            (Err(source), None) => {
+                return Err(Error::UnreadableFile {
+                    path: path.display().to_string(),
+                    source,
+                });
+            }
+            (Err(error), Some(importer)) => {
+                tracing::warn!(
+                    "skipping {}, imported by {}: {}",
+                    path.display(),
+                    importer.display(),
+                    error
+                );
+                continue;
+            }
+        };
+        let base = path.parent().unwrap_or(Path::new("."));
+        for import in imported_paths(&rnix::Root::parse(&content).syntax()) {
+            let imported = base.join(import);
+            if imported.exists() {
+                pending.push((imported, Some(path.clone())));
+            }
+        }
+        files.push(path);
+    }
+    return Ok(files);
+}
+
+/// Lowercase hexadecimal encoding, i.e. for digests
+pub fn hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect();
 }
 
 pub fn user_agent() -> String {
     return format!("uptix/{}", env!("CARGO_PKG_VERSION"));
 }
 
+/// Where a dependency was declared, with 1-based line and column numbers
+#[derive(PartialEq, Clone, Debug)]
+pub struct Location {
+    pub file_path: String,
+    pub line: usize,
+    pub column: usize,
+    pub span: miette::SourceSpan,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.file_path, self.line, self.column)
+    }
+}
+
 pub struct ParsingContext {
     file_path: String,
     file_contents: String,
+    prefixes: Vec<String>,
+    imports: BTreeMap<String, String>,
 }
 
 impl ParsingContext {
@@ -40,72 +322,230 @@ impl ParsingContext {
         Self {
             file_path: file_path.to_string(),
             file_contents: file_contents.to_string(),
+            prefixes: vec!["uptix".to_string()],
+            imports: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_prefix(&self, prefix: &str) -> bool {
+        return self.prefixes.iter().any(|p| p == prefix);
+    }
+
+    pub fn add_prefix(&mut self, prefix: &str) {
+        if !self.is_prefix(prefix) {
+            self.prefixes.push(prefix.to_string());
+        }
+    }
+
+    /// Registers a binding for an uptix function (i.e. from
+    /// `inherit (uptix) dockerImage;`)
+    pub fn add_import(&mut self, name: &str, function: &str) {
+        self.imports.insert(name.to_string(), function.to_string());
+    }
+
+    /// Normalizes calls through any of the accepted prefixes (i.e. `u.dockerImage`)
+    /// or imported bindings into the canonical `uptix.dockerImage` form
+    pub fn function_name(&self, select: &str) -> Option<String> {
+        let (prefix, name) = match select.rsplit_once('.') {
+            Some(parts) => parts,
+            None => return self.imports.get(select).cloned(),
+        };
+        if !self.is_prefix(prefix) {
+            return None;
         }
+        return Some(format!("uptix.{}", name));
     }
 
     pub fn src(&self) -> miette::NamedSource {
         miette::NamedSource::new(self.file_path.clone(), self.file_contents.clone())
     }
-}
 
-fn value_from_nix(node: &SyntaxNode) -> Result<Value, Error> {
-    if node.kind() == SyntaxKind::NODE_STRING {
-        let mut w = node.text().to_string();
-        w.pop();
-        w.remove(0);
-        return Ok(serde_json::Value::String(w));
-    }
-
-    if node.kind() == SyntaxKind::NODE_LITERAL {
-        let token = node.first_token().unwrap();
-        return match token.kind() {
-            SyntaxKind::TOKEN_INTEGER => {
-                let v = token.text().parse::<i32>().unwrap();
-                Ok(serde_json::Value::from(v))
-            }
-            SyntaxKind::TOKEN_FLOAT => {
-                let v = token.text().parse::<f32>().unwrap();
-                Ok(serde_json::Value::from(v))
-            }
-            _ => Err(Error::NixParsingError(format!(
-                "Unexpected token kind {:#?}",
-                token.kind()
-            ))),
+    pub fn location(&self, node: &SyntaxNode) -> Location {
+        return self.location_at(
+            usize::from(node.text_range().start()),
+            usize::from(node.text_range().len()),
+        );
+    }
+
+    pub fn location_at(&self, start: usize, len: usize) -> Location {
+        let before = &self.file_contents[..start];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        return Location {
+            file_path: self.file_path.clone(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            span: (start, len).into(),
         };
     }
 
-    if node.kind() == SyntaxKind::NODE_IDENT {
-        return match node.text().to_string().as_str() {
-            "true" => Ok(serde_json::Value::Bool(true)),
-            "false" => Ok(serde_json::Value::Bool(false)),
-            identifier => Err(Error::NixParsingError(format!(
-                "Unexpected identifier {}",
-                identifier,
-            ))),
+    /// Points an error about the argument of an uptix function at it, so
+    /// that it's reported along with the source around it. Errors which
+    /// aren't about the argument itself are returned as they are.
+    pub fn invalid_argument(&self, function: &str, node: &SyntaxNode, error: Error) -> Error {
+        let message = match error {
+            Error::NixParsingError(message) => message,
+            Error::JSONParsingError(error) => error.to_string(),
+            error => return error,
+        };
+        return Error::InvalidArgument {
+            function: function.to_string(),
+            message,
+            src: self.src(),
+            argument_pos: (
+                usize::from(node.text_range().start()),
+                usize::from(node.text_range().len()),
+            )
+                .into(),
         };
     }
 
-    if node.kind() != SyntaxKind::NODE_ATTR_SET {
-        return Err(Error::NixParsingError(format!(
-            "Expected attr set, found {:#?}",
-            node.kind()
-        )));
+    pub fn syntax_error(&self, error: &ParseError) -> Error {
+        let eof = self.file_contents.len();
+        let (start, len, message) = match error {
+            ParseError::Unexpected(range) | ParseError::UnexpectedExtra(range) => (
+                range.start().into(),
+                range.len().into(),
+                "unexpected token".to_string(),
+            ),
+            ParseError::UnexpectedWanted(got, range, wanted) => (
+                range.start().into(),
+                range.len().into(),
+                format!("unexpected {:?}, wanted any of {:?}", got, wanted),
+            ),
+            ParseError::UnexpectedDoubleBind(range) => (
+                range.start().into(),
+                range.len().into(),
+                "pattern is bound twice".to_string(),
+            ),
+            ParseError::DuplicatedArgs(range, name) => (
+                range.start().into(),
+                range.len().into(),
+                format!("argument {} is duplicated", name),
+            ),
+            ParseError::UnexpectedEOF => (eof, 0, "unexpected end of file".to_string()),
+            ParseError::UnexpectedEOFWanted(wanted) => (
+                eof,
+                0,
+                format!("unexpected end of file, wanted any of {:?}", wanted),
+            ),
+            _ => (0, 0, error.to_string()),
+        };
+        return Error::NixSyntaxError {
+            src: self.src(),
+            pos: (start, len).into(),
+            message,
+        };
     }
+}
 
-    let mut attrs: Map<String, serde_json::Value> = Map::new();
-    for child in node.children() {
-        if child.kind() != SyntaxKind::NODE_KEY_VALUE {
-            return Err(Error::NixParsingError(format!(
-                "Expected key/value pair, got {:#?}",
-                child.kind()
-            )));
+/// Quotes a string as a nix string literal
+pub fn nix_string(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${");
+    return format!("\"{}\"", escaped);
+}
+
+/// Returns the contents of a string literal, with escape sequences already
+/// interpreted. Strings with interpolations can't be evaluated statically, so
+/// `None` is returned for those.
+pub fn string_value(node: &SyntaxNode) -> Option<String> {
+    let string = ast::Str::cast(node.clone())?;
+    let mut value = String::new();
+    for part in string.normalized_parts() {
+        match part {
+            InterpolPart::Literal(literal) => value.push_str(&literal),
+            InterpolPart::Interpolation(_) => return None,
         }
-        let key = child.first_child().unwrap();
-        let value = key.next_sibling().unwrap();
-        attrs.insert(key.text().to_string(), value_from_nix(&value)?);
     }
+    return Some(value);
+}
 
-    return Ok(Value::Object(attrs));
+fn value_from_nix(node: &SyntaxNode) -> Result<Value, Error> {
+    let expr = Expr::cast(node.clone()).ok_or_else(|| {
+        Error::NixParsingError(format!("Unexpected node kind {:#?}", node.kind()))
+    })?;
+    return match expr {
+        Expr::Str(_) => match string_value(node) {
+            Some(s) => Ok(Value::String(s)),
+            None => Err(Error::NixParsingError(
+                "String interpolation is not supported".to_string(),
+            )),
+        },
+        Expr::Literal(literal) => match literal.kind() {
+            LiteralKind::Integer(v) => v
+                .value()
+                .map(Value::from)
+                .map_err(|e| Error::NixParsingError(e.to_string())),
+            LiteralKind::Float(v) => v
+                .value()
+                .map(Value::from)
+                .map_err(|e| Error::NixParsingError(e.to_string())),
+            LiteralKind::Uri(v) => Ok(Value::String(v.to_string())),
+        },
+        Expr::PathAbs(_) | Expr::PathRel(_) => match nix_path_literal(node) {
+            Some(path) => Ok(Value::String(path)),
+            None => Err(Error::NixParsingError(
+                "Path interpolation is not supported".to_string(),
+            )),
+        },
+        // negative numbers are negated literals, which are parsed along with
+        // their sign so that i.e. -9223372036854775808 doesn't overflow
+        Expr::UnaryOp(op) if op.operator() == Some(UnaryOpKind::Negate) => {
+            let text = format!("-{}", op.expr().map_or(String::new(), |v| v.to_string()));
+            if let Ok(v) = text.parse::<i64>() {
+                return Ok(Value::from(v));
+            }
+            match text.parse().ok().and_then(serde_json::Number::from_f64) {
+                Some(v) => Ok(Value::Number(v)),
+                None => Err(Error::NixParsingError(format!(
+                    "Unsupported expression {}",
+                    text
+                ))),
+            }
+        }
+        Expr::Ident(ident) => match ident.to_string().as_str() {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            "null" => Ok(Value::Null),
+            identifier => Err(Error::NixParsingError(format!(
+                "Unexpected identifier {}",
+                identifier,
+            ))),
+        },
+        Expr::Paren(paren) => match paren.expr() {
+            Some(inner) => value_from_nix(inner.syntax()),
+            None => Err(Error::NixParsingError(
+                "Empty parentheses are not supported".to_string(),
+            )),
+        },
+        Expr::List(list) => Ok(Value::Array(
+            list.items()
+                .map(|item| value_from_nix(item.syntax()))
+                .collect::<Result<_, _>>()?,
+        )),
+        Expr::AttrSet(attr_set) => {
+            if attr_set.inherits().next().is_some() {
+                return Err(Error::NixParsingError(
+                    "Inherit is not supported".to_string(),
+                ));
+            }
+            let mut attrs: Map<String, Value> = Map::new();
+            for entry in attr_set.attrpath_values() {
+                let (key, value) = match (entry.attrpath(), entry.value()) {
+                    (Some(key), Some(value)) => (key, value),
+                    _ => return Err(Error::NixParsingError("Incomplete attribute".to_string())),
+                };
+                attrs.insert(key.to_string(), value_from_nix(value.syntax())?);
+            }
+            Ok(Value::Object(attrs))
+        }
+        other => Err(Error::NixParsingError(format!(
+            "Expected attr set, found {:#?}",
+            other.syntax().kind()
+        ))),
+    };
 }
 
 pub fn from_attr_set<T>(node: &SyntaxNode) -> Result<T, Error>
@@ -113,14 +553,22 @@ where
     T: serde::de::DeserializeOwned,
 {
     let value = value_from_nix(node)?;
-    let json = value.to_string();
-    return Ok(serde_json::from_str::<T>(&json).unwrap());
+    return Ok(serde_json::from_value::<T>(value)?);
 }
 
 #[cfg(test)]
 mod tests {
-    use super::from_attr_set;
+    use super::{
+        discover_imported_nix_files, discover_nix_files, discover_root_files,
+        discover_template_files, discover_workflow_files, from_attr_set, read_source,
+        value_from_nix,
+    };
+    use crate::config::RootConfig;
+    use crate::error::Error;
+    use proptest::prelude::*;
     use serde::{Deserialize, Serialize};
+    use serde_json::Value;
+    use std::fs;
 
     #[derive(Serialize, Deserialize, PartialEq, Debug)]
     pub struct A {
@@ -139,18 +587,18 @@ mod tests {
 
     #[test]
     fn it_deserializes_attr_sets() {
-        let ast = rnix::parse(
+        let ast = rnix::Root::parse(
             r#"{
                 a = "foo";
                 b = {
                     b = "bar";
                     c = 42;
-                    d = 3.1415;
+                    d = 2.5;
                     f = 7;
                 };
             }"#,
         );
-        let attrset = ast.node().first_child().unwrap();
+        let attrset = ast.syntax().first_child().unwrap();
         assert_eq!(
             from_attr_set::<A>(&attrset).unwrap(),
             A {
@@ -158,11 +606,307 @@ mod tests {
                 b: B {
                     b: "bar".to_string(),
                     c: 42,
-                    d: 3.1415,
+                    d: 2.5,
                     e: None,
                     f: Some(7),
                 }
             },
         );
     }
+
+    /// Values which can be written as nix literals
+    fn json_value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            "\\PC*".prop_map(Value::from),
+        ];
+        return leaf.prop_recursive(4, 32, 4, |inner| {
+            prop_oneof![
+                proptest::collection::vec(inner.clone(), 0..4).prop_map(Value::from),
+                proptest::collection::btree_map("x[a-z0-9_]{0,6}", inner, 0..4)
+                    .prop_map(|map| Value::Object(map.into_iter().collect())),
+            ]
+        });
+    }
+
+    fn to_nix(value: &Value) -> String {
+        return match value {
+            Value::String(s) => format!(
+                "\"{}\"",
+                s.replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace("${", "\\${")
+                    .replace('\n', "\\n")
+                    .replace('\r', "\\r")
+                    .replace('\t', "\\t")
+            ),
+            Value::Array(items) => format!(
+                "[ {} ]",
+                items
+                    .iter()
+                    .map(|i| format!("({})", to_nix(i)))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            Value::Object(attrs) => format!(
+                "{{ {} }}",
+                attrs
+                    .iter()
+                    .map(|(k, v)| format!("{} = {};", k, to_nix(v)))
+                    .collect::<String>()
+            ),
+            other => other.to_string(),
+        };
+    }
+
+    proptest! {
+        #[test]
+        fn it_deserializes_any_attr_set(
+            attrs in proptest::collection::btree_map("x[a-z0-9_]{0,6}", json_value(), 0..6)
+        ) {
+            let value = Value::Object(attrs.into_iter().collect());
+            let ast = rnix::Root::parse(&to_nix(&value));
+            prop_assert!(ast.errors().is_empty());
+            let attrset = ast.syntax().first_child().unwrap();
+            prop_assert_eq!(from_attr_set::<Value>(&attrset).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn it_follows_imports() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("hosts")).unwrap();
+        fs::create_dir_all(root.join("modules")).unwrap();
+        fs::create_dir_all(root.join("vendor")).unwrap();
+        fs::write(
+            root.join("configuration.nix"),
+            "{ imports = [ ./hosts/foo.nix ./modules ./missing.nix ./vendor <nixpkgs/foo> ]; }",
+        )
+        .unwrap();
+        fs::write(
+            root.join("hosts/foo.nix"),
+            "{ x = import ../modules/bar.nix; y = import ../configuration.nix; }",
+        )
+        .unwrap();
+        fs::write(root.join("modules/default.nix"), "{ }").unwrap();
+        fs::write(root.join("modules/bar.nix"), "{ }").unwrap();
+        fs::write(root.join("vendor/unrelated.nix"), "{ }").unwrap();
+
+        let mut files: Vec<_> = discover_imported_nix_files(&root.join("configuration.nix"))
+            .unwrap()
+            .iter()
+            .map(|f| f.strip_prefix(root).unwrap().to_str().unwrap().to_string())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                "configuration.nix",
+                "hosts/../modules/bar.nix",
+                "hosts/foo.nix",
+                "modules/default.nix",
+            ],
+        );
+        // the entry point itself has to be there
+        assert!(matches!(
+            discover_imported_nix_files(&root.join("vendor")),
+            Err(Error::UnreadableFile { .. })
+        ));
+    }
+
+    #[test]
+    fn it_excludes_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("hosts")).unwrap();
+        fs::create_dir_all(root.join("third_party/foo")).unwrap();
+        fs::create_dir_all(root.join("tests/fixtures")).unwrap();
+        fs::write(root.join("configuration.nix"), "{ }").unwrap();
+        fs::write(root.join("hosts/foo.nix"), "{ }").unwrap();
+        fs::write(root.join("third_party/foo/default.nix"), "{ }").unwrap();
+        fs::write(root.join("tests/fixtures/bar.nix"), "{ }").unwrap();
+        fs::write(root.join("tests/baz.nix"), "{ }").unwrap();
+
+        let root_config = RootConfig {
+            path: root.to_str().unwrap().to_string(),
+            exclude: vec!["third_party/**".to_string(), "tests/fixtures".to_string()],
+            ..Default::default()
+        };
+        let mut files: Vec<_> = discover_nix_files(&root_config)
+            .unwrap()
+            .iter()
+            .map(|f| f.strip_prefix(root).unwrap().to_str().unwrap().to_string())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec!["configuration.nix", "hosts/foo.nix", "tests/baz.nix"],
+        );
+    }
+
+    #[test]
+    fn it_scans_multiple_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("hosts/old")).unwrap();
+        fs::create_dir_all(root.join("pkgs")).unwrap();
+        fs::write(root.join("default.nix"), "{ }").unwrap();
+        fs::write(root.join("hosts/foo.nix"), "{ }").unwrap();
+        fs::write(root.join("hosts/old/bar.nix"), "{ }").unwrap();
+        fs::write(root.join("pkgs/baz.nix"), "{ }").unwrap();
+
+        let root_at = |path: &str, exclude: &[&str]| RootConfig {
+            path: root.join(path).to_str().unwrap().to_string(),
+            exclude: exclude.iter().map(|e| e.to_string()).collect(),
+            ..Default::default()
+        };
+        let roots = vec![
+            root_at("hosts", &["old/**"]),
+            root_at("pkgs", &[]),
+            root_at("pkgs/", &[]),
+        ];
+        let files: Vec<_> = discover_root_files(&roots, discover_nix_files)
+            .unwrap()
+            .iter()
+            .map(|f| f.strip_prefix(root).unwrap().to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(files, vec!["hosts/foo.nix", "pkgs/baz.nix"]);
+
+        let missing = vec![root_at("modules", &[])];
+        assert!(discover_root_files(&missing, |_| Ok(vec![])).is_err());
+    }
+
+    #[test]
+    fn it_handles_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        let outside = dir.path().join("outside");
+        fs::create_dir_all(root.join("hosts")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(root.join("hosts/foo.nix"), "{ }").unwrap();
+        fs::write(outside.join("bar.nix"), "{ }").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("linked")).unwrap();
+        std::os::unix::fs::symlink(&root, root.join("hosts/loop")).unwrap();
+
+        let discover = |follow_symlinks| {
+            let root_config = RootConfig {
+                path: root.to_str().unwrap().to_string(),
+                follow_symlinks,
+                ..Default::default()
+            };
+            let mut files: Vec<_> = discover_nix_files(&root_config)
+                .unwrap()
+                .iter()
+                .map(|f| f.strip_prefix(&root).unwrap().to_str().unwrap().to_string())
+                .collect();
+            files.sort();
+            return files;
+        };
+        assert_eq!(discover(false), vec!["hosts/foo.nix"]);
+        assert_eq!(discover(true), vec!["hosts/foo.nix", "linked/bar.nix"]);
+    }
+
+    #[test]
+    fn it_skips_large_and_binary_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = dir.path().join("text.nix");
+        let binary = dir.path().join("binary.nix");
+        fs::write(&text, "{ foo = 42; }").unwrap();
+        fs::write(&binary, b"{ \x00\x01 }").unwrap();
+
+        let text_path = text.to_str().unwrap();
+        assert_eq!(read_source(text_path, 0).unwrap(), "{ foo = 42; }");
+        assert_eq!(read_source(text_path, 13).unwrap(), "{ foo = 42; }");
+        assert!(matches!(
+            read_source(text_path, 12),
+            Err(Error::OversizedFile { size: 13, .. })
+        ));
+        assert!(matches!(
+            read_source(binary.to_str().unwrap(), 0),
+            Err(Error::BinaryFile { .. })
+        ));
+    }
+
+    #[test]
+    fn it_discovers_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("terranix")).unwrap();
+        fs::write(root.join("default.nix"), "{ }").unwrap();
+        fs::write(root.join("default.nix.in"), "{ }").unwrap();
+        fs::write(root.join("terranix/main.tf.json"), "{ }").unwrap();
+        fs::write(root.join("terranix/README.md"), "").unwrap();
+
+        let root_config = RootConfig {
+            path: root.to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let patterns = vec!["**/*.nix.in".to_string(), "**/*.tf.json".to_string()];
+        let mut files: Vec<_> = discover_template_files(&root_config, &patterns)
+            .unwrap()
+            .iter()
+            .map(|f| f.strip_prefix(root).unwrap().to_str().unwrap().to_string())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["default.nix.in", "terranix/main.tf.json"]);
+    }
+
+    #[test]
+    fn it_discovers_workflows() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let root_config = RootConfig {
+            path: root.to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        assert!(discover_workflow_files(&root_config).unwrap().is_empty());
+
+        fs::create_dir_all(root.join(".github/workflows")).unwrap();
+        fs::write(root.join(".github/workflows/ci.yml"), "").unwrap();
+        fs::write(root.join(".github/workflows/release.yaml"), "").unwrap();
+        fs::write(root.join(".github/workflows/README.md"), "").unwrap();
+        let files: Vec<_> = discover_workflow_files(&root_config)
+            .unwrap()
+            .iter()
+            .map(|f| f.strip_prefix(root).unwrap().to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            files,
+            vec![".github/workflows/ci.yml", ".github/workflows/release.yaml"]
+        );
+    }
+
+    #[test]
+    fn it_deserializes_nix_values() {
+        let ast = rnix::Root::parse(
+            r#"{
+                escaped = "foo \"bar\"\n";
+                multiline = ''
+                  foo
+                '';
+                path = ./foo/bar.nix;
+                list = [ 1 (2) true null ];
+            }"#,
+        );
+        let attrset = ast.syntax().first_child().unwrap();
+        assert_eq!(
+            from_attr_set::<serde_json::Value>(&attrset).unwrap(),
+            serde_json::json!({
+                "escaped": "foo \"bar\"\n",
+                "multiline": "foo\n",
+                "path": "./foo/bar.nix",
+                "list": [1, 2, true, null],
+            }),
+        );
+    }
+
+    #[test]
+    fn it_rejects_interpolation() {
+        let ast = rnix::Root::parse(r#"{ foo = "${bar}"; }"#);
+        let attrset = ast.syntax().first_child().unwrap();
+        assert!(value_from_nix(&attrset).is_err());
+    }
 }
@@ -0,0 +1,109 @@
+use crate::config::GitHubConfig;
+use crate::deps::github::{self, GitHubHost};
+use crate::error::Error;
+use crate::lock::{GitHubLock, LockData};
+use crate::nar;
+use base64::Engine;
+use std::fmt;
+
+/// A source which hashed to something else than uptix.lock has for it
+#[derive(PartialEq, Debug)]
+pub struct Mismatch {
+    /// what was fetched, i.e. `owner/repo@rev` or the URL of an asset
+    pub source: String,
+    pub locked: String,
+    pub fetched: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(
+            f,
+            "{} hashes to {}, but uptix.lock has {}",
+            self.source, self.fetched, self.locked
+        );
+    }
+}
+
+/// Turns a hash into nix's base32, which uptix writes, so that SRI hashes
+/// (i.e. `sha256-...`) written by hand compare the same
+pub fn normalize(hash: &str) -> String {
+    let decoded = hash
+        .strip_prefix("sha256-")
+        .and_then(|sri| base64::engine::general_purpose::STANDARD.decode(sri).ok());
+    return match decoded {
+        Some(bytes) => nar::to_nix_base32(&bytes),
+        None => hash.to_string(),
+    };
+}
+
+/// Whether an entry has sources to fetch again. Images don't, since their
+/// digest already is the hash of what they are.
+pub fn is_verifiable(lock: &LockData) -> bool {
+    return matches!(lock, LockData::GitHub(_) | LockData::Release(_));
+}
+
+fn compare(source: String, locked: &str, fetched: String) -> Option<Mismatch> {
+    if normalize(locked) == normalize(&fetched) {
+        return None;
+    }
+    return Some(Mismatch {
+        source,
+        locked: locked.to_string(),
+        fetched,
+    });
+}
+
+async fn verify_repository(
+    config: &GitHubConfig,
+    lock: &GitHubLock,
+    with_nix: bool,
+) -> Result<Option<Mismatch>, Error> {
+    let host = lock.githubBase.as_deref().unwrap_or(github::DEFAULT_HOST);
+    let github = GitHubHost::new(config, host);
+    let fetched = github::rehash(&github, lock, with_nix).await?;
+    let source = format!("{}/{}@{}", lock.owner, lock.repo, lock.rev);
+    return Ok(compare(source, &lock.sha256, fetched));
+}
+
+/// Fetches the sources of an entry again, either the way uptix does or
+/// through nix's fetchers, and compares their hashes with uptix.lock
+pub async fn verify(
+    config: &GitHubConfig,
+    lock: &LockData,
+    with_nix: bool,
+) -> Result<Vec<Mismatch>, Error> {
+    let mut mismatches = vec![];
+    match lock {
+        LockData::GitHub(lock) => {
+            mismatches.extend(verify_repository(config, lock, with_nix).await?)
+        }
+        LockData::Release(release) => {
+            mismatches.extend(verify_repository(config, &release.lock, with_nix).await?);
+            let asset = &release.asset;
+            let fetched = github::rehash_asset(&asset.url, with_nix).await?;
+            mismatches.extend(compare(asset.url.clone(), &asset.sha256, fetched));
+        }
+        _ => (),
+    }
+    return Ok(mismatches);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, normalize};
+
+    #[test]
+    fn it_compares_hashes_in_any_format() {
+        let base32 = "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73";
+        let sri = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+        assert_eq!(normalize(sri), base32);
+        assert_eq!(normalize(base32), base32);
+        assert_eq!(compare("a".to_string(), sri, base32.to_string()), None);
+        let mismatch = compare("a".to_string(), base32, "something else".to_string()).unwrap();
+        assert_eq!(
+            mismatch.to_string(),
+            format!("a hashes to something else, but uptix.lock has {}", base32)
+        );
+    }
+}